@@ -1,16 +1,19 @@
 use core::f64;
 
-#[derive(Debug)]
+use vm::string::Str;
+
+#[derive(Debug, Clone)]
 pub(crate) enum ParseObj {
     Nil,
     Bool(bool),
     Int(i32),
     Float(f64),
-    Str(String),
-    Ident(String), // TODO: use a u16 for idx instead use String directly
+    // the strings of the code are interned, see `Str`
+    Str(Str),
+    Ident(Str),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 #[allow(unused)]
 pub(crate) struct Located<T> {
     pub node: T,
@@ -25,14 +28,46 @@ impl<T> Located<T> {
 pub(crate) type Stmt = Located<StmtKind>;
 pub(crate) type Expr = Located<ExprKind>;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) enum StmtKind {
-    ExprStmt { expr: Box<Expr> },
-    VarDec { name: String, value: Box<Expr> },
-    While { test: Box<Expr>, body: Vec<Stmt> },
+    ExprStmt {
+        expr: Box<Expr>,
+    },
+    VarDec {
+        name: Str,
+        value: Box<Expr>,
+    },
+    // `'label: while test { body }`
+    While {
+        label: Option<Str>,
+        test: Box<Expr>,
+        body: Vec<Stmt>,
+    },
+    For {
+        label: Option<Str>,
+        pattern: Pattern,
+        iter: Box<Expr>,
+        body: Vec<Stmt>,
+    },
+    EnumDec {
+        name: Str,
+        variants: Vec<(Str, Vec<Str>)>,
+    },
+    StructDec {
+        name: Str,
+        fields: Vec<Str>,
+    },
+    // `fn name(a, b) { body }`
+    // `fn* name()` for a generator
+    FnDec {
+        name: Str,
+        params: Vec<Str>,
+        body: Vec<Stmt>,
+        generator: bool,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) enum ExprKind {
     Literal {
         value: ParseObj,
@@ -57,15 +92,153 @@ pub(crate) enum ExprKind {
         body: Vec<Stmt>,
         orelse: Vec<Stmt>,
     },
+    Tuple {
+        items: Vec<Expr>,
+    },
+    List {
+        items: Vec<Expr>,
+    },
+    // `#{ key: value }`
+    Map {
+        items: Vec<(Expr, Expr)>,
+    },
+    // `start..end step n`
+    Range {
+        start: Box<Expr>,
+        end: Box<Expr>,
+        step: Option<Box<Expr>>,
+        inclusive: bool,
+    },
+    // `Shape::Circle(1)` or `Shape::Empty`
+    Variant {
+        enum_name: Str,
+        variant: Str,
+        args: Vec<Expr>,
+    },
+    // `Point { x: 1, y: 2 }`
+    StructLit {
+        name: Str,
+        fields: Vec<(Str, Expr)>,
+    },
+    // `point.x`
+    Field {
+        object: Box<Expr>,
+        name: Str,
+    },
+    // `tuple.0`
+    Item {
+        object: Box<Expr>,
+        index: u8,
+    },
+    // `f(a, b)`
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+    },
+    // `list[0]`, `map["key"]`
+    Index {
+        object: Box<Expr>,
+        index: Box<Expr>,
+    },
+    // `target = value`, the target is a variable, field or index
+    Assign {
+        target: Box<Expr>,
+        value: Box<Expr>,
+    },
+    Match {
+        subject: Box<Expr>,
+        arms: Vec<MatchArm>,
+    },
+    // `'label: loop { body }`, the value is given by `break`
+    Loop {
+        label: Option<Str>,
+        body: Vec<Stmt>,
+    },
+    // `break 'label value`
+    Break {
+        label: Option<Str>,
+        value: Option<Box<Expr>>,
+    },
+    // `continue 'label`
+    Continue {
+        label: Option<Str>,
+    },
+    // `return value`
+    Return {
+        value: Option<Box<Expr>>,
+    },
+    // `yield value`, it is the value given back by the host
+    Yield {
+        value: Option<Box<Expr>>,
+    },
+    // `try { body } catch name { handler } finally { finally }`, one of `catch` and
+    // `finally` at least, the name is optional.
+    Try {
+        body: Vec<Stmt>,
+        catch: Option<(Option<Str>, Vec<Stmt>)>,
+        finally: Option<Vec<Stmt>>,
+    },
+    // `throw value`
+    Throw {
+        value: Box<Expr>,
+    },
+    // `value?`, the value in `Some(v)` or `Ok(v)`, it returns `None` and `Err(e)`
+    Propagate {
+        value: Box<Expr>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Box<Expr>>,
+    pub body: Box<Expr>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Pattern {
+    // `_`
+    Wildcard,
+    // `1`, `"str"`, `true`, `nil`
+    Literal(ParseObj),
+    // `1..5` or `1..=5`
+    Range {
+        start: ParseObj,
+        end: ParseObj,
+        inclusive: bool,
+    },
+    // `x` or `x @ 1..=5`
+    Bind {
+        name: Str,
+        sub: Option<Box<Pattern>>,
+    },
+    // `(a, b)`
+    Tuple(Vec<Pattern>),
+    // `[a, b]`, or `[a, .., z]` when `suffix` is some
+    List {
+        prefix: Vec<Pattern>,
+        suffix: Option<Vec<Pattern>>,
+    },
+    // `Point { x, y: 0, .. }`
+    Struct {
+        name: Str,
+        fields: Vec<(Str, Pattern)>,
+    },
+    // `Shape::Circle(r)`
+    Variant {
+        enum_name: Str,
+        variant: Str,
+        fields: Vec<Pattern>,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) enum UnaryOp {
     Not,
     Neg,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub(crate) enum BinaryOp {
     Add,
     Sub,
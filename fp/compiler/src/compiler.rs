@@ -1,36 +1,249 @@
-use std::collections::HashMap;
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
-use vm::{chunk::Chunk, op::OpCode, value::Value};
+use vm::{
+    chunk::{Chunk, Handler},
+    object::{EnumDef, EnumObj, Function, StructDef, StructObj, VariantDef},
+    op::OpCode,
+    peephole, prelude,
+    string::Str,
+    value::Value,
+};
 
-use crate::ast::{BinaryOp, Expr, ExprKind, ParseObj, Stmt, StmtKind, UnaryOp};
+use crate::ast::{BinaryOp, Expr, ExprKind, MatchArm, ParseObj, Pattern, Stmt, StmtKind, UnaryOp};
+use crate::diagnostic::{Error, Warning};
+use crate::exhaustive::Checker;
+
+mod register;
+
+// the code made by the compiler, the bytecode of `Chunk` or the register code of
+// `vm::register`, see `register`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    Stack,
+    Register,
+}
+
+impl Backend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Stack => "stack",
+            Backend::Register => "register",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Backend> {
+        [Backend::Stack, Backend::Register]
+            .into_iter()
+            .find(|backend| backend.name() == name)
+    }
+}
 
 pub(crate) struct Compiler {
+    backend: Backend,
+    // the chunks go through `peephole` once they are compiled
+    optimize: bool,
+    chunk: Chunk,
+    global: HashMap<Str, u16>,
+    // the index of the next new global, the names may be forgotten but not the indexes
+    next_global: usize,
+    // locals of every block and their slot in the stack
+    scope: Vec<Vec<(Str, u16)>>,
+    scope_depth: usize,
+    // how many values are in the stack at this point of the code,
+    // blocks are exprs, so there may be temporary values under the locals.
+    stack_top: u16,
+    enums: HashMap<Str, Rc<EnumDef>>,
+    structs: HashMap<Str, Rc<StructDef>>,
+    warnings: Vec<Warning>,
+    errors: Vec<Error>,
+    // the loops around this point of the code, the innermost is the last
+    loops: Vec<Loop>,
+    // the `finally`s of the `try`s around this point, they run before a jump out of them
+    finallies: Vec<Vec<Stmt>>,
+    // the functions around the one being compiled, the outermost is the first
+    enclosing: Vec<FnState>,
+}
+
+// what a function has its own, saved when a nested function is compiled
+struct FnState {
     chunk: Chunk,
-    global: HashMap<String, u16>,
-    scope: Vec<HashMap<String, u16>>,
+    scope: Vec<Vec<(Str, u16)>>,
     scope_depth: usize,
     stack_top: u16,
+    loops: Vec<Loop>,
+    finallies: Vec<Vec<Stmt>>,
+}
+
+struct Loop {
+    label: Option<Str>,
+    // where `continue` jumps back to
+    start: usize,
+    // how many values are in the stack at `start`
+    continue_top: u16,
+    // how many values are in the stack after the loop, not counting its value
+    break_top: u16,
+    // the `break`s waiting for the end of the loop
+    breaks: Vec<usize>,
+    // only `loop` gives a value, `while` and `for` are statements
+    has_value: bool,
+    // how many `finally`s are around the loop
+    finallies: usize,
+}
+
+// what a failed compile should not change, see `Compiler::compile`
+struct Checkpoint {
+    global: HashMap<Str, u16>,
+    next_global: usize,
+    enums: HashMap<Str, Rc<EnumDef>>,
+    structs: HashMap<Str, Rc<StructDef>>,
+}
+
+// how to get a part of the value being matched, see `emit_path`
+#[derive(Clone, Copy)]
+enum Step {
+    Item(u8),
+    ItemBack(u8),
 }
 
 impl Compiler {
     pub(crate) fn new() -> Compiler {
+        // `Option` and `Result` can be declared again, like any other enum
+        let enums = [prelude::option_def(), prelude::result_def()]
+            .into_iter()
+            .map(|def| (Str::intern(&def.name), Rc::new(def)))
+            .collect();
         Compiler {
+            backend: Backend::Stack,
+            optimize: true,
             chunk: Chunk::new(),
             global: HashMap::new(),
+            next_global: 0,
             scope: Vec::new(),
             scope_depth: 0,
             stack_top: 0,
+            enums,
+            structs: HashMap::new(),
+            warnings: Vec::new(),
+            errors: Vec::new(),
+            loops: Vec::new(),
+            finallies: Vec::new(),
+            enclosing: Vec::new(),
         }
     }
 
-    pub(crate) fn compile(&mut self, ast: Stmt) {
+    // the chunk returns the value of the last stmt when `keep_value` and it's an expr stmt.
+    // when there are errors, the compiler is the same as before the call.
+    pub(crate) fn compile(&mut self, ast: Vec<Stmt>, keep_value: bool) -> Result<(), Vec<Error>> {
         #[cfg(feature = "compiler_dev")]
         println!("compile ast: {:#?}", ast);
 
-        self.compile_stmt(ast);
+        let checkpoint = self.checkpoint();
+        // the functions can call the ones declared after them
+        for stmt in ast.iter() {
+            if let StmtKind::FnDec { name, .. } = &stmt.node {
+                self.declare_global(name.clone());
+            }
+        }
+        match self.backend {
+            // the script is compiled like a function, see `register`
+            Backend::Register if !register::needs_stack(&ast, false) => {
+                register::compile_script(self, ast, keep_value)
+            }
+            _ => self.compile_script(ast, keep_value),
+        }
+
+        if self.errors.is_empty() {
+            let chunk = std::mem::take(&mut self.chunk);
+            self.chunk = self.optimized(chunk);
+            return Ok(());
+        }
+        self.rollback(checkpoint);
+        Err(std::mem::take(&mut self.errors))
+    }
+
+    fn compile_script(&mut self, ast: Vec<Stmt>, keep_value: bool) {
+        let mut ast = ast;
+        let last = match ast.last() {
+            Some(Stmt {
+                node: StmtKind::ExprStmt { .. },
+            }) if keep_value => ast.pop(),
+            _ => None,
+        };
+        for stmt in ast {
+            self.compile_stmt(stmt);
+        }
+        if let Some(Stmt {
+            node: StmtKind::ExprStmt { expr },
+        }) = last
+        {
+            self.compile_expr(*expr);
+            // `Return` takes it
+            self.pop_slots(1);
+        }
         self.emit_opcode(OpCode::Return);
     }
 
+    pub(crate) fn set_backend(&mut self, backend: Backend) {
+        self.backend = backend;
+    }
+
+    pub(crate) fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    pub(crate) fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    pub(crate) fn optimize(&self) -> bool {
+        self.optimize
+    }
+
+    fn optimized(&self, chunk: Chunk) -> Chunk {
+        match self.optimize {
+            true => peephole::optimize(chunk),
+            false => chunk,
+        }
+    }
+
+    // compile the code without keeping anything, e.g. the global variables
+    pub(crate) fn compile_dry(
+        &mut self,
+        ast: Vec<Stmt>,
+        keep_value: bool,
+    ) -> Result<Chunk, Vec<Error>> {
+        let checkpoint = self.checkpoint();
+        self.compile(ast, keep_value)?;
+        let chunk = std::mem::take(&mut self.chunk);
+        self.rollback(checkpoint);
+        Ok(chunk)
+    }
+
+    fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            global: self.global.clone(),
+            next_global: self.next_global,
+            enums: self.enums.clone(),
+            structs: self.structs.clone(),
+        }
+    }
+
+    fn rollback(&mut self, checkpoint: Checkpoint) {
+        self.global = checkpoint.global;
+        self.next_global = checkpoint.next_global;
+        self.enums = checkpoint.enums;
+        self.structs = checkpoint.structs;
+        self.chunk = Chunk::new();
+        self.scope.clear();
+        self.scope_depth = 0;
+        self.stack_top = 0;
+        self.loops.clear();
+        self.finallies.clear();
+        self.enclosing.clear();
+        self.warnings.clear();
+    }
+
     pub(crate) fn pop_chunk(&mut self) -> Chunk {
         #[cfg(feature = "compiler_dev")]
         println!("compiled chunk: {:#04x?}", self.chunk);
@@ -38,6 +251,42 @@ impl Compiler {
         std::mem::replace(&mut self.chunk, Chunk::new())
     }
 
+    pub(crate) fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    pub(crate) fn global(&self, name: &str) -> Option<u16> {
+        self.global.get(name).copied()
+    }
+
+    // the index of the global, a new one when it isn't declared.
+    // `None` when there are too many globals.
+    pub(crate) fn declare_global(&mut self, name: Str) -> Option<u16> {
+        if let Some(&i) = self.global.get(&name) {
+            return Some(i);
+        }
+        if self.next_global > u16::MAX as usize {
+            return None;
+        }
+        let i = self.next_global as u16;
+        self.next_global += 1;
+        self.global.insert(name, i);
+        Some(i)
+    }
+
+    pub(crate) fn globals(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.global.iter().map(|(name, i)| (name.as_str(), *i))
+    }
+
+    pub(crate) fn struct_fields(&self) -> impl Iterator<Item = &str> {
+        let fields = self.structs.values().flat_map(|def| def.fields.iter());
+        fields.map(|field| field.as_str())
+    }
+
+    pub(crate) fn retain_globals(&mut self, mut keep: impl FnMut(u16) -> bool) {
+        self.global.retain(|_, i| keep(*i));
+    }
+
     fn compile_stmt(&mut self, stmt: Stmt) {
         match stmt.node {
             StmtKind::ExprStmt { expr } => {
@@ -45,63 +294,441 @@ impl Compiler {
                 self.emit_opcode(OpCode::Pop)
             }
             StmtKind::VarDec { name, value } => self.compile_var_dec(name, *value),
-            StmtKind::While { test, body } => self.compile_while(*test, body),
+            StmtKind::While { label, test, body } => self.compile_while(label, *test, body),
+            StmtKind::For {
+                label,
+                pattern,
+                iter,
+                body,
+            } => self.compile_for(label, pattern, *iter, body),
+            StmtKind::EnumDec { name, variants } => self.compile_enum_dec(name, variants),
+            StmtKind::StructDec { name, fields } => self.compile_struct_dec(name, fields),
+            StmtKind::FnDec {
+                name,
+                params,
+                body,
+                generator,
+            } => self.compile_fn_dec(name, params, body, generator),
         }
     }
 
-    fn compile_var_dec(&mut self, name: String, value: Expr) {
+    fn compile_var_dec(&mut self, name: Str, value: Expr) {
         self.compile_expr(value);
 
         if self.scope_depth == 0 {
-            // TODO: ensure len of global low than u16::MAX
-            if self.global.len() > u16::MAX as usize {
-                todo!()
-            }
-            let i = self.global.len() as u16;
-            self.global.insert(name, i);
+            let i = match self.declare_global(name) {
+                Some(i) => i,
+                None => {
+                    self.error("too many global variables".to_owned());
+                    self.emit_opcode(OpCode::Pop);
+                    return;
+                }
+            };
 
-            if i > u8::MAX as u16 {
-                self.emit_opcode(OpCode::SetGlobalL);
-                self.emit_long_byte(i);
-                return;
+            return self.emit_set_global(i);
+        }
+
+        self.add_local(name);
+    }
+
+    // the function is compiled to its own chunk, which is a constant of this one.
+    // the arguments are the first locals of the function, e.g. `fn add(a, b) { a + b }`:
+    // GetLocal 0
+    // GetLocal 1
+    // Add
+    // Return
+    // a function only sees its own locals and the global variables.
+    // a generator is the same function, calling it makes a coroutine instead of running it
+    fn compile_fn_dec(&mut self, name: Str, params: Vec<Str>, body: Vec<Stmt>, generator: bool) {
+        if params.len() > u8::MAX as usize {
+            return self.error(format!("function `{}` has too many parameters", name));
+        }
+        // a global function can call itself
+        let global = match self.scope_depth {
+            0 => match self.declare_global(name.clone()) {
+                Some(i) => Some(i),
+                None => return self.error("too many global variables".to_owned()),
+            },
+            _ => None,
+        };
+
+        let fun = match self.backend {
+            Backend::Register if !register::needs_stack(&body, generator) => {
+                let outer = self.local_names();
+                register::compile_function(self, outer, &name, params, body)
             }
+            _ => self.compile_function(&name, params, body, generator),
+        };
+        self.emit_constant(fun);
+        match global {
+            Some(i) => self.emit_set_global(i),
+            None => self.add_local(name),
+        }
+    }
 
-            self.emit(OpCode::SetGlobal as u8);
-            self.emit(i as u8);
+    fn compile_function(
+        &mut self,
+        name: &str,
+        params: Vec<Str>,
+        body: Vec<Stmt>,
+        generator: bool,
+    ) -> Value {
+        self.begin_function();
+        self.begin_scope();
+        let arity = params.len() as u8;
+        for param in params {
+            self.push_slot();
+            self.add_local(param);
+        }
+        self.compile_block(body);
+        self.emit_opcode(OpCode::Return);
+        let chunk = self.end_function();
+
+        Value::Function(Rc::new(Function {
+            name: name.to_owned(),
+            arity,
+            chunk: Rc::new(chunk),
+            generator,
+            registers: None,
+        }))
+    }
+
+    // a function of the stack backend in a function of the register backend, `outer`
+    // are the locals of the functions around it
+    fn compile_stack_function(
+        &mut self,
+        outer: Vec<Str>,
+        name: &str,
+        params: Vec<Str>,
+        body: Vec<Stmt>,
+        generator: bool,
+    ) -> Value {
+        let outer = outer.into_iter().map(|name| (name, 0)).collect();
+        let scope = std::mem::replace(&mut self.scope, vec![outer]);
+        let fun = self.compile_function(name, params, body, generator);
+        self.scope = scope;
+        fun
+    }
+
+    // the locals of this function and of the ones around it, for the errors
+    fn local_names(&self) -> Vec<Str> {
+        let outer = self.enclosing.iter().flat_map(|state| state.scope.iter());
+        let locals = outer.chain(self.scope.iter()).flatten();
+        locals.map(|(name, _)| name.clone()).collect()
+    }
+
+    // return from the function, it's an expr like `break` to keep the stack tracked:
+    // { value }        <- `Nil` when there is no value
+    // Return
+    fn compile_return(&mut self, value: Option<Expr>) {
+        if self.enclosing.is_empty() {
+            self.error("`return` outside of a function".to_owned());
+            return self.emit_opcode(OpCode::Nil);
+        }
+        match value {
+            Some(value) => self.compile_expr(value),
+            None => self.emit_opcode(OpCode::Nil),
+        }
+        self.run_finallies(0);
+        self.emit_opcode(OpCode::Return);
+    }
+
+    // `value?`, it returns `None` and `Err(e)` like `return` and goes on with the value
+    // in `Some(v)` and `Ok(v)`:
+    //     { value }
+    // +-- JumpIfOk     <- `v` replaces `Some(v)` or `Ok(v)`
+    // |   { finally }  <- the `finally`s around it, see `run_finallies`
+    // |   Return
+    // +->
+    fn compile_propagate(&mut self, value: Expr) {
+        if self.enclosing.is_empty() {
+            self.error("`?` outside of a function".to_owned());
+            return self.emit_opcode(OpCode::Nil);
+        }
+        self.compile_expr(value);
+        let to_ok = self.emit_jump(OpCode::JumpIfOk);
+        self.run_finallies(0);
+        self.emit_opcode(OpCode::Return);
+        self.patch_jump(to_ok);
+    }
+
+    // run the `finally`s from the innermost down to the `from`th before a jump out of them
+    // a `finally` only sees the ones around it, e.g. for a `return` in it.
+    fn run_finallies(&mut self, from: usize) {
+        let finallies = self.finallies.split_off(from);
+        for (i, finally) in finallies.iter().enumerate().rev() {
+            self.finallies.extend_from_slice(&finallies[..i]);
+            self.compile_block(finally.clone());
+            self.emit_opcode(OpCode::Pop);
+            self.finallies.truncate(from);
+        }
+        self.finallies.extend(finallies);
+    }
+
+    // it will generate:
+    //     { body }         <- a block expr, the handler of `catch` covers it
+    // +-- Jump
+    // |   { catch }        <- the error is the local of the block, it's pushed by the vm
+    // |   BlockEnd 1
+    // +-> { finally }      <- the value is dropped, the handler of `finally` covers
+    //     Pop                 the catch, or the body when there is no catch
+    // +-- Jump
+    // |   { finally }      <- the error is pushed by the vm
+    // |   Pop
+    // |   Throw            <- throw the error again
+    // +-> { other }
+    fn compile_try(
+        &mut self,
+        body: Vec<Stmt>,
+        catch: Option<(Option<Str>, Vec<Stmt>)>,
+        finally: Option<Vec<Stmt>>,
+    ) {
+        let height = self.stack_top;
+        if let Some(finally) = &finally {
+            self.finallies.push(finally.clone());
+        }
+        let start = self.chunk.get_code_len();
+        self.compile_block(body);
+        let mut covered = (start, self.chunk.get_code_len());
+        let to_finally = self.emit_jump(OpCode::Jump);
+
+        if let Some((name, body)) = catch {
+            let target = self.chunk.get_code_len();
+            self.chunk.add_handler(Handler {
+                start: covered.0,
+                end: covered.1,
+                target,
+                height,
+            });
+            self.stack_top = height;
+            self.push_slot();
+            self.begin_scope();
+            // a name no code can use when it isn't given
+            self.add_local(name.unwrap_or_else(|| Str::intern("(error)")));
+            self.compile_block(body);
+            self.end_scope();
+            covered = (target, self.chunk.get_code_len());
+        }
+        self.patch_jump(to_finally);
+
+        let finally = match finally {
+            Some(finally) => finally,
+            None => return,
+        };
+        self.finallies.pop();
+        self.compile_block(finally.clone());
+        self.emit_opcode(OpCode::Pop);
+        let to_end = self.emit_jump(OpCode::Jump);
+
+        let target = self.chunk.get_code_len();
+        self.chunk.add_handler(Handler {
+            start: covered.0,
+            end: covered.1,
+            target,
+            height,
+        });
+        self.stack_top = height;
+        self.push_slot();
+        self.compile_block(finally);
+        self.emit_opcode(OpCode::Pop);
+        self.emit_opcode(OpCode::Throw);
+        self.patch_jump(to_end);
+    }
+
+    // enums and structs only live in the compiler,
+    // the values refer to the definitions directly.
+    fn compile_enum_dec(&mut self, name: Str, variants: Vec<(Str, Vec<Str>)>) {
+        if variants.len() > u16::MAX as usize {
+            self.error(format!("enum `{}` has too many variants", name));
+            return;
+        }
+        if variants
+            .iter()
+            .any(|(_, fields)| fields.len() > u8::MAX as usize)
+        {
+            self.error(format!("a variant of `{}` has too many fields", name));
             return;
         }
+        let variants = variants
+            .into_iter()
+            .map(|(name, fields)| VariantDef {
+                name: name.to_string(),
+                arity: fields.len() as u8,
+            })
+            .collect();
+        let def = EnumDef {
+            name: name.to_string(),
+            variants,
+        };
+        self.enums.insert(name, Rc::new(def));
+    }
 
-        // TODO: check the same variable name
-        self.add_local(name);
+    fn compile_struct_dec(&mut self, name: Str, fields: Vec<Str>) {
+        if fields.len() > u8::MAX as usize {
+            self.error(format!("struct `{}` has too many fields", name));
+            return;
+        }
+        let def = StructDef {
+            name: name.to_string(),
+            fields: fields.iter().map(Str::to_string).collect(),
+        };
+        self.structs.insert(name, Rc::new(def));
     }
 
     // it will generate:
     // { other }
     // { test } <------+   <- an expr
     // +-- JumpIfFalse |   <- will pop and check the value in the top of stack
-    // |   { body }    |   <- stmt, will not return a value
+    // |   { body }    |   <- a block expr
+    // |   Pop         |   <- drop the value of the body
     // |   JumpBack ---+
-    // +-> { other }
-    fn compile_while(&mut self, test: Expr, body: Vec<Stmt>) {
+    // +-> { other }        <- `break` jumps here, `continue` jumps to the test
+    fn compile_while(&mut self, label: Option<Str>, test: Expr, body: Vec<Stmt>) {
+        let start = self.chunk.get_code_len();
+        self.begin_loop(label, start, self.stack_top, self.stack_top, false);
         self.compile_expr(test);
-        self.emit_opcode(OpCode::JumpIfFalse);
-        self.emit_long_byte(0);
-        let now = self.chunk.get_code_len();
-        self.compile_block(body, false);
-        let end = self.chunk.get_code_len();
-        let len = end - now + 3;
-        if len > u16::MAX as usize {
-            todo!()
-        }
-        let len = len as u16;
-        self.emit_opcode(OpCode::JumpBack);
-        self.emit_long_byte(len);
-        self.emit_backfill_long(now - 2, len);
+        let exit = self.emit_jump(OpCode::JumpIfFalse);
+        self.compile_block(body);
+        self.emit_opcode(OpCode::Pop);
+        self.emit_jump_back(start);
+        self.patch_jump(exit);
+        self.end_loop();
+    }
+
+    // it will generate:
+    // { other }
+    // { iter }             <- an expr
+    // GetIter              <- saved as a local without name
+    // ForIter <-------+    <- push the next item as a local, or jump out
+    // |  N            |
+    // |  { bindings } |    <- locals of the pattern
+    // |  { body }     |    <- a block expr
+    // |  BlockEnd     |    <- shift the item and bindings
+    // |  Pop          |    <- drop the value of the body
+    // |  JumpBack ----+
+    // +-> Pop              <- drop the iterator
+    //     { other }        <- `break` jumps here, `continue` jumps to ForIter
+    fn compile_for(&mut self, label: Option<Str>, pattern: Pattern, iter: Expr, body: Vec<Stmt>) {
+        let base = self.stack_top;
+        self.compile_expr(iter);
+        self.emit_opcode(OpCode::GetIter);
+        self.begin_scope();
+        self.add_local(Str::intern(""));
+
+        let start = self.chunk.get_code_len();
+        self.begin_loop(label, start, self.stack_top, base, false);
+        let exit = self.emit_jump(OpCode::ForIter);
+        self.push_slot();
+
+        self.begin_scope();
+        self.add_local(Str::intern(""));
+        let slot = self.stack_top - 1;
+        // the pattern of a for loop should always match
+        let mut fails = Vec::new();
+        self.compile_pattern_test(&pattern, slot, &mut Vec::new(), &mut fails);
+        self.compile_pattern_bind(pattern, slot, &mut Vec::new());
+        self.compile_block(body);
         self.end_scope();
+        self.emit_opcode(OpCode::Pop);
+        self.emit_jump_back(start);
+
+        if !fails.is_empty() {
+            let base = self.stack_top + 1;
+            for fail in fails {
+                self.patch_jump(fail);
+            }
+            self.stack_top = base;
+            self.emit_opcode(OpCode::NoMatch);
+            self.pop_slots(1);
+        }
+
+        self.patch_jump(exit);
+        self.drop_scope();
+        self.end_loop();
+    }
+
+    // it will generate:
+    // { other }
+    // { body } <------+    <- a block expr
+    // Pop             |    <- drop the value of the body
+    // JumpBack -------+    <- `continue` jumps back to the body too
+    // { other }            <- `break` jumps here with the value of the loop
+    fn compile_loop(&mut self, label: Option<Str>, body: Vec<Stmt>) {
+        let start = self.chunk.get_code_len();
+        self.begin_loop(label, start, self.stack_top, self.stack_top, true);
+        self.compile_block(body);
+        self.emit_opcode(OpCode::Pop);
+        self.emit_jump_back(start);
+        self.end_loop();
+        // the value given by `break`
+        self.push_slot();
+    }
+
+    // drop the values above the loop and jump out, it will generate:
+    // { value }        <- only for `loop`, `Nil` when there is no value
+    // BlockEnd         <- drop the locals and temporaries under the value,
+    // N                   `Pop`s instead for `while` and `for`
+    // Jump             <- to the end of the loop
+    // break is an expr without a value, but it's treated as one to keep the stack tracked.
+    fn compile_break(&mut self, label: Option<Str>, value: Option<Expr>) {
+        let i = match self.find_loop(label.as_deref(), "break") {
+            Some(i) => i,
+            None => return self.emit_opcode(OpCode::Nil),
+        };
+        let break_top = self.loops[i].break_top;
+        if self.loops[i].has_value {
+            match value {
+                Some(value) => self.compile_expr(value),
+                None => self.emit_opcode(OpCode::Nil),
+            }
+            self.run_finallies(self.loops[i].finallies);
+            let top = self.stack_top;
+            self.emit_block_end(top - 1 - break_top);
+            let jump = self.emit_jump(OpCode::Jump);
+            self.loops[i].breaks.push(jump);
+            self.stack_top = top;
+        } else {
+            if value.is_some() {
+                self.error("only `loop` can `break` with a value".to_owned());
+            }
+            self.run_finallies(self.loops[i].finallies);
+            let top = self.stack_top;
+            for _ in break_top..top {
+                self.emit_opcode(OpCode::Pop);
+            }
+            let jump = self.emit_jump(OpCode::Jump);
+            self.loops[i].breaks.push(jump);
+            self.stack_top = top;
+            self.push_slot();
+        }
+    }
+
+    // drop the values above the start of the loop and jump back, it will generate:
+    // Pop              <- for every local and temporary
+    // JumpBack
+    fn compile_continue(&mut self, label: Option<Str>) {
+        let i = match self.find_loop(label.as_deref(), "continue") {
+            Some(i) => i,
+            None => return self.emit_opcode(OpCode::Nil),
+        };
+        let (start, continue_top) = (self.loops[i].start, self.loops[i].continue_top);
+        self.run_finallies(self.loops[i].finallies);
+        let top = self.stack_top;
+        for _ in continue_top..top {
+            self.emit_opcode(OpCode::Pop);
+        }
+        self.emit_jump_back(start);
+        self.stack_top = top;
+        self.push_slot();
     }
 
     fn compile_expr(&mut self, expr: Expr) {
         match expr.node {
+            ExprKind::Binary {
+                left,
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                right,
+            } => self.compile_logical(*left, op, *right),
             ExprKind::Binary { left, op, right } => {
                 self.compile_expr(*left);
                 self.compile_expr(*right);
@@ -118,12 +745,211 @@ impl Compiler {
                 self.emit_unary_op(op);
             }
             ExprKind::Block { inner } => {
-                self.compile_block(inner, true);
+                self.compile_block(inner);
             }
             ExprKind::If { test, body, orelse } => {
                 self.compile_if(*test, body, orelse);
             }
+            ExprKind::Tuple { items } => {
+                let n = self.compile_items(items);
+                self.emit_opcode(OpCode::MakeTuple);
+                self.emit(n);
+                self.pop_slots(n as u16);
+                self.push_slot();
+            }
+            ExprKind::Call { callee, args } => self.compile_call(*callee, args),
+            ExprKind::List { items } => {
+                let n = self.compile_items(items);
+                self.emit_opcode(OpCode::MakeList);
+                self.emit(n);
+                self.pop_slots(n as u16);
+                self.push_slot();
+            }
+            ExprKind::Map { items } => {
+                if items.len() > u8::MAX as usize {
+                    self.error("too many items in a map literal".to_owned());
+                    return self.emit_opcode(OpCode::Nil);
+                }
+                let n = items.len() as u8;
+                for (key, value) in items {
+                    self.compile_expr(key);
+                    self.compile_expr(value);
+                }
+                self.emit_opcode(OpCode::MakeMap);
+                self.emit(n);
+                self.pop_slots(n as u16 * 2);
+                self.push_slot();
+            }
+            ExprKind::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } => {
+                self.compile_expr(*start);
+                self.compile_expr(*end);
+                let mut flags = inclusive as u8;
+                if let Some(step) = step {
+                    self.compile_expr(*step);
+                    self.pop_slots(1);
+                    flags |= 0b10;
+                }
+                self.emit_opcode(OpCode::MakeRange);
+                self.emit(flags);
+                self.pop_slots(1);
+            }
+            ExprKind::Variant {
+                enum_name,
+                variant,
+                args,
+            } => self.compile_variant(enum_name, variant, args),
+            ExprKind::StructLit { name, fields } => self.compile_struct_lit(name, fields),
+            ExprKind::Field { object, name } => {
+                self.compile_expr(*object);
+                self.emit_constant(Value::Str(name));
+                self.emit_opcode(OpCode::GetField);
+            }
+            ExprKind::Item { object, index } => {
+                self.compile_expr(*object);
+                self.emit_opcode(OpCode::GetItem);
+                self.emit(index);
+            }
+            ExprKind::Index { object, index } => {
+                self.compile_expr(*object);
+                self.compile_expr(*index);
+                self.emit_opcode(OpCode::GetIndex);
+            }
+            ExprKind::Assign { target, value } => self.compile_assign(*target, *value),
+            ExprKind::Match { subject, arms } => self.compile_match(*subject, arms),
+            ExprKind::Loop { label, body } => self.compile_loop(label, body),
+            ExprKind::Break { label, value } => self.compile_break(label, value.map(|v| *v)),
+            ExprKind::Continue { label } => self.compile_continue(label),
+            ExprKind::Return { value } => self.compile_return(value.map(|v| *v)),
+            ExprKind::Try {
+                body,
+                catch,
+                finally,
+            } => self.compile_try(body, catch, finally),
+            // an expr like `return`, the value is the slot of the expr
+            ExprKind::Throw { value } => {
+                self.compile_expr(*value);
+                self.emit_opcode(OpCode::Throw);
+            }
+            ExprKind::Propagate { value } => self.compile_propagate(*value),
+            ExprKind::Yield { value } => {
+                match value {
+                    Some(value) => self.compile_expr(*value),
+                    None => self.emit_opcode(OpCode::Nil),
+                }
+                // the value is replaced by the one given back
+                self.emit_opcode(OpCode::Yield);
+            }
+        }
+    }
+
+    // a call of a field is a method call, e.g. `request.header("x")`:
+    // { object }
+    // Constant     <- the name of the field
+    // { args }
+    // Invoke
+    // N            <- how many arguments
+    fn compile_call(&mut self, callee: Expr, args: Vec<Expr>) {
+        let n = match callee.node {
+            ExprKind::Field { object, name } => {
+                self.compile_expr(*object);
+                self.emit_constant(Value::Str(name));
+                let n = self.compile_items(args);
+                self.emit_opcode(OpCode::Invoke);
+                self.emit(n);
+                // the name too
+                n as u16 + 1
+            }
+            callee => {
+                self.compile_expr(Expr::new(callee));
+                let n = self.compile_items(args);
+                self.emit_opcode(OpCode::Call);
+                self.emit(n);
+                n as u16
+            }
+        };
+        self.pop_slots(n);
+    }
+
+    // the right side is only evaluated when needed, it will generate:
+    // `left && right`              `left || right`
+    // { left }                     { left }
+    // +--- JumpIfFalse             +--- JumpIfFalse
+    // |    { right }               |    True
+    // |    Jump -------+           |    Jump -------+
+    // +--> False       |           +--> { right }   |
+    //      { other } <-+                { other } <-+
+    fn compile_logical(&mut self, left: Expr, op: BinaryOp, right: Expr) {
+        let is_and = matches!(op, BinaryOp::And);
+        self.compile_expr(left);
+        let to_else = self.emit_jump(OpCode::JumpIfFalse);
+        let right = if is_and {
+            self.compile_expr(right);
+            None
+        } else {
+            self.emit_opcode(OpCode::True);
+            Some(right)
+        };
+        let to_end = self.emit_jump(OpCode::Jump);
+
+        // only one of the branches runs
+        self.pop_slots(1);
+        self.patch_jump(to_else);
+        match right {
+            Some(right) => self.compile_expr(right),
+            None => self.emit_opcode(OpCode::False),
         }
+        self.patch_jump(to_end);
+    }
+
+    // assignment is an expr with the value nil
+    fn compile_assign(&mut self, target: Expr, value: Expr) {
+        match target.node {
+            ExprKind::Literal {
+                value: ParseObj::Ident(name),
+            } => {
+                self.compile_expr(value);
+                if let Some(slot) = self.resolve_local(&name) {
+                    if slot > u8::MAX as u16 {
+                        self.emit_opcode(OpCode::SetLocalL);
+                        self.emit_long_byte(slot);
+                    } else {
+                        self.emit_opcode(OpCode::SetLocal);
+                        self.emit(slot as u8);
+                    }
+                } else if let Some(i) = self.global.get(&name).copied() {
+                    if i > u8::MAX as u16 {
+                        self.emit_opcode(OpCode::SetGlobalL);
+                        self.emit_long_byte(i);
+                    } else {
+                        self.emit_opcode(OpCode::SetGlobal);
+                        self.emit(i as u8);
+                    }
+                } else {
+                    self.undefined_variable(&name);
+                    self.emit_opcode(OpCode::Pop);
+                }
+            }
+            ExprKind::Field { object, name } => {
+                self.compile_expr(*object);
+                self.emit_constant(Value::Str(name));
+                self.compile_expr(value);
+                self.emit_opcode(OpCode::SetField);
+            }
+            ExprKind::Index { object, index } => {
+                self.compile_expr(*object);
+                self.compile_expr(*index);
+                self.compile_expr(value);
+                self.emit_opcode(OpCode::SetIndex);
+            }
+            // checked by the parser
+            _ => unreachable!(),
+        }
+        self.emit_opcode(OpCode::Nil);
     }
 
     // it will generate:
@@ -132,37 +958,25 @@ impl Compiler {
     // +--- JumpIfFalse     <- will pop and check the value in the top of stack
     // |    { body }        <- a block expr
     // |    Jump -------+
-    // +--> { else }    |   <- a block expr
+    // +--> { else }    |   <- a block expr, `Nil` when there is no else
     //      { other } <-+
     // the 'if' should be treated as an expr, that means `let a = if false { 1 } else { 2 }` is fine.
     // when there isn't a left value, 'if' will be treated as a ExprStmt, it will drop the value.
     fn compile_if(&mut self, test: Expr, body: Vec<Stmt>, orelse: Vec<Stmt>) {
-        // TODO: this funcation is really a piece of shit.
         self.compile_expr(test);
-        self.emit_opcode(OpCode::JumpIfFalse);
-        self.emit_long_byte(0);
-        let now = self.chunk.get_code_len();
-        self.compile_block(body, true);
-        let end = self.chunk.get_code_len();
-        if end - now + 3 > u16::MAX as usize {
-            todo!()
-        }
-        if orelse.is_empty() {
-            // no else
-            self.emit_backfill_long(now - 2, (end - now) as u16);
-            return;
-        }
-        self.emit_backfill_long(now - 2, (end - now) as u16 + 3);
+        let to_else = self.emit_jump(OpCode::JumpIfFalse);
+        self.compile_block(body);
+        let to_end = self.emit_jump(OpCode::Jump);
 
-        self.emit_opcode(OpCode::Jump);
-        self.emit_long_byte(0);
-        let now = self.chunk.get_code_len();
-        self.compile_block(orelse, true);
-        let end = self.chunk.get_code_len();
-        if end - now + 3 > u16::MAX as usize {
-            todo!()
+        // only one of the branches runs
+        self.pop_slots(1);
+        self.patch_jump(to_else);
+        if orelse.is_empty() {
+            self.emit_opcode(OpCode::Nil);
+        } else {
+            self.compile_block(orelse);
         }
-        self.emit_backfill_long(now - 2, (end - now) as u16);
+        self.patch_jump(to_end);
     }
 
     // it will generate:
@@ -171,35 +985,28 @@ impl Compiler {
     //    Nil             when last Stmt in block isn't ExprStmt
     // 3. { body }
     //    { last expr }   when last Stmt is a ExprStmt, it will be treated as an expr
-    //    BlockEnd        <- always have this one, it will shift all the local variable in block and keep the return value.
+    //    BlockEnd        <- when there are local variables, it will shift all the local variable in block and keep the return value.
     //    N               <- determine how many local variable should be shift.
     // block is an expr, it always return a value.
     // when there isn't a left value, the block will be treated as a ExprStmt and drop the return value.
-    fn compile_block(&mut self, inner: Vec<Stmt>, expr: bool) {
+    fn compile_block(&mut self, inner: Vec<Stmt>) {
         self.begin_scope();
-        if inner.is_empty() {
-            self.emit_opcode(OpCode::Nil);
-            return;
-        }
         let mut inner = inner;
-        // the inner isn't empty
-        let end = inner.pop().unwrap();
-
-        for stmt in inner {
-            self.compile_stmt(stmt);
-        }
+        match inner.pop() {
+            None => self.emit_opcode(OpCode::Nil),
+            Some(end) => {
+                for stmt in inner {
+                    self.compile_stmt(stmt);
+                }
 
-        if expr {
-            if let StmtKind::ExprStmt { expr } = end.node {
-                self.compile_expr(*expr);
-            } else {
-                self.compile_stmt(end);
-                self.emit_opcode(OpCode::Nil);
+                if let StmtKind::ExprStmt { expr } = end.node {
+                    self.compile_expr(*expr);
+                } else {
+                    self.compile_stmt(end);
+                    self.emit_opcode(OpCode::Nil);
+                }
             }
-        } else {
-            self.compile_stmt(end);
         }
-
         self.end_scope();
     }
 
@@ -214,70 +1021,681 @@ impl Compiler {
         }
     }
 
-    fn compile_variable(&mut self, name: String) {
-        if self.scope_depth == 0 {
-            if let Some(i) = self.global.get(&name).copied() {
-                if i > u8::MAX as u16 {
-                    self.emit_opcode(OpCode::GetGlobalL);
-                    self.emit_long_byte(i);
-                } else {
-                    self.emit_opcode(OpCode::GetGlobal);
-                    self.emit(i as u8);
-                }
-            } else {
-                todo!()
-            }
+    fn compile_variable(&mut self, name: Str) {
+        if let Some(slot) = self.resolve_local(&name) {
+            self.emit_get_local(slot);
             return;
         }
 
-        if let Some(i) = self.scope[self.scope_depth - 1].get(&name).copied() {
+        if let Some(i) = self.global.get(&name).copied() {
             if i > u8::MAX as u16 {
-                self.emit_opcode(OpCode::GetLocalL);
+                self.emit_opcode(OpCode::GetGlobalL);
                 self.emit_long_byte(i);
             } else {
-                self.emit_opcode(OpCode::GetLocal);
+                self.emit_opcode(OpCode::GetGlobal);
                 self.emit(i as u8);
             }
-        } else {
-            todo!()
+            return;
+        }
+
+        self.undefined_variable(&name);
+        self.emit_opcode(OpCode::Nil);
+    }
+
+    fn undefined_variable(&mut self, name: &str) {
+        let outer = self.enclosing.iter().flat_map(|state| state.scope.iter());
+        if outer.flatten().any(|(local, _)| local == name) {
+            return self.error(format!(
+                "can't use the local variable `{}` of the outer function",
+                name
+            ));
+        }
+        self.error(format!("undefined variable `{}`", name));
+    }
+
+    // compile exprs and return how many of them
+    fn compile_items(&mut self, items: Vec<Expr>) -> u8 {
+        if items.len() > u8::MAX as usize {
+            self.error("too many items, the limit is 255".to_owned());
+            return 0;
+        }
+        let n = items.len() as u8;
+        for item in items {
+            self.compile_expr(item);
+        }
+        n
+    }
+
+    // a variant without fields is just a constant,
+    // otherwise it will generate:
+    // Constant  <- the variant without fields
+    // { args }
+    // Construct
+    // N         <- how many fields
+    fn compile_variant(&mut self, enum_name: Str, variant: Str, args: Vec<Expr>) {
+        let template = match self.variant_template(&enum_name, &variant, args.len()) {
+            Some(template) => template,
+            None => return self.emit_opcode(OpCode::Nil),
+        };
+        self.emit_constant(template);
+        if args.is_empty() {
+            return;
+        }
+        let n = self.compile_items(args);
+        self.emit_opcode(OpCode::Construct);
+        self.emit(n);
+        self.pop_slots(n as u16);
+    }
+
+    // the variant without fields, `None` when it can't be made with `n` fields
+    fn variant_template(&mut self, enum_name: &Str, variant: &Str, n: usize) -> Option<Value> {
+        let def = match self.enums.get(enum_name) {
+            Some(def) => def.clone(),
+            None => {
+                self.error(format!("undefined enum `{}`", enum_name));
+                return None;
+            }
+        };
+        let (tag, arity) = match def.variant(variant) {
+            Some((tag, v)) => (tag, v.arity),
+            None => {
+                self.error(format!("`{}` has no variant `{}`", enum_name, variant));
+                return None;
+            }
+        };
+        if n != arity as usize {
+            let given = if n == 1 { "was" } else { "were" };
+            self.error(format!(
+                "`{}::{}` takes {} but {} {} given",
+                enum_name,
+                variant,
+                fields(arity as usize),
+                n,
+                given
+            ));
+            return None;
         }
+        Some(enum_template(def, tag))
+    }
+
+    // fields are evaluated in the order of the struct definition
+    fn compile_struct_lit(&mut self, name: Str, fields: Vec<(Str, Expr)>) {
+        let (template, values) = match self.struct_values(&name, fields) {
+            Some(found) => found,
+            None => return self.emit_opcode(OpCode::Nil),
+        };
+        self.emit_constant(template);
+        let n = self.compile_items(values);
+        self.emit_opcode(OpCode::Construct);
+        self.emit(n);
+        self.pop_slots(n as u16);
+    }
+
+    // the struct without fields and the values of the fields in the order of its
+    // definition, `None` when a field is missing or unknown
+    fn struct_values(
+        &mut self,
+        name: &Str,
+        fields: Vec<(Str, Expr)>,
+    ) -> Option<(Value, Vec<Expr>)> {
+        let def = match self.structs.get(name) {
+            Some(def) => def.clone(),
+            None => {
+                self.error(format!("undefined struct `{}`", name));
+                return None;
+            }
+        };
+        let mut fields: HashMap<Str, Expr> = fields.into_iter().collect();
+        let mut values = Vec::with_capacity(def.fields.len());
+        for field in def.fields.iter() {
+            match fields.remove(field.as_str()) {
+                Some(value) => values.push(value),
+                None => {
+                    self.error(format!("missing field `{}` of `{}`", field, name));
+                    return None;
+                }
+            }
+        }
+        if let Some(field) = fields.keys().next() {
+            self.error(format!("struct `{}` has no field `{}`", name, field));
+            return None;
+        }
+        Some((struct_template(def), values))
+    }
+
+    // it will generate:
+    // { subject }              <- saved as a local without name
+    // SwitchTag                <- only when all the arms match variants of the same enum,
+    // { table }                   jump to the first arm which may match the variant.
+    // { test of arm 1 } ---+   <- each failed test jumps to the next arm
+    // { bindings }         |   <- locals of the arm
+    // { guard }            |
+    // JumpIfFalse ------+  |
+    // { body }          |  |
+    // BlockEnd          |  |   <- shift the bindings
+    // Jump ---------------------------+
+    // Pop * N        <--+  |          |   <- the guard failed, drop the bindings
+    // { test of arm 2 } <--+          |
+    // ...                             |
+    // NoMatch                         |   <- runtime error, nothing matched
+    // BlockEnd, 1           <---------+   <- shift the subject and keep the value of the arm
+    fn compile_match(&mut self, subject: Expr, arms: Vec<MatchArm>) {
+        self.check_match(&arms);
+
+        self.compile_expr(subject);
+        self.begin_scope();
+        // the subject can't be named by the program
+        self.add_local(Str::intern(""));
+        let slot = self.stack_top - 1;
+        let base = self.stack_top;
+
+        let mut table = self.emit_switch_tag(&arms, slot);
+
+        let mut end_jumps = Vec::new();
+        for (i, arm) in arms.into_iter().enumerate() {
+            if let Some(table) = &mut table {
+                table.land(self, Some(i));
+            }
+
+            let mut fails = Vec::new();
+            self.compile_pattern_test(&arm.pattern, slot, &mut Vec::new(), &mut fails);
+
+            self.begin_scope();
+            self.compile_pattern_bind(arm.pattern, slot, &mut Vec::new());
+            let guard = arm.guard.map(|guard| {
+                self.compile_expr(*guard);
+                self.emit_jump(OpCode::JumpIfFalse)
+            });
+            self.compile_expr(*arm.body);
+            let bindings = self.end_scope();
+            end_jumps.push(self.emit_jump(OpCode::Jump));
+
+            self.stack_top = base + bindings;
+            if let Some(guard) = guard {
+                self.patch_jump(guard);
+                for _ in 0..bindings {
+                    self.emit_opcode(OpCode::Pop);
+                }
+            }
+            self.stack_top = base;
+            for fail in fails {
+                self.patch_jump(fail);
+            }
+        }
+
+        if let Some(table) = &mut table {
+            table.land(self, None);
+        }
+        self.emit_opcode(OpCode::NoMatch);
+        // the same as any arm, leave a value
+        self.push_slot();
+
+        for jump in end_jumps {
+            self.patch_jump(jump);
+        }
+        self.end_scope();
+    }
+
+    fn check_match(&mut self, arms: &[MatchArm]) {
+        let checker = Checker::new(&self.enums, &self.structs);
+        for message in checker.check(arms) {
+            self.warnings.push(Warning::new(message));
+        }
+    }
+
+    // emit the SwitchTag when the arms are variants of one enum, wildcards or bindings.
+    fn emit_switch_tag(&mut self, arms: &[MatchArm], slot: u16) -> Option<SwitchTable> {
+        // None for the arms matching any value
+        let mut tags = Vec::with_capacity(arms.len());
+        let mut def: Option<Rc<EnumDef>> = None;
+        for arm in arms {
+            let mut pattern = &arm.pattern;
+            while let Pattern::Bind { sub: Some(sub), .. } = pattern {
+                pattern = sub;
+            }
+            match pattern {
+                Pattern::Wildcard | Pattern::Bind { .. } => tags.push(None),
+                Pattern::Variant {
+                    enum_name, variant, ..
+                } => {
+                    let this = self.enums.get(enum_name)?;
+                    if def.as_ref().is_some_and(|def| !Rc::ptr_eq(def, this)) {
+                        return None;
+                    }
+                    let (tag, _) = this.variant(variant)?;
+                    tags.push(Some(tag));
+                    def = Some(this.clone());
+                }
+                _ => return None,
+            }
+        }
+        let def = def?;
+        if def.variants.len() > u8::MAX as usize {
+            return None;
+        }
+
+        let n = def.variants.len();
+        // where each entry goes, the last entry is for values not from the enum
+        let targets = (0..=n)
+            .map(|tag| {
+                tags.iter().position(|t| match t {
+                    Some(t) => *t as usize == tag,
+                    None => true,
+                })
+            })
+            .collect();
+
+        self.emit_get_local(slot);
+        self.emit_constant(enum_template(def, 0));
+        self.emit_opcode(OpCode::SwitchTag);
+        self.emit(n as u8);
+        let start = self.chunk.get_code_len();
+        for _ in 0..=n {
+            self.emit_wide_byte(0);
+        }
+        Some(SwitchTable {
+            start,
+            end: self.chunk.get_code_len(),
+            targets,
+        })
+    }
+
+    // emit the tests of a pattern, the failed tests will jump to the `fails`.
+    // the stack is the same before and after the tests.
+    fn compile_pattern_test(
+        &mut self,
+        pattern: &Pattern,
+        slot: u16,
+        path: &mut Vec<Step>,
+        fails: &mut Vec<usize>,
+    ) {
+        match pattern {
+            Pattern::Wildcard | Pattern::Bind { sub: None, .. } => {}
+            Pattern::Bind { sub: Some(sub), .. } => {
+                self.compile_pattern_test(sub, slot, path, fails);
+            }
+            Pattern::Literal(value) => {
+                self.emit_path(slot, path);
+                self.compile_literal(value.clone());
+                self.emit_opcode(OpCode::Eq);
+                fails.push(self.emit_jump(OpCode::JumpIfFalse));
+            }
+            Pattern::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                // start <= value
+                self.emit_path(slot, path);
+                self.compile_literal(start.clone());
+                self.emit_binary_op(BinaryOp::GtE);
+                fails.push(self.emit_jump(OpCode::JumpIfFalse));
+                // value < end, or value <= end
+                self.emit_path(slot, path);
+                self.compile_literal(end.clone());
+                if *inclusive {
+                    self.emit_binary_op(BinaryOp::LtE);
+                } else {
+                    self.emit_binary_op(BinaryOp::Lt);
+                }
+                fails.push(self.emit_jump(OpCode::JumpIfFalse));
+            }
+            Pattern::Tuple(items) => {
+                let n = self.pattern_len(items.len());
+                self.emit_path(slot, path);
+                self.emit_opcode(OpCode::MatchTuple);
+                self.emit(n);
+                fails.push(self.emit_jump(OpCode::JumpIfFalse));
+                for (i, item) in items.iter().enumerate() {
+                    path.push(Step::Item(i as u8));
+                    self.compile_pattern_test(item, slot, path, fails);
+                    path.pop();
+                }
+            }
+            Pattern::List { prefix, suffix } => {
+                self.emit_path(slot, path);
+                match suffix {
+                    None => {
+                        self.emit_opcode(OpCode::MatchList);
+                        let n = self.pattern_len(prefix.len());
+                        self.emit(n);
+                    }
+                    Some(suffix) => {
+                        self.emit_opcode(OpCode::MatchListMin);
+                        let n = self.pattern_len(prefix.len() + suffix.len());
+                        self.emit(n);
+                    }
+                }
+                fails.push(self.emit_jump(OpCode::JumpIfFalse));
+                for (i, item) in prefix.iter().enumerate() {
+                    path.push(Step::Item(i as u8));
+                    self.compile_pattern_test(item, slot, path, fails);
+                    path.pop();
+                }
+                for (i, item) in suffix.iter().flatten().rev().enumerate() {
+                    path.push(Step::ItemBack(i as u8));
+                    self.compile_pattern_test(item, slot, path, fails);
+                    path.pop();
+                }
+            }
+            Pattern::Struct { name, fields } => {
+                let def = match self.structs.get(name) {
+                    Some(def) => def.clone(),
+                    None => return self.error(format!("undefined struct `{}`", name)),
+                };
+                self.emit_path(slot, path);
+                self.emit_constant(struct_template(def.clone()));
+                self.emit_opcode(OpCode::MatchKind);
+                fails.push(self.emit_jump(OpCode::JumpIfFalse));
+                for (field, item) in fields {
+                    let i = match def.field(field) {
+                        Some(i) => i,
+                        None => {
+                            self.error(format!("struct `{}` has no field `{}`", name, field));
+                            continue;
+                        }
+                    };
+                    path.push(Step::Item(i as u8));
+                    self.compile_pattern_test(item, slot, path, fails);
+                    path.pop();
+                }
+            }
+            Pattern::Variant {
+                enum_name,
+                variant,
+                fields,
+            } => {
+                let def = match self.enums.get(enum_name) {
+                    Some(def) => def.clone(),
+                    None => return self.error(format!("undefined enum `{}`", enum_name)),
+                };
+                let tag = match def.variant(variant) {
+                    Some((tag, v)) if v.arity as usize == fields.len() => tag,
+                    Some((_, v)) => {
+                        return self.error(format!(
+                            "`{}::{}` has {} but the pattern has {}",
+                            enum_name,
+                            variant,
+                            self::fields(v.arity as usize),
+                            fields.len()
+                        ))
+                    }
+                    None => {
+                        return self.error(format!("`{}` has no variant `{}`", enum_name, variant))
+                    }
+                };
+                self.emit_path(slot, path);
+                self.emit_constant(enum_template(def, tag));
+                self.emit_opcode(OpCode::MatchKind);
+                fails.push(self.emit_jump(OpCode::JumpIfFalse));
+                for (i, item) in fields.iter().enumerate() {
+                    path.push(Step::Item(i as u8));
+                    self.compile_pattern_test(item, slot, path, fails);
+                    path.pop();
+                }
+            }
+        }
+    }
+
+    // push the bound values as locals, the tests are already passed
+    fn compile_pattern_bind(&mut self, pattern: Pattern, slot: u16, path: &mut Vec<Step>) {
+        match pattern {
+            Pattern::Wildcard | Pattern::Literal(_) | Pattern::Range { .. } => {}
+            Pattern::Bind { name, sub } => {
+                self.emit_path(slot, path);
+                self.add_local(name);
+                if let Some(sub) = sub {
+                    self.compile_pattern_bind(*sub, slot, path);
+                }
+            }
+            Pattern::Tuple(items) | Pattern::Variant { fields: items, .. } => {
+                for (i, item) in items.into_iter().enumerate() {
+                    path.push(Step::Item(i as u8));
+                    self.compile_pattern_bind(item, slot, path);
+                    path.pop();
+                }
+            }
+            Pattern::List { prefix, suffix } => {
+                for (i, item) in prefix.into_iter().enumerate() {
+                    path.push(Step::Item(i as u8));
+                    self.compile_pattern_bind(item, slot, path);
+                    path.pop();
+                }
+                for (i, item) in suffix.into_iter().flatten().rev().enumerate() {
+                    path.push(Step::ItemBack(i as u8));
+                    self.compile_pattern_bind(item, slot, path);
+                    path.pop();
+                }
+            }
+            Pattern::Struct { name, fields } => {
+                // the errors are found in the tests
+                let def = match self.structs.get(&name) {
+                    Some(def) => def.clone(),
+                    None => return,
+                };
+                for (field, item) in fields {
+                    let i = match def.field(&field) {
+                        Some(i) => i,
+                        None => continue,
+                    };
+                    path.push(Step::Item(i as u8));
+                    self.compile_pattern_bind(item, slot, path);
+                    path.pop();
+                }
+            }
+        }
+    }
+
+    // push the part of the subject at `path`
+    fn emit_path(&mut self, slot: u16, path: &[Step]) {
+        self.emit_get_local(slot);
+        for step in path {
+            match step {
+                Step::Item(i) => {
+                    self.emit_opcode(OpCode::GetItem);
+                    self.emit(*i);
+                }
+                Step::ItemBack(i) => {
+                    self.emit_opcode(OpCode::GetItemBack);
+                    self.emit(*i);
+                }
+            }
+        }
+    }
+
+    fn pattern_len(&mut self, len: usize) -> u8 {
+        if len > u8::MAX as usize {
+            self.error("too many items in a pattern, the limit is 255".to_owned());
+        }
+        len as u8
+    }
+
+    fn error(&mut self, message: String) {
+        self.errors.push(Error::new(message));
     }
 
     // scope
     fn begin_scope(&mut self) {
         self.scope_depth += 1;
-        self.scope.push(HashMap::new());
+        self.scope.push(Vec::new());
     }
 
-    fn end_scope(&mut self) {
+    // return how many locals are shifted
+    fn end_scope(&mut self) -> u16 {
         self.scope_depth -= 1;
 
-        // TODO: check the size of 'count'
-        let count = self.scope.last().unwrap().len() as u8;
+        let count = self.scope.pop().unwrap().len() as u16;
         if count == 0 {
-            return;
+            return 0;
+        }
+        self.emit_block_end(count);
+        self.pop_slots(count);
+        count
+    }
+
+    // end the scope and drop the locals, no value is kept
+    fn drop_scope(&mut self) {
+        self.scope_depth -= 1;
+
+        let count = self.scope.pop().unwrap().len();
+        for _ in 0..count {
+            self.emit_opcode(OpCode::Pop);
+        }
+    }
+
+    // function
+    fn begin_function(&mut self) {
+        let state = FnState {
+            chunk: std::mem::take(&mut self.chunk),
+            scope: std::mem::take(&mut self.scope),
+            scope_depth: std::mem::replace(&mut self.scope_depth, 0),
+            stack_top: std::mem::replace(&mut self.stack_top, 0),
+            loops: std::mem::take(&mut self.loops),
+            finallies: std::mem::take(&mut self.finallies),
+        };
+        self.enclosing.push(state);
+    }
+
+    // go back to the enclosing function and return the chunk of this one
+    fn end_function(&mut self) -> Chunk {
+        let state = self.enclosing.pop().unwrap();
+        self.scope = state.scope;
+        self.scope_depth = state.scope_depth;
+        self.stack_top = state.stack_top;
+        self.loops = state.loops;
+        self.finallies = state.finallies;
+        let chunk = std::mem::replace(&mut self.chunk, state.chunk);
+        self.optimized(chunk)
+    }
+
+    // loop
+    fn begin_loop(
+        &mut self,
+        label: Option<Str>,
+        start: usize,
+        continue_top: u16,
+        break_top: u16,
+        has_value: bool,
+    ) {
+        self.loops.push(Loop {
+            label,
+            start,
+            continue_top,
+            break_top,
+            breaks: Vec::new(),
+            has_value,
+            finallies: self.finallies.len(),
+        });
+    }
+
+    // let the `break`s land here
+    fn end_loop(&mut self) {
+        let lp = self.loops.pop().unwrap();
+        for jump in lp.breaks {
+            self.patch_jump(jump);
+        }
+    }
+
+    // the innermost loop, or the loop with the label
+    fn find_loop(&mut self, label: Option<&str>, keyword: &str) -> Option<usize> {
+        let found = match label {
+            None => self.loops.len().checked_sub(1),
+            Some(label) => self
+                .loops
+                .iter()
+                .rposition(|lp| lp.label.as_deref() == Some(label)),
+        };
+        if found.is_none() {
+            match label {
+                Some(label) => self.error(format!("undeclared label `'{}`", label)),
+                None => self.error(format!("`{}` outside of a loop", keyword)),
+            }
         }
-        self.scope.pop();
-        self.emit_opcode(OpCode::BlockEnd);
-        self.emit(count);
-        self.stack_top -= count as u16;
+        found
+    }
+
+    // the value of the local is already in the top of stack
+    fn add_local(&mut self, name: Str) {
+        let slot = self.stack_top - 1;
+        self.scope[self.scope_depth - 1].push((name, slot));
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<u16> {
+        self.scope
+            .iter()
+            .rev()
+            .flat_map(|locals| locals.iter().rev())
+            .find(|(local, _)| local == name)
+            .map(|(_, slot)| *slot)
     }
 
-    fn add_local(&mut self, name: String) {
-        self.scope[self.scope_depth - 1].insert(name, self.stack_top);
+    // track the values in the stack
+    fn push_slot(&mut self) {
         if self.stack_top == u16::MAX {
-            todo!()
+            return self.error("too many values in the stack".to_owned());
         }
         self.stack_top += 1;
     }
 
+    // saturated, the stack is not tracked well after an error
+    fn pop_slots(&mut self, n: u16) {
+        self.stack_top = self.stack_top.saturating_sub(n);
+    }
+
     // emit family
     fn emit(&mut self, code: u8) {
         self.chunk.write_code(code);
     }
 
+    // emit an opcode and track how it changes the stack,
+    // the opcodes with an operand deciding that are tracked by the caller.
     fn emit_opcode(&mut self, code: OpCode) {
+        use OpCode::*;
+
         self.chunk.write_code(code as u8);
+        match code {
+            True | False | Nil | Constant | ConstantL | ConstantW | GetGlobal | GetGlobalL
+            | GetLocal | GetLocalL => self.push_slot(),
+            Add | Sub | Mult | Div | Eq | Gt | Lt | Pop | SetGlobal | SetGlobalL | SetLocal
+            | SetLocalL | JumpIfFalse | GetField | MatchKind | GetIndex => self.pop_slots(1),
+            SwitchTag => self.pop_slots(2),
+            SetField | SetIndex => self.pop_slots(3),
+            _ => {}
+        }
+    }
+
+    // shift `n` values under the top of stack, the stack is tracked by the caller
+    fn emit_block_end(&mut self, n: u16) {
+        if n == 0 {
+            return;
+        }
+        if n > u8::MAX as u16 {
+            self.emit_opcode(OpCode::BlockEndL);
+            self.emit_long_byte(n);
+        } else {
+            self.emit_opcode(OpCode::BlockEnd);
+            self.emit(n as u8);
+        }
+    }
+
+    fn emit_set_global(&mut self, i: u16) {
+        if i > u8::MAX as u16 {
+            self.emit_opcode(OpCode::SetGlobalL);
+            self.emit_long_byte(i);
+        } else {
+            self.emit_opcode(OpCode::SetGlobal);
+            self.emit(i as u8);
+        }
+    }
+
+    fn emit_get_local(&mut self, slot: u16) {
+        if slot > u8::MAX as u16 {
+            self.emit_opcode(OpCode::GetLocalL);
+            self.emit_long_byte(slot);
+        } else {
+            self.emit_opcode(OpCode::GetLocal);
+            self.emit(slot as u8);
+        }
     }
 
     fn emit_binary_op(&mut self, op: BinaryOp) {
@@ -288,33 +1706,44 @@ impl Compiler {
             BinaryOp::Div => self.emit_opcode(OpCode::Div),
             BinaryOp::Eq => self.emit_opcode(OpCode::Eq),
             BinaryOp::NotEq => {
-                self.emit(OpCode::Eq as u8);
-                self.emit(OpCode::Not as u8);
+                self.emit_opcode(OpCode::Eq);
+                self.emit_opcode(OpCode::Not);
             }
             BinaryOp::Gt => self.emit_opcode(OpCode::Gt),
-            BinaryOp::GtE => todo!(),
+            BinaryOp::GtE => {
+                self.emit_opcode(OpCode::Lt);
+                self.emit_opcode(OpCode::Not);
+            }
             BinaryOp::Lt => self.emit_opcode(OpCode::Lt),
-            BinaryOp::LtE => todo!(),
-            BinaryOp::And => todo!(),
-            BinaryOp::Or => todo!(),
+            BinaryOp::LtE => {
+                self.emit_opcode(OpCode::Gt);
+                self.emit_opcode(OpCode::Not);
+            }
+            // `&&` and `||` are compiled by `compile_logical`
+            BinaryOp::And | BinaryOp::Or => unreachable!(),
         }
     }
 
     fn emit_unary_op(&mut self, op: UnaryOp) {
         match op {
             UnaryOp::Not => self.emit_opcode(OpCode::Not),
-            UnaryOp::Neg => todo!(),
+            UnaryOp::Neg => self.emit_opcode(OpCode::Neg),
         }
     }
 
     fn emit_constant(&mut self, value: Value) {
         let index = self.chunk.write_constant(value);
-        self.emit_opcode(OpCode::Constant);
-        if index > u16::MAX as usize {
-            todo!()
+        if index > u32::MAX as usize {
+            self.error("too many constants".to_owned());
+            self.push_slot();
+        } else if index > u16::MAX as usize {
+            self.emit_opcode(OpCode::ConstantW);
+            self.emit_wide_byte(index as u32);
         } else if index > u8::MAX as usize {
+            self.emit_opcode(OpCode::ConstantL);
             self.emit_long_byte(index as u16);
         } else {
+            self.emit_opcode(OpCode::Constant);
             self.emit(index as u8);
         }
     }
@@ -325,9 +1754,837 @@ impl Compiler {
         self.emit(bytes[1]);
     }
 
-    fn emit_backfill_long(&mut self, ip: usize, b: u16) {
-        let bytes = b.to_be_bytes();
-        self.chunk.backfill(ip, bytes[0]);
-        self.chunk.backfill(ip + 1, bytes[1]);
+    fn emit_wide_byte(&mut self, b: u32) {
+        for byte in b.to_be_bytes() {
+            self.emit(byte);
+        }
+    }
+
+    fn emit_backfill_wide(&mut self, ip: usize, b: u32) {
+        for (i, byte) in b.to_be_bytes().into_iter().enumerate() {
+            self.chunk.backfill(ip + i, byte);
+        }
+    }
+
+    // emit a forward jump and return where its offset is, the offset is set by `patch_jump`
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_opcode(op);
+        self.emit_wide_byte(0);
+        self.chunk.get_code_len() - 4
+    }
+
+    // let the jump land here
+    fn patch_jump(&mut self, at: usize) {
+        let offset = self.chunk.get_code_len() - at - 4;
+        if offset > u32::MAX as usize {
+            return self.error("the code is too long to jump over".to_owned());
+        }
+        self.emit_backfill_wide(at, offset as u32);
+    }
+
+    // the vm jumps back from the offset of JumpBack
+    fn emit_jump_back(&mut self, start: usize) {
+        // counted from the end of the opcode
+        let offset = self.chunk.get_code_len() + 1 - start;
+        if offset > u32::MAX as usize {
+            self.error("the code is too long to jump over".to_owned());
+        } else if offset > u16::MAX as usize {
+            self.emit_opcode(OpCode::JumpBackW);
+            self.emit_wide_byte(offset as u32);
+        } else {
+            self.emit_opcode(OpCode::JumpBack);
+            self.emit_long_byte(offset as u16);
+        }
+    }
+}
+
+// the entries of a SwitchTag waiting for the arms
+struct SwitchTable {
+    start: usize,
+    end: usize,
+    // the arm of each entry, none for NoMatch
+    targets: Vec<Option<usize>>,
+}
+
+impl SwitchTable {
+    // the arm `arm` starts here
+    fn land(&mut self, compiler: &mut Compiler, arm: Option<usize>) {
+        let offset = compiler.chunk.get_code_len() - self.end;
+        if offset > u32::MAX as usize {
+            return compiler.error("the code is too long to jump over".to_owned());
+        }
+        for (i, target) in self.targets.iter().enumerate() {
+            if *target == arm {
+                compiler.emit_backfill_wide(self.start + i * 4, offset as u32);
+            }
+        }
+    }
+}
+
+// e.g. `1 field`, `2 fields`
+fn fields(n: usize) -> String {
+    format!("{} field{}", n, if n == 1 { "" } else { "s" })
+}
+
+fn enum_template(def: Rc<EnumDef>, tag: u16) -> Value {
+    Value::Enum(Rc::new(EnumObj {
+        def,
+        tag,
+        fields: Vec::new(),
+    }))
+}
+
+fn struct_template(def: Rc<StructDef>) -> Value {
+    Value::Struct(Rc::new(StructObj {
+        def,
+        fields: RefCell::new(Vec::new()),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use vm::{
+        error::RuntimeError, gc::GcConfig, limits::Limits, native::Arity, value::Value, vm::Vm,
+    };
+
+    use crate::{lexer::Cursor, parser::Parser};
+
+    use super::Compiler;
+
+    fn compile(code: &str) -> Compiler {
+        let mut parser = Parser::new(Cursor::new(code));
+        let mut compiler = Compiler::new();
+        let ast = parser.parse().expect("fail to parse");
+        compiler.compile(ast, false).expect("fail to compile");
+        compiler
+    }
+
+    // run the code and get the global variable `name`
+    fn run_get(code: &str, name: &str) -> Value {
+        let mut compiler = compile(code);
+        let mut vm = Vm::new();
+        vm.interpret(compiler.pop_chunk()).expect("fail to run");
+        let i = compiler.global[name];
+        vm.get_global(i).cloned().unwrap()
+    }
+
+    fn warnings(code: &str) -> Vec<String> {
+        let mut compiler = compile(code);
+        let warnings = compiler.take_warnings();
+        warnings.iter().map(|w| w.message().to_owned()).collect()
+    }
+
+    // the output written by `print`, shared with the test
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl std::io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // run the code with the native functions, and get what it prints
+    fn run_output(code: &str) -> (Result<(), RuntimeError>, String) {
+        let mut compiler = Compiler::new();
+        let mut vm = Vm::new();
+        let output = Output::default();
+        vm.set_output(output.clone());
+        let natives: Vec<_> = vm
+            .natives()
+            .map(|(n, v)| (n.to_owned(), v.clone()))
+            .collect();
+        for (name, value) in natives {
+            let i = compiler.declare_global(name.into()).unwrap();
+            vm.define_global(i, value);
+        }
+        let ast = Parser::new(Cursor::new(code))
+            .parse()
+            .expect("fail to parse");
+        compiler.compile(ast, false).expect("fail to compile");
+        let result = vm.interpret(compiler.pop_chunk()).map(|_| ());
+        let output = String::from_utf8(output.0.take()).unwrap();
+        (result, output)
+    }
+
+    fn errors(code: &str) -> Vec<String> {
+        let mut parser = Parser::new(Cursor::new(code));
+        let mut compiler = Compiler::new();
+        let ast = parser.parse().expect("fail to parse");
+        let errors = compiler.compile(ast, false).unwrap_err();
+        errors.iter().map(|e| e.message().to_owned()).collect()
+    }
+
+    const SHAPE: &str = "enum Shape { Circle(r), Rect(w, h), Empty }\n";
+
+    #[test]
+    fn test_locals_under_temporaries() {
+        let code = "let a = 1 + { let b = 2 let c = b * 3 c + b }";
+        assert_eq!(run_get(code, "a"), Value::Int(9));
+    }
+
+    #[test]
+    fn test_if_without_else() {
+        let code = "let a = if 1 > 2 { 1 }";
+        assert_eq!(run_get(code, "a"), Value::Nil);
+    }
+
+    #[test]
+    fn test_match_enum() {
+        let code = "
+            let area = match Shape::Rect(2, 3) {
+                Shape::Circle(r) => r * r * 3,
+                Shape::Rect(w, h) => w * h,
+                Shape::Empty => 0,
+            }
+        ";
+        assert_eq!(run_get(&(SHAPE.to_owned() + code), "area"), Value::Int(6));
+    }
+
+    #[test]
+    fn test_match_guard() {
+        let code = "
+            let s = Shape::Circle(1)
+            let kind = match s {
+                Shape::Circle(r) if r > 1 => \"big\",
+                Shape::Circle(_) => \"small\",
+                _ => \"other\",
+            }
+        ";
+        let kind = run_get(&(SHAPE.to_owned() + code), "kind");
+        assert_eq!(kind, Value::Str("small".into()));
+    }
+
+    #[test]
+    fn test_match_range_and_binding() {
+        let code = "
+            let a = match 4 { x @ 1..=5 => x * 10, _ => 0 }
+            let b = match 5 { 1..5 => \"in\", _ => \"out\" }
+            let c = match -1 { -1 => true, _ => false }
+        ";
+        assert_eq!(run_get(code, "a"), Value::Int(40));
+        assert_eq!(run_get(code, "b"), Value::Str("out".into()));
+        assert_eq!(run_get(code, "c"), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_match_tuple_and_list() {
+        let code = "
+            let a = match (1, (2, 3)) { (x, (2, y)) => x + y, _ => 0 }
+            let b = match [1, 2, 3, 4] { [first, .., last] => first * last }
+            let c = match [1, 2] { [] => 0, [_] => 1, [_, _, ..] => 2 }
+        ";
+        assert_eq!(run_get(code, "a"), Value::Int(4));
+        assert_eq!(run_get(code, "b"), Value::Int(4));
+        assert_eq!(run_get(code, "c"), Value::Int(2));
+    }
+
+    #[test]
+    fn test_match_struct() {
+        let code = "
+            struct Point { x, y }
+            let p = Point { y: 2, x: 1 }
+            let a = match p { Point { x: 0, .. } => 0, Point { x, y } => x + y }
+            let b = p.y
+        ";
+        assert_eq!(run_get(code, "a"), Value::Int(3));
+        assert_eq!(run_get(code, "b"), Value::Int(2));
+    }
+
+    #[test]
+    fn test_match_no_arm() {
+        let mut compiler = compile("match 1 { 2 => 2 }");
+        let mut vm = Vm::new();
+        let result = vm.interpret(compiler.pop_chunk());
+        assert_eq!(result, Err(RuntimeError::NoMatchArm));
+    }
+
+    #[test]
+    fn test_exhaustive() {
+        let code = "
+            match Shape::Empty {
+                Shape::Circle(_) => 1,
+                Shape::Rect(_, _) => 2,
+                Shape::Empty => 3,
+            }
+        ";
+        assert!(warnings(&(SHAPE.to_owned() + code)).is_empty());
+    }
+
+    #[test]
+    fn test_non_exhaustive() {
+        let code = "
+            match Shape::Empty {
+                Shape::Circle(_) => 1,
+                Shape::Rect(w, h) if w == h => 2,
+            }
+            match (true, Shape::Empty) {
+                (true, _) => 1,
+                (false, Shape::Empty) => 2,
+                (_, Shape::Circle(_)) => 3,
+            }
+        ";
+        assert_eq!(
+            warnings(&(SHAPE.to_owned() + code)),
+            vec![
+                "non-exhaustive match: pattern `Shape::Rect(_, _)` not covered",
+                "non-exhaustive match: pattern `(false, Shape::Rect(_, _))` not covered",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unreachable_arm() {
+        let code = "match 1 { _ => 1, 2 => 2 }";
+        assert_eq!(warnings(code), vec!["unreachable pattern in match arm 2"]);
+    }
+
+    #[test]
+    fn test_assign() {
+        let code = "
+            let a = 1
+            let b = { let c = 2 c = c + a c }
+            a = a + 10
+            let list = [1, 2]
+            list[1] = 3
+            struct Point { x, y }
+            let p = Point { x: 1, y: 2 }
+            p.x = list[1]
+        ";
+        assert_eq!(run_get(code, "a"), Value::Int(11));
+        assert_eq!(run_get(code, "b"), Value::Int(3));
+        assert_eq!(run_get(code, "list").to_string(), "[1, 3]");
+        assert_eq!(run_get(code, "p").to_string(), "Point { x: 3, y: 2 }");
+    }
+
+    #[test]
+    fn test_for_range() {
+        let code = "
+            let a = 0
+            for i in 0..10 { a = a + i }
+            let b = 0
+            for i in 0..=10 { b = b + i }
+            let c = 0
+            for i in 10..0 step -3 { c = c * 100 + i }
+        ";
+        assert_eq!(run_get(code, "a"), Value::Int(45));
+        assert_eq!(run_get(code, "b"), Value::Int(55));
+        assert_eq!(run_get(code, "c"), Value::Int(10070401));
+    }
+
+    #[test]
+    fn test_for_range_value() {
+        let code = "
+            let r = 0..6 step 2
+            let a = 0
+            for i in r { for j in r { a = a + i * j } }
+        ";
+        assert_eq!(run_get(code, "r").to_string(), "0..6 step 2");
+        assert_eq!(run_get(code, "a"), Value::Int(36));
+    }
+
+    #[test]
+    fn test_for_iterables() {
+        let code = "
+            let s = \"\"
+            for c in \"abc\" { s = c + s }
+            let sum = 0
+            for x in [1, 2, 3] { let double = x * 2 sum = sum + double }
+            for x in (4, 5) { sum = sum + x }
+            let m = #{ \"a\": 1, \"b\": 2 }
+            m[\"c\"] = 3
+            let keys = \"\"
+            for (k, v) in m { keys = keys + k sum = sum + v }
+        ";
+        assert_eq!(run_get(code, "s"), Value::Str("cba".into()));
+        assert_eq!(run_get(code, "keys"), Value::Str("abc".into()));
+        assert_eq!(run_get(code, "sum"), Value::Int(27));
+    }
+
+    #[test]
+    fn test_for_not_iterable() {
+        let mut compiler = compile("for x in 1 { x }");
+        let mut vm = Vm::new();
+        let result = vm.interpret(compiler.pop_chunk());
+        assert!(matches!(result, Err(RuntimeError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_break_continue() {
+        let code = "
+            let a = 0
+            let i = 0
+            while true {
+                i = i + 1
+                if i > 10 { break }
+                if i == 5 { continue }
+                a = a + i
+            }
+            let b = 0
+            for x in 0..100 {
+                let double = x * 2
+                if x == 3 { continue }
+                if x == 6 { break }
+                b = b + double
+            }
+        ";
+        assert_eq!(run_get(code, "a"), Value::Int(50));
+        assert_eq!(run_get(code, "b"), Value::Int(24));
+    }
+
+    #[test]
+    fn test_loop_value() {
+        let code = "
+            let n = 0
+            let a = 1 + loop {
+                n = n + 1
+                let m = n * n
+                if m > 50 { break m }
+            }
+            let b = loop { break }
+            let c = { let x = 1; (x, loop { let y = 2 break x + y }) }
+        ";
+        assert_eq!(run_get(code, "a"), Value::Int(65));
+        assert_eq!(run_get(code, "b"), Value::Nil);
+        assert_eq!(run_get(code, "c").to_string(), "(1, 3)");
+    }
+
+    #[test]
+    fn test_labeled_loops() {
+        let code = "
+            let pairs = 0
+            'outer: for i in 0..10 {
+                let x = i
+                for j in 0..10 {
+                    let y = j
+                    if y > x { continue 'outer }
+                    if x == 5 { break 'outer }
+                    pairs = pairs + 1
+                }
+            }
+            let found = 'search: loop {
+                for i in 1..10 {
+                    match i * i {
+                        n if n > 20 => break 'search (i, n),
+                        _ => continue,
+                    }
+                }
+            }
+        ";
+        assert_eq!(run_get(code, "pairs"), Value::Int(15));
+        assert_eq!(run_get(code, "found").to_string(), "(5, 25)");
+    }
+
+    // `n` statements adding the numbers from 0 to n-1 to `name`, each number is a new constant
+    fn long_body(name: &str, n: usize) -> String {
+        (0..n)
+            .map(|i| format!("{} = {} + {}\n", name, name, i))
+            .collect()
+    }
+
+    #[test]
+    fn test_long_jumps() {
+        let n = 20000;
+        let sum = (0..n as i64).sum::<i64>();
+        let code = format!(
+            "
+            let a = 0
+            if a == 0 {{ {} }} else {{ a = -1 }}
+            let i = 0
+            let b = 0
+            while i < 2 {{ {} i = i + 1 }}
+            let c = 0
+            for _ in 0..2 {{ {} }}
+            enum E {{ X, Y }}
+            let d = 0
+            for e in [E::X, E::Y] {{
+                match e {{
+                    E::X => {{ {} }}
+                    E::Y => {{ d = d + 1 }}
+                }}
+            }}
+            ",
+            long_body("a", n),
+            long_body("b", n),
+            long_body("c", n),
+            long_body("d", n),
+        );
+        let chunk = compile(&code).pop_chunk();
+        assert!(chunk.get_code_len() > 4 * u16::MAX as usize);
+
+        assert_eq!(run_get(&code, "a"), Value::Int(sum));
+        assert_eq!(run_get(&code, "b"), Value::Int(2 * sum));
+        assert_eq!(run_get(&code, "c"), Value::Int(2 * sum));
+        assert_eq!(run_get(&code, "d"), Value::Int(sum + 1));
+    }
+
+    #[test]
+    fn test_many_constants() {
+        let n = u16::MAX as usize + 1000;
+        let code = format!("let a = 0 {} let b = a", long_body("a", n));
+        let mut compiler = compile(&code);
+        let chunk = compiler.pop_chunk();
+        assert!(chunk.get_constant(n).is_some());
+
+        let mut vm = Vm::new();
+        vm.interpret(chunk).expect("fail to run");
+        let sum = (0..n as i64).sum();
+        assert_eq!(vm.get_global(compiler.global["b"]), Some(&Value::Int(sum)));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(errors("let a = b"), ["undefined variable `b`"]);
+        assert_eq!(errors("break"), ["`break` outside of a loop"]);
+        assert_eq!(errors("let a = c\nd = 1").len(), 2);
+        assert_eq!(errors("return 1"), ["`return` outside of a function"]);
+        assert_eq!(
+            errors("{ let a = 1; fn f() { a } }"),
+            ["can't use the local variable `a` of the outer function"]
+        );
+        assert_eq!(errors("fn f() { break }"), ["`break` outside of a loop"]);
+        assert_eq!(
+            errors("Option::Some()"),
+            ["`Option::Some` takes 1 field but 0 were given"]
+        );
+        assert_eq!(
+            errors("enum E { A(x, y) }\nE::A(1)"),
+            ["`E::A` takes 2 fields but 1 was given"]
+        );
+        assert_eq!(
+            errors("match 1 { Option::Some(a, b) => 1, _ => 2 }"),
+            ["`Option::Some` has 1 field but the pattern has 2"]
+        );
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut compiler = compile("let a = 1");
+        compiler.pop_chunk();
+        let ast = Parser::new(Cursor::new("let b = 2\nlet c = d"))
+            .parse()
+            .unwrap();
+        assert!(compiler.compile(ast, false).is_err());
+        assert!(!compiler.global.contains_key("b"));
+        assert!(compiler.global.contains_key("a"));
+
+        // a failure in a `try` leaves no `finally` to run on the next jumps
+        for code in [
+            "try { let c = d } finally { print(1) }",
+            "while true { try { break\nlet c = d } finally { print(1) } }",
+            "fn f() { try { return e } finally { print(1) } }",
+        ] {
+            let ast = Parser::new(Cursor::new(code)).parse().unwrap();
+            assert!(compiler.compile(ast, false).is_err());
+            assert!(compiler.finallies.is_empty(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_functions() {
+        let code = "
+            fn fib(n) { if n < 2 { return n } fib(n - 1) + fib(n - 2) }
+            let a = fib(15)
+            fn is_even(n) { if n == 0 { true } else { is_odd(n - 1) } }
+            fn is_odd(n) { if n == 0 { false } else { is_even(n - 1) } }
+            let b = (is_even(10), is_odd(10))
+            let c = {
+                let x = 2
+                fn square(x) { let y = x * x; y }
+                square(x) + square(3)
+            }
+            fn find(list, target) {
+                for (i, x) in list { if x == target { return i } }
+            }
+            let d = (find([(0, 5), (1, 7)], 7), find([], 1))
+        ";
+        assert_eq!(run_get(code, "a"), Value::Int(610));
+        assert_eq!(run_get(code, "b").to_string(), "(true, false)");
+        assert_eq!(run_get(code, "c"), Value::Int(13));
+        assert_eq!(run_get(code, "d").to_string(), "(1, nil)");
+    }
+
+    #[test]
+    fn test_builtins() {
+        let code = r#"
+            print("a", 1)
+            println("", [1, "b"], nil)
+            println(type_of(1.5), type_of(Option::Some(1)), len("abc"), len([1, 2]), len(#{1: 2}))
+            println(str(1) + "2", int("12") + 1, int(2.7), float(1) / 2.0)
+            assert(1 < 2)
+        "#;
+        let (result, output) = run_output(code);
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            output,
+            "a 1 [1, \"b\"] nil\nfloat Option 3 2 1\n12 13 2 0.5\n"
+        );
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let (result, output) = run_output("println(1)\nassert(false, \"oops\")\nprintln(2)");
+        assert_eq!(result, Err(RuntimeError::Panic("oops".to_owned())));
+        assert_eq!(output, "1\n");
+        let (result, _) = run_output("len(1, 2)");
+        let error = RuntimeError::WrongArity {
+            name: "len".to_owned(),
+            arity: Arity::Exact(1),
+            found: 2,
+        };
+        assert_eq!(result, Err(error));
+        let (result, _) = run_output("let f = 1\nf()");
+        assert_eq!(result, Err(RuntimeError::NotCallable("int".to_owned())));
+        // a global can replace a builtin
+        let (result, output) = run_output("let len = 3\nprintln(len)");
+        assert_eq!((result, output.as_str()), (Ok(()), "3\n"));
+    }
+
+    #[test]
+    fn test_coroutines() {
+        let code = r#"
+            fn* count(n) { for i in 0..n { yield i } }
+            let total = 0
+            for i in count(4) { total = total + i }
+            println(total)
+
+            fn ping() {
+                let got = yield "ping"
+                yield got + "!"
+                "done"
+            }
+            let co = coroutine(ping)
+            println(status(co), resume(co), resume(co, "pong"), resume(co), status(co))
+
+            fn* inner() { yield 1; panic("boom") }
+            fn* outer() { for x in inner() { yield x } }
+            let gen = outer()
+            println(resume(gen))
+            resume(gen)
+        "#;
+        let (result, output) = run_output(code);
+        assert_eq!(output, "6\nsuspended ping pong! done dead\n1\n");
+        let trace = ["inner", "resume", "outer", "resume", "<script>"];
+        let error = RuntimeError::Coroutine {
+            error: Box::new(RuntimeError::Panic("boom".to_owned())),
+            trace: trace.iter().map(|s| s.to_string()).collect(),
+        };
+        assert_eq!(result, Err(error));
+    }
+
+    #[test]
+    fn test_struct_iter() {
+        let code = r#"
+            struct Countdown { n, next }
+            fn tick(c) { if c.n > 0 { c.n = c.n - 1; Option::Some(c.n) } else { Option::None } }
+            let c = Countdown { n: 3, next: tick }
+            for i in c { print(i, "") }
+            println(c.n)
+        "#;
+        let (result, output) = run_output(code);
+        assert_eq!((result, output.as_str()), (Ok(()), "2 1 0 0\n"));
+        let code = "struct S { next }\nfn one(s) { 1 }\nlet s = S { next: one }\nfor x in s { }";
+        let error =
+            RuntimeError::TypeError("`next` must return an `Option`, found `int`".to_owned());
+        assert_eq!(run_output(code).0, Err(error));
+        let code = "struct P { x }\nlet p = P { x: 1 }\nfor x in p { }";
+        let error = RuntimeError::TypeError("`P { x: 1 }` is not iterable".to_owned());
+        assert_eq!(run_output(code).0, Err(error));
+    }
+
+    #[test]
+    fn test_overflow() {
+        // every int path fails the same way, the error can be caught
+        let code = r#"
+            let big = 1073741824 * 1073741824 * 4
+            let max = big - 1 + big
+            let min = -big - big
+            fn inc(x) { x + 1 }
+            println(max, min, max + 0.5 > 0)
+            println(try { max + 1 } catch e { e.kind }, try { min - 1 } catch e { e.kind })
+            println(try { big * 2 } catch e { e.kind }, try { -min } catch e { e.message })
+            println(try { inc(max) } catch e { e.kind })
+        "#;
+        let (result, output) = run_output(code);
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            output,
+            "9223372036854775807 -9223372036854775808 true\n\
+             Overflow Overflow\n\
+             Overflow integer overflow\n\
+             Overflow\n"
+        );
+        let (result, _) = run_output("let big = 1073741824 * 1073741824 * 4\nbig + big");
+        assert_eq!(result, Err(RuntimeError::Overflow));
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let code = r#"
+            fn check(n) { if n < 0 { throw "negative" } n }
+            fn safe(n) { try { check(n) } catch e { e } finally { print("f ") } }
+            println(safe(1), safe(-1))
+
+            let e = try { 1 / 0 } catch e { e }
+            println(e.kind, e.message, e.trace)
+            fn inner() { [1][3] }
+            fn outer() { inner() }
+            println(try { outer() } catch e { (e.kind, e.trace) })
+
+            fn first(list) {
+                for x in list { try { return x } finally { print("r ") } }
+            }
+            println(first([7, 8]))
+            let n = 0
+            while n < 3 { try { n = n + 1; continue } finally { print(n, "") } }
+            println(try { let x = 1; x + 1 } catch { 0 })
+            try { throw 5 } finally { println("cleanup") }
+        "#;
+        let (result, output) = run_output(code);
+        assert_eq!(
+            output,
+            "f f 1 negative\n\
+             DivisionByZero division by zero [\"<script>\"]\n\
+             (\"IndexError\", [\"inner\", \"outer\", \"<script>\"])\n\
+             r 7\n\
+             1 2 3 2\n\
+             cleanup\n"
+        );
+        assert_eq!(result, Err(RuntimeError::Thrown(Value::Int(5))));
+
+        // the limits can't be caught
+        let mut compiler = compile("try { while true {} } catch { 0 }");
+        let mut vm = Vm::new();
+        vm.set_limits(Limits {
+            fuel: Some(1000),
+            ..Limits::default()
+        });
+        let result = vm.interpret(compiler.pop_chunk());
+        assert_eq!(result, Err(RuntimeError::OutOfFuel));
+    }
+
+    #[test]
+    fn test_options() {
+        let code = r#"
+            fn parse(s) { if s == "" { Err("empty") } else { Ok(int(s)) } }
+            fn sum(a, b) { Ok(parse(a)? + parse(b)?) }
+            println(sum("1", "2"), sum("1", ""), sum("", "x"))
+
+            fn first(list) { if len(list) > 0 { Some(list[0]) } else { None } }
+            fn twice(list) { try { Some(first(list)? * 2) } finally { print("f ") } }
+            println(twice([3]), twice([]))
+
+            fn double(x) { x * 2 }
+            fn fail(x) { Err(x) }
+            fn positive(x) { if x > 0 { Some(x) } else { None } }
+            println(Some(2).map(double), None.map(double), Err(1).map(double))
+            println(Some(2).and_then(positive), Some(-1).and_then(positive), Ok(0).and_then(fail))
+            println(Some(1).unwrap_or(0), None.unwrap_or(0), Err("e").unwrap_or(0))
+
+            let name = match first(["a"]) { Some(x) => x, None => "none" }
+            println(name)
+        "#;
+        let (result, output) = run_output(code);
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            output,
+            "Ok(3) Err(\"empty\") Err(\"empty\")\n\
+             f f Some(6) None\n\
+             Some(4) None Err(1)\n\
+             Some(2) None Err(0)\n\
+             1 0 0\n\
+             a\n"
+        );
+
+        assert_eq!(errors("Some(1)?"), ["`?` outside of a function"]);
+        let result = run_output("fn f() { 1? }\nf()").0;
+        assert!(matches!(result, Err(RuntimeError::TypeError(_))));
+        // each `map` runs `f` in a nested run, they are capped
+        let result = run_output("fn f(x) { Some(x).map(f) }\nf(1)").0;
+        assert_eq!(result, Err(RuntimeError::CallDepthExceeded));
+        let result = run_output("fn f(x) { Ok(x).and_then(f) }\nf(1)").0;
+        assert_eq!(result, Err(RuntimeError::CallDepthExceeded));
+    }
+
+    #[test]
+    fn test_collect_host_values() {
+        // a cycle only the host holds is kept by the collections
+        let code = r#"
+            let a = [nil]
+            a[0] = a
+            keep(a)
+            a = nil
+            let n = 0
+            while n < 1000 {
+                let b = [nil]
+                b[0] = b
+                n = n + 1
+            }
+        "#;
+        let kept = Rc::new(RefCell::new(Value::Nil));
+        let mut vm = Vm::new();
+        let host = kept.clone();
+        vm.define_native("keep", Arity::Exact(1), move |_, args| {
+            *host.borrow_mut() = args[0].clone();
+            Ok(Value::Nil)
+        });
+        vm.set_gc_config(GcConfig {
+            threshold: 1024,
+            ..GcConfig::default()
+        });
+        let mut compiler = Compiler::new();
+        let i = compiler.declare_global("keep".into()).unwrap();
+        vm.define_global(i, vm.native("keep").unwrap().clone());
+        let ast = Parser::new(Cursor::new(code)).parse().unwrap();
+        compiler.compile(ast, false).unwrap();
+        vm.interpret(compiler.pop_chunk()).unwrap();
+        vm.collect_garbage();
+        assert!(vm.gc_stats().swept > 0);
+        let kept = kept.borrow();
+        let Value::List(ref list) = *kept else {
+            panic!("unexpected {:?}", kept);
+        };
+        assert!(matches!(list.borrow()[0], Value::List(ref item) if Rc::ptr_eq(item, list)));
+    }
+
+    #[test]
+    fn test_collect_cycles() {
+        let code = r#"
+            struct Node { next }
+            let kept = Node { next: nil }
+            kept.next = [kept]
+            let n = 0
+            while n < 1000 {
+                let node = Node { next: nil }
+                node.next = [node, (node,)]
+                n = n + 1
+            }
+        "#;
+        let mut compiler = compile(code);
+        let mut vm = Vm::new();
+        vm.set_gc_config(GcConfig {
+            threshold: 1024,
+            ..GcConfig::default()
+        });
+        vm.interpret(compiler.pop_chunk()).unwrap();
+        let stats = vm.gc_stats();
+        assert!(stats.collections > 0);
+        assert!(stats.swept > 0);
+
+        vm.collect_garbage();
+        // `kept` and its list are left
+        assert_eq!(vm.gc_stats().objects, 2);
+        let Some(Value::Struct(kept)) = vm.get_global(compiler.global["kept"]) else {
+            panic!("`kept` is not a struct");
+        };
+        assert!(
+            matches!(kept.get("next"), Some(Value::List(ref list)) if list.borrow().len() == 1)
+        );
     }
 }
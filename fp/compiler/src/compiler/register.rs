@@ -0,0 +1,991 @@
+// the register backend, see `vm::register`. it compiles the same ast to functions of
+// register code, the script is a function too and the chunk only calls it:
+// Constant     <- the script
+// Call 0
+// Return
+// a local has a register of the window for its whole scope and an expr is compiled
+// into the register given to it, the temporaries are the registers above the locals.
+// a function using `match`, `try`, `yield` or a pattern in `for`, or a `fn*`, is left
+// to the stack backend, see `needs_stack`. the script is a function too, the two kinds
+// of functions call each other.
+
+use std::{mem, rc::Rc};
+
+use vm::{
+    chunk::Chunk,
+    object::Function,
+    op::OpCode,
+    register::{Code, Instr, Reg},
+    string::Str,
+    value::Value,
+};
+
+use super::Compiler;
+use crate::ast::{BinaryOp, Expr, ExprKind, ParseObj, Pattern, Stmt, StmtKind, UnaryOp};
+
+pub(super) fn compile_script(compiler: &mut Compiler, ast: Vec<Stmt>, keep_value: bool) {
+    let mut ast = ast;
+    let last = match ast.last() {
+        Some(Stmt {
+            node: StmtKind::ExprStmt { .. },
+        }) if keep_value => ast.pop(),
+        _ => None,
+    };
+    let keep = last.is_some();
+
+    let mut registers = Registers {
+        compiler: &mut *compiler,
+        fun: FnState::default(),
+        enclosing: Vec::new(),
+    };
+    let value = registers.alloc();
+    for stmt in ast {
+        registers.compile_stmt(stmt);
+    }
+    match last {
+        Some(Stmt {
+            node: StmtKind::ExprStmt { expr },
+        }) => registers.compile_expr(*expr, value),
+        _ => registers.emit_nil(value),
+    }
+    registers.emit(Instr::Return { src: value });
+    let state = mem::take(&mut registers.fun);
+    let script = registers.finish_function("<script>", 0, state);
+
+    compiler.emit_constant(script);
+    compiler.emit_opcode(OpCode::Call);
+    compiler.emit(0);
+    if !keep {
+        compiler.emit_opcode(OpCode::Pop);
+    }
+    compiler.emit_opcode(OpCode::Return);
+}
+
+// a function of the register backend in a function of the stack backend, `outer` are
+// the locals of the functions around it
+pub(super) fn compile_function(
+    compiler: &mut Compiler,
+    outer: Vec<Str>,
+    name: &str,
+    params: Vec<Str>,
+    body: Vec<Stmt>,
+) -> Value {
+    let mut registers = Registers {
+        compiler,
+        fun: FnState::default(),
+        enclosing: Vec::new(),
+    };
+    let outer = outer.into_iter().map(|name| (name, 0)).collect();
+    registers.fun.scope.push(outer);
+    registers.compile_function(name, params, body)
+}
+
+// the code has what only the stack backend compiles, the functions declared in it
+// don't count, each of them is compiled by the backend it needs
+pub(super) fn needs_stack(body: &[Stmt], generator: bool) -> bool {
+    generator || body.iter().any(stmt_needs_stack)
+}
+
+fn stmt_needs_stack(stmt: &Stmt) -> bool {
+    match &stmt.node {
+        StmtKind::ExprStmt { expr } | StmtKind::VarDec { value: expr, .. } => {
+            expr_needs_stack(expr)
+        }
+        StmtKind::While { test, body, .. } => expr_needs_stack(test) || needs_stack(body, false),
+        StmtKind::For {
+            pattern,
+            iter,
+            body,
+            ..
+        } => {
+            !matches!(pattern, Pattern::Bind { sub: None, .. } | Pattern::Wildcard)
+                || expr_needs_stack(iter)
+                || needs_stack(body, false)
+        }
+        StmtKind::EnumDec { .. } | StmtKind::StructDec { .. } | StmtKind::FnDec { .. } => false,
+    }
+}
+
+fn expr_needs_stack(expr: &Expr) -> bool {
+    let any = |exprs: &[Expr]| exprs.iter().any(expr_needs_stack);
+    let maybe = |expr: &Option<Box<Expr>>| expr.as_deref().is_some_and(expr_needs_stack);
+    match &expr.node {
+        ExprKind::Match { .. } | ExprKind::Try { .. } | ExprKind::Yield { .. } => true,
+        ExprKind::Literal { .. } | ExprKind::Continue { .. } => false,
+        ExprKind::Group { body: value }
+        | ExprKind::Unary { operand: value, .. }
+        | ExprKind::Field { object: value, .. }
+        | ExprKind::Item { object: value, .. }
+        | ExprKind::Throw { value }
+        | ExprKind::Propagate { value } => expr_needs_stack(value),
+        ExprKind::Binary { left, right, .. }
+        | ExprKind::Index {
+            object: left,
+            index: right,
+        }
+        | ExprKind::Assign {
+            target: left,
+            value: right,
+        } => expr_needs_stack(left) || expr_needs_stack(right),
+        ExprKind::Block { inner: body } | ExprKind::Loop { body, .. } => needs_stack(body, false),
+        ExprKind::If { test, body, orelse } => {
+            expr_needs_stack(test) || needs_stack(body, false) || needs_stack(orelse, false)
+        }
+        ExprKind::Tuple { items } | ExprKind::List { items } => any(items),
+        ExprKind::Variant { args, .. } => any(args),
+        ExprKind::Call { callee, args } => expr_needs_stack(callee) || any(args),
+        ExprKind::Map { items } => items
+            .iter()
+            .any(|(key, value)| expr_needs_stack(key) || expr_needs_stack(value)),
+        ExprKind::StructLit { fields, .. } => {
+            fields.iter().any(|(_, value)| expr_needs_stack(value))
+        }
+        ExprKind::Range {
+            start, end, step, ..
+        } => expr_needs_stack(start) || expr_needs_stack(end) || maybe(step),
+        ExprKind::Break { value, .. } | ExprKind::Return { value } => maybe(value),
+    }
+}
+
+struct Registers<'a> {
+    compiler: &'a mut Compiler,
+    fun: FnState,
+    // the functions around the one being compiled, the outermost is the first
+    enclosing: Vec<FnState>,
+}
+
+#[derive(Default)]
+struct FnState {
+    instrs: Vec<Instr>,
+    constants: Vec<Value>,
+    // locals of every block and their register
+    scope: Vec<Vec<(Str, Reg)>>,
+    // the first free register
+    top: usize,
+    // the loops around this point of the code, the innermost is the last
+    loops: Vec<Loop>,
+}
+
+struct Loop {
+    label: Option<Str>,
+    // where `continue` jumps back to
+    start: usize,
+    // the `break`s waiting for the end of the loop
+    breaks: Vec<usize>,
+    // where `break` puts the value, only `loop` gives one
+    value: Option<Reg>,
+}
+
+impl Registers<'_> {
+    fn compile_stmt(&mut self, stmt: Stmt) {
+        match stmt.node {
+            StmtKind::ExprStmt { expr } => {
+                let top = self.fun.top;
+                let r = self.alloc();
+                self.compile_expr(*expr, r);
+                self.fun.top = top;
+            }
+            StmtKind::VarDec { name, value } => self.compile_var_dec(name, *value),
+            StmtKind::While { label, test, body } => self.compile_while(label, *test, body),
+            StmtKind::For {
+                label,
+                pattern,
+                iter,
+                body,
+            } => self.compile_for(label, pattern, *iter, body),
+            StmtKind::EnumDec { name, variants } => self.compiler.compile_enum_dec(name, variants),
+            StmtKind::StructDec { name, fields } => self.compiler.compile_struct_dec(name, fields),
+            StmtKind::FnDec {
+                name,
+                params,
+                body,
+                generator,
+            } => self.compile_fn_dec(name, params, body, generator),
+        }
+    }
+
+    // the globals are the variables of the script out of any block
+    fn compile_var_dec(&mut self, name: Str, value: Expr) {
+        if !self.is_global() {
+            let r = self.alloc();
+            self.compile_expr(value, r);
+            return self.add_local(name, r);
+        }
+        let top = self.fun.top;
+        let r = self.alloc();
+        self.compile_expr(value, r);
+        match self.compiler.declare_global(name) {
+            Some(i) => {
+                self.emit(Instr::SetGlobal { i, src: r });
+            }
+            None => self.error("too many global variables".to_owned()),
+        }
+        self.fun.top = top;
+    }
+
+    // the arguments are the first registers of the function, e.g. `fn add(a, b) { a + b }`:
+    // Add r2, r0, r1
+    // Return r2
+    fn compile_fn_dec(&mut self, name: Str, params: Vec<Str>, body: Vec<Stmt>, generator: bool) {
+        if params.len() > u8::MAX as usize {
+            return self.error(format!("function `{}` has too many parameters", name));
+        }
+        // a global function can call itself
+        let global = match self.is_global() {
+            true => match self.compiler.declare_global(name.clone()) {
+                Some(i) => Some(i),
+                None => return self.error("too many global variables".to_owned()),
+            },
+            false => None,
+        };
+
+        let fun = match needs_stack(&body, generator) {
+            true => {
+                let outer = self.local_names();
+                self.compiler
+                    .compile_stack_function(outer, &name, params, body, generator)
+            }
+            false => self.compile_function(&name, params, body),
+        };
+        let i = self.constant(fun);
+        let top = self.fun.top;
+        let r = self.alloc();
+        self.emit(Instr::Constant { dst: r, i });
+        match global {
+            Some(i) => {
+                self.emit(Instr::SetGlobal { i, src: r });
+                self.fun.top = top;
+            }
+            None => self.add_local(name, r),
+        }
+    }
+
+    fn compile_function(&mut self, name: &str, params: Vec<Str>, body: Vec<Stmt>) -> Value {
+        self.enclosing.push(mem::take(&mut self.fun));
+        self.begin_scope();
+        let arity = params.len() as u8;
+        for param in params {
+            let r = self.alloc();
+            self.add_local(param, r);
+        }
+        let value = self.alloc();
+        self.compile_block(body, value);
+        self.emit(Instr::Return { src: value });
+        let outer = self.enclosing.pop().unwrap();
+        let state = mem::replace(&mut self.fun, outer);
+        self.finish_function(name, arity, state)
+    }
+
+    fn finish_function(&mut self, name: &str, arity: u8, state: FnState) -> Value {
+        let code = match Code::new(state.instrs, state.constants, arity) {
+            Ok(code) => code,
+            Err(e) => {
+                self.error(format!("invalid register code of `{}`: {}", name, e));
+                return Value::Nil;
+            }
+        };
+        Value::Function(Rc::new(Function {
+            name: name.to_owned(),
+            arity,
+            chunk: Rc::new(Chunk::new()),
+            generator: false,
+            registers: Some(Rc::new(code)),
+        }))
+    }
+
+    // it will generate:
+    // { test } <---------+
+    // +-- JumpIfFalse    |
+    // |   { body }       |
+    // |   Jump ----------+
+    // +-> { other }          <- `break` jumps here, `continue` jumps to the test
+    fn compile_while(&mut self, label: Option<Str>, test: Expr, body: Vec<Stmt>) {
+        let start = self.fun.instrs.len();
+        self.begin_loop(label, start, None);
+        let top = self.fun.top;
+        let test = self.operand(test);
+        let exit = self.emit(Instr::JumpIfFalse { test, to: 0 });
+        let value = self.alloc();
+        self.compile_block(body, value);
+        self.fun.top = top;
+        self.emit_jump_to(start);
+        self.patch_jump(exit);
+        self.end_loop();
+    }
+
+    // it will generate:
+    // { iter }
+    // GetIter              <- the iterator replaces the value in its register
+    // ForIter <-------+    <- the next item in the register of the local, or jump out
+    // |  { body }     |
+    // |  Jump --------+
+    // +-> { other }        <- `break` jumps here, `continue` jumps to ForIter
+    fn compile_for(&mut self, label: Option<Str>, pattern: Pattern, iter: Expr, body: Vec<Stmt>) {
+        let top = self.fun.top;
+        let it = self.alloc();
+        self.compile_expr(iter, it);
+        self.emit(Instr::GetIter { dst: it, src: it });
+        let item = self.alloc();
+
+        let start = self.fun.instrs.len();
+        self.begin_loop(label, start, None);
+        let exit = self.emit(Instr::ForIter {
+            dst: item,
+            iter: it,
+            exit: 0,
+        });
+        self.begin_scope();
+        match pattern {
+            Pattern::Bind { name, sub: None } => self.add_local(name, item),
+            Pattern::Wildcard => {}
+            // the other patterns are compiled by the stack backend
+            _ => unreachable!(),
+        }
+        let value = self.alloc();
+        self.compile_block(body, value);
+        self.end_scope();
+        self.emit_jump_to(start);
+        self.patch_jump(exit);
+        self.end_loop();
+        self.fun.top = top;
+    }
+
+    fn compile_expr(&mut self, expr: Expr, dst: Reg) {
+        match expr.node {
+            ExprKind::Binary {
+                left,
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                right,
+            } => self.compile_logical(*left, op, *right, dst),
+            ExprKind::Binary { left, op, right } => self.compile_binary(*left, op, *right, dst),
+            ExprKind::Group { body } => self.compile_expr(*body, dst),
+            ExprKind::Literal { value } => self.compile_literal(value, dst),
+            ExprKind::Unary { op, operand } => {
+                let top = self.fun.top;
+                let src = self.operand(*operand);
+                match op {
+                    UnaryOp::Not => self.emit(Instr::Not { dst, src }),
+                    UnaryOp::Neg => self.emit(Instr::Neg { dst, src }),
+                };
+                self.fun.top = top;
+            }
+            ExprKind::Block { inner } => self.compile_block(inner, dst),
+            ExprKind::If { test, body, orelse } => self.compile_if(*test, body, orelse, dst),
+            ExprKind::Tuple { items } => {
+                let top = self.fun.top;
+                let (start, n) = self.compile_items(items);
+                self.emit(Instr::MakeTuple { dst, start, n });
+                self.fun.top = top;
+            }
+            ExprKind::List { items } => {
+                let top = self.fun.top;
+                let (start, n) = self.compile_items(items);
+                self.emit(Instr::MakeList { dst, start, n });
+                self.fun.top = top;
+            }
+            ExprKind::Map { items } => {
+                if items.len() > u8::MAX as usize {
+                    self.error("too many items in a map literal".to_owned());
+                    return self.emit_nil(dst);
+                }
+                let top = self.fun.top;
+                let (start, n) = (self.next_reg(), items.len() as u8);
+                for (key, value) in items {
+                    let r = self.alloc();
+                    self.compile_expr(key, r);
+                    let r = self.alloc();
+                    self.compile_expr(value, r);
+                }
+                self.emit(Instr::MakeMap { dst, start, n });
+                self.fun.top = top;
+            }
+            ExprKind::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } => {
+                let top = self.fun.top;
+                let from = self.alloc();
+                self.compile_expr(*start, from);
+                let to = self.alloc();
+                self.compile_expr(*end, to);
+                let mut flags = inclusive as u8;
+                if let Some(step) = step {
+                    let r = self.alloc();
+                    self.compile_expr(*step, r);
+                    flags |= 0b10;
+                }
+                self.emit(Instr::MakeRange {
+                    dst,
+                    start: from,
+                    flags,
+                });
+                self.fun.top = top;
+            }
+            ExprKind::Variant {
+                enum_name,
+                variant,
+                args,
+            } => match self
+                .compiler
+                .variant_template(&enum_name, &variant, args.len())
+            {
+                Some(template) => self.compile_construct(template, args, dst),
+                None => self.emit_nil(dst),
+            },
+            ExprKind::StructLit { name, fields } => {
+                match self.compiler.struct_values(&name, fields) {
+                    Some((template, values)) => self.compile_construct(template, values, dst),
+                    None => self.emit_nil(dst),
+                }
+            }
+            ExprKind::Field { object, name } => {
+                let top = self.fun.top;
+                let object = self.operand(*object);
+                let name = self.constant(Value::Str(name));
+                self.emit(Instr::GetField { dst, object, name });
+                self.fun.top = top;
+            }
+            ExprKind::Item { object, index } => {
+                let top = self.fun.top;
+                let src = self.operand(*object);
+                self.emit(Instr::GetItem { dst, src, i: index });
+                self.fun.top = top;
+            }
+            ExprKind::Index { object, index } => {
+                let top = self.fun.top;
+                let (object, index) = self.operands(*object, *index);
+                self.emit(Instr::GetIndex { dst, object, index });
+                self.fun.top = top;
+            }
+            ExprKind::Call { callee, args } => self.compile_call(*callee, args, dst),
+            ExprKind::Assign { target, value } => self.compile_assign(*target, *value, dst),
+            ExprKind::Loop { label, body } => self.compile_loop(label, body, dst),
+            ExprKind::Break { label, value } => self.compile_break(label, value.map(|v| *v)),
+            ExprKind::Continue { label } => self.compile_continue(label),
+            ExprKind::Return { value } => self.compile_return(value.map(|v| *v), dst),
+            ExprKind::Throw { value } => {
+                let top = self.fun.top;
+                let src = self.alloc();
+                self.compile_expr(*value, src);
+                self.emit(Instr::Throw { src });
+                self.fun.top = top;
+            }
+            ExprKind::Propagate { value } => self.compile_propagate(*value, dst),
+            // compiled by the stack backend, see `needs_stack`
+            ExprKind::Match { .. } | ExprKind::Try { .. } | ExprKind::Yield { .. } => {
+                unreachable!()
+            }
+        }
+    }
+
+    fn compile_literal(&mut self, value: ParseObj, dst: Reg) {
+        match value {
+            ParseObj::Nil => self.emit_nil(dst),
+            ParseObj::Bool(value) => {
+                self.emit(Instr::Bool { dst, value });
+            }
+            ParseObj::Int(v) => self.emit_constant(Value::Int(v as i64), dst),
+            ParseObj::Float(v) => self.emit_constant(Value::Float(v), dst),
+            ParseObj::Str(s) => self.emit_constant(Value::Str(s), dst),
+            ParseObj::Ident(name) => self.compile_variable(name, dst),
+        }
+    }
+
+    fn compile_variable(&mut self, name: Str, dst: Reg) {
+        if let Some(src) = self.resolve_local(&name) {
+            if src != dst {
+                self.emit(Instr::Move { dst, src });
+            }
+            return;
+        }
+        match self.compiler.global(&name) {
+            Some(i) => {
+                self.emit(Instr::GetGlobal { dst, i });
+            }
+            None => {
+                self.undefined_variable(&name);
+                self.emit_nil(dst);
+            }
+        }
+    }
+
+    fn undefined_variable(&mut self, name: &str) {
+        let outer = self.enclosing.iter().flat_map(|state| state.scope.iter());
+        if outer.flatten().any(|(local, _)| local == name) {
+            return self.error(format!(
+                "can't use the local variable `{}` of the outer function",
+                name
+            ));
+        }
+        self.error(format!("undefined variable `{}`", name));
+    }
+
+    // `a != b` is `Eq` then `Not`, like the bytecode
+    fn compile_binary(&mut self, left: Expr, op: BinaryOp, right: Expr, dst: Reg) {
+        let top = self.fun.top;
+        let (a, b) = self.operands(left, right);
+        let (instr, not) = match op {
+            BinaryOp::Add => (Instr::Add { dst, a, b }, false),
+            BinaryOp::Sub => (Instr::Sub { dst, a, b }, false),
+            BinaryOp::Mult => (Instr::Mult { dst, a, b }, false),
+            BinaryOp::Div => (Instr::Div { dst, a, b }, false),
+            BinaryOp::Eq => (Instr::Eq { dst, a, b }, false),
+            BinaryOp::NotEq => (Instr::Eq { dst, a, b }, true),
+            BinaryOp::Gt => (Instr::Gt { dst, a, b }, false),
+            BinaryOp::GtE => (Instr::Lt { dst, a, b }, true),
+            BinaryOp::Lt => (Instr::Lt { dst, a, b }, false),
+            BinaryOp::LtE => (Instr::Gt { dst, a, b }, true),
+            // `&&` and `||` are compiled by `compile_logical`
+            BinaryOp::And | BinaryOp::Or => unreachable!(),
+        };
+        self.emit(instr);
+        if not {
+            self.emit(Instr::Not { dst, src: dst });
+        }
+        self.fun.top = top;
+    }
+
+    // the right side is only evaluated when needed, it will generate:
+    // `left && right`              `left || right`
+    // { left }                     { left }
+    // +--- JumpIfFalse             +--- JumpIfFalse
+    // |    { right }               |    Bool true
+    // |    Jump -------+           |    Jump -------+
+    // +--> Bool false  |           +--> { right }   |
+    //      { other } <-+                { other } <-+
+    fn compile_logical(&mut self, left: Expr, op: BinaryOp, right: Expr, dst: Reg) {
+        let is_and = matches!(op, BinaryOp::And);
+        self.compile_expr(left, dst);
+        let to_else = self.emit(Instr::JumpIfFalse { test: dst, to: 0 });
+        let right = if is_and {
+            self.compile_expr(right, dst);
+            None
+        } else {
+            self.emit(Instr::Bool { dst, value: true });
+            Some(right)
+        };
+        let to_end = self.emit(Instr::Jump { to: 0 });
+        self.patch_jump(to_else);
+        match right {
+            Some(right) => self.compile_expr(right, dst),
+            None => {
+                self.emit(Instr::Bool { dst, value: false });
+            }
+        }
+        self.patch_jump(to_end);
+    }
+
+    fn compile_if(&mut self, test: Expr, body: Vec<Stmt>, orelse: Vec<Stmt>, dst: Reg) {
+        let top = self.fun.top;
+        let test = self.operand(test);
+        let to_else = self.emit(Instr::JumpIfFalse { test, to: 0 });
+        self.fun.top = top;
+        self.compile_block(body, dst);
+        let to_end = self.emit(Instr::Jump { to: 0 });
+        self.patch_jump(to_else);
+        if orelse.is_empty() {
+            self.emit_nil(dst);
+        } else {
+            self.compile_block(orelse, dst);
+        }
+        self.patch_jump(to_end);
+    }
+
+    // the value of the last expr stmt, or nil
+    fn compile_block(&mut self, inner: Vec<Stmt>, dst: Reg) {
+        self.begin_scope();
+        let mut inner = inner;
+        match inner.pop() {
+            None => self.emit_nil(dst),
+            Some(end) => {
+                for stmt in inner {
+                    self.compile_stmt(stmt);
+                }
+                if let StmtKind::ExprStmt { expr } = end.node {
+                    self.compile_expr(*expr, dst);
+                } else {
+                    self.compile_stmt(end);
+                    self.emit_nil(dst);
+                }
+            }
+        }
+        self.end_scope();
+    }
+
+    // a call of a field is a method call, the arguments follow the object:
+    // Invoke r0, r1, #0, 2     <- `r1.name(r2, r3)`
+    // the other callees are in the register before the arguments:
+    // Call r0, r1, 2           <- `r1(r2, r3)`
+    fn compile_call(&mut self, callee: Expr, args: Vec<Expr>, dst: Reg) {
+        let top = self.fun.top;
+        match callee.node {
+            ExprKind::Field { object, name } => {
+                let object_reg = self.alloc();
+                self.compile_expr(*object, object_reg);
+                let name = self.constant(Value::Str(name));
+                let (_, n) = self.compile_items(args);
+                self.emit(Instr::Invoke {
+                    dst,
+                    object: object_reg,
+                    name,
+                    n,
+                });
+            }
+            callee => {
+                let callee_reg = self.alloc();
+                self.compile_expr(Expr::new(callee), callee_reg);
+                let (_, n) = self.compile_items(args);
+                self.emit(Instr::Call {
+                    dst,
+                    callee: callee_reg,
+                    n,
+                });
+            }
+        }
+        self.fun.top = top;
+    }
+
+    // assignment is an expr with the value nil
+    fn compile_assign(&mut self, target: Expr, value: Expr, dst: Reg) {
+        let top = self.fun.top;
+        match target.node {
+            ExprKind::Literal {
+                value: ParseObj::Ident(name),
+            } => {
+                if let Some(local) = self.resolve_local(&name) {
+                    // the value is made in the local when nothing reads it after a write
+                    if is_direct(&value) {
+                        self.compile_expr(value, local);
+                    } else {
+                        let r = self.alloc();
+                        self.compile_expr(value, r);
+                        self.emit(Instr::Move { dst: local, src: r });
+                    }
+                } else if let Some(i) = self.compiler.global(&name) {
+                    let r = self.alloc();
+                    self.compile_expr(value, r);
+                    self.emit(Instr::SetGlobal { i, src: r });
+                } else {
+                    self.undefined_variable(&name);
+                }
+            }
+            ExprKind::Field { object, name } => {
+                let object_reg = self.alloc();
+                self.compile_expr(*object, object_reg);
+                let name = self.constant(Value::Str(name));
+                let src = self.alloc();
+                self.compile_expr(value, src);
+                self.emit(Instr::SetField {
+                    object: object_reg,
+                    name,
+                    src,
+                });
+            }
+            ExprKind::Index { object, index } => {
+                let object_reg = self.alloc();
+                self.compile_expr(*object, object_reg);
+                let index_reg = self.alloc();
+                self.compile_expr(*index, index_reg);
+                let src = self.alloc();
+                self.compile_expr(value, src);
+                self.emit(Instr::SetIndex {
+                    object: object_reg,
+                    index: index_reg,
+                    src,
+                });
+            }
+            // checked by the parser
+            _ => unreachable!(),
+        }
+        self.fun.top = top;
+        self.emit_nil(dst);
+    }
+
+    // the template, then `Construct` when there are fields
+    fn compile_construct(&mut self, template: Value, fields: Vec<Expr>, dst: Reg) {
+        let template = self.constant(template);
+        if fields.is_empty() {
+            self.emit(Instr::Constant { dst, i: template });
+            return;
+        }
+        let top = self.fun.top;
+        let (start, n) = self.compile_items(fields);
+        self.emit(Instr::Construct {
+            dst,
+            template,
+            start,
+            n,
+        });
+        self.fun.top = top;
+    }
+
+    // it will generate:
+    // { body } <------+
+    // Jump -----------+    <- `continue` jumps back to the body too
+    // { other }            <- `break` jumps here, its value is already in `dst`
+    fn compile_loop(&mut self, label: Option<Str>, body: Vec<Stmt>, dst: Reg) {
+        let start = self.fun.instrs.len();
+        self.begin_loop(label, start, Some(dst));
+        let top = self.fun.top;
+        let value = self.alloc();
+        self.compile_block(body, value);
+        self.fun.top = top;
+        self.emit_jump_to(start);
+        self.end_loop();
+    }
+
+    // the value of `loop` goes to its register, the registers of the locals and
+    // temporaries in the loop don't need to be dropped
+    fn compile_break(&mut self, label: Option<Str>, value: Option<Expr>) {
+        let i = match self.find_loop(label.as_deref(), "break") {
+            Some(i) => i,
+            None => return,
+        };
+        match (self.fun.loops[i].value, value) {
+            (Some(dst), Some(value)) => self.compile_expr(value, dst),
+            (Some(dst), None) => self.emit_nil(dst),
+            (None, Some(_)) => self.error("only `loop` can `break` with a value".to_owned()),
+            (None, None) => {}
+        }
+        let jump = self.emit(Instr::Jump { to: 0 });
+        self.fun.loops[i].breaks.push(jump);
+    }
+
+    fn compile_continue(&mut self, label: Option<Str>) {
+        if let Some(i) = self.find_loop(label.as_deref(), "continue") {
+            self.emit_jump_to(self.fun.loops[i].start);
+        }
+    }
+
+    fn compile_return(&mut self, value: Option<Expr>, dst: Reg) {
+        if self.enclosing.is_empty() {
+            self.error("`return` outside of a function".to_owned());
+            return self.emit_nil(dst);
+        }
+        match value {
+            Some(value) => self.compile_expr(value, dst),
+            None => self.emit_nil(dst),
+        }
+        self.emit(Instr::Return { src: dst });
+    }
+
+    // `value?`, it returns `None` and `Err(e)` and goes on with the value in `Some(v)`
+    // and `Ok(v)`:
+    //     { value }
+    // +-- JumpIfOk     <- `v` replaces `Some(v)` or `Ok(v)`
+    // |   Return
+    // +->
+    fn compile_propagate(&mut self, value: Expr, dst: Reg) {
+        if self.enclosing.is_empty() {
+            self.error("`?` outside of a function".to_owned());
+            return self.emit_nil(dst);
+        }
+        self.compile_expr(value, dst);
+        let to_ok = self.emit(Instr::JumpIfOk { src: dst, to: 0 });
+        self.emit(Instr::Return { src: dst });
+        self.patch_jump(to_ok);
+    }
+
+    // compile exprs to the registers from the first free one, it returns the first
+    // register and how many exprs
+    fn compile_items(&mut self, items: Vec<Expr>) -> (Reg, u8) {
+        let start = self.next_reg();
+        if items.len() > u8::MAX as usize {
+            self.error("too many items, the limit is 255".to_owned());
+            return (start, 0);
+        }
+        let n = items.len() as u8;
+        for item in items {
+            let r = self.alloc();
+            self.compile_expr(item, r);
+        }
+        (start, n)
+    }
+
+    // the register of a local is read in place, the other exprs get a temporary
+    fn operand(&mut self, expr: Expr) -> Reg {
+        if let ExprKind::Literal {
+            value: ParseObj::Ident(name),
+        } = &expr.node
+        {
+            if let Some(r) = self.resolve_local(name) {
+                return r;
+            }
+        }
+        let r = self.alloc();
+        self.compile_expr(expr, r);
+        r
+    }
+
+    // the left local is read in place only when the right expr can't assign it
+    fn operands(&mut self, left: Expr, right: Expr) -> (Reg, Reg) {
+        let a = if is_simple(&right) {
+            self.operand(left)
+        } else {
+            let r = self.alloc();
+            self.compile_expr(left, r);
+            r
+        };
+        (a, self.operand(right))
+    }
+
+    fn error(&mut self, message: String) {
+        self.compiler.error(message);
+    }
+
+    // the locals of this function and of the ones around it, for the errors
+    fn local_names(&self) -> Vec<Str> {
+        let states = self.enclosing.iter().chain([&self.fun]);
+        let locals = states.flat_map(|state| state.scope.iter()).flatten();
+        locals.map(|(name, _)| name.clone()).collect()
+    }
+
+    fn is_global(&self) -> bool {
+        self.enclosing.is_empty() && self.fun.scope.is_empty()
+    }
+
+    // scope
+    fn begin_scope(&mut self) {
+        self.fun.scope.push(Vec::new());
+    }
+
+    // the registers of the locals are free again
+    fn end_scope(&mut self) {
+        if let Some(first) = self.fun.scope.pop().unwrap().first() {
+            self.fun.top = first.1 as usize;
+        }
+    }
+
+    fn add_local(&mut self, name: Str, r: Reg) {
+        self.fun.scope.last_mut().unwrap().push((name, r));
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<Reg> {
+        self.fun
+            .scope
+            .iter()
+            .rev()
+            .flat_map(|locals| locals.iter().rev())
+            .find(|(local, _)| local == name)
+            .map(|(_, r)| *r)
+    }
+
+    // the next free register
+    fn alloc(&mut self) -> Reg {
+        let r = self.next_reg();
+        if self.fun.top == Reg::MAX as usize + 1 {
+            self.error("too many registers in a function".to_owned());
+        } else {
+            self.fun.top += 1;
+        }
+        r
+    }
+
+    fn next_reg(&self) -> Reg {
+        self.fun.top.min(Reg::MAX as usize) as Reg
+    }
+
+    // loop
+    fn begin_loop(&mut self, label: Option<Str>, start: usize, value: Option<Reg>) {
+        self.fun.loops.push(Loop {
+            label,
+            start,
+            breaks: Vec::new(),
+            value,
+        });
+    }
+
+    // let the `break`s land here
+    fn end_loop(&mut self) {
+        let lp = self.fun.loops.pop().unwrap();
+        for jump in lp.breaks {
+            self.patch_jump(jump);
+        }
+    }
+
+    // the innermost loop, or the loop with the label
+    fn find_loop(&mut self, label: Option<&str>, keyword: &str) -> Option<usize> {
+        let found = match label {
+            None => self.fun.loops.len().checked_sub(1),
+            Some(label) => self
+                .fun
+                .loops
+                .iter()
+                .rposition(|lp| lp.label.as_deref() == Some(label)),
+        };
+        if found.is_none() {
+            match label {
+                Some(label) => self.error(format!("undeclared label `'{}`", label)),
+                None => self.error(format!("`{}` outside of a loop", keyword)),
+            }
+        }
+        found
+    }
+
+    // emit family
+    fn emit(&mut self, instr: Instr) -> usize {
+        self.fun.instrs.push(instr);
+        self.fun.instrs.len() - 1
+    }
+
+    fn emit_nil(&mut self, dst: Reg) {
+        self.emit(Instr::Nil { dst });
+    }
+
+    fn emit_constant(&mut self, value: Value, dst: Reg) {
+        let i = self.constant(value);
+        self.emit(Instr::Constant { dst, i });
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        self.fun.constants.push(value);
+        let i = self.fun.constants.len() - 1;
+        if i > u32::MAX as usize {
+            self.error("too many constants".to_owned());
+        }
+        i as u32
+    }
+
+    fn emit_jump_to(&mut self, to: usize) {
+        self.emit(Instr::Jump { to: to as u32 });
+    }
+
+    // let the jump at `at` land here
+    fn patch_jump(&mut self, at: usize) {
+        let here = self.fun.instrs.len();
+        if here > u32::MAX as usize {
+            return self.error("the code is too long to jump over".to_owned());
+        }
+        match &mut self.fun.instrs[at] {
+            Instr::Jump { to } | Instr::JumpIfFalse { to, .. } | Instr::JumpIfOk { to, .. } => {
+                *to = here as u32
+            }
+            Instr::ForIter { exit, .. } => *exit = here as u32,
+            _ => unreachable!(),
+        }
+    }
+}
+
+// the expr doesn't assign any variable
+fn is_simple(expr: &Expr) -> bool {
+    match &expr.node {
+        ExprKind::Literal { .. } => true,
+        ExprKind::Group { body } => is_simple(body),
+        ExprKind::Unary { operand, .. } => is_simple(operand),
+        ExprKind::Binary { left, right, .. } => is_simple(left) && is_simple(right),
+        ExprKind::Field { object, .. } | ExprKind::Item { object, .. } => is_simple(object),
+        _ => false,
+    }
+}
+
+// the expr writes its register once, after its operands are read
+fn is_direct(expr: &Expr) -> bool {
+    match &expr.node {
+        ExprKind::Literal { .. } | ExprKind::Unary { .. } => true,
+        ExprKind::Group { body } => is_direct(body),
+        ExprKind::Binary { op, .. } => !matches!(op, BinaryOp::And | BinaryOp::Or),
+        _ => false,
+    }
+}
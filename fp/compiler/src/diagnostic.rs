@@ -0,0 +1,64 @@
+use core::fmt;
+
+// something wrong but the code still can be compiled, e.g. a non-exhaustive match.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    message: String,
+}
+
+impl Warning {
+    pub(crate) fn new(message: String) -> Self {
+        Warning { message }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "warning: {}", self.message)
+    }
+}
+
+// the code can't be compiled, e.g. a syntax error or an undefined variable.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    message: String,
+    incomplete: bool,
+}
+
+impl Error {
+    pub(crate) fn new(message: String) -> Self {
+        Error {
+            message,
+            incomplete: false,
+        }
+    }
+
+    // the code ends too early, e.g. an unclosed `{`
+    pub(crate) fn incomplete(message: String) -> Self {
+        Error {
+            message,
+            incomplete: true,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    // more code may fix the error
+    pub fn is_incomplete(&self) -> bool {
+        self.incomplete
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error: {}", self.message)
+    }
+}
+
+impl std::error::Error for Error {}
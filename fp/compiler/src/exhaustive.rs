@@ -0,0 +1,258 @@
+// exhaustiveness and reachability checking for `match`.
+//
+// it's the "usefulness" algorithm from "Warnings for pattern matching" (Luc Maranget),
+// the same idea as rustc: a pattern is useful if it matches some value that none of the
+// previous rows match. a match is exhaustive if `_` is not useful after all the arms.
+//
+// fpig is dynamic, so a column is assumed to only hold the type of the constructors in it,
+// e.g. covering every variant of `Shape` is exhaustive even though an int could show up.
+
+use std::{collections::HashMap, rc::Rc};
+
+use vm::{
+    object::{EnumDef, StructDef},
+    string::Str,
+};
+
+use crate::ast::{MatchArm, ParseObj, Pattern};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ctor {
+    Variant(Rc<EnumDef>, u16),
+    Struct(Rc<StructDef>),
+    Tuple(usize),
+    Bool(bool),
+}
+
+impl Ctor {
+    fn arity(&self) -> usize {
+        match self {
+            Ctor::Variant(def, tag) => def.variants[*tag as usize].arity as usize,
+            Ctor::Struct(def) => def.fields.len(),
+            Ctor::Tuple(n) => *n,
+            Ctor::Bool(_) => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Pat {
+    Wild,
+    Ctor(Ctor, Vec<Pat>),
+    // literals, ranges and lists, there are always values they don't cover
+    Opaque,
+}
+
+pub(crate) struct Checker<'a> {
+    enums: &'a HashMap<Str, Rc<EnumDef>>,
+    structs: &'a HashMap<Str, Rc<StructDef>>,
+}
+
+impl<'a> Checker<'a> {
+    pub(crate) fn new(
+        enums: &'a HashMap<Str, Rc<EnumDef>>,
+        structs: &'a HashMap<Str, Rc<StructDef>>,
+    ) -> Self {
+        Checker { enums, structs }
+    }
+
+    // returns the warning messages of the match
+    pub(crate) fn check(&self, arms: &[MatchArm]) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let mut rows = Vec::new();
+
+        for (i, arm) in arms.iter().enumerate() {
+            let pat = self.lower(&arm.pattern);
+            if useful(&rows, std::slice::from_ref(&pat)).is_none() {
+                warnings.push(format!("unreachable pattern in match arm {}", i + 1));
+            }
+            // a guarded arm may not match, so it covers nothing
+            if arm.guard.is_none() {
+                rows.push(vec![pat]);
+            }
+        }
+
+        if let Some(witness) = useful(&rows, &[Pat::Wild]) {
+            warnings.push(format!(
+                "non-exhaustive match: pattern `{}` not covered",
+                display(&witness[0])
+            ));
+        }
+
+        warnings
+    }
+
+    fn lower(&self, pattern: &Pattern) -> Pat {
+        match pattern {
+            Pattern::Wildcard | Pattern::Bind { sub: None, .. } => Pat::Wild,
+            Pattern::Bind { sub: Some(sub), .. } => self.lower(sub),
+            Pattern::Literal(ParseObj::Bool(b)) => Pat::Ctor(Ctor::Bool(*b), Vec::new()),
+            Pattern::Literal(_) | Pattern::Range { .. } | Pattern::List { .. } => Pat::Opaque,
+            Pattern::Tuple(items) => Pat::Ctor(
+                Ctor::Tuple(items.len()),
+                items.iter().map(|p| self.lower(p)).collect(),
+            ),
+            Pattern::Struct { name, fields } => {
+                let def = match self.structs.get(name) {
+                    Some(def) => def.clone(),
+                    None => return Pat::Opaque,
+                };
+                let mut args = vec![Pat::Wild; def.fields.len()];
+                for (field, pattern) in fields {
+                    if let Some(i) = def.field(field) {
+                        args[i] = self.lower(pattern);
+                    }
+                }
+                Pat::Ctor(Ctor::Struct(def), args)
+            }
+            Pattern::Variant {
+                enum_name,
+                variant,
+                fields,
+            } => {
+                let def = match self.enums.get(enum_name) {
+                    Some(def) => def.clone(),
+                    None => return Pat::Opaque,
+                };
+                let tag = match def.variant(variant) {
+                    Some((tag, v)) if v.arity as usize == fields.len() => tag,
+                    _ => return Pat::Opaque,
+                };
+                let args = fields.iter().map(|p| self.lower(p)).collect();
+                Pat::Ctor(Ctor::Variant(def, tag), args)
+            }
+        }
+    }
+}
+
+// returns a witness (the values `q` matches but `rows` don't) when `q` is useful
+fn useful(rows: &[Vec<Pat>], q: &[Pat]) -> Option<Vec<Pat>> {
+    let (head, rest) = match q.split_first() {
+        Some(split) => split,
+        None if rows.is_empty() => return Some(Vec::new()),
+        None => return None,
+    };
+
+    match head {
+        Pat::Ctor(ctor, args) => {
+            let q = [args.as_slice(), rest].concat();
+            let witness = useful(&specialize(rows, ctor), &q)?;
+            Some(rebuild(ctor, witness))
+        }
+        Pat::Opaque => {
+            let witness = useful(&default(rows), rest)?;
+            Some([vec![Pat::Wild], witness].concat())
+        }
+        Pat::Wild => {
+            let used = head_ctors(rows);
+            match complete(&used) {
+                Some(all) => all.into_iter().find_map(|ctor| {
+                    let q = [vec![Pat::Wild; ctor.arity()], rest.to_vec()].concat();
+                    let witness = useful(&specialize(rows, &ctor), &q)?;
+                    Some(rebuild(&ctor, witness))
+                }),
+                None => {
+                    let witness = useful(&default(rows), rest)?;
+                    let head = match missing(&used) {
+                        Some(ctor) => {
+                            let args = vec![Pat::Wild; ctor.arity()];
+                            Pat::Ctor(ctor, args)
+                        }
+                        None => Pat::Wild,
+                    };
+                    Some([vec![head], witness].concat())
+                }
+            }
+        }
+    }
+}
+
+// the rows which match `ctor`, with the head replaced by its arguments
+fn specialize(rows: &[Vec<Pat>], ctor: &Ctor) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter_map(|row| match &row[0] {
+            Pat::Ctor(c, args) if c == ctor => Some([args.as_slice(), &row[1..]].concat()),
+            Pat::Wild => Some([vec![Pat::Wild; ctor.arity()], row[1..].to_vec()].concat()),
+            _ => None,
+        })
+        .collect()
+}
+
+// the rows which match anything in the head
+fn default(rows: &[Vec<Pat>]) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter(|row| matches!(row[0], Pat::Wild))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+fn rebuild(ctor: &Ctor, mut witness: Vec<Pat>) -> Vec<Pat> {
+    let rest = witness.split_off(ctor.arity());
+    [vec![Pat::Ctor(ctor.clone(), witness)], rest].concat()
+}
+
+fn head_ctors(rows: &[Vec<Pat>]) -> Vec<Ctor> {
+    let mut ctors: Vec<Ctor> = Vec::new();
+    for row in rows {
+        if let Pat::Ctor(c, _) = &row[0] {
+            if !ctors.contains(c) {
+                ctors.push(c.clone());
+            }
+        }
+    }
+    ctors
+}
+
+// all the constructors of the type when `used` covers all of them
+fn complete(used: &[Ctor]) -> Option<Vec<Ctor>> {
+    let all = all_ctors(used)?;
+    if all.iter().all(|c| used.contains(c)) {
+        Some(all)
+    } else {
+        None
+    }
+}
+
+fn missing(used: &[Ctor]) -> Option<Ctor> {
+    all_ctors(used)?.into_iter().find(|c| !used.contains(c))
+}
+
+// all the constructors of the type of `used`, none if `used` has mixed types
+fn all_ctors(used: &[Ctor]) -> Option<Vec<Ctor>> {
+    let all = match used.first()? {
+        Ctor::Variant(def, _) => (0..def.variants.len() as u16)
+            .map(|tag| Ctor::Variant(def.clone(), tag))
+            .collect(),
+        Ctor::Bool(_) => vec![Ctor::Bool(true), Ctor::Bool(false)],
+        c => vec![c.clone()],
+    };
+    let same_type = |c: &Ctor| match c {
+        Ctor::Bool(_) => all.contains(&Ctor::Bool(true)),
+        c => all.contains(c),
+    };
+    if used.iter().all(same_type) {
+        Some(all)
+    } else {
+        None
+    }
+}
+
+fn display(pat: &Pat) -> String {
+    let list = |args: &[Pat]| args.iter().map(display).collect::<Vec<_>>().join(", ");
+
+    match pat {
+        Pat::Wild | Pat::Opaque => "_".to_owned(),
+        Pat::Ctor(Ctor::Bool(b), _) => b.to_string(),
+        Pat::Ctor(Ctor::Tuple(1), args) => format!("({},)", list(args)),
+        Pat::Ctor(Ctor::Tuple(_), args) => format!("({})", list(args)),
+        Pat::Ctor(Ctor::Struct(def), _) => format!("{} {{ .. }}", def.name),
+        Pat::Ctor(Ctor::Variant(def, tag), args) => {
+            let variant = &def.variants[*tag as usize].name;
+            if args.is_empty() {
+                format!("{}::{}", def.name, variant)
+            } else {
+                format!("{}::{}({})", def.name, variant, list(args))
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::ops::Range;
+
+use crate::{lexer::Cursor, token::TokenKind};
+
+// how a token looks, e.g. in the REPL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Keyword,
+    // `true`, `false` and `nil`
+    Literal,
+    Number,
+    Str,
+    Ident,
+    Label,
+    Punct,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    // bytes of the code
+    pub range: Range<usize>,
+    pub style: Style,
+}
+
+// the tokens of the code, the spaces between them aren't included
+pub fn spans(code: &str) -> Vec<Span> {
+    let mut cursor = Cursor::new(code);
+    let mut spans = Vec::new();
+    loop {
+        let (token, range) = cursor.advance_token_span();
+        let style = match token.kind() {
+            TokenKind::Eof => break,
+            TokenKind::True | TokenKind::False | TokenKind::Nil => Style::Literal,
+            TokenKind::Int { .. } | TokenKind::Float { .. } => Style::Number,
+            TokenKind::Str { .. } => Style::Str,
+            TokenKind::Ident { .. } => Style::Ident,
+            TokenKind::Label { .. } => Style::Label,
+            TokenKind::Error { .. } => Style::Error,
+            kind if is_keyword(kind) => Style::Keyword,
+            _ => Style::Punct,
+        };
+        spans.push(Span { range, style });
+    }
+    spans
+}
+
+fn is_keyword(kind: &TokenKind) -> bool {
+    use TokenKind::*;
+
+    matches!(
+        kind,
+        Let | If
+            | Else
+            | For
+            | In
+            | While
+            | Loop
+            | Break
+            | Continue
+            | Fun
+            | Return
+            | Yield
+            | Try
+            | Catch
+            | Finally
+            | Throw
+            | Match
+            | Enum
+            | Struct
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{spans, Style};
+
+    #[test]
+    fn test_spans() {
+        let code = "let a = \"b\" + 1.5 'x: nil $";
+        let spans: Vec<_> = spans(code)
+            .into_iter()
+            .map(|span| (&code[span.range], span.style))
+            .collect();
+        let expect = [
+            ("let", Style::Keyword),
+            ("a", Style::Ident),
+            ("=", Style::Punct),
+            ("\"b\"", Style::Str),
+            ("+", Style::Punct),
+            ("1.5", Style::Number),
+            ("'x", Style::Label),
+            (":", Style::Punct),
+            ("nil", Style::Literal),
+            ("$", Style::Error),
+        ];
+        assert_eq!(spans, expect);
+    }
+}
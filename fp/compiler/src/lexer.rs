@@ -1,10 +1,14 @@
 use crate::token::{LexError, Token, TokenKind};
-use std::str::Chars;
+use std::{ops::Range, str::Chars};
+
+use vm::string::Str;
 
 pub(crate) const EOF_CHAR: char = '\0';
 
 pub(crate) struct Cursor<'a> {
     chars: Chars<'a>,
+    // the length of the input in bytes
+    len: usize,
 }
 
 // keyword and built-in value and so on
@@ -13,9 +17,21 @@ const PREDEFINED: &[(&str, TokenKind)] = &[
     ("if", TokenKind::If),
     ("else", TokenKind::Else),
     ("for", TokenKind::For),
+    ("in", TokenKind::In),
     ("while", TokenKind::While),
+    ("loop", TokenKind::Loop),
+    ("break", TokenKind::Break),
+    ("continue", TokenKind::Continue),
     ("fn", TokenKind::Fun),
     ("return", TokenKind::Return),
+    ("yield", TokenKind::Yield),
+    ("try", TokenKind::Try),
+    ("catch", TokenKind::Catch),
+    ("finally", TokenKind::Finally),
+    ("throw", TokenKind::Throw),
+    ("match", TokenKind::Match),
+    ("enum", TokenKind::Enum),
+    ("struct", TokenKind::Struct),
     ("true", TokenKind::True),
     ("false", TokenKind::False),
     ("nil", TokenKind::Nil),
@@ -51,6 +67,10 @@ fn is_whitespace(c: char) -> bool {
     )
 }
 
+pub(crate) fn keywords() -> impl Iterator<Item = &'static str> {
+    PREDEFINED.iter().map(|(s, _)| *s)
+}
+
 // identifier start. same as rustc
 fn is_ident_start(c: char) -> bool {
     c == '_' || unicode_xid::UnicodeXID::is_xid_start(c)
@@ -66,9 +86,15 @@ impl<'a> Cursor<'a> {
     pub(crate) fn new(input: &'a str) -> Cursor<'a> {
         Cursor {
             chars: input.chars(),
+            len: input.len(),
         }
     }
 
+    // the byte offset of the next char in the input
+    pub(crate) fn offset(&self) -> usize {
+        self.len - self.chars.as_str().len()
+    }
+
     fn first(&self) -> char {
         self.chars.clone().next().unwrap_or(EOF_CHAR)
     }
@@ -113,14 +139,33 @@ impl Cursor<'_> {
             '*' => TokenKind::Star,
             '/' => TokenKind::Slash,
             ',' => TokenKind::Comma,
-            '.' => TokenKind::Dot,
             ';' => TokenKind::Semi,
+            '@' => TokenKind::At,
+            '#' => TokenKind::Hash,
+            '?' => TokenKind::Question,
             '(' => TokenKind::OpenParen,
             ')' => TokenKind::CloseParen,
             '{' => TokenKind::OpenBrace,
             '}' => TokenKind::CloseBrace,
+            '[' => TokenKind::OpenBracket,
+            ']' => TokenKind::CloseBracket,
 
-            // one or two symbol tokens
+            // one or more symbol tokens
+            '.' if self.first() == '.' => {
+                self.bump();
+                if self.first() == '=' {
+                    self.bump();
+                    TokenKind::DotDotEq
+                } else {
+                    TokenKind::DotDot
+                }
+            }
+            '.' => TokenKind::Dot,
+            ':' if self.first() == ':' => {
+                self.bump();
+                TokenKind::ColonColon
+            }
+            ':' => TokenKind::Colon,
             '!' if self.first() == '=' => {
                 self.bump();
                 TokenKind::BangEq
@@ -130,6 +175,10 @@ impl Cursor<'_> {
                 self.bump();
                 TokenKind::EqEq
             }
+            '=' if self.first() == '>' => {
+                self.bump();
+                TokenKind::FatArrow
+            }
             '=' => TokenKind::Eq,
             '>' if self.first() == '=' => {
                 self.bump();
@@ -155,6 +204,17 @@ impl Cursor<'_> {
             // identifier or predefined (e.g. let, if, else, for...)
             c if is_ident_start(c) => self.ident_or_predefined(c),
 
+            // label of loops, like 'outer
+            '\'' if is_ident_start(self.first()) => {
+                let start = self.bump();
+                match self.ident_or_predefined(start) {
+                    TokenKind::Ident { name } => TokenKind::Label { name },
+                    _ => TokenKind::Error {
+                        kind: LexError::UnknownChar('\''),
+                    },
+                }
+            }
+
             // string
             '"' => self.string(),
 
@@ -173,6 +233,14 @@ impl Cursor<'_> {
         Token::new(token_kind)
     }
 
+    // the same as `advance_token`, and the byte range of the token in the input
+    pub(crate) fn advance_token_span(&mut self) -> (Token, Range<usize>) {
+        self.skip_space();
+        let start = self.offset();
+        let token = self.advance_token();
+        (token, start..self.offset())
+    }
+
     // numbers, like 123, 123.4
     // NOTE: 01 is same as 1, but .1 or 1. should NOT be treated as number,
     // see tests::test_literal_number for more information.
@@ -181,15 +249,15 @@ impl Cursor<'_> {
         lexeme.push(start);
 
         // the part of integer
-        while matches!(self.first(), '0'..='9') {
+        while self.first().is_ascii_digit() {
             lexeme.push(self.bump());
         }
 
         // the part of decimal
-        if self.first() == '.' && matches!(self.second(), '0'..='9') {
+        if self.first() == '.' && self.second().is_ascii_digit() {
             lexeme.push(self.bump());
 
-            while matches!(self.first(), '0'..='9') {
+            while self.first().is_ascii_digit() {
                 lexeme.push(self.bump());
             }
 
@@ -201,15 +269,15 @@ impl Cursor<'_> {
         TokenKind::Int { value }
     }
 
-    // normal string
+    // normal string, it can take more than one line
     fn string(&mut self) -> TokenKind {
         let mut lexeme = String::with_capacity(8);
-        while !matches!(self.first(), EOF_CHAR | '"' | '\n') {
+        while !matches!(self.first(), EOF_CHAR | '"') {
             lexeme.push(self.bump());
         }
 
         // the " is not close
-        if matches!(self.first(), EOF_CHAR | '\n') {
+        if self.first() == EOF_CHAR {
             return TokenKind::Error {
                 kind: LexError::NotClose('"'),
             };
@@ -217,7 +285,9 @@ impl Cursor<'_> {
 
         // eat the close "
         self.bump();
-        TokenKind::Str { value: lexeme }
+        TokenKind::Str {
+            value: Str::intern(&lexeme),
+        }
     }
 
     // custom identifier or predefined (e.g. let, if, true...)
@@ -234,7 +304,9 @@ impl Cursor<'_> {
             return kind.clone();
         }
 
-        TokenKind::Ident { name: lexeme }
+        TokenKind::Ident {
+            name: Str::intern(&lexeme),
+        }
     }
 
     fn skip_space(&mut self) {
@@ -249,10 +321,7 @@ mod tests {
     macro_rules! tokens {
         ($($kind: expr),+ $(,)?) => {
             {
-                let mut tokens = Vec::new();
-                $(
-                    tokens.push(Token::new($kind));
-                ) *
+                let tokens = vec![$(Token::new($kind)),+];
                 tokens.into_iter()
             }
         };
@@ -287,6 +356,31 @@ mod tests {
         assert!(tokenize_nonloc(input).eq(expect));
     }
 
+    #[test]
+    fn test_brackets_and_punctuation() {
+        use TokenKind::*;
+        let input = "[ ] @ # ? : :: => .. ..= 0..10 1..=2";
+        let expect = tokens![
+            OpenBracket,
+            CloseBracket,
+            At,
+            Hash,
+            Question,
+            Colon,
+            ColonColon,
+            FatArrow,
+            DotDot,
+            DotDotEq,
+            Int { value: 0 },
+            DotDot,
+            Int { value: 10 },
+            Int { value: 1 },
+            DotDotEq,
+            Int { value: 2 },
+        ];
+        assert!(tokenize_nonloc(input).eq(expect));
+    }
+
     #[test]
     fn test_one_or_two_chars() {
         use TokenKind::*;
@@ -310,7 +404,7 @@ mod tests {
     fn test_literal_str() {
         let input = "\"abc\"";
         let expect = tokens!(TokenKind::Str {
-            value: "abc".to_string()
+            value: Str::intern("abc")
         });
         assert!(tokenize_nonloc(input).eq(expect));
     }
@@ -349,10 +443,10 @@ mod tests {
         let input = "this_is_an_identifier 自定义的标识";
         let expect = tokens![
             Ident {
-                name: "this_is_an_identifier".to_string()
+                name: Str::intern("this_is_an_identifier")
             },
             Ident {
-                name: "自定义的标识".to_string()
+                name: Str::intern("自定义的标识")
             },
         ];
         assert!(tokenize_nonloc(input).eq(expect));
@@ -362,8 +456,36 @@ mod tests {
     fn test_keywords() {
         use TokenKind::*;
 
-        let input = "let if else for while fn return";
-        let expect = tokens![Let, If, Else, For, While, Fun, Return,];
+        let input =
+            "let if else for in while loop break continue fn return yield try catch finally throw match enum struct";
+        let expect = tokens![
+            Let, If, Else, For, In, While, Loop, Break, Continue, Fun, Return, Yield, Try, Catch,
+            Finally, Throw, Match, Enum, Struct,
+        ];
+        assert!(tokenize_nonloc(input).eq(expect));
+    }
+
+    #[test]
+    fn test_label() {
+        use TokenKind::*;
+
+        let input = "'outer: break 'outer ' 'loop";
+        let expect = tokens![
+            Label {
+                name: vm::string::Str::intern("outer")
+            },
+            Colon,
+            Break,
+            Label {
+                name: vm::string::Str::intern("outer")
+            },
+            Error {
+                kind: LexError::UnknownChar('\'')
+            },
+            Error {
+                kind: LexError::UnknownChar('\'')
+            },
+        ];
         assert!(tokenize_nonloc(input).eq(expect));
     }
 }
@@ -1,5 +1,8 @@
 mod ast;
 mod compiler;
+mod diagnostic;
+mod exhaustive;
+mod highlight;
 mod lexer;
 mod location;
 mod parser;
@@ -7,7 +10,28 @@ mod token;
 
 use lexer::Cursor;
 use parser::Parser;
-use vm::chunk::Chunk;
+use vm::{chunk::Chunk, string::Str, vm::Vm};
+
+pub use compiler::Backend;
+pub use diagnostic::{Error, Warning};
+pub use highlight::{spans, Span, Style};
+pub use parser::Input;
+
+// `let`, `if`, `true`... e.g. for completion in the REPL
+pub fn keywords() -> impl Iterator<Item = &'static str> {
+    lexer::keywords()
+}
+
+// if the code is a whole program, nothing is compiled
+pub fn check(raw_code: &str) -> Input {
+    Parser::new(Cursor::new(raw_code)).check_input()
+}
+
+// the syntax tree of the code, for debugging
+pub fn dump_ast(raw_code: &str) -> Result<String, Error> {
+    let ast = Parser::new(Cursor::new(raw_code)).parse()?;
+    Ok(format!("{:#?}", ast))
+}
 
 pub struct Compiler {
     compiler: compiler::Compiler,
@@ -20,12 +44,97 @@ impl Compiler {
         }
     }
 
-    pub fn compile(&mut self, raw_code: &str) -> Chunk {
+    // when it fails, nothing is changed, e.g. no global variable is declared
+    pub fn compile(&mut self, raw_code: &str) -> Result<Chunk, Vec<Error>> {
+        self.compile_chunk(raw_code, false)
+    }
+
+    // the same as `compile`, but the chunk returns the value of the code
+    // when the last statement is an expression, e.g. `let a = 1 a + 1`.
+    pub fn compile_value(&mut self, raw_code: &str) -> Result<Chunk, Vec<Error>> {
+        self.compile_chunk(raw_code, true)
+    }
+
+    // the same as `compile_value`, but nothing is changed even when it succeeds,
+    // e.g. to look at the bytecode of the code.
+    pub fn compile_dry(&mut self, raw_code: &str) -> Result<Chunk, Vec<Error>> {
+        let cursor = Cursor::new(raw_code);
+        let mut parser = Parser::new(cursor);
+        let ast = parser.parse().map_err(|e| vec![e])?;
+        self.compiler.compile_dry(ast, true)
+    }
+
+    fn compile_chunk(&mut self, raw_code: &str, keep_value: bool) -> Result<Chunk, Vec<Error>> {
         let cursor = Cursor::new(raw_code);
         let mut parser = Parser::new(cursor);
-        let ast = parser.parse();
-        self.compiler.compile(*ast);
-        self.compiler.pop_chunk()
+        let ast = parser.parse().map_err(|e| vec![e])?;
+        self.compiler.compile(ast, keep_value)?;
+        Ok(self.compiler.pop_chunk())
+    }
+
+    // the code compiled from now on, the globals are shared by both backends
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.compiler.set_backend(backend)
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.compiler.backend()
+    }
+
+    // the peephole pass over the bytecode, on by default, see `vm::peephole`
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.compiler.set_optimize(optimize)
+    }
+
+    pub fn optimize(&self) -> bool {
+        self.compiler.optimize()
+    }
+
+    // declare the native functions of the vm as global variables, the ones already
+    // declared are skipped, e.g. a global variable with the same name.
+    pub fn link_natives(&mut self, vm: &mut Vm) {
+        let natives: Vec<_> = vm
+            .natives()
+            .filter(|(name, _)| self.compiler.global(name).is_none())
+            .map(|(name, value)| (Str::intern(name), value.clone()))
+            .collect();
+        for (name, value) in natives {
+            if let Some(i) = self.compiler.declare_global(name) {
+                vm.define_global(i, value);
+            }
+        }
+    }
+
+    // the index of a global variable, see `Vm::get_global`
+    pub fn global(&self, name: &str) -> Option<u16> {
+        self.compiler.global(name)
+    }
+
+    // the index of the global variable, a new one when it isn't declared.
+    // `None` when there are too many global variables.
+    pub fn declare_global(&mut self, name: &str) -> Option<u16> {
+        self.compiler.declare_global(Str::intern(name))
+    }
+
+    // the names and indexes of the global variables
+    pub fn globals(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.compiler.globals()
+    }
+
+    // the fields of every struct declared
+    pub fn struct_fields(&self) -> impl Iterator<Item = &str> {
+        self.compiler.struct_fields()
+    }
+
+    // forget the global variables `keep` returns false for, their indexes are never reused.
+    // e.g. the globals never set because the code failed when running.
+    pub fn retain_globals(&mut self, keep: impl FnMut(u16) -> bool) {
+        self.compiler.retain_globals(keep)
+    }
+
+    // warnings of the code compiled since the last call
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        self.compiler.take_warnings()
     }
 }
 
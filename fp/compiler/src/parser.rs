@@ -1,15 +1,32 @@
-use crate::ast::{ExprKind, ParseObj, Stmt, StmtKind};
+use vm::string::Str;
+
+use crate::ast::{ExprKind, MatchArm, ParseObj, Pattern, Stmt, StmtKind};
 
 use crate::{
     ast::{BinaryOp, Expr, UnaryOp},
+    diagnostic::Error,
     lexer::Cursor,
-    token::{Token, TokenKind},
+    token::{LexError, Token, TokenKind},
 };
 
+type ParseResult<T> = Result<T, Error>;
+
+// see `Parser::check_input`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    Complete,
+    // the code ends too early, e.g. `while x < 10 {` or `1 +`
+    Incomplete,
+    Error(Error),
+}
+
 pub(crate) struct Parser<'a> {
     cursor: Cursor<'a>,
     now: Token,
     next: Token,
+    // `Ident {` is not a struct literal in the test of `if`, `while` and `match`,
+    // the `{` starts the body there.
+    no_struct: bool,
 }
 
 impl Parser<'_> {
@@ -18,115 +35,569 @@ impl Parser<'_> {
             cursor,
             now: Token::default(),
             next: Token::default(),
+            no_struct: false,
         }
     }
 
-    pub(crate) fn parse(&mut self) -> Box<Stmt> {
+    // stop at the first syntax error
+    pub(crate) fn parse(&mut self) -> ParseResult<Vec<Stmt>> {
         self.eat();
-        self.declaration()
+        let mut program = Vec::new();
+        while !self.check(&[TokenKind::Eof]) {
+            program.push(*self.declaration()?);
+        }
+        Ok(program)
+    }
+
+    // if the code is a whole program, the REPL waits for more lines when it is incomplete
+    pub(crate) fn check_input(&mut self) -> Input {
+        match self.parse() {
+            Ok(_) => Input::Complete,
+            Err(e) if e.is_incomplete() => Input::Incomplete,
+            Err(e) => Input::Error(e),
+        }
     }
 
     // variable declaration, function declaration...
-    fn declaration(&mut self) -> Box<Stmt> {
-        match self.peek().kind() {
+    fn declaration(&mut self) -> ParseResult<Box<Stmt>> {
+        let stmt = match self.peek().kind() {
             TokenKind::Let => {
                 self.eat(); // eat the Token Let
-                self.var_declaration()
+                self.var_declaration()?
             }
-            _ => self.statement(),
-        }
+            TokenKind::Enum => {
+                self.eat(); // eat the enum
+                self.enum_declaration()?
+            }
+            TokenKind::Struct => {
+                self.eat(); // eat the struct
+                self.struct_declaration()?
+            }
+            TokenKind::Fun => {
+                self.eat(); // eat the fn
+                self.fn_declaration()?
+            }
+            _ => self.statement()?,
+        };
+        // `;` is optional between statements
+        while self.check_eat(&[TokenKind::Semi]) {}
+        Ok(stmt)
     }
 
-    fn var_declaration(&mut self) -> Box<Stmt> {
-        // TODO: remove clone()
-        match self.peek().kind().clone() {
-            TokenKind::Ident { name } => {
-                self.eat();
-                if self.check_eat(&[TokenKind::Eq]) {
-                    let expr = self.expression();
-                    return Box::new(Stmt::new(StmtKind::VarDec { name, value: expr }));
-                }
+    fn var_declaration(&mut self) -> ParseResult<Box<Stmt>> {
+        let name = self.ident()?;
+        if self.check_eat(&[TokenKind::Eq]) {
+            let expr = self.expression()?;
+            return Ok(Box::new(Stmt::new(StmtKind::VarDec { name, value: expr })));
+        }
 
-                let expr = Box::new(Expr::new(ExprKind::Literal {
-                    value: ParseObj::Nil,
-                }));
-                Box::new(Stmt::new(StmtKind::VarDec { name, value: expr }))
+        let expr = Box::new(Expr::new(ExprKind::Literal {
+            value: ParseObj::Nil,
+        }));
+        Ok(Box::new(Stmt::new(StmtKind::VarDec { name, value: expr })))
+    }
+
+    // enum Shape { Circle(r), Rect(w, h), Empty }
+    fn enum_declaration(&mut self) -> ParseResult<Box<Stmt>> {
+        let name = self.ident()?;
+        self.expect(TokenKind::OpenBrace)?;
+        let mut variants = Vec::new();
+        while !self.check(&[TokenKind::CloseBrace, TokenKind::Eof]) {
+            let variant = self.ident()?;
+            let mut fields = Vec::new();
+            if self.check_eat(&[TokenKind::OpenParen]) {
+                fields = self.comma_list(TokenKind::CloseParen, |p| p.ident())?;
+            }
+            variants.push((variant, fields));
+            if !self.check_eat(&[TokenKind::Comma]) {
+                break;
             }
-            _ => todo!(),
         }
+        self.expect(TokenKind::CloseBrace)?;
+        Ok(Box::new(Stmt::new(StmtKind::EnumDec { name, variants })))
+    }
+
+    // struct Point { x, y }
+    fn struct_declaration(&mut self) -> ParseResult<Box<Stmt>> {
+        let name = self.ident()?;
+        self.expect(TokenKind::OpenBrace)?;
+        let fields = self.comma_list(TokenKind::CloseBrace, |p| p.ident())?;
+        Ok(Box::new(Stmt::new(StmtKind::StructDec { name, fields })))
+    }
+
+    // fn add(a, b) { a + b }
+    fn fn_declaration(&mut self) -> ParseResult<Box<Stmt>> {
+        let generator = self.check_eat(&[TokenKind::Star]);
+        let name = self.ident()?;
+        self.expect(TokenKind::OpenParen)?;
+        let params = self.comma_list(TokenKind::CloseParen, |p| p.ident())?;
+        self.expect(TokenKind::OpenBrace)?;
+        let body = self.block_body()?;
+        Ok(Box::new(Stmt::new(StmtKind::FnDec {
+            name,
+            params,
+            body,
+            generator,
+        })))
     }
 
-    fn statement(&mut self) -> Box<Stmt> {
-        match self.peek().kind() {
+    fn statement(&mut self) -> ParseResult<Box<Stmt>> {
+        let label = self.label()?;
+        let stmt = match self.peek().kind() {
             TokenKind::While => {
                 self.eat(); // eat the while
-                self.while_stmt()
+                self.while_stmt(label)?
             }
+            TokenKind::For => {
+                self.eat(); // eat the for
+                self.for_stmt(label)?
+            }
+            TokenKind::Loop => {
+                self.eat(); // eat the loop
+                Box::new(Stmt::new(StmtKind::ExprStmt {
+                    expr: self.loop_expr(label)?,
+                }))
+            }
+            _ if label.is_some() => return Err(self.error("a loop after the label")),
             _ => Box::new(Stmt::new(StmtKind::ExprStmt {
-                expr: self.expression(),
+                expr: self.expression()?,
             })),
+        };
+        Ok(stmt)
+    }
+
+    // `'label:` before a loop
+    fn label(&mut self) -> ParseResult<Option<Str>> {
+        match self.peek().kind().clone() {
+            TokenKind::Label { name } => {
+                self.eat();
+                self.expect(TokenKind::Colon)?;
+                Ok(Some(name))
+            }
+            _ => Ok(None),
         }
     }
 
-    fn while_stmt(&mut self) -> Box<Stmt> {
-        let test = self.expression();
-        let body = self.block_body();
-        Box::new(Stmt::new(StmtKind::While { test, body }))
+    fn while_stmt(&mut self, label: Option<Str>) -> ParseResult<Box<Stmt>> {
+        let test = self.test_expression()?;
+        self.expect(TokenKind::OpenBrace)?;
+        let body = self.block_body()?;
+        Ok(Box::new(Stmt::new(StmtKind::While { label, test, body })))
     }
 
-    fn expression(&mut self) -> Box<Expr> {
-        match self.peek().kind().clone() {
+    // for pattern in iter { body }
+    fn for_stmt(&mut self, label: Option<Str>) -> ParseResult<Box<Stmt>> {
+        let pattern = self.pattern()?;
+        self.expect(TokenKind::In)?;
+        let iter = self.test_expression()?;
+        self.expect(TokenKind::OpenBrace)?;
+        let body = self.block_body()?;
+        Ok(Box::new(Stmt::new(StmtKind::For {
+            label,
+            pattern,
+            iter,
+            body,
+        })))
+    }
+
+    fn expression(&mut self) -> ParseResult<Box<Expr>> {
+        let expr = match self.peek().kind().clone() {
             TokenKind::OpenBrace => {
                 self.eat(); // eat the {
-                let inner = self.block_body();
+                let inner = self.block_body()?;
                 Box::new(Expr::new(ExprKind::Block { inner }))
             }
             TokenKind::If => {
                 self.eat(); // eat the if
-                self.if_expr()
+                self.if_expr()?
+            }
+            TokenKind::Match => {
+                self.eat(); // eat the match
+                self.match_expr()?
+            }
+            TokenKind::Try => {
+                self.eat(); // eat the try
+                self.try_expr()?
+            }
+            TokenKind::Throw => {
+                self.eat(); // eat the throw
+                let value = self.expression()?;
+                Box::new(Expr::new(ExprKind::Throw { value }))
+            }
+            TokenKind::Label { .. } => {
+                let label = self.label()?;
+                self.expect(TokenKind::Loop)?;
+                self.loop_expr(label)?
+            }
+            TokenKind::Loop => {
+                self.eat(); // eat the loop
+                self.loop_expr(None)?
+            }
+            TokenKind::Break => {
+                self.eat(); // eat the break
+                let label = self.label_ref();
+                let mut value = None;
+                if self.starts_expr() {
+                    value = Some(self.expression()?);
+                }
+                Box::new(Expr::new(ExprKind::Break { label, value }))
+            }
+            TokenKind::Continue => {
+                self.eat(); // eat the continue
+                let label = self.label_ref();
+                Box::new(Expr::new(ExprKind::Continue { label }))
+            }
+            TokenKind::Return => {
+                self.eat(); // eat the return
+                let mut value = None;
+                if self.starts_expr() {
+                    value = Some(self.expression()?);
+                }
+                Box::new(Expr::new(ExprKind::Return { value }))
             }
-            _ => self.expr_and(),
+            TokenKind::Yield => {
+                self.eat(); // eat the yield
+                let mut value = None;
+                if self.starts_expr() {
+                    value = Some(self.expression()?);
+                }
+                Box::new(Expr::new(ExprKind::Yield { value }))
+            }
+            _ => self.assignment()?,
+        };
+        Ok(expr)
+    }
+
+    fn loop_expr(&mut self, label: Option<Str>) -> ParseResult<Box<Expr>> {
+        self.expect(TokenKind::OpenBrace)?;
+        let body = self.block_body()?;
+        Ok(Box::new(Expr::new(ExprKind::Loop { label, body })))
+    }
+
+    // the `'label` after `break` or `continue`
+    fn label_ref(&mut self) -> Option<Str> {
+        match self.peek().kind().clone() {
+            TokenKind::Label { name } => {
+                self.eat();
+                Some(name)
+            }
+            _ => None,
+        }
+    }
+
+    // if the next token can start an expression, e.g. the value after `break`
+    fn starts_expr(&self) -> bool {
+        use TokenKind::*;
+
+        matches!(
+            self.peek().kind(),
+            Minus
+                | Bang
+                | Hash
+                | OpenParen
+                | OpenBrace
+                | OpenBracket
+                | Ident { .. }
+                | Label { .. }
+                | Str { .. }
+                | Int { .. }
+                | Float { .. }
+                | True
+                | False
+                | Nil
+                | If
+                | Match
+                | Loop
+                | Break
+                | Continue
+                | Return
+                | Yield
+                | Try
+                | Throw
+        )
+    }
+
+    fn assignment(&mut self) -> ParseResult<Box<Expr>> {
+        let target = self.expr_range()?;
+
+        if !self.check_eat(&[TokenKind::Eq]) {
+            return Ok(target);
         }
+        if !matches!(
+            target.node,
+            ExprKind::Literal {
+                value: ParseObj::Ident(_)
+            } | ExprKind::Field { .. }
+                | ExprKind::Index { .. }
+        ) {
+            return Err(Error::new(
+                "only variables, fields and indexes can be assigned".to_owned(),
+            ));
+        }
+        let value = self.expression()?;
+        Ok(Box::new(Expr::new(ExprKind::Assign { target, value })))
     }
 
-    fn block_body(&mut self) -> Vec<Stmt> {
+    // `start..end`, `start..=end`, `start..end step n`
+    fn expr_range(&mut self) -> ParseResult<Box<Expr>> {
+        let start = self.expr_and()?;
+
+        if !self.check_eat(&[TokenKind::DotDot, TokenKind::DotDotEq]) {
+            return Ok(start);
+        }
+        let inclusive = self.now.kind() == &TokenKind::DotDotEq;
+        let end = self.expr_and()?;
+        let mut step = None;
+        if matches!(self.peek().kind(), TokenKind::Ident { name } if name == "step") {
+            self.eat();
+            step = Some(self.expr_and()?);
+        }
+        Ok(Box::new(Expr::new(ExprKind::Range {
+            start,
+            end,
+            step,
+            inclusive,
+        })))
+    }
+
+    // the expression before a `{` body
+    fn test_expression(&mut self) -> ParseResult<Box<Expr>> {
+        let no_struct = std::mem::replace(&mut self.no_struct, true);
+        let expr = self.expression()?;
+        self.no_struct = no_struct;
+        Ok(expr)
+    }
+
+    fn block_body(&mut self) -> ParseResult<Vec<Stmt>> {
+        let no_struct = std::mem::replace(&mut self.no_struct, false);
         let mut inner = Vec::new();
         while !self.check(&[TokenKind::CloseBrace, TokenKind::Eof]) {
-            inner.push(*self.declaration());
+            inner.push(*self.declaration()?);
         }
+        self.no_struct = no_struct;
 
-        if !self.check_eat(&[TokenKind::CloseBrace]) {
-            todo!()
-        }
+        self.expect(TokenKind::CloseBrace)?;
 
-        inner
+        Ok(inner)
     }
 
-    fn if_expr(&mut self) -> Box<Expr> {
-        let test = self.expression();
-        if !self.check_eat(&[TokenKind::OpenBrace]) {
-            todo!()
+    fn try_expr(&mut self) -> ParseResult<Box<Expr>> {
+        self.expect(TokenKind::OpenBrace)?;
+        let body = self.block_body()?;
+        let mut catch = None;
+        if self.check_eat(&[TokenKind::Catch]) {
+            let name = match self.peek().kind() {
+                TokenKind::Ident { .. } => Some(self.ident()?),
+                _ => None,
+            };
+            self.expect(TokenKind::OpenBrace)?;
+            catch = Some((name, self.block_body()?));
+        }
+        let mut finally = None;
+        if self.check_eat(&[TokenKind::Finally]) {
+            self.expect(TokenKind::OpenBrace)?;
+            finally = Some(self.block_body()?);
         }
-        let body = self.block_body();
+        if catch.is_none() && finally.is_none() {
+            return Err(self.error("`catch` or `finally`"));
+        }
+        Ok(Box::new(Expr::new(ExprKind::Try {
+            body,
+            catch,
+            finally,
+        })))
+    }
+
+    fn if_expr(&mut self) -> ParseResult<Box<Expr>> {
+        let test = self.test_expression()?;
+        self.expect(TokenKind::OpenBrace)?;
+        let body = self.block_body()?;
 
         // else
         if !self.check_eat(&[TokenKind::Else]) {
             let orelse = Vec::new();
-            return Box::new(Expr::new(ExprKind::If { test, body, orelse }));
+            return Ok(Box::new(Expr::new(ExprKind::If { test, body, orelse })));
         }
-        if !self.check_eat(&[TokenKind::OpenBrace]) {
-            todo!()
+        self.expect(TokenKind::OpenBrace)?;
+        let orelse = self.block_body()?;
+
+        Ok(Box::new(Expr::new(ExprKind::If { test, body, orelse })))
+    }
+
+    // match subject {
+    //     pattern if guard => expr,
+    // }
+    fn match_expr(&mut self) -> ParseResult<Box<Expr>> {
+        let subject = self.test_expression()?;
+        self.expect(TokenKind::OpenBrace)?;
+        let mut arms = Vec::new();
+        while !self.check(&[TokenKind::CloseBrace, TokenKind::Eof]) {
+            let pattern = self.pattern()?;
+            let mut guard = None;
+            if self.check_eat(&[TokenKind::If]) {
+                guard = Some(self.expression()?);
+            }
+            self.expect(TokenKind::FatArrow)?;
+            let body = self.expression()?;
+            arms.push(MatchArm {
+                pattern,
+                guard,
+                body,
+            });
+            self.check_eat(&[TokenKind::Comma]);
         }
-        let orelse = self.block_body();
+        self.expect(TokenKind::CloseBrace)?;
+        Ok(Box::new(Expr::new(ExprKind::Match { subject, arms })))
+    }
 
-        Box::new(Expr::new(ExprKind::If { test, body, orelse }))
+    fn pattern(&mut self) -> ParseResult<Pattern> {
+        use TokenKind::*;
+
+        let pattern = match self.peek().kind().clone() {
+            Ident { name } if name == "_" => {
+                self.eat();
+                Pattern::Wildcard
+            }
+            Ident { name } => {
+                self.eat();
+                // `Some(x)` is short for `Option::Some(x)`
+                let path = if self.check_eat(&[ColonColon]) {
+                    Some((name.clone(), self.ident()?))
+                } else {
+                    prelude_enum(&name).map(|enum_name| (enum_name, name.clone()))
+                };
+                if let Some((enum_name, variant)) = path {
+                    let mut fields = Vec::new();
+                    if self.check_eat(&[OpenParen]) {
+                        fields = self.comma_list(CloseParen, |p| p.pattern())?;
+                    }
+                    return Ok(Pattern::Variant {
+                        enum_name,
+                        variant,
+                        fields,
+                    });
+                }
+                if self.check_eat(&[OpenBrace]) {
+                    return self.struct_pattern(name);
+                }
+                let mut sub = None;
+                if self.check_eat(&[At]) {
+                    sub = Some(Box::new(self.pattern()?));
+                }
+                Pattern::Bind { name, sub }
+            }
+            OpenParen => {
+                self.eat();
+                let mut items = Vec::new();
+                let mut trailing_comma = false;
+                while !self.check(&[CloseParen, Eof]) {
+                    items.push(self.pattern()?);
+                    trailing_comma = self.check_eat(&[Comma]);
+                    if !trailing_comma {
+                        break;
+                    }
+                }
+                self.expect(CloseParen)?;
+                // `(p)` is just p, `(p,)` is a tuple
+                if items.len() == 1 && !trailing_comma {
+                    return Ok(items.pop().unwrap());
+                }
+                Pattern::Tuple(items)
+            }
+            OpenBracket => {
+                self.eat();
+                let mut prefix = Vec::new();
+                let mut suffix = None;
+                while !self.check(&[CloseBracket, Eof]) {
+                    if self.check_eat(&[DotDot]) {
+                        if suffix.is_some() {
+                            return Err(crate::diagnostic::Error::new(
+                                "`..` can only be used once in a list pattern".to_owned(),
+                            ));
+                        }
+                        suffix = Some(Vec::new());
+                    } else if let Some(suffix) = &mut suffix {
+                        suffix.push(self.pattern()?);
+                    } else {
+                        prefix.push(self.pattern()?);
+                    }
+                    if !self.check_eat(&[Comma]) {
+                        break;
+                    }
+                }
+                self.expect(CloseBracket)?;
+                Pattern::List { prefix, suffix }
+            }
+            _ => {
+                let start = self.literal()?;
+                if self.check_eat(&[DotDot, DotDotEq]) {
+                    let inclusive = self.now.kind() == &DotDotEq;
+                    let end = self.literal()?;
+                    return Ok(Pattern::Range {
+                        start,
+                        end,
+                        inclusive,
+                    });
+                }
+                Pattern::Literal(start)
+            }
+        };
+        Ok(pattern)
     }
 
-    fn expr_and(&mut self) -> Box<Expr> {
-        let mut left = self.expr_or();
+    // `Point { x, y: 0, .. }`, the `{` is already eaten
+    fn struct_pattern(&mut self, name: Str) -> ParseResult<Pattern> {
+        use TokenKind::*;
+
+        let mut fields = Vec::new();
+        while !self.check(&[CloseBrace, Eof]) {
+            if self.check_eat(&[DotDot]) {
+                break;
+            }
+            let field = self.ident()?;
+            let pattern = if self.check_eat(&[Colon]) {
+                self.pattern()?
+            } else {
+                Pattern::Bind {
+                    name: field.clone(),
+                    sub: None,
+                }
+            };
+            fields.push((field, pattern));
+            if !self.check_eat(&[Comma]) {
+                break;
+            }
+        }
+        self.expect(CloseBrace)?;
+        Ok(Pattern::Struct { name, fields })
+    }
+
+    // literal in patterns
+    fn literal(&mut self) -> ParseResult<ParseObj> {
+        use TokenKind::*;
+
+        let negative = self.check_eat(&[Minus]);
+        let value = match self.peek().kind().clone() {
+            Int { value } if negative => ParseObj::Int(-value),
+            Float { value } if negative => ParseObj::Float(-value),
+            _ if negative => return Err(self.error("a number")),
+            Int { value } => ParseObj::Int(value),
+            Float { value } => ParseObj::Float(value),
+            Str { value } => ParseObj::Str(value),
+            True => ParseObj::Bool(true),
+            False => ParseObj::Bool(false),
+            Nil => ParseObj::Nil,
+            _ => return Err(self.error("a pattern")),
+        };
+        self.eat();
+        Ok(value)
+    }
+
+    fn expr_and(&mut self) -> ParseResult<Box<Expr>> {
+        let mut left = self.expr_or()?;
 
         while self.check_eat(&[TokenKind::And]) {
-            let right = self.expr_or();
+            let right = self.expr_or()?;
             left = Box::new(Expr::new(ExprKind::Binary {
                 left,
                 op: BinaryOp::And,
@@ -134,14 +605,14 @@ impl Parser<'_> {
             }));
         }
 
-        left
+        Ok(left)
     }
 
-    fn expr_or(&mut self) -> Box<Expr> {
-        let mut left = self.expr_equal();
+    fn expr_or(&mut self) -> ParseResult<Box<Expr>> {
+        let mut left = self.expr_equal()?;
 
         while self.check_eat(&[TokenKind::Or]) {
-            let right = self.expr_equal();
+            let right = self.expr_equal()?;
             left = Box::new(Expr::new(ExprKind::Binary {
                 left,
                 op: BinaryOp::Or,
@@ -149,28 +620,28 @@ impl Parser<'_> {
             }));
         }
 
-        left
+        Ok(left)
     }
 
-    fn expr_equal(&mut self) -> Box<Expr> {
-        let mut left = self.expr_comparison();
+    fn expr_equal(&mut self) -> ParseResult<Box<Expr>> {
+        let mut left = self.expr_comparison()?;
 
         use TokenKind::*;
         while self.check_eat(&[EqEq, BangEq]) {
             let op = match self.now.kind() {
                 EqEq => BinaryOp::Eq,
                 BangEq => BinaryOp::NotEq,
-                _ => todo!(),
+                _ => unreachable!(),
             };
-            let right = self.expr_comparison();
+            let right = self.expr_comparison()?;
             left = Box::new(Expr::new(ExprKind::Binary { left, op, right }));
         }
 
-        left
+        Ok(left)
     }
 
-    fn expr_comparison(&mut self) -> Box<Expr> {
-        let mut left = self.term();
+    fn expr_comparison(&mut self) -> ParseResult<Box<Expr>> {
+        let mut left = self.term()?;
 
         use TokenKind::*;
         while self.check_eat(&[Gt, GtE, Lt, LtE]) {
@@ -179,65 +650,117 @@ impl Parser<'_> {
                 GtE => BinaryOp::GtE,
                 Lt => BinaryOp::Lt,
                 LtE => BinaryOp::LtE,
-                _ => todo!(),
+                _ => unreachable!(),
             };
-            let right = self.term();
+            let right = self.term()?;
             left = Box::new(Expr::new(ExprKind::Binary { left, op, right }));
         }
 
-        left
+        Ok(left)
     }
 
-    fn term(&mut self) -> Box<Expr> {
-        let mut left = self.factor();
+    fn term(&mut self) -> ParseResult<Box<Expr>> {
+        let mut left = self.factor()?;
 
         use TokenKind::*;
         while self.check_eat(&[Plus, Minus]) {
             let op = match self.now.kind() {
                 Plus => BinaryOp::Add,
                 Minus => BinaryOp::Sub,
-                _ => todo!(),
+                _ => unreachable!(),
             };
-            let right = self.factor();
+            let right = self.factor()?;
             left = Box::new(Expr::new(ExprKind::Binary { left, op, right }));
         }
 
-        left
+        Ok(left)
     }
 
-    fn factor(&mut self) -> Box<Expr> {
-        let mut left = self.unary();
+    fn factor(&mut self) -> ParseResult<Box<Expr>> {
+        let mut left = self.unary()?;
 
         use TokenKind::*;
         while self.check_eat(&[Star, Slash]) {
             let op = match self.now.kind() {
                 Star => BinaryOp::Mult,
                 Slash => BinaryOp::Div,
-                _ => todo!(),
+                _ => unreachable!(),
             };
-            let right = self.unary();
+            let right = self.unary()?;
             left = Box::new(Expr::new(ExprKind::Binary { left, op, right }));
         }
 
-        left
+        Ok(left)
     }
 
-    fn unary(&mut self) -> Box<Expr> {
+    fn unary(&mut self) -> ParseResult<Box<Expr>> {
         use TokenKind::*;
         if self.check_eat(&[Bang, Minus]) {
             let op = match self.now.kind() {
                 Bang => UnaryOp::Not,
                 Minus => UnaryOp::Neg,
-                _ => todo!(),
+                _ => unreachable!(),
             };
-            let operand = self.unary();
-            return Box::new(Expr::new(ExprKind::Unary { op, operand }));
+            let operand = self.unary()?;
+            return Ok(Box::new(Expr::new(ExprKind::Unary { op, operand })));
         }
 
-        self.primary()
+        self.postfix()
     }
 
-    fn primary(&mut self) -> Box<Expr> {
+    // field, item or index access, calls and `?`, e.g. `point.x`, `tuple.0`, `list[0]`,
+    // `f(x)`, `f(x)?`
+    fn postfix(&mut self) -> ParseResult<Box<Expr>> {
+        let mut expr = self.primary()?;
+
+        while self.check_eat(&[
+            TokenKind::Dot,
+            TokenKind::OpenBracket,
+            TokenKind::OpenParen,
+            TokenKind::Question,
+        ]) {
+            if self.now.kind() == &TokenKind::Question {
+                expr = Box::new(Expr::new(ExprKind::Propagate { value: expr }));
+                continue;
+            }
+            if self.now.kind() == &TokenKind::OpenParen {
+                let no_struct = std::mem::replace(&mut self.no_struct, false);
+                let args = self.comma_list(TokenKind::CloseParen, |p| Ok(*p.expression()?))?;
+                self.no_struct = no_struct;
+                expr = Box::new(Expr::new(ExprKind::Call { callee: expr, args }));
+                continue;
+            }
+            if self.now.kind() == &TokenKind::OpenBracket {
+                let no_struct = std::mem::replace(&mut self.no_struct, false);
+                let index = self.expression()?;
+                self.no_struct = no_struct;
+                self.expect(TokenKind::CloseBracket)?;
+                expr = Box::new(Expr::new(ExprKind::Index {
+                    object: expr,
+                    index,
+                }));
+                continue;
+            }
+
+            expr = match self.peek().kind().clone() {
+                TokenKind::Ident { name } => {
+                    Box::new(Expr::new(ExprKind::Field { object: expr, name }))
+                }
+                TokenKind::Int { value } if (0..=u8::MAX as i32).contains(&value) => {
+                    Box::new(Expr::new(ExprKind::Item {
+                        object: expr,
+                        index: value as u8,
+                    }))
+                }
+                _ => return Err(self.error("a field name or an index after `.`")),
+            };
+            self.eat();
+        }
+
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> ParseResult<Box<Expr>> {
         use TokenKind::*;
 
         let expr = match self.peek().kind() {
@@ -259,24 +782,171 @@ impl Parser<'_> {
             Str { value } => Box::new(Expr::new(ExprKind::Literal {
                 value: ParseObj::Str(value.clone()),
             })),
-            Ident { name } => Box::new(Expr::new(ExprKind::Literal {
-                value: ParseObj::Ident(name.clone()),
-            })),
-            OpenParen => {
-                // TODO: support empty () expr
+            Ident { name } => {
+                let name = name.clone();
                 self.eat();
-                let expr_inner = self.expression();
-                if !self.check_eat(&[CloseParen]) {
-                    todo!()
+                if self.check_eat(&[ColonColon]) {
+                    return self.variant(name);
                 }
+                if let Some(enum_name) = prelude_enum(&name) {
+                    return self.variant_args(enum_name, name);
+                }
+                if !self.no_struct && self.check_eat(&[OpenBrace]) {
+                    return self.struct_literal(name);
+                }
+                return Ok(Box::new(Expr::new(ExprKind::Literal {
+                    value: ParseObj::Ident(name),
+                })));
+            }
+            OpenParen => {
+                self.eat();
+                let no_struct = std::mem::replace(&mut self.no_struct, false);
+                let expr = self.group_or_tuple()?;
+                self.no_struct = no_struct;
                 // already eated )
-                return Box::new(Expr::new(ExprKind::Group { body: expr_inner }));
+                return Ok(expr);
             }
-            _ => todo!(),
+            OpenBracket => {
+                self.eat();
+                let no_struct = std::mem::replace(&mut self.no_struct, false);
+                let items = self.comma_list(CloseBracket, |p| Ok(*p.expression()?))?;
+                self.no_struct = no_struct;
+                return Ok(Box::new(Expr::new(ExprKind::List { items })));
+            }
+            Hash => {
+                self.eat();
+                self.expect(OpenBrace)?;
+                let no_struct = std::mem::replace(&mut self.no_struct, false);
+                let items = self.comma_list(CloseBrace, |p| {
+                    let key = *p.expression()?;
+                    p.expect(Colon)?;
+                    Ok((key, *p.expression()?))
+                })?;
+                self.no_struct = no_struct;
+                return Ok(Box::new(Expr::new(ExprKind::Map { items })));
+            }
+            // blocks, `if` and `match` as an operand, e.g. `1 + { 2 }`
+            OpenBrace | If | Match | Loop | Label { .. } | Break | Continue => {
+                return self.expression()
+            }
+            _ => return Err(self.error("an expression")),
         };
         self.eat();
 
-        expr
+        Ok(expr)
+    }
+
+    // `()`, `(expr)` or `(expr, ...)`, the `(` is already eaten
+    fn group_or_tuple(&mut self) -> ParseResult<Box<Expr>> {
+        let mut items = Vec::new();
+        let mut trailing_comma = false;
+        while !self.check(&[TokenKind::CloseParen, TokenKind::Eof]) {
+            items.push(*self.expression()?);
+            trailing_comma = self.check_eat(&[TokenKind::Comma]);
+            if !trailing_comma {
+                break;
+            }
+        }
+        self.expect(TokenKind::CloseParen)?;
+
+        if items.len() == 1 && !trailing_comma {
+            let body = Box::new(items.pop().unwrap());
+            return Ok(Box::new(Expr::new(ExprKind::Group { body })));
+        }
+        Ok(Box::new(Expr::new(ExprKind::Tuple { items })))
+    }
+
+    // `Shape::Circle(1)` or `Shape::Empty`, the `::` is already eaten
+    fn variant(&mut self, enum_name: Str) -> ParseResult<Box<Expr>> {
+        let variant = self.ident()?;
+        self.variant_args(enum_name, variant)
+    }
+
+    // the arguments of a variant, if it has any
+    fn variant_args(&mut self, enum_name: Str, variant: Str) -> ParseResult<Box<Expr>> {
+        let mut args = Vec::new();
+        if self.check_eat(&[TokenKind::OpenParen]) {
+            let no_struct = std::mem::replace(&mut self.no_struct, false);
+            args = self.comma_list(TokenKind::CloseParen, |p| Ok(*p.expression()?))?;
+            self.no_struct = no_struct;
+        }
+        Ok(Box::new(Expr::new(ExprKind::Variant {
+            enum_name,
+            variant,
+            args,
+        })))
+    }
+
+    // `Point { x: 1, y }`, the `{` is already eaten
+    fn struct_literal(&mut self, name: Str) -> ParseResult<Box<Expr>> {
+        let fields = self.comma_list(TokenKind::CloseBrace, |p| {
+            let field = p.ident()?;
+            let value = if p.check_eat(&[TokenKind::Colon]) {
+                *p.expression()?
+            } else {
+                // `Point { x }` is short for `Point { x: x }`
+                Expr::new(ExprKind::Literal {
+                    value: ParseObj::Ident(field.clone()),
+                })
+            };
+            Ok((field, value))
+        })?;
+        Ok(Box::new(Expr::new(ExprKind::StructLit { name, fields })))
+    }
+
+    // items separated by `,` (trailing `,` is allowed), also eats the `close`
+    fn comma_list<T>(
+        &mut self,
+        close: TokenKind,
+        mut item: impl FnMut(&mut Self) -> ParseResult<T>,
+    ) -> ParseResult<Vec<T>> {
+        let mut items = Vec::new();
+        while !self.check(&[close.clone(), TokenKind::Eof]) {
+            items.push(item(self)?);
+            if !self.check_eat(&[TokenKind::Comma]) {
+                break;
+            }
+        }
+        self.expect(close)?;
+        Ok(items)
+    }
+
+    fn ident(&mut self) -> ParseResult<Str> {
+        match self.peek().kind().clone() {
+            TokenKind::Ident { name } => {
+                self.eat();
+                Ok(name)
+            }
+            _ => Err(self.error("an identifier")),
+        }
+    }
+
+    fn expect(&mut self, kind: TokenKind) -> ParseResult<()> {
+        if !self.check_eat(std::slice::from_ref(&kind)) {
+            return Err(self.error(&format!("`{}`", kind)));
+        }
+        Ok(())
+    }
+
+    // the next token is not the `expected` one
+    fn error(&self, expected: &str) -> Error {
+        let message = match self.peek().kind() {
+            TokenKind::Error {
+                kind: kind @ LexError::NotClose(_),
+            } => return Error::incomplete(kind.to_string()),
+            TokenKind::Error { kind } => kind.to_string(),
+            TokenKind::Eof => {
+                let message = format!("expected {}, found the end of input", expected);
+                return Error::incomplete(message);
+            }
+            TokenKind::Ident { name } => format!("expected {}, found `{}`", expected, name),
+            TokenKind::Label { name } => format!("expected {}, found `'{}`", expected, name),
+            TokenKind::Str { value } => format!("expected {}, found `\"{}\"`", expected, value),
+            TokenKind::Int { value } => format!("expected {}, found `{}`", expected, value),
+            TokenKind::Float { value } => format!("expected {}, found `{}`", expected, value),
+            kind => format!("expected {}, found `{}`", expected, kind),
+        };
+        Error::new(message)
     }
 
     fn eat(&mut self) {
@@ -306,3 +976,36 @@ impl Parser<'_> {
         &self.next
     }
 }
+
+// the enum of a variant which can be used without it, e.g. `Some(1)` and `None`
+fn prelude_enum(variant: &str) -> Option<Str> {
+    match variant {
+        "Some" | "None" => Some(Str::intern("Option")),
+        "Ok" | "Err" => Some(Str::intern("Result")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::Cursor;
+
+    use super::{Input, Parser};
+
+    fn check(code: &str) -> Input {
+        Parser::new(Cursor::new(code)).check_input()
+    }
+
+    #[test]
+    fn test_check() {
+        assert_eq!(check("let a = 1\n"), Input::Complete);
+        assert_eq!(check("while x < 10 {\n"), Input::Incomplete);
+        assert_eq!(check("let a = [1, (2\n"), Input::Incomplete);
+        assert_eq!(check("let a = \"abc\n"), Input::Incomplete);
+        assert_eq!(check("let a = 1 +\n"), Input::Incomplete);
+        assert_eq!(check("if a { 1 } else\n"), Input::Incomplete);
+        assert!(matches!(check("let a = )"), Input::Error(_)));
+        assert!(matches!(check("let a = 1 }\n{"), Input::Error(_)));
+        assert!(matches!(check("let a = $"), Input::Error(_)));
+    }
+}
@@ -1,6 +1,8 @@
 use core::fmt;
 use std::default;
 
+use vm::string::Str;
+
 #[derive(Debug, PartialEq)]
 pub(crate) struct Token {
     kind: TokenKind,
@@ -35,24 +37,30 @@ impl default::Default for Token {
 #[derive(Debug, PartialEq, Clone)]
 pub(crate) enum TokenKind {
     // single character
-    Plus, Minus, Star, Slash, // + - * /
-    Comma, Dot, Semi,         // , . ;
-    OpenParen, CloseParen,    // ( )
-    OpenBrace, CloseBrace,    // { }
-
-    // one or two character
-    Bang, BangEq, // ! !=
-    Eq, EqEq,     // = ==
-    Gt, GtE,      // > >=
-    Lt, LtE,      // < <=
-    /*BitAnd,*/And, // & &&
-    /*BitOr,*/Or,   // | ||
+    Plus, Minus, Star, Slash,  // + - * /
+    Comma, Dot, Semi,          // , . ;
+    At, Hash, Question,        // @ # ?
+    OpenParen, CloseParen,     // ( )
+    OpenBrace, CloseBrace,     // { }
+    OpenBracket, CloseBracket, // [ ]
+
+    // one or more character
+    Bang, BangEq,      // ! !=
+    Eq, EqEq,          // = ==
+    FatArrow,          // =>
+    Colon, ColonColon, // : ::
+    DotDot, DotDotEq,  // .. ..=
+    Gt, GtE,           // > >=
+    Lt, LtE,           // < <=
+    /*BitAnd,*/And,    // & &&
+    /*BitOr,*/Or,      // | ||
 
     // ident
-    Ident { name: String },
+    Ident { name: Str },
+    Label { name: Str }, // 'name
 
     // literals
-    Str { value: String },
+    Str { value: Str },
     Int { value: i32 },
     Float { value: f64 },
     True,
@@ -62,9 +70,16 @@ pub(crate) enum TokenKind {
     // keywords
     Let,              // let
     If, Else,         // if else
-    For, While,       // for while
+    For, In, While,   // for in while
+    Loop,             // loop
+    Break, Continue,  // break continue
     Fun,              // fn
     Return,           // return
+    Yield,            // yield
+    Try, Catch,       // try catch
+    Finally, Throw,   // finally throw
+    Match,            // match
+    Enum, Struct,     // enum struct
 
     // other
     Error { kind: LexError },
@@ -80,6 +95,8 @@ impl fmt::Display for TokenKind {
             CloseParen => write!(f, ")"),
             OpenBrace => write!(f, "{{"),
             CloseBrace => write!(f, "}}"),
+            OpenBracket => write!(f, "["),
+            CloseBracket => write!(f, "]"),
             Plus => write!(f, "+"),
             Minus => write!(f, "-"),
             Star => write!(f, "*"),
@@ -87,6 +104,14 @@ impl fmt::Display for TokenKind {
             Comma => write!(f, ","),
             Dot => write!(f, "."),
             Semi => write!(f, ";"),
+            At => write!(f, "@"),
+            Hash => write!(f, "#"),
+            Question => write!(f, "?"),
+            Colon => write!(f, ":"),
+            ColonColon => write!(f, "::"),
+            DotDot => write!(f, ".."),
+            DotDotEq => write!(f, "..="),
+            FatArrow => write!(f, "=>"),
             Bang => write!(f, "!"),
             BangEq => write!(f, "!="),
             Eq => write!(f, "="),
@@ -96,6 +121,7 @@ impl fmt::Display for TokenKind {
             Lt => write!(f, "<"),
             LtE => write!(f, "<="),
             Ident { name } => write!(f, "(ident) {}", name),
+            Label { name } => write!(f, "(label) '{}", name),
             Str { value } => write!(f, "(str) {}", value),
             Int { value } => write!(f, "(int) {}", value),
             Float { value } => write!(f, "(float) {}", value),
@@ -106,12 +132,24 @@ impl fmt::Display for TokenKind {
             If => write!(f, "if"),
             Else => write!(f, "else"),
             For => write!(f, "for"),
+            In => write!(f, "in"),
             While => write!(f, "while"),
+            Loop => write!(f, "loop"),
+            Break => write!(f, "break"),
+            Continue => write!(f, "continue"),
             And => write!(f, "and"),
             Or => write!(f, "or"),
             Fun => write!(f, "fn"),
             Return => write!(f, "return"),
-            Error { kind } => write!(f, "error: {:#?}", kind), // TODO: make error info better.
+            Yield => write!(f, "yield"),
+            Try => write!(f, "try"),
+            Catch => write!(f, "catch"),
+            Finally => write!(f, "finally"),
+            Throw => write!(f, "throw"),
+            Match => write!(f, "match"),
+            Enum => write!(f, "enum"),
+            Struct => write!(f, "struct"),
+            Error { kind } => write!(f, "error: {}", kind),
             Eof => write!(f, "eof"),
         }
     }
@@ -122,3 +160,12 @@ pub(crate) enum LexError {
     UnknownChar(char),
     NotClose(char),
 }
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnknownChar(c) => write!(f, "unknown character `{}`", c),
+            LexError::NotClose(c) => write!(f, "`{}` is not closed", c),
+        }
+    }
+}
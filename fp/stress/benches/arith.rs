@@ -0,0 +1,90 @@
+// arithmetic loops on the locals of a function, the best time of a few runs of each. the values in the stack are
+// words with the `nanbox` feature, run it with and without to compare:
+// `cargo bench -p stress --bench arith [--features nanbox]`
+
+use std::time::{Duration, Instant};
+
+use compiler::Compiler;
+use vm::vm::Vm;
+
+const RUNS: usize = 5;
+
+const SCRIPTS: &[(&str, &str)] = &[
+    (
+        "int sum",
+        r#"
+        fn run() {
+            let n = 0
+            for i in 0..1000000 { n = n + i * 2 - 1 }
+        }
+        run()
+        "#,
+    ),
+    (
+        "int compare",
+        r#"
+        fn run() {
+            let n = 0
+            for i in 0..1000000 {
+                if i < 500000 { n = n + 1 }
+                if i > 900000 { n = n - 1 }
+            }
+        }
+        run()
+        "#,
+    ),
+    (
+        "float",
+        r#"
+        fn run() {
+            let x = 0.0
+            for i in 0..500000 { x = x * 0.5 + 1.5 }
+        }
+        run()
+        "#,
+    ),
+    (
+        "collatz",
+        r#"
+        fn run() {
+            let steps = 0
+            for i in 1..3000 {
+                let n = i
+                for _ in 0..1000 {
+                    if n == 1 { break }
+                    if n - n / 2 * 2 == 0 { n = n / 2 } else { n = 3 * n + 1 }
+                    steps = steps + 1
+                }
+            }
+        }
+        run()
+        "#,
+    ),
+];
+
+fn time(code: &str) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let mut compiler = Compiler::new();
+            let mut vm = Vm::new();
+            compiler.link_natives(&mut vm);
+            let chunk = compiler.compile(code).expect("fail to compile");
+            let start = Instant::now();
+            vm.interpret(chunk).expect("fail to run");
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let repr = if cfg!(feature = "nanbox") {
+        "nanbox"
+    } else {
+        "enum"
+    };
+    println!("values: {}", repr);
+    for (name, code) in SCRIPTS {
+        println!("{:<12} {:>10.2?}", name, time(code));
+    }
+}
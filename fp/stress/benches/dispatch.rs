@@ -0,0 +1,57 @@
+// the dispatch loop: calls, arithmetic loops and string building.
+// `cargo bench -p stress --bench dispatch -- --save file` then `--compare file`
+
+use stress::bench::Bench;
+
+fn main() {
+    let mut bench = Bench::from_args();
+    bench.script(
+        "fib",
+        "
+        fn fib(n) { if n < 2 { return n } fib(n - 1) + fib(n - 2) }
+        fib(25)
+        ",
+    );
+    bench.script(
+        "loops",
+        "
+        fn run() {
+            let n = 0
+            for i in 0..1000 {
+                for j in 0..1000 { n = n + i - j }
+            }
+            n
+        }
+        run()
+        ",
+    );
+    bench.script(
+        "while",
+        "
+        fn run() {
+            let i = 0
+            let n = 0
+            while i < 1000000 {
+                if i > 500000 { n = n + 1 }
+                i = i + 1
+            }
+            n
+        }
+        run()
+        ",
+    );
+    bench.script(
+        "strings",
+        r#"
+        fn run() {
+            let s = ""
+            for i in 0..20000 { s = s + str(i) + "," }
+            let parts = 0
+            for c in s { if c == "," { parts = parts + 1 } }
+            parts
+        }
+        run()
+        "#,
+    );
+    bench.finish();
+}
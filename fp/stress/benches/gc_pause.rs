@@ -0,0 +1,65 @@
+// the longest pause of the collector on a big heap, stop-the-world vs generational.
+// the script keeps a big map of lists, then the host calls `tick` like a game loop,
+// each call makes cycles which only the collector can free. the slowest tick is the
+// pause the host sees.
+
+use std::time::{Duration, Instant};
+
+use compiler::Compiler;
+use vm::{
+    gc::{GcConfig, GcMode},
+    vm::Vm,
+};
+
+const CODE: &str = "
+let keep = #{}
+for i in 0..200000 { keep[i] = [i, (i,)] }
+fn tick() {
+    for i in 0..100 {
+        let a = [nil, nil, nil, nil]
+        a[0] = a
+    }
+}
+";
+
+const TICKS: usize = 5000;
+
+fn run(mode: GcMode) {
+    let mut compiler = Compiler::new();
+    let mut vm = Vm::new();
+    vm.set_gc_config(GcConfig {
+        mode,
+        ..GcConfig::default()
+    });
+    let chunk = compiler.compile(CODE).expect("fail to compile");
+    vm.interpret(chunk).expect("fail to run");
+    let tick = compiler.global("tick").expect("no `tick`");
+    let tick = vm.get_global(tick).cloned().unwrap();
+
+    let mut times = Vec::with_capacity(TICKS);
+    let start = Instant::now();
+    for _ in 0..TICKS {
+        let start = Instant::now();
+        vm.call_value(&tick, &[]).expect("fail to tick");
+        times.push(start.elapsed());
+    }
+    let elapsed = start.elapsed();
+    times.sort();
+
+    let stats = vm.gc_stats();
+    println!(
+        "{:<14} total {:>9.2?}  median tick {:>9.2?}  max tick {:>9.2?}  collections {:>4} (minor {:>4})",
+        format!("{:?}", mode),
+        elapsed,
+        times[TICKS / 2],
+        times.last().copied().unwrap_or(Duration::ZERO),
+        stats.collections,
+        stats.minor,
+    );
+}
+
+fn main() {
+    for mode in [GcMode::StopTheWorld, GcMode::Generational] {
+        run(mode);
+    }
+}
@@ -0,0 +1,75 @@
+// scripts which move, compare and build strings, the best time of a few runs of each.
+
+use std::time::{Duration, Instant};
+
+use compiler::Compiler;
+use vm::vm::Vm;
+
+const RUNS: usize = 5;
+
+const SCRIPTS: &[(&str, &str)] = &[
+    (
+        "copy",
+        r#"
+        let s = "a string long enough to be worth copying around"
+        let n = 0
+        for i in 0..300000 {
+            let a = s
+            let b = a
+            if b == s { n = n + 1 }
+        }
+        "#,
+    ),
+    (
+        "compare",
+        r#"
+        let names = ["alpha", "beta", "gamma", "delta"]
+        let n = 0
+        for i in 0..100000 {
+            for name in names {
+                if name == "gamma" { n = n + 1 }
+            }
+        }
+        "#,
+    ),
+    (
+        "map keys",
+        r#"
+        let m = #{"red": 0, "green": 0, "blue": 0}
+        for i in 0..100000 {
+            m["red"] = m["red"] + 1
+            m["blue"] = m["green"] + i
+        }
+        "#,
+    ),
+    (
+        "build",
+        r#"
+        let s = ""
+        for i in 0..2000 { s = s + str(i) + "," }
+        let parts = 0
+        for c in s { if c == "," { parts = parts + 1 } }
+        "#,
+    ),
+];
+
+fn time(code: &str) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let mut compiler = Compiler::new();
+            let mut vm = Vm::new();
+            compiler.link_natives(&mut vm);
+            let chunk = compiler.compile(code).expect("fail to compile");
+            let start = Instant::now();
+            vm.interpret(chunk).expect("fail to run");
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    for (name, code) in SCRIPTS {
+        println!("{:<10} {:>10.2?}", name, time(code));
+    }
+}
@@ -0,0 +1,113 @@
+// a small harness for the benches in `benches/`: the best time of a few runs of each
+// script, and how it changed from the times saved by an earlier run, e.g.
+//   cargo bench -p stress --bench dispatch -- --save /tmp/before.txt
+//   (change the vm)
+//   cargo bench -p stress --bench dispatch -- --compare /tmp/before.txt
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    time::{Duration, Instant},
+};
+
+use compiler::Compiler;
+use vm::vm::Vm;
+
+pub struct Bench {
+    runs: usize,
+    save: Option<String>,
+    baseline: HashMap<String, Duration>,
+    times: Vec<(String, Duration)>,
+}
+
+impl Bench {
+    // `--runs n`, `--save file` and `--compare file`, the other arguments are the ones
+    // cargo gives, e.g. `--bench`
+    pub fn from_args() -> Bench {
+        let mut bench = Bench {
+            runs: 5,
+            save: None,
+            baseline: HashMap::new(),
+            times: Vec::new(),
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--runs" => {
+                    bench.runs = args
+                        .next()
+                        .and_then(|n| n.parse().ok())
+                        .expect("`--runs` takes a number")
+                }
+                "--save" => bench.save = Some(args.next().expect("`--save` takes a file")),
+                "--compare" => {
+                    let path = args.next().expect("`--compare` takes a file");
+                    let saved = fs::read_to_string(&path).expect("fail to read the baseline");
+                    bench.baseline = parse(&saved);
+                }
+                _ => {}
+            }
+        }
+        bench
+    }
+
+    // the script is compiled before each run, only the run is timed
+    pub fn script(&mut self, name: &str, code: &str) {
+        let best = (0..self.runs)
+            .map(|_| {
+                let mut compiler = Compiler::new();
+                let mut vm = Vm::new();
+                compiler.link_natives(&mut vm);
+                let chunk = compiler.compile(code).expect("fail to compile");
+                let start = Instant::now();
+                vm.interpret(chunk).expect("fail to run");
+                start.elapsed()
+            })
+            .min()
+            .unwrap();
+
+        print!("{:<14} {:>10.2?}", name, best);
+        if let Some(before) = self.baseline.get(name) {
+            let speedup = before.as_secs_f64() / best.as_secs_f64();
+            print!("  (was {:.2?}, {:.2}x)", before, speedup);
+        }
+        println!();
+        self.times.push((name.to_owned(), best));
+    }
+
+    pub fn finish(self) {
+        if let Some(path) = self.save {
+            let lines: String = self
+                .times
+                .iter()
+                .map(|(name, time)| format!("{}\t{}\n", name, time.as_nanos()))
+                .collect();
+            fs::write(&path, lines).expect("fail to save the times");
+        }
+    }
+}
+
+// `name<tab>nanoseconds` a line
+fn parse(saved: &str) -> HashMap<String, Duration> {
+    saved
+        .lines()
+        .filter_map(|line| {
+            let (name, nanos) = line.split_once('\t')?;
+            Some((name.to_owned(), Duration::from_nanos(nanos.parse().ok()?)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::parse;
+
+    #[test]
+    fn test_parse() {
+        let times = parse("fib\t1500\nbroken line\nloops\t20\n");
+        assert_eq!(times.len(), 2);
+        assert_eq!(times["fib"], Duration::from_nanos(1500));
+    }
+}
@@ -0,0 +1,107 @@
+// generators of big programs, e.g. thousands of globals or locals,
+// to make sure the long opcodes work. the programs are run in `tests/`.
+
+pub mod bench;
+
+use std::{cell::RefCell, io, rc::Rc};
+
+use compiler::Compiler;
+use vm::{error::RuntimeError, value::Value, vm::Vm};
+
+// an output for `Vm::set_output` kept in memory, the clones write in the same buffer
+#[derive(Clone, Default)]
+pub struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Output {
+    // what was printed since the last `take`, the scripts only print text
+    pub fn take(&self) -> String {
+        String::from_utf8(self.0.take()).expect("the output isn't utf-8")
+    }
+}
+
+impl io::Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// run the code and get the global variables `names`
+pub fn run(code: &str, names: &[&str]) -> Result<Vec<Option<Value>>, RuntimeError> {
+    let mut compiler = Compiler::new();
+    let mut vm = Vm::new();
+    let chunk = compiler.compile(code).expect("fail to compile");
+    vm.interpret(chunk)?;
+
+    let values = names
+        .iter()
+        .map(|name| {
+            let i = compiler.global(name)?;
+            vm.get_global(i).cloned()
+        })
+        .collect();
+    Ok(values)
+}
+
+// `let g0 = 0`, `let g1 = g0 + 1`... the last one is `n - 1`,
+// then every global is doubled in place.
+pub fn globals(n: usize) -> String {
+    let mut code = String::from("let g0 = 0\n");
+    for i in 1..n {
+        code += &format!("let g{} = g{} + 1\n", i, i - 1);
+    }
+    for i in 0..n {
+        code += &format!("g{} = g{} * 2\n", i, i);
+    }
+    code
+}
+
+// the same as `globals`, but the variables are locals in a block and `name` is
+// the value of the last one
+pub fn locals(name: &str, n: usize) -> String {
+    let mut code = format!("let {} = {{\nlet l0 = 0\n", name);
+    for i in 1..n {
+        code += &format!("let l{} = l{} + 1\n", i, i - 1);
+    }
+    for i in 0..n {
+        code += &format!("l{} = l{} * 2\n", i, i);
+    }
+    code += &format!("l{}\n}}\n", n - 1);
+    code
+}
+
+// `depth` nested blocks with `n` locals each, `name` is the sum of the last local
+// of every block
+pub fn nested_locals(name: &str, depth: usize, n: usize) -> String {
+    let mut code = format!("let {} = ", name);
+    for d in 0..depth {
+        code += "{\n";
+        for i in 0..n {
+            code += &format!("let l{}_{} = {}\n", d, i, i);
+        }
+    }
+    let sum = (0..depth)
+        .map(|d| format!("l{}_{}", d, n - 1))
+        .collect::<Vec<_>>()
+        .join(" + ");
+    code += &sum;
+    code += &"\n}".repeat(depth);
+    code += "\n";
+    code
+}
+
+// a `loop` making `n` locals every time, `continue` is taken once and `name` is
+// given by `break` from under the locals
+pub fn loop_locals(name: &str, n: usize) -> String {
+    let mut code = format!("let {}_i = 0\nlet {} = loop {{\n", name, name);
+    for i in 0..n {
+        code += &format!("let l{} = {}\n", i, i);
+    }
+    code += &format!("{}_i = {}_i + 1\n", name, name);
+    code += &format!("if {}_i == 1 {{ continue }}\n", name);
+    code += &format!("break l{} + {}_i\n}}\n", n - 1, name);
+    code
+}
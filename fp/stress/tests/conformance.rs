@@ -0,0 +1,211 @@
+// every script is run on both backends, and with and without the peephole pass. they
+// must print the same and give the same value or the same error.
+
+use compiler::{Backend, Compiler};
+use stress::Output;
+use vm::{debug, vm::Vm};
+
+// what the script prints, then its value or its error, or the errors of the compiler
+fn run(code: &str, backend: Backend, optimize: bool) -> String {
+    let mut compiler = Compiler::new();
+    compiler.set_backend(backend);
+    compiler.set_optimize(optimize);
+    let mut vm = Vm::new();
+    let output = Output::default();
+    vm.set_output(output.clone());
+    compiler.link_natives(&mut vm);
+    let chunk = match compiler.compile_value(code) {
+        Ok(chunk) => chunk,
+        Err(errors) => return format!("errors: {:?}", errors),
+    };
+    let result = match vm.interpret(chunk) {
+        Ok(value) => format!("{:?}", value),
+        Err(e) => format!("error: {}", e),
+    };
+    output.take() + &result
+}
+
+const SCRIPTS: &[&str] = &[
+    // arithmetic and comparisons
+    "1 + 2 * 3 - 4 / 2",
+    "let a = 7\nlet b = 2.5\nlet t = (a - 10, -a, a * b, a / 2, 1.0 / 3.0)\nt",
+    "(1 < 2, 2 <= 2, 3 > 4, 3 >= 4, 1 == 1.0, \"a\" != \"b\", !true)",
+    "\"ab\" + \"cd\"",
+    "let x = 3\nlet t = (x > 1 && x < 5, x < 1 || x == 3, false && 1, 1 || false)\nt",
+    // blocks, if and variables
+    "let a = { let b = 2\nlet c = b * b\nc + b }\na",
+    "let a = 1\n{ let a = a + 1\na = a * 10\nprint(a) }\na",
+    "let n = 5\nif n > 3 { \"big\" } else { \"small\" }",
+    "if false { 1 }",
+    "let a = 1\na = a + 1\na = -a\na",
+    "let a = 1\nlet b = a + { a = 2; 3 } + a\nlet d = { let c = 1\nc + { c = 5; c } }\nlet t = (b, d)\nt",
+    "{ let a = 1\nlet b = 2\na = b && a\nlet t = (a, b)\nt }",
+    // loops
+    "let s = 0\nlet i = 0\nwhile i < 10 { s = s + i\ni = i + 1 }\ns",
+    "let s = 0\nfor i in 0..10 { if i == 7 { break }\nif i == 2 { continue }\ns = s + i }\ns",
+    "let s = \"\"\nfor c in [\"x\", \"y\", \"z\"] { s = s + c }\ns",
+    "struct Down { n, next }\nfn tick(d) { if d.n > 0 { d.n = d.n - 1; Option::Some(d.n) } else { Option::None } }\nlet s = 0\nlet d = Down { n: 4, next: tick }\nfor x in d { s = s * 10 + x }\ns",
+    "let i = 0\nloop { i = i + 1\nif i > 4 { break i * 100 } }",
+    "let n = 0\n'outer: for i in 0..5 { for j in 0..5 { if j > i { continue 'outer }\nif i == 4 { break 'outer }\nn = n + 1 } }\nn",
+    "for i in 0..10 step 3 { print(i) }\nfor i in 3..=1 step -1 { print(i) }",
+    // functions
+    "fn fib(n) { if n < 2 { n } else { fib(n - 1) + fib(n - 2) } }\nfib(15)",
+    "fn add(a, b) { a + b }\nfn twice(f, x) { f(f(x, 1), 1) }\ntwice(add, 5)",
+    "fn first(list) { for x in list { if x > 2 { return x } }\nreturn }\nlet t = (first([1, 3, 5]), first([]))\nt",
+    "fn outer() { fn inner(x) { x * 2 }\ninner(21) }\nouter()",
+    "fn count(n) { let i = 0\nwhile i < n { i = i + 1 }\ni }\ncount(1000)",
+    "fn even(n) { if n == 0 { true } else { odd(n - 1) } }\nfn odd(n) { if n == 0 { false } else { even(n - 1) } }\neven(10)",
+    "fn f(a, b) { a }\nf(1)",
+    // collections and objects
+    "let l = [1, 2, 3]\nl[1] = 20\nlet t = (l, l[2], len(l), (1, \"a\").1)\nt",
+    "let m = #{\"a\": 1, \"b\": 2}\nm[\"c\"] = m[\"a\"] + m[\"b\"]\nm[\"c\"]",
+    "let m = #{}\nm[[1]]",
+    "struct Point { x, y }\nlet p = Point { y: 2, x: 1 }\np.x = p.x + p.y\nlet t = (p, p.x)\nt",
+    "enum Shape { Circle(r), Square(s), Empty }\nlet t = (Shape::Circle(2), Shape::Empty)\nt",
+    "struct Counter { step }\nfn inc(x) { x + 1 }\nlet c = Counter { step: inc }\nc.step(41)",
+    "let o = Option::Some(3)\nlet t = (o.map(str), o.and_then(type_of), Option::None.unwrap_or(4))\nt",
+    "fn half(n) { if int(n / 2) * 2 == n { Result::Ok(int(n / 2)) } else { Result::Err(n) } }\nfn quarter(n) { let h = half(n)?\nhalf(h) }\nlet t = (quarter(8), quarter(6))\nt",
+    // natives and errors
+    "print(\"a\", 1)\nprintln(type_of(1.5), str(2) + \"x\")\nint(\"42\") + 1",
+    "let a = 1\na + nil",
+    "1 / 0",
+    "-\"a\"",
+    "!1",
+    "undefined_fn_value()",
+    "let a = 1\na(2)",
+    "fn thrower() { throw \"boom\" }\nprint(\"before\")\nthrower()",
+    "let l = [1]\nl[5]",
+    "assert(1 == 2, \"not equal\")",
+    // the ints out of 64 bits, the literals are 32-bit
+    "let big = 1073741824 * 1073741824 * 4\nbig + big",
+    "let big = 1073741824 * 1073741824 * 4\nlet max = big - 1 + big\nfn inc(x) { x + 1 }\ninc(max)",
+    "fn dec(x) { x - 1 }\nlet big = 1073741824 * 1073741824 * 4\ndec(-big - big)",
+    "fn sq(x) { x * x }\nsq(1073741824 * 1073741824)",
+    "fn neg(x) { -x }\nlet big = 1073741824 * 1073741824 * 4\nneg(-big - big)",
+    // the outer local is seen from a function of the other backend too
+    "fn f() { let a = 1\nfn g() { match a { _ => 1 } }\ng() }\nf()",
+    "fn f() { let a = 1\nmatch a { _ => 0 }\nfn g() { a }\ng() }\nf()",
+];
+
+// the code the register backend leaves to the stack backend, in a function or in the
+// script, see `needs_stack`. the jumps of `match`, `try` and `yield` are moved by the
+// peephole pass.
+const STACK_SCRIPTS: &[&str] = &[
+    "let n = 0\nlet s = 0\nwhile n < 5 { try { n = n + 1\nif n != 3 { continue }\ns = s + n } finally { print(n) } }\ns",
+    "fn f(x) { try { if x != 0 { throw x }\nnil; 1 } catch e { e + 1 } finally { print(\"f\") } }\nlet t = (f(0), f(4))\nt",
+    "fn g(n) { let a = n\nmatch a + 1 { 1 => \"one\", x if x < 4 => \"small\", _ => \"big\" } }\nlet t = (g(0), g(2), g(9))\nt",
+    "enum E { A(x), B, C }\nfn h(e) { match e { E::A(x) if x != 1 => x + 1, E::A(_) => 0, _ => -1 } }\nlet t = (h(E::A(1)), h(E::A(5)), h(E::C))\nt",
+    "fn* up(n) { let i = 0\nwhile i < n { yield i + 10\ni = i + 1 } }\nlet s = 0\nfor x in up(4) { s = s + x }\ns",
+    "let i = 0\nlet n = 0\nwhile i < 10 { i = i + 1\nif i >= 8 { break }\nif i <= 2 { continue }\nn = n + 1 }\nn",
+    "let a = 0\nwhile a < 3 { let b = a\na = b + 1 }\ntry { a = a / 0 } catch e { e.kind }",
+    // the functions of both backends in the same code
+    "fn outer(x) { fn inc(y) { y + 1 }\nmatch inc(x) { 1 => \"one\", n => str(n) } }\nlet t = (outer(0), outer(5))\nt",
+    "fn pick(x) { fn first(v) { match v { (a, _) => a } }\nfirst((x, 2)) * 10 }\npick(4)",
+    "fn dot(pairs) { let s = 0\nfor (a, b) in pairs { s = s + a * b }\ns }\nfn twice(x) { dot(x) * 2 }\ntwice([(1, 2), (3, 4)])",
+    "fn boom(x) { if x > 1 { throw x * 10 }\nx }\nfn* gen(n) { for i in 0..n { yield boom(i) } }\nlet s = 0\ntry { for x in gen(5) { s = s + x + 1 } } catch e { print(e, \"\") }\ns",
+    // the traces of the caught errors have the functions of both backends
+    "fn f(x) { x / 0 }\nfn g(x) { f(x) + 1 }\ntry { g(1) } catch e { e.trace }",
+    "fn r(x) { x / 0 }\nfn s() { try { r(1) } catch e { e.trace } }\nfn q() { s() }\nq()",
+    "fn s() { try { match 1 { 2 => 0 } } catch e { e.trace } }\nfn q() { s() }\nq()",
+    "fn f(x) { x.y }\nfn g() { Option::Some(1).map(f) }\ntry { g() } catch e { (e.kind, e.trace) }",
+    "fn bad(x) { x + nil }\nfn* gen() { yield 1\nyield bad(2) }\ntry { for x in gen() { x } } catch e { e.trace }",
+];
+
+#[test]
+fn test_conformance() {
+    for code in SCRIPTS.iter().chain(STACK_SCRIPTS) {
+        let stack = run(code, Backend::Stack, true);
+        for (backend, optimize) in [
+            (Backend::Register, true),
+            (Backend::Stack, false),
+            (Backend::Register, false),
+        ] {
+            assert_eq!(
+                stack,
+                run(code, backend, optimize),
+                "{} with optimize {} differs on:\n{}",
+                backend.name(),
+                optimize,
+                code
+            );
+        }
+    }
+    for code in STACK_SCRIPTS {
+        let stack = run(code, Backend::Stack, true);
+        assert!(!stack.starts_with("errors:"), "{}", stack);
+    }
+}
+
+#[test]
+fn test_peephole() {
+    // the superinstructions are made, and only with the pass
+    let code = "fn f(n) { let i = 0\nwhile i < n { if i != 2 { 1 }\n7\ni = i + 1 } }";
+    let mut compiler = Compiler::new();
+    let bytecode = debug::disassemble(&compiler.compile_dry(code).unwrap());
+    for op in ["AddLocalConstant", "NotEq", "LtJumpIfFalse"] {
+        assert!(bytecode.contains(op), "no {} in:\n{}", op, bytecode);
+    }
+    // the `7` is dropped with its `Pop`
+    assert!(!bytecode.contains("(7)"), "{}", bytecode);
+    compiler.set_optimize(false);
+    let bytecode = debug::disassemble(&compiler.compile_dry(code).unwrap());
+    assert!(!bytecode.contains("NotEq") && bytecode.contains("(7)"));
+}
+
+#[test]
+fn test_stress_programs() {
+    let programs = [
+        stress::globals(300),
+        stress::locals("a", 300) + "a",
+        stress::nested_locals("a", 4, 50) + "a",
+        stress::loop_locals("a", 100) + "a",
+    ];
+    for code in programs {
+        let stack = run(&code, Backend::Stack, true);
+        assert_eq!(stack, run(&code, Backend::Register, true));
+        assert_eq!(stack, run(&code, Backend::Stack, false));
+        assert_eq!(stack, run(&code, Backend::Register, false));
+    }
+}
+
+#[test]
+fn test_fallback() {
+    // only the functions needing the stack backend are compiled by it
+    let mut compiler = Compiler::new();
+    compiler.set_backend(Backend::Register);
+    let mut vm = Vm::new();
+    compiler.link_natives(&mut vm);
+    let code = "fn m(x) { match x { _ => 1 } }\nfn* g() { yield 1 }\nfn r(x) { x + 1 }\nfn p() { fn q() { 2 }\nfor (a, b) in [] { }\nq }";
+    let chunk = compiler.compile(code).unwrap();
+    vm.interpret(chunk).unwrap();
+    let chunk = compiler.compile_value("p()").unwrap();
+    let q = vm.interpret(chunk).unwrap().unwrap();
+    let registers = |value: &vm::value::Value| match value {
+        vm::value::Value::Function(fun) => fun.registers.is_some(),
+        value => panic!("unexpected {:?}", value),
+    };
+    let global = |name| vm.get_global(compiler.global(name).unwrap()).unwrap();
+    assert!(!registers(global("m")) && !registers(global("g")) && !registers(global("p")));
+    assert!(registers(global("r")) && registers(&q));
+}
+
+#[test]
+fn test_mixed() {
+    // a session may switch backends, their functions call each other
+    let mut compiler = Compiler::new();
+    let mut vm = Vm::new();
+    compiler.link_natives(&mut vm);
+    let chunk = compiler.compile("fn stack_double(x) { x * 2 }").unwrap();
+    vm.interpret(chunk).unwrap();
+    compiler.set_backend(Backend::Register);
+    let chunk = compiler
+        .compile("fn register_inc(x) { stack_double(x) + 1 }")
+        .unwrap();
+    vm.interpret(chunk).unwrap();
+    compiler.set_backend(Backend::Stack);
+    let chunk = compiler.compile_value("register_inc(20)").unwrap();
+    assert_eq!(
+        vm.interpret(chunk).unwrap(),
+        Some(vm::value::Value::Int(41))
+    );
+}
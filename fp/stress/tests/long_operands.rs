@@ -0,0 +1,40 @@
+use stress::{globals, locals, loop_locals, nested_locals, run};
+use vm::value::Value;
+
+#[test]
+fn test_globals() {
+    let n = 5000;
+    let values = run(&globals(n), &["g0", "g255", "g256", "g4999"]).unwrap();
+    let expect = [0, 510, 512, 9998].map(|v| Some(Value::Int(v)));
+    assert_eq!(values, expect);
+}
+
+#[test]
+fn test_locals() {
+    let n = 3000;
+    let code = locals("a", n) + "let b = a + 1";
+    let values = run(&code, &["a", "b"]).unwrap();
+    assert_eq!(values, [Some(Value::Int(5998)), Some(Value::Int(5999))]);
+}
+
+#[test]
+fn test_nested_locals() {
+    let code = nested_locals("a", 4, 300) + "let b = a";
+    let values = run(&code, &["a", "b"]).unwrap();
+    assert_eq!(values, [Some(Value::Int(1196)), Some(Value::Int(1196))]);
+}
+
+#[test]
+fn test_loop_locals() {
+    let code = loop_locals("a", 1000) + "let b = a";
+    let values = run(&code, &["a", "b", "a_i"]).unwrap();
+    let expect = [1001, 1001, 2].map(|v| Some(Value::Int(v)));
+    assert_eq!(values, expect);
+}
+
+#[test]
+fn test_globals_and_locals() {
+    let code = globals(1000) + &locals("a", 1000) + "let b = a + g999";
+    let values = run(&code, &["b"]).unwrap();
+    assert_eq!(values, [Some(Value::Int(3996))]);
+}
@@ -0,0 +1,117 @@
+// what the native functions may touch, see `Vm::with_capabilities`.
+// the default is pure, the scripts can only compute and print.
+
+use std::path::{Path, PathBuf};
+
+// the names, paths... a capability is given for
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Scope<T> {
+    #[default]
+    Nothing,
+    Only(Vec<T>),
+    All,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Capabilities {
+    // the files under these paths, e.g. a directory for everything in it
+    pub fs_read: Scope<PathBuf>,
+    pub fs_write: Scope<PathBuf>,
+    // the environment variables
+    pub env: Scope<String>,
+    pub clock: bool,
+    pub random: bool,
+    pub subprocess: bool,
+}
+
+impl Capabilities {
+    pub fn pure() -> Capabilities {
+        Capabilities::default()
+    }
+
+    // e.g. for the REPL, the user runs their own code
+    pub fn all() -> Capabilities {
+        Capabilities {
+            fs_read: Scope::All,
+            fs_write: Scope::All,
+            env: Scope::All,
+            clock: true,
+            random: true,
+            subprocess: true,
+        }
+    }
+
+    pub fn can_read(&self, path: &Path) -> bool {
+        under(&self.fs_read, path)
+    }
+
+    pub fn can_write(&self, path: &Path) -> bool {
+        under(&self.fs_write, path)
+    }
+
+    pub fn can_read_env(&self, name: &str) -> bool {
+        match &self.env {
+            Scope::Nothing => false,
+            Scope::Only(names) => names.iter().any(|n| n == name),
+            Scope::All => true,
+        }
+    }
+}
+
+// the links and `..` are resolved first, so `allowed/../secret` is not under `allowed`
+fn under(scope: &Scope<PathBuf>, path: &Path) -> bool {
+    let roots = match scope {
+        Scope::Nothing => return false,
+        Scope::Only(roots) => roots,
+        Scope::All => return true,
+    };
+    let path = match resolve(path) {
+        Some(path) => path,
+        None => return false,
+    };
+    roots
+        .iter()
+        .filter_map(|root| resolve(root))
+        .any(|root| path.starts_with(root))
+}
+
+// the absolute path without links, a file which doesn't exist yet is resolved by its
+// directory, e.g. the file to write.
+fn resolve(path: &Path) -> Option<PathBuf> {
+    if let Ok(path) = path.canonicalize() {
+        return Some(path);
+    }
+    let name = path.file_name()?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Some(parent.canonicalize().ok()?.join(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf};
+
+    use super::{Capabilities, Scope};
+
+    #[test]
+    fn test_paths() {
+        let root = env::temp_dir().join(format!("fpig_caps_{}", std::process::id()));
+        let allowed = root.join("allowed");
+        fs::create_dir_all(&allowed).unwrap();
+        fs::write(root.join("secret"), "").unwrap();
+
+        let caps = Capabilities {
+            fs_read: Scope::Only(vec![allowed.clone()]),
+            ..Capabilities::pure()
+        };
+        assert!(caps.can_read(&allowed.join("new")));
+        assert!(!caps.can_read(&allowed.join("../secret")));
+        assert!(!caps.can_read(&root.join("secret")));
+        assert!(!caps.can_write(&allowed.join("new")));
+        assert!(!caps.can_read(&PathBuf::from("/no/such/dir/file")));
+        assert!(Capabilities::all().can_write(&root.join("secret")));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,12 +1,28 @@
+use std::cell::Cell;
+
 use crate::location::Location;
 
-use crate::value::Value;
+use crate::{error::RuntimeError, op::OpCode, value::Value};
 
 #[derive(Debug)]
 pub struct Chunk {
     code: Vec<u8>,
     constants: Vec<Value>,
     locations: Vec<Location>,
+    // the `try`s, an inner one is before the outer ones
+    handlers: Vec<Handler>,
+    // `verify` passed since the last change, the vm reads the code without checks
+    verified: Cell<bool>,
+}
+
+// an error thrown by the code from `start` to `end` jumps to `target`,
+// the stack is cut to `height` values of the frame and the error is pushed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Handler {
+    pub start: usize,
+    pub end: usize,
+    pub target: usize,
+    pub height: u16,
 }
 
 impl Chunk {
@@ -15,6 +31,8 @@ impl Chunk {
             code: Vec::with_capacity(8),
             constants: Vec::with_capacity(8),
             locations: Vec::new(),
+            handlers: Vec::new(),
+            verified: Cell::new(false),
         }
     }
 
@@ -35,6 +53,11 @@ impl Chunk {
         Some(u16::from_be_bytes(bytes))
     }
 
+    pub fn get_wide_bytes(&self, start: usize) -> Option<u32> {
+        let bytes = self.code.get(start..start + 4)?;
+        Some(u32::from_be_bytes(bytes.try_into().ok()?))
+    }
+
     pub fn get_constant(&self, i: usize) -> Option<&Value> {
         self.constants.get(i)
     }
@@ -43,7 +66,12 @@ impl Chunk {
         self.locations.get(i)
     }
 
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
     pub fn write_code(&mut self, code: u8) {
+        self.verified.set(false);
         self.code.push(code);
     }
 
@@ -56,13 +84,104 @@ impl Chunk {
         self.locations.push(l)
     }
 
+    pub fn add_handler(&mut self, handler: Handler) {
+        self.verified.set(false);
+        self.handlers.push(handler);
+    }
+
+    pub fn handlers(&self) -> &[Handler] {
+        &self.handlers
+    }
+
+    // the innermost handler of the instruction read up to `ip`
+    pub fn handler(&self, ip: usize) -> Option<&Handler> {
+        self.handlers.iter().find(|h| h.start < ip && ip <= h.end)
+    }
+
     pub fn get_code_len(&self) -> usize {
         self.code.len()
     }
 
     pub fn backfill(&mut self, ip: usize, byte: u8) {
+        self.verified.set(false);
         self.code[ip] = byte;
     }
+
+    pub fn is_verified(&self) -> bool {
+        self.verified.get()
+    }
+
+    // check the code can be run without reading out of it: every opcode is known, its
+    // operands and its constants are there, the jumps and the handlers go to an
+    // instruction and the last one doesn't go on past the end. the chunks of the
+    // functions in the constants are checked when they are called.
+    pub fn verify(&self) -> Result<(), RuntimeError> {
+        if self.verified.get() {
+            return Ok(());
+        }
+        let code = &self.code;
+        let mut starts = vec![false; code.len()];
+        let mut targets = Vec::new();
+        let mut last = None;
+        let mut ip = 0;
+        while ip < code.len() {
+            starts[ip] = true;
+            let op = OpCode::try_from(code[ip]).map_err(RuntimeError::InvalidOpCode)?;
+            let at = ip + 1;
+            let mut next = at + op.operands();
+            if next > code.len() {
+                return Err(RuntimeError::UnexpectedEnd);
+            }
+            let long = || usize::from(u16::from_be_bytes([code[at], code[at + 1]]));
+            let wide = |i: usize| u32::from_be_bytes(code[i..i + 4].try_into().unwrap()) as usize;
+            let constant = match op {
+                OpCode::Constant => Some(usize::from(code[at])),
+                OpCode::ConstantL => Some(long()),
+                OpCode::ConstantW => Some(wide(at)),
+                OpCode::AddLocalConstant => Some(usize::from(code[at + 1])),
+                _ => None,
+            };
+            if let Some(i) = constant.filter(|i| *i >= self.constants.len()) {
+                return Err(RuntimeError::InvalidConstant(i));
+            }
+            match op {
+                OpCode::Jump
+                | OpCode::JumpIfFalse
+                | OpCode::ForIter
+                | OpCode::JumpIfOk
+                | OpCode::LtJumpIfFalse => targets.push((ip, Some(next + wide(at)))),
+                OpCode::JumpBack => targets.push((ip, at.checked_sub(long()))),
+                OpCode::JumpBackW => targets.push((ip, at.checked_sub(wide(at)))),
+                OpCode::SwitchTag => {
+                    let n = usize::from(code[at]);
+                    next = at + 1 + (n + 1) * 4;
+                    if next > code.len() {
+                        return Err(RuntimeError::UnexpectedEnd);
+                    }
+                    let entries = (0..=n).map(|entry| (ip, Some(next + wide(at + 1 + entry * 4))));
+                    targets.extend(entries);
+                }
+                _ => {}
+            }
+            last = Some(op);
+            ip = next;
+        }
+        if !last.is_some_and(OpCode::ends_block) {
+            return Err(RuntimeError::UnexpectedEnd);
+        }
+
+        for h in &self.handlers {
+            let target = (h.start <= h.end && h.end <= code.len()).then_some(h.target);
+            targets.push((h.start, target));
+        }
+        for (ip, target) in targets {
+            if target.and_then(|target| starts.get(target)) != Some(&true) {
+                return Err(RuntimeError::InvalidJump(ip));
+            }
+        }
+        self.verified.set(true);
+        Ok(())
+    }
 }
 
 impl Default for Chunk {
@@ -70,3 +189,69 @@ impl Default for Chunk {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Chunk, Handler};
+    use crate::{error::RuntimeError, op::OpCode, value::Value};
+
+    fn chunk(codes: &[u8]) -> Chunk {
+        let mut chunk = Chunk::new();
+        chunk.write_constant(Value::Int(1));
+        for code in codes {
+            chunk.write_code(*code);
+        }
+        chunk
+    }
+
+    #[test]
+    fn test_verify() {
+        let jump = OpCode::Jump as u8;
+        let ok = chunk(&[
+            OpCode::Constant as u8,
+            0,
+            jump,
+            0,
+            0,
+            0,
+            0,
+            OpCode::Return as u8,
+        ]);
+        assert_eq!(ok.verify(), Ok(()));
+        assert!(ok.is_verified());
+
+        let cases = [
+            (vec![], RuntimeError::UnexpectedEnd),
+            (vec![OpCode::Nil as u8], RuntimeError::UnexpectedEnd),
+            (vec![OpCode::Constant as u8], RuntimeError::UnexpectedEnd),
+            (vec![0xFF], RuntimeError::InvalidOpCode(0xFF)),
+            (
+                vec![OpCode::Constant as u8, 1, OpCode::Return as u8],
+                RuntimeError::InvalidConstant(1),
+            ),
+            // past the end of the code, then before its start
+            (
+                vec![OpCode::Constant as u8, 0, jump, 0xFF, 0xFF, 0xFF, 0xFA],
+                RuntimeError::InvalidJump(2),
+            ),
+            (
+                vec![OpCode::JumpBack as u8, 0, 2],
+                RuntimeError::InvalidJump(0),
+            ),
+        ];
+        for (codes, error) in cases {
+            assert_eq!(chunk(&codes).verify(), Err(error));
+        }
+
+        let mut chunk = chunk(&[OpCode::Nil as u8, OpCode::Return as u8]);
+        chunk.verify().unwrap();
+        chunk.add_handler(Handler {
+            start: 0,
+            end: 1,
+            target: 5,
+            height: 0,
+        });
+        assert!(!chunk.is_verified());
+        assert_eq!(chunk.verify(), Err(RuntimeError::InvalidJump(0)));
+    }
+}
@@ -0,0 +1,259 @@
+// conversions between rust values and script values, e.g. for the arguments and
+// the result of a native function, or the globals read by the host.
+
+use std::{cell::RefCell, collections::HashMap, hash::Hash, rc::Rc};
+
+use crate::{error::RuntimeError, map::Map, prelude, value::Value};
+
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+pub trait FromValue: Sized {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError>;
+}
+
+fn expected(name: &str, value: &Value) -> RuntimeError {
+    RuntimeError::TypeError(format!(
+        "expected `{}`, found `{}`",
+        name,
+        value.type_name()
+    ))
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Nil
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(expected("bool", value)),
+        }
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Value {
+        Value::Int(self)
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Int(v) => Ok(*v),
+            _ => Err(expected("int", value)),
+        }
+    }
+}
+
+impl IntoValue for i32 {
+    fn into_value(self) -> Value {
+        Value::Int(self as i64)
+    }
+}
+
+impl FromValue for i32 {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        let v = i64::from_value(value)?;
+        i32::try_from(v).map_err(|_| RuntimeError::TypeError(format!("{} is out of i32", v)))
+    }
+}
+
+impl IntoValue for usize {
+    fn into_value(self) -> Value {
+        Value::Int(self as i64)
+    }
+}
+
+impl FromValue for usize {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        let v = i64::from_value(value)?;
+        usize::try_from(v).map_err(|_| RuntimeError::TypeError(format!("{} is out of usize", v)))
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Value {
+        Value::Float(self)
+    }
+}
+
+// an int is converted too, e.g. `1` for a `f64` argument
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Float(v) => Ok(*v),
+            Value::Int(v) => Ok(*v as f64),
+            _ => Err(expected("float", value)),
+        }
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        Value::Str(self.into())
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        Value::Str(self.into())
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Str(s) => Ok(s.to_string()),
+            _ => Err(expected("str", value)),
+        }
+    }
+}
+
+// the `Option` and the `Result` of the prelude, see `prelude`
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(v) => prelude::some(v.into_value()),
+            None => prelude::none(),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match prelude::variant_of(value, "Option") {
+            Some((0, fields)) => T::from_value(&fields[0]).map(Some),
+            Some(_) => Ok(None),
+            None => Err(expected("Option", value)),
+        }
+    }
+}
+
+impl<T: IntoValue, E: IntoValue> IntoValue for Result<T, E> {
+    fn into_value(self) -> Value {
+        match self {
+            Ok(v) => prelude::ok(v.into_value()),
+            Err(e) => prelude::err(e.into_value()),
+        }
+    }
+}
+
+impl<T: FromValue, E: FromValue> FromValue for Result<T, E> {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match prelude::variant_of(value, "Result") {
+            Some((0, fields)) => T::from_value(&fields[0]).map(Ok),
+            Some((_, fields)) => E::from_value(&fields[0]).map(Err),
+            None => Err(expected("Result", value)),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        let items = self.into_iter().map(IntoValue::into_value).collect();
+        Value::List(Rc::new(RefCell::new(items)))
+    }
+}
+
+// from a list or a tuple
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::List(items) => items.borrow().iter().map(T::from_value).collect(),
+            Value::Tuple(items) => items.iter().map(T::from_value).collect(),
+            _ => Err(expected("list", value)),
+        }
+    }
+}
+
+// the keys which can't be hashed by the scripts are skipped, e.g. floats
+impl<K: IntoValue, V: IntoValue> IntoValue for HashMap<K, V> {
+    fn into_value(self) -> Value {
+        let mut map = Map::new();
+        for (k, v) in self {
+            let _ = map.insert(k.into_value(), v.into_value());
+        }
+        Value::Map(Rc::new(RefCell::new(map)))
+    }
+}
+
+impl<K: FromValue + Eq + Hash, V: FromValue> FromValue for HashMap<K, V> {
+    fn from_value(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Map(map) => map
+                .borrow()
+                .entries()
+                .map(|(k, v)| Ok((K::from_value(k)?, V::from_value(v)?)))
+                .collect(),
+            _ => Err(expected("map", value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{error::RuntimeError, value::Value};
+
+    use super::{FromValue, IntoValue};
+
+    #[test]
+    fn test_round_trip() {
+        let list = vec![Some(1i64), None, Some(3)].into_value();
+        assert_eq!(list.to_string(), "[Some(1), None, Some(3)]");
+        assert_eq!(
+            Vec::<Option<i64>>::from_value(&list),
+            Ok(vec![Some(1), None, Some(3)])
+        );
+
+        let results = vec![Ok(1i64), Err("e".to_owned())].into_value();
+        assert_eq!(results.to_string(), "[Ok(1), Err(\"e\")]");
+        assert_eq!(
+            Vec::<Result<i64, String>>::from_value(&results),
+            Ok(vec![Ok(1), Err("e".to_owned())])
+        );
+
+        let map = HashMap::from([("a".to_owned(), 1.5)]).into_value();
+        assert_eq!(map.to_string(), "#{\"a\": 1.5}");
+        let back = HashMap::<String, f64>::from_value(&map).unwrap();
+        assert_eq!(back["a"], 1.5);
+
+        assert_eq!(f64::from_value(&Value::Int(2)), Ok(2.0));
+        assert_eq!("s".into_value(), Value::Str("s".into()));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = RuntimeError::TypeError("expected `int`, found `str`".to_owned());
+        assert_eq!(i64::from_value(&"1".into_value()), Err(error));
+        assert!(i32::from_value(&Value::Int(1 << 40)).is_err());
+        assert!(Vec::<i64>::from_value(&vec![1.5].into_value()).is_err());
+        assert!(Option::<i64>::from_value(&Value::Nil).is_err());
+        assert!(Option::<i64>::from_value(&Ok::<i64, i64>(1).into_value()).is_err());
+    }
+}
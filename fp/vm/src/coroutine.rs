@@ -0,0 +1,76 @@
+// a function run on its own stack, it stops at each `yield` until it is resumed.
+// a `fn*` function makes one when it is called, `coroutine(f)` makes one from any function.
+
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Ordering,
+    fmt,
+};
+
+use crate::{stack::Stack, value::Value, vm::Context};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    // not started yet, or stopped at a `yield`
+    Suspended,
+    Running,
+    // it resumed another coroutine and waits for it
+    Normal,
+    // returned or failed
+    Dead,
+}
+
+impl Status {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Status::Suspended => "suspended",
+            Status::Running => "running",
+            Status::Normal => "normal",
+            Status::Dead => "dead",
+        }
+    }
+}
+
+pub struct Coroutine {
+    pub(crate) status: Cell<Status>,
+    pub(crate) started: Cell<bool>,
+    // the stack and the frames while it isn't running, the function and its arguments
+    // are on the stack until it starts.
+    pub(crate) context: RefCell<Option<Context>>,
+}
+
+impl Coroutine {
+    pub fn new(fun: Value, args: &[Value]) -> Coroutine {
+        let mut stack = Stack::with_capacity(args.len() + 1);
+        stack.push(fun);
+        stack.extend(args.iter().cloned());
+        Coroutine {
+            status: Cell::new(Status::Suspended),
+            started: Cell::new(false),
+            context: RefCell::new(Some(Context::new(stack))),
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        self.status.get()
+    }
+}
+
+impl fmt::Debug for Coroutine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<coroutine {}>", self.status().name())
+    }
+}
+
+// a coroutine is only equal to itself
+impl PartialEq for Coroutine {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl PartialOrd for Coroutine {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (self == other).then_some(Ordering::Equal)
+    }
+}
@@ -1 +1,249 @@
+use std::fmt::Write;
 
+use crate::{
+    chunk::Chunk,
+    op::OpCode,
+    register::{Code, Instr},
+    value::Value,
+};
+
+// how the operands after an opcode are read
+#[derive(Clone, Copy)]
+enum Operand {
+    None,
+    Byte,
+    Long,
+    Constant,
+    ConstantL,
+    ConstantW,
+    // a slot and a constant
+    LocalConstant,
+    // a u32 offset forward
+    Jump,
+    // a u16 or u32 offset backward
+    JumpBack,
+    JumpBackW,
+    // the count of entries and a u32 offset for each of them and the fallback
+    Table,
+}
+
+fn operand(op: OpCode) -> Operand {
+    match op {
+        OpCode::Constant => Operand::Constant,
+        OpCode::ConstantL => Operand::ConstantL,
+        OpCode::ConstantW => Operand::ConstantW,
+        OpCode::AddLocalConstant => Operand::LocalConstant,
+        OpCode::Jump
+        | OpCode::JumpIfFalse
+        | OpCode::ForIter
+        | OpCode::JumpIfOk
+        | OpCode::LtJumpIfFalse => Operand::Jump,
+        OpCode::JumpBack => Operand::JumpBack,
+        OpCode::JumpBackW => Operand::JumpBackW,
+        OpCode::SwitchTag => Operand::Table,
+        op => match op.operands() {
+            0 => Operand::None,
+            1 => Operand::Byte,
+            _ => Operand::Long,
+        },
+    }
+}
+
+// one instruction a line, e.g.
+// 0000 Constant        0 (1)
+// 0002 JumpIfFalse     5 (-> 0012)
+// the functions in the constants follow, each under a `fn name:` line.
+pub fn disassemble(chunk: &Chunk) -> String {
+    let mut out = String::new();
+    let mut ip = 0;
+    while ip < chunk.get_code_len() {
+        ip = instruction(chunk, ip, &mut out);
+        out.push('\n');
+    }
+    for h in chunk.handlers() {
+        writeln!(
+            out,
+            "try {:04}..{:04} -> {:04}, height {}",
+            h.start, h.end, h.target, h.height
+        )
+        .unwrap();
+    }
+    let functions = (0..).map_while(|i| chunk.get_constant(i));
+    write_functions(functions, &mut out);
+    out
+}
+
+// the code of the register backend, e.g.
+// 0000 Constant r1, #0 (1)
+// 0001 Add r2, r0, r1
+pub fn disassemble_registers(code: &Code) -> String {
+    let mut out = String::new();
+    for (i, instr) in code.instrs().iter().enumerate() {
+        write!(out, "{:04} {}", i, instr).unwrap();
+        if let Instr::Constant { i, .. } = instr {
+            match &code.constants()[*i as usize] {
+                Value::Str(s) => write!(out, " ({:?})", s).unwrap(),
+                value => write!(out, " ({})", value).unwrap(),
+            }
+        }
+        out.push('\n');
+    }
+    write_functions(code.constants().iter(), &mut out);
+    out
+}
+
+// the functions in the constants, each under a `fn name:` line
+fn write_functions<'a>(constants: impl Iterator<Item = &'a Value>, out: &mut String) {
+    for value in constants {
+        if let Value::Function(fun) = value {
+            write!(out, "\nfn {}:\n", fun.name).unwrap();
+            match &fun.registers {
+                Some(code) => out.push_str(&disassemble_registers(code)),
+                None => out.push_str(&disassemble(&fun.chunk)),
+            }
+        }
+    }
+}
+
+// write the instruction at `ip`, it returns the start of the next one
+fn instruction(chunk: &Chunk, ip: usize, out: &mut String) -> usize {
+    let byte = chunk.get_byte(ip).unwrap_or_default();
+    let op = match OpCode::try_from(byte) {
+        Ok(op) => op,
+        Err(_) => {
+            write!(out, "{:04} <invalid {:#04x}>", ip, byte).unwrap();
+            return ip + 1;
+        }
+    };
+    let (name, operand) = (format!("{:?}", op), operand(op));
+    write!(out, "{:04} ", ip).unwrap();
+    match operand {
+        Operand::None => out.push_str(&name),
+        _ => write!(out, "{:<15}", name).unwrap(),
+    }
+
+    let start = ip + 1;
+    let constant = |out: &mut String, i: Option<usize>| match i {
+        Some(i) => match chunk.get_constant(i) {
+            Some(Value::Str(s)) => write!(out, " {} ({:?})", i, s).unwrap(),
+            Some(value) => write!(out, " {} ({})", i, value).unwrap(),
+            None => write!(out, " {} <invalid>", i).unwrap(),
+        },
+        None => out.push_str(" <end>"),
+    };
+    match operand {
+        Operand::None => start,
+        Operand::Byte => {
+            write_operand(out, chunk.get_byte(start));
+            start + 1
+        }
+        Operand::Long => {
+            write_operand(out, chunk.get_long_bytes(start));
+            start + 2
+        }
+        Operand::Constant => {
+            constant(out, chunk.get_byte(start).map(usize::from));
+            start + 1
+        }
+        Operand::ConstantL => {
+            constant(out, chunk.get_long_bytes(start).map(usize::from));
+            start + 2
+        }
+        Operand::ConstantW => {
+            constant(out, chunk.get_wide_bytes(start).map(|i| i as usize));
+            start + 4
+        }
+        Operand::LocalConstant => {
+            write_operand(out, chunk.get_byte(start));
+            constant(out, chunk.get_byte(start + 1).map(usize::from));
+            start + 2
+        }
+        Operand::Jump => {
+            let next = start + 4;
+            let offset = chunk.get_wide_bytes(start).map(|o| o as usize);
+            write_jump(out, offset, |o| next + o);
+            next
+        }
+        Operand::JumpBack => {
+            let offset = chunk.get_long_bytes(start).map(usize::from);
+            write_jump(out, offset, |o| start.wrapping_sub(o));
+            start + 2
+        }
+        Operand::JumpBackW => {
+            let offset = chunk.get_wide_bytes(start).map(|o| o as usize);
+            write_jump(out, offset, |o| start.wrapping_sub(o));
+            start + 4
+        }
+        Operand::Table => {
+            let n = match chunk.get_byte(start) {
+                Some(n) => n as usize,
+                None => {
+                    out.push_str(" <end>");
+                    return start;
+                }
+            };
+            write!(out, " {}", n).unwrap();
+            let next = start + 1 + (n + 1) * 4;
+            for entry in 0..=n {
+                let offset = chunk.get_wide_bytes(start + 1 + entry * 4);
+                match offset {
+                    Some(o) => write!(out, " {:04}", next + o as usize).unwrap(),
+                    None => out.push_str(" <end>"),
+                }
+            }
+            next
+        }
+    }
+}
+
+fn write_operand(out: &mut String, operand: Option<impl std::fmt::Display>) {
+    match operand {
+        Some(operand) => write!(out, " {}", operand).unwrap(),
+        None => out.push_str(" <end>"),
+    }
+}
+
+fn write_jump(out: &mut String, offset: Option<usize>, target: impl Fn(usize) -> usize) {
+    match offset {
+        Some(offset) => write!(out, " {} (-> {:04})", offset, target(offset)).unwrap(),
+        None => out.push_str(" <end>"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{chunk::Chunk, op::OpCode, value::Value};
+
+    use super::disassemble;
+
+    #[test]
+    fn test_disassemble() {
+        let mut chunk = Chunk::new();
+        let i = chunk.write_constant(Value::Int(1));
+        let codes = [
+            OpCode::Constant as u8,
+            i as u8,
+            OpCode::JumpIfFalse as u8,
+            0,
+            0,
+            0,
+            1,
+            OpCode::Nil as u8,
+            OpCode::JumpBack as u8,
+            0,
+            9,
+            OpCode::Return as u8,
+        ];
+        for code in codes {
+            chunk.write_code(code);
+        }
+        let expect = "\
+0000 Constant        0 (1)
+0002 JumpIfFalse     1 (-> 0008)
+0007 Nil
+0008 JumpBack        9 (-> 0000)
+0011 Return
+";
+        assert_eq!(disassemble(&chunk), expect);
+    }
+}
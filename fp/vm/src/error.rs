@@ -0,0 +1,156 @@
+use core::fmt;
+
+use crate::{native::Arity, value::Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeError {
+    // the chunk is broken, these should never happen with a chunk from the compiler
+    UnexpectedEnd,
+    InvalidOpCode(u8),
+    InvalidConstant(usize),
+    // the jump (or the start of the `try`) at the position goes somewhere which isn't
+    // an instruction
+    InvalidJump(usize),
+    StackUnderflow,
+
+    // errors caused by the running program
+    UndefinedGlobal(u16),
+    TypeError(String),
+    IndexOutOfRange(usize),
+    DivisionByZero,
+    // an int out of 64 bits
+    Overflow,
+    KeyNotFound(String),
+    NoMatchArm,
+    NotCallable(String),
+    WrongArity {
+        name: String,
+        arity: Arity,
+        found: usize,
+    },
+    // `panic` or a failed `assert`
+    Panic(String),
+    Io(String),
+    // `throw value` and nothing caught it
+    Thrown(Value),
+    // a native function needs a capability the vm doesn't have
+    PermissionDenied(String),
+    // a `yield` where no host can resume, e.g. in a function called by a native one
+    CantSuspend,
+    NotSuspended,
+    // a chunk started while one is running, e.g. by a native function
+    AlreadyRunning,
+    // an error in a coroutine, with the functions it went through, see `Vm::coroutine_error`
+    Coroutine {
+        error: Box<RuntimeError>,
+        trace: Vec<String>,
+    },
+
+    // a limit of the run is reached, see `Limits`
+    OutOfFuel,
+    CallDepthExceeded,
+    StackOverflow,
+    OutOfMemory,
+    Timeout,
+    Interrupted,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use RuntimeError::*;
+
+        match self {
+            UnexpectedEnd => write!(f, "unexpected end of bytecode"),
+            InvalidOpCode(byte) => write!(f, "invalid opcode {:#04x}", byte),
+            InvalidConstant(i) => write!(f, "invalid constant index {}", i),
+            InvalidJump(ip) => write!(f, "invalid jump at {}", ip),
+            StackUnderflow => write!(f, "stack underflow"),
+            UndefinedGlobal(i) => write!(f, "undefined global #{}", i),
+            TypeError(msg) => write!(f, "type error: {}", msg),
+            IndexOutOfRange(i) => write!(f, "index {} out of range", i),
+            DivisionByZero => write!(f, "division by zero"),
+            Overflow => write!(f, "integer overflow"),
+            KeyNotFound(key) => write!(f, "key {} not found", key),
+            NoMatchArm => write!(f, "no match arm matched the value"),
+            NotCallable(type_name) => write!(f, "`{}` is not callable", type_name),
+            WrongArity { name, arity, found } => {
+                write!(f, "`{}` takes {}, found {}", name, arity, found)
+            }
+            Panic(message) => write!(f, "panic: {}", message),
+            Io(message) => write!(f, "io error: {}", message),
+            Thrown(value) => write!(f, "uncaught {}", value),
+            PermissionDenied(what) => write!(f, "permission denied: {}", what),
+            CantSuspend => write!(f, "the run can't be suspended here"),
+            NotSuspended => write!(f, "there is no suspended run to resume"),
+            AlreadyRunning => write!(f, "a chunk can't start while one is running"),
+            Coroutine { error, trace } => write!(f, "{}\n  trace: {}", error, trace.join(" <- ")),
+            OutOfFuel => write!(f, "the instruction budget is used up"),
+            CallDepthExceeded => write!(f, "too many nested calls"),
+            StackOverflow => write!(f, "too many values in the stack"),
+            OutOfMemory => write!(f, "the memory cap is reached"),
+            Timeout => write!(f, "the time is up"),
+            Interrupted => write!(f, "interrupted"),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl RuntimeError {
+    // the `kind` of the error seen by `catch`
+    pub fn kind(&self) -> &'static str {
+        use RuntimeError::*;
+
+        match self {
+            UnexpectedEnd | InvalidOpCode(_) | InvalidConstant(_) | InvalidJump(_)
+            | StackUnderflow => "BrokenChunk",
+            UndefinedGlobal(_) => "UndefinedGlobal",
+            TypeError(_) => "TypeError",
+            IndexOutOfRange(_) => "IndexError",
+            DivisionByZero => "DivisionByZero",
+            Overflow => "Overflow",
+            KeyNotFound(_) => "KeyError",
+            NoMatchArm => "NoMatchArm",
+            NotCallable(_) => "NotCallable",
+            WrongArity { .. } => "WrongArity",
+            Panic(_) => "Panic",
+            Io(_) => "IoError",
+            Thrown(_) => "Thrown",
+            PermissionDenied(_) => "PermissionDenied",
+            CantSuspend | NotSuspended => "CantSuspend",
+            AlreadyRunning => "AlreadyRunning",
+            Coroutine { error, .. } => error.kind(),
+            OutOfFuel | CallDepthExceeded | StackOverflow | OutOfMemory | Timeout | Interrupted => {
+                "Limit"
+            }
+        }
+    }
+
+    // the `message` of the error seen by `catch`, without the kind
+    pub fn message(&self) -> String {
+        use RuntimeError::*;
+
+        match self {
+            TypeError(message) | Panic(message) | Io(message) | PermissionDenied(message) => {
+                message.clone()
+            }
+            Coroutine { error, .. } => error.message(),
+            error => error.to_string(),
+        }
+    }
+
+    // the limits and a broken chunk always abort the run
+    pub fn is_catchable(&self) -> bool {
+        use RuntimeError::*;
+
+        match self {
+            UnexpectedEnd | InvalidOpCode(_) | InvalidConstant(_) | InvalidJump(_)
+            | StackUnderflow => false,
+            OutOfFuel | CallDepthExceeded | StackOverflow | OutOfMemory | Timeout | Interrupted => {
+                false
+            }
+            Coroutine { error, .. } => error.is_catchable(),
+            _ => true,
+        }
+    }
+}
@@ -0,0 +1,502 @@
+// the heap of the values made by the scripts: tuples, lists, maps, enums, structs,
+// iterators and coroutines. the values are `Rc`s, so most of them are freed as soon as
+// they aren't used, the collector is for the cycles, e.g. a list pushed into itself.
+//
+// a collection marks what is reachable from the roots and clears the lists, maps,
+// structs and coroutines which are not, which breaks the cycles. the roots are the
+// stack, the globals, the frames and the parked coroutines of the vm, and the values
+// kept by the host or a native function: an object with more `Rc`s than the heap
+// accounts for is used from outside of it. there are no upvalues, a function only
+// sees its locals and the globals.
+//
+// the values are `Rc`s rather than handles into the heap, a clone is a count and the
+// host keeps a value like any rust value, without rooting it. what keeps a collection
+// from clearing a value held out of the vm is this invariant: every `Rc` of an object
+// is held by another object, seen by `children`, or it is a root. a value in a rust
+// local of a native function, in the host or in a `UserData` is an `Rc` no object
+// holds, so it and what it holds are marked. it fails safe: an `Rc` `children` misses
+// makes its object a root, the object may leak but is never cleared while it is used.
+//
+// in the generational mode most collections only look at the objects made since the
+// last one, the nursery, so a pause doesn't grow with the heap. the others are old,
+// an old object holding a young one is an `Rc` the nursery doesn't account for, it
+// needs no write barrier. the whole heap is collected when it grows like in the
+// stop-the-world mode.
+
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::{Rc, Weak},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    coroutine::{Coroutine, Status},
+    iter::Iter,
+    limits,
+    map::Map,
+    object::{EnumObj, StructObj},
+    value::Value,
+};
+
+// the heap isn't collected before it has this many bytes
+const MIN_THRESHOLD: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GcMode {
+    // every collection looks at the whole heap
+    #[default]
+    StopTheWorld,
+    // the nursery is collected more often than the whole heap, for short pauses
+    Generational,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GcConfig {
+    pub mode: GcMode,
+    // the whole heap is collected when it grows to `growth` times what was left
+    pub growth: f64,
+    // bytes of the heap before the first collection
+    pub threshold: usize,
+    // bytes allocated before the nursery is collected, in the generational mode
+    pub nursery: usize,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            mode: GcMode::StopTheWorld,
+            growth: 2.0,
+            threshold: MIN_THRESHOLD,
+            nursery: MIN_THRESHOLD / 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GcStats {
+    pub collections: u64,
+    // the collections of the nursery only, they are in `collections` too
+    pub minor: u64,
+    // the longest collection
+    pub max_pause: Duration,
+    // objects cleared by the collections, the ones freed by their `Rc` aren't counted
+    pub swept: u64,
+    // objects alive and their bytes, as of the last collection
+    pub objects: usize,
+    pub bytes: usize,
+    // bytes allocated since the last collection, the whole heap is collected when
+    // `bytes + allocated` is over the threshold.
+    pub allocated: usize,
+    pub threshold: usize,
+}
+
+enum Object {
+    Tuple(Weak<Vec<Value>>),
+    List(Weak<RefCell<Vec<Value>>>),
+    Map(Weak<RefCell<Map>>),
+    Enum(Weak<EnumObj>),
+    Struct(Weak<StructObj>),
+    Iter(Weak<RefCell<Iter>>),
+    Coroutine(Weak<Coroutine>),
+}
+
+impl Object {
+    fn new(value: &Value) -> Option<Object> {
+        let object = match value {
+            Value::Tuple(items) => Object::Tuple(Rc::downgrade(items)),
+            Value::List(items) => Object::List(Rc::downgrade(items)),
+            Value::Map(map) => Object::Map(Rc::downgrade(map)),
+            Value::Enum(e) => Object::Enum(Rc::downgrade(e)),
+            Value::Struct(s) => Object::Struct(Rc::downgrade(s)),
+            Value::Iter(iter) => Object::Iter(Rc::downgrade(iter)),
+            Value::Coroutine(co) => Object::Coroutine(Rc::downgrade(co)),
+            _ => return None,
+        };
+        Some(object)
+    }
+
+    // None once it is freed
+    fn upgrade(&self) -> Option<Value> {
+        let value = match self {
+            Object::Tuple(items) => Value::Tuple(items.upgrade()?),
+            Object::List(items) => Value::List(items.upgrade()?),
+            Object::Map(map) => Value::Map(map.upgrade()?),
+            Object::Enum(e) => Value::Enum(e.upgrade()?),
+            Object::Struct(s) => Value::Struct(s.upgrade()?),
+            Object::Iter(iter) => Value::Iter(iter.upgrade()?),
+            Object::Coroutine(co) => Value::Coroutine(co.upgrade()?),
+        };
+        Some(value)
+    }
+
+    // the address stays with the `Weak` when the object is freed
+    fn address(&self) -> *const () {
+        match self {
+            Object::Tuple(items) => items.as_ptr() as *const (),
+            Object::List(items) => items.as_ptr() as *const (),
+            Object::Map(map) => map.as_ptr() as *const (),
+            Object::Enum(e) => e.as_ptr() as *const (),
+            Object::Struct(s) => s.as_ptr() as *const (),
+            Object::Iter(iter) => iter.as_ptr() as *const (),
+            Object::Coroutine(co) => co.as_ptr() as *const (),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match self {
+            Object::Tuple(items) => items.strong_count() > 0,
+            Object::List(items) => items.strong_count() > 0,
+            Object::Map(map) => map.strong_count() > 0,
+            Object::Enum(e) => e.strong_count() > 0,
+            Object::Struct(s) => s.strong_count() > 0,
+            Object::Iter(iter) => iter.strong_count() > 0,
+            Object::Coroutine(co) => co.strong_count() > 0,
+        }
+    }
+}
+
+pub(crate) struct Heap {
+    // the old objects first, then the nursery
+    objects: Vec<Object>,
+    old: usize,
+    // the addresses of the objects, a freed one isn't reused while its `Weak` is here
+    tracked: HashSet<*const ()>,
+    config: GcConfig,
+    stats: GcStats,
+}
+
+impl Heap {
+    pub(crate) fn new(config: GcConfig) -> Heap {
+        Heap {
+            objects: Vec::new(),
+            old: 0,
+            tracked: HashSet::new(),
+            config,
+            stats: GcStats {
+                threshold: config.threshold,
+                ..GcStats::default()
+            },
+        }
+    }
+
+    pub(crate) fn config(&self) -> &GcConfig {
+        &self.config
+    }
+
+    pub(crate) fn set_config(&mut self, config: GcConfig) {
+        self.config = config;
+        self.stats.threshold = config.threshold.max(self.next_threshold());
+    }
+
+    pub(crate) fn stats(&self) -> GcStats {
+        GcStats {
+            objects: self.objects.len(),
+            ..self.stats
+        }
+    }
+
+    // add a value just made and the objects in it which aren't in the heap yet,
+    // true when it is time to collect.
+    pub(crate) fn track(&mut self, value: &Value) -> bool {
+        if address(value).is_none() {
+            return false;
+        }
+        let mut todo = vec![value.clone()];
+        while let Some(value) = todo.pop() {
+            let Some(object) = Object::new(&value) else {
+                continue;
+            };
+            if !self.tracked.insert(address(&value).unwrap()) {
+                continue;
+            }
+            self.objects.push(object);
+            self.stats.allocated += limits::shallow_size(&value);
+            children(&value, &mut |child| todo.push(child.clone()));
+        }
+        let limit = match self.config.mode {
+            GcMode::StopTheWorld => self.stats.threshold.saturating_sub(self.stats.bytes),
+            GcMode::Generational => self.config.nursery,
+        };
+        cfg!(feature = "gc_stress") || self.stats.allocated >= limit
+    }
+
+    // collect the nursery or the whole heap, see `GcMode`
+    pub(crate) fn collect<'a>(&mut self, roots: impl Iterator<Item = Cow<'a, Value>>) {
+        let full = self.config.mode == GcMode::StopTheWorld
+            || self.stats.bytes + self.stats.allocated >= self.stats.threshold;
+        self.collect_from(if full { 0 } else { self.old }, roots);
+    }
+
+    pub(crate) fn collect_all<'a>(&mut self, roots: impl Iterator<Item = Cow<'a, Value>>) {
+        self.collect_from(0, roots);
+    }
+
+    // the objects before `start` are out of the collection, what they hold is a root
+    fn collect_from<'a>(&mut self, start: usize, roots: impl Iterator<Item = Cow<'a, Value>>) {
+        let begin = Instant::now();
+        let live: Vec<Value> = self.objects[start..]
+            .iter()
+            .filter_map(Object::upgrade)
+            .collect();
+        let index: HashMap<*const (), usize> = live
+            .iter()
+            .enumerate()
+            .map(|(i, value)| (address(value).unwrap(), i))
+            .collect();
+
+        // the `Rc`s of each object held by the others, an object borrowed right now
+        // can't be looked into, it is a root and what it holds is used from outside.
+        let mut held = vec![0; live.len()];
+        let mut todo = Vec::new();
+        for (i, value) in live.iter().enumerate() {
+            let seen = children(value, &mut |child| {
+                if let Some(&j) = address(child).and_then(|a| index.get(&a)) {
+                    held[j] += 1;
+                }
+            });
+            if !seen {
+                todo.push(i);
+            }
+        }
+        // one `Rc` is in `live`
+        for (i, value) in live.iter().enumerate() {
+            if strong_count(value) > held[i] + 1 {
+                todo.push(i);
+            }
+        }
+        todo.extend(roots.filter_map(|root| index.get(&address(&root)?)));
+
+        let mut marked = vec![false; live.len()];
+        while let Some(i) = todo.pop() {
+            if std::mem::replace(&mut marked[i], true) {
+                continue;
+            }
+            children(&live[i], &mut |child| {
+                if let Some(&j) = address(child).and_then(|a| index.get(&a)) {
+                    if !marked[j] {
+                        todo.push(j);
+                    }
+                }
+            });
+        }
+
+        let mut bytes = 0;
+        for (value, marked) in live.iter().zip(&marked) {
+            if *marked {
+                bytes += limits::shallow_size(value);
+            } else {
+                clear(value);
+                self.stats.swept += 1;
+            }
+        }
+        // the cleared objects are freed here
+        drop(live);
+        let mut young = self.objects.split_off(start);
+        young.retain(|object| {
+            let alive = object.is_alive();
+            if !alive {
+                self.tracked.remove(&object.address());
+            }
+            alive
+        });
+        self.objects.append(&mut young);
+        self.old = self.objects.len();
+
+        self.stats.collections += 1;
+        if start == 0 {
+            self.stats.bytes = bytes;
+            self.stats.threshold = self.next_threshold();
+        } else {
+            self.stats.minor += 1;
+            self.stats.bytes += bytes;
+        }
+        self.stats.allocated = 0;
+        self.stats.max_pause = self.stats.max_pause.max(begin.elapsed());
+    }
+
+    fn next_threshold(&self) -> usize {
+        ((self.stats.bytes as f64 * self.config.growth) as usize).max(self.config.threshold)
+    }
+}
+
+// where the object is, None for the values which aren't in the heap
+fn address(value: &Value) -> Option<*const ()> {
+    let ptr = match value {
+        Value::Tuple(items) => Rc::as_ptr(items) as *const (),
+        Value::List(items) => Rc::as_ptr(items) as *const (),
+        Value::Map(map) => Rc::as_ptr(map) as *const (),
+        Value::Enum(e) => Rc::as_ptr(e) as *const (),
+        Value::Struct(s) => Rc::as_ptr(s) as *const (),
+        Value::Iter(iter) => Rc::as_ptr(iter) as *const (),
+        Value::Coroutine(co) => Rc::as_ptr(co) as *const (),
+        _ => return None,
+    };
+    Some(ptr)
+}
+
+fn strong_count(value: &Value) -> usize {
+    match value {
+        Value::Tuple(items) => Rc::strong_count(items),
+        Value::List(items) => Rc::strong_count(items),
+        Value::Map(map) => Rc::strong_count(map),
+        Value::Enum(e) => Rc::strong_count(e),
+        Value::Struct(s) => Rc::strong_count(s),
+        Value::Iter(iter) => Rc::strong_count(iter),
+        Value::Coroutine(co) => Rc::strong_count(co),
+        _ => 0,
+    }
+}
+
+// the values held by the object, false when it is borrowed and can't be looked into
+fn children(value: &Value, f: &mut dyn FnMut(&Value)) -> bool {
+    match value {
+        Value::Tuple(items) => items.iter().for_each(f),
+        Value::Enum(e) => e.fields.iter().for_each(f),
+        Value::List(items) => match items.try_borrow() {
+            Ok(items) => items.iter().for_each(f),
+            Err(_) => return false,
+        },
+        Value::Map(map) => match map.try_borrow() {
+            Ok(map) => map.entries().for_each(|(k, v)| {
+                f(k);
+                f(v);
+            }),
+            Err(_) => return false,
+        },
+        Value::Struct(s) => match s.fields.try_borrow() {
+            Ok(fields) => fields.iter().for_each(f),
+            Err(_) => return false,
+        },
+        Value::Iter(iter) => match iter.try_borrow() {
+            Ok(iter) => match &*iter {
+                Iter::List { list, .. } => f(&Value::List(list.clone())),
+                Iter::Tuple { items, .. } => f(&Value::Tuple(items.clone())),
+                Iter::Map { map, .. } => f(&Value::Map(map.clone())),
+                Iter::Range { .. } | Iter::Str { .. } => {}
+            },
+            Err(_) => return false,
+        },
+        // a running coroutine's stack is the stack of the vm
+        Value::Coroutine(co) => match co.context.try_borrow() {
+            Ok(context) => context
+                .iter()
+                .flat_map(|c| c.stack.heap_values())
+                .for_each(|child| f(&child)),
+            Err(_) => return false,
+        },
+        _ => {}
+    }
+    true
+}
+
+// drop what an unreachable object holds, tuples, enums and iterators can't be in a
+// cycle without one of the others, they only hold older values.
+fn clear(value: &Value) {
+    match value {
+        Value::List(items) => {
+            let _ = items.take();
+        }
+        Value::Map(map) => {
+            let _ = map.take();
+        }
+        Value::Struct(s) => {
+            let _ = s.fields.take();
+        }
+        Value::Coroutine(co) => {
+            let _ = co.context.take();
+            co.status.set(Status::Dead);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, cell::RefCell, rc::Rc};
+
+    use super::{GcConfig, GcMode, Heap};
+    use crate::value::Value;
+
+    fn list(items: Vec<Value>) -> Value {
+        Value::List(Rc::new(RefCell::new(items)))
+    }
+
+    fn push(list: &Value, value: Value) {
+        let Value::List(items) = list else {
+            unreachable!()
+        };
+        items.borrow_mut().push(value);
+    }
+
+    // a list in a cycle with itself, and a `Weak` of it
+    fn cycle() -> (Value, std::rc::Weak<RefCell<Vec<Value>>>) {
+        let a = list(Vec::new());
+        push(&a, a.clone());
+        let Value::List(items) = &a else {
+            unreachable!()
+        };
+        let weak = Rc::downgrade(items);
+        (a, weak)
+    }
+
+    #[test]
+    fn test_collect() {
+        let mut heap = Heap::new(GcConfig::default());
+
+        // a cycle only the heap knows about
+        let a = list(Vec::new());
+        let b = list(vec![a.clone()]);
+        push(&a, b.clone());
+        heap.track(&a);
+        let Value::List(weak) = &a else {
+            unreachable!()
+        };
+        let weak = Rc::downgrade(weak);
+        drop((a, b));
+
+        // a cycle used from the stack and one kept by the host
+        let stack = list(Vec::new());
+        push(&stack, stack.clone());
+        heap.track(&stack);
+        let kept = list(Vec::new());
+        push(&kept, list(vec![kept.clone()]));
+        heap.track(&kept);
+
+        heap.collect([Cow::Borrowed(&stack)].into_iter());
+        assert!(weak.upgrade().is_none());
+        let stats = heap.stats();
+        assert_eq!((stats.collections, stats.swept, stats.objects), (1, 2, 3));
+        let Value::List(items) = &stack else {
+            unreachable!()
+        };
+        assert_eq!(items.borrow().len(), 1);
+    }
+
+    #[test]
+    fn test_generational() {
+        let mut heap = Heap::new(GcConfig {
+            mode: GcMode::Generational,
+            ..GcConfig::default()
+        });
+        let (old, old_weak) = cycle();
+        heap.track(&old);
+        // the nursery is the whole heap
+        heap.collect([].into_iter());
+        drop(old);
+
+        let (young, young_weak) = cycle();
+        heap.track(&young);
+        drop(young);
+        // only the nursery
+        heap.collect([].into_iter());
+        assert!(young_weak.upgrade().is_none());
+        assert!(old_weak.upgrade().is_some());
+        assert_eq!((heap.stats().minor, heap.stats().swept), (1, 1));
+
+        heap.collect_all([].into_iter());
+        assert!(old_weak.upgrade().is_none());
+        assert_eq!(heap.stats().objects, 0);
+    }
+}
@@ -0,0 +1,178 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{map::Map, value::Value};
+
+// `start..end`, `start..=end` and `start..end step n`, the numbers are made lazily.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub step: i64,
+    pub inclusive: bool,
+}
+
+impl Range {
+    // None when the step is 0
+    pub fn new(start: i64, end: i64, step: i64, inclusive: bool) -> Option<Self> {
+        if step == 0 {
+            return None;
+        }
+        Some(Range {
+            start,
+            end,
+            step,
+            inclusive,
+        })
+    }
+
+    pub fn contains(&self, n: i64) -> bool {
+        match (self.step > 0, self.inclusive) {
+            (true, true) => n <= self.end,
+            (true, false) => n < self.end,
+            (false, true) => n >= self.end,
+            (false, false) => n > self.end,
+        }
+    }
+}
+
+// the state of a `for ... in` loop, made by `GetIter`
+#[derive(Debug)]
+pub enum Iter {
+    Range {
+        next: Option<i64>,
+        range: Range,
+    },
+    List {
+        list: Rc<RefCell<Vec<Value>>>,
+        index: usize,
+    },
+    Tuple {
+        items: Rc<Vec<Value>>,
+        index: usize,
+    },
+    Map {
+        map: Rc<RefCell<Map>>,
+        index: usize,
+    },
+    Str {
+        chars: Vec<char>,
+        index: usize,
+    },
+}
+
+impl Iter {
+    // None when the value can't be iterated
+    pub fn new(value: &Value) -> Option<Self> {
+        let iter = match value {
+            Value::Range(range) => Iter::Range {
+                next: Some(range.start),
+                range: *range,
+            },
+            Value::List(list) => Iter::List {
+                list: list.clone(),
+                index: 0,
+            },
+            Value::Tuple(items) => Iter::Tuple {
+                items: items.clone(),
+                index: 0,
+            },
+            Value::Map(map) => Iter::Map {
+                map: map.clone(),
+                index: 0,
+            },
+            Value::Str(s) => Iter::Str {
+                chars: s.chars().collect(),
+                index: 0,
+            },
+            _ => return None,
+        };
+        Some(iter)
+    }
+}
+
+impl Iterator for Iter {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        match self {
+            Iter::Range { next, range } => {
+                let n = next.filter(|n| range.contains(*n))?;
+                // stop at the next time when overflowed
+                *next = n.checked_add(range.step);
+                Some(Value::Int(n))
+            }
+            Iter::List { list, index } => {
+                let value = list.borrow().get(*index).cloned()?;
+                *index += 1;
+                Some(value)
+            }
+            Iter::Tuple { items, index } => {
+                let value = items.get(*index).cloned()?;
+                *index += 1;
+                Some(value)
+            }
+            Iter::Map { map, index } => {
+                let (k, v) = map.borrow().entry(*index).cloned()?;
+                *index += 1;
+                Some(Value::Tuple(Rc::new(vec![k, v])))
+            }
+            Iter::Str { chars, index } => {
+                let c = chars.get(*index)?;
+                *index += 1;
+                Some(Value::Str(c.to_string().into()))
+            }
+        }
+    }
+}
+
+// iterators are only equal to themselves
+impl PartialEq for Iter {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl PartialOrd for Iter {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        if self == other {
+            Some(std::cmp::Ordering::Equal)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Iter, Range};
+    use crate::value::Value;
+
+    fn collect(range: Range) -> Vec<i64> {
+        Iter::new(&Value::Range(range))
+            .unwrap()
+            .map(|v| match v {
+                Value::Int(n) => n,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_range() {
+        assert_eq!(collect(Range::new(0, 3, 1, false).unwrap()), vec![0, 1, 2]);
+        assert_eq!(
+            collect(Range::new(0, 3, 1, true).unwrap()),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(collect(Range::new(0, 7, 3, false).unwrap()), vec![0, 3, 6]);
+        assert_eq!(collect(Range::new(3, 0, -1, false).unwrap()), vec![3, 2, 1]);
+        assert_eq!(collect(Range::new(3, 3, 1, false).unwrap()), vec![]);
+        assert_eq!(Range::new(0, 3, 0, false), None);
+    }
+
+    #[test]
+    fn test_range_overflow() {
+        let range = Range::new(i64::MAX - 1, i64::MAX, 1, true).unwrap();
+        assert_eq!(collect(range), vec![i64::MAX - 1, i64::MAX]);
+    }
+}
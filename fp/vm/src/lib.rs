@@ -1,6 +1,24 @@
+pub mod capability;
 pub mod chunk;
+pub mod convert;
+pub mod coroutine;
 pub mod debug;
+pub mod error;
+pub mod gc;
+pub mod iter;
+pub mod limits;
 pub mod location;
+pub mod map;
+#[cfg(feature = "nanbox")]
+pub mod nanbox;
+pub mod native;
+pub mod object;
 pub mod op;
+pub mod peephole;
+pub mod prelude;
+pub mod register;
+mod stack;
+pub mod string;
+pub mod userdata;
 pub mod value;
 pub mod vm;
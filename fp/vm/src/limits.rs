@@ -0,0 +1,112 @@
+// limits of a run for untrusted scripts, a run is `Vm::interpret` or a call from rust.
+// every limit is off by default.
+
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    mem,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::value::Value;
+
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    // instructions run
+    pub fuel: Option<u64>,
+    // functions called and not returned yet
+    pub call_depth: Option<usize>,
+    // values in the stack
+    pub stack_size: Option<usize>,
+    // bytes of the values reachable from the stack and the globals, it is measured
+    // from time to time, so a run may go over it by a fraction of it.
+    pub heap_bytes: Option<usize>,
+    // wall-clock time of a run
+    pub timeout: Option<Duration>,
+}
+
+// abort the running vm from another thread, or the next run when none is running
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle {
+    flag: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    // true once for each `interrupt`
+    pub(crate) fn take(&self) -> bool {
+        self.flag.swap(false, Ordering::Relaxed)
+    }
+}
+
+// the bytes owned by the value itself, not the values in it
+pub(crate) fn shallow_size(value: &Value) -> usize {
+    let slot = mem::size_of::<Value>();
+    match value {
+        // a shared string is counted for each value
+        Value::Str(s) => s.len(),
+        Value::Tuple(items) => items.len() * slot,
+        Value::List(items) => items.borrow().capacity() * slot,
+        Value::Map(map) => map.borrow().len() * 3 * slot,
+        Value::Enum(e) => e.fields.len() * slot,
+        Value::Struct(s) => s.fields.borrow().len() * slot,
+        Value::Coroutine(co) => match &*co.context.borrow() {
+            Some(context) => context.stack.bytes(),
+            None => 0,
+        },
+        _ => 0,
+    }
+}
+
+// the bytes of the values reachable from `roots`, a shared value is counted once
+pub(crate) fn heap_size<'a>(roots: impl Iterator<Item = Cow<'a, Value>>) -> usize {
+    let mut seen = HashSet::new();
+    let mut todo: Vec<Value> = roots.map(Cow::into_owned).collect();
+    let mut size = 0;
+    while let Some(value) = todo.pop() {
+        let ptr = match &value {
+            Value::Tuple(items) => items.as_ptr() as *const (),
+            Value::List(items) => items.as_ptr() as *const (),
+            Value::Map(map) => map.as_ptr() as *const (),
+            Value::Enum(e) => Rc::as_ptr(e) as *const (),
+            Value::Struct(s) => Rc::as_ptr(s) as *const (),
+            Value::Coroutine(co) => Rc::as_ptr(co) as *const (),
+            _ => {
+                size += shallow_size(&value);
+                continue;
+            }
+        };
+        if !seen.insert(ptr) {
+            continue;
+        }
+        size += shallow_size(&value);
+        match &value {
+            Value::Tuple(items) => todo.extend(items.iter().cloned()),
+            Value::List(items) => todo.extend(items.borrow().iter().cloned()),
+            Value::Map(map) => {
+                for (k, v) in map.borrow().entries() {
+                    todo.push(k.clone());
+                    todo.push(v.clone());
+                }
+            }
+            Value::Enum(e) => todo.extend(e.fields.iter().cloned()),
+            Value::Struct(s) => todo.extend(s.fields.borrow().iter().cloned()),
+            // a running coroutine's stack is the stack of the vm
+            Value::Coroutine(co) => {
+                if let Some(context) = &*co.context.borrow() {
+                    todo.extend(context.stack.heap_values().map(Cow::into_owned));
+                }
+            }
+            _ => {}
+        }
+    }
+    size
+}
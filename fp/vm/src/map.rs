@@ -0,0 +1,125 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use crate::{string::Str, value::Value};
+
+// the values which can be the key of a map
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Nil,
+    Bool(bool),
+    Int(i64),
+    Str(Str),
+    Tuple(Vec<Key>),
+}
+
+impl Key {
+    fn new(value: &Value) -> Option<Key> {
+        let key = match value {
+            Value::Nil => Key::Nil,
+            Value::Bool(b) => Key::Bool(*b),
+            Value::Int(v) => Key::Int(*v),
+            Value::Str(s) => Key::Str(s.clone()),
+            Value::Tuple(items) => Key::Tuple(items.iter().map(Key::new).collect::<Option<_>>()?),
+            _ => return None,
+        };
+        Some(key)
+    }
+}
+
+// the key is a float, a list or something else can't be hashed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Unhashable;
+
+// a hash map keeping the insertion order
+#[derive(Debug, Default)]
+pub struct Map {
+    entries: Vec<(Value, Value)>,
+    index: HashMap<Key, usize>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &Value) -> Result<Option<&Value>, Unhashable> {
+        let key = Key::new(key).ok_or(Unhashable)?;
+        Ok(self.index.get(&key).map(|&i| &self.entries[i].1))
+    }
+
+    pub fn insert(&mut self, key: Value, value: Value) -> Result<(), Unhashable> {
+        let hashed = Key::new(&key).ok_or(Unhashable)?;
+        match self.index.get(&hashed) {
+            Some(&i) => self.entries[i].1 = value,
+            None => {
+                self.index.insert(hashed, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    // the entry in insertion order
+    pub fn entry(&self, i: usize) -> Option<&(Value, Value)> {
+        self.entries.get(i)
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &(Value, Value)> {
+        self.entries.iter()
+    }
+
+    pub(crate) fn into_entries(self) -> impl Iterator<Item = (Value, Value)> {
+        self.entries.into_iter()
+    }
+}
+
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.entries().all(|(k, v)| other.get(k) == Ok(Some(v)))
+    }
+}
+
+// maps are not ordered, only equal maps can be compared
+impl PartialOrd for Map {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if self == other {
+            Some(Ordering::Equal)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Map, Unhashable};
+    use crate::value::Value;
+
+    #[test]
+    fn test_insert_and_get() {
+        let mut map = Map::new();
+        map.insert(Value::Str("a".into()), Value::Int(1)).unwrap();
+        map.insert(Value::Int(2), Value::Int(2)).unwrap();
+        map.insert(Value::Str("a".into()), Value::Int(3)).unwrap();
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&Value::Str("a".into())), Ok(Some(&Value::Int(3))));
+        assert_eq!(map.get(&Value::Int(1)), Ok(None));
+        assert_eq!(map.entry(1), Some(&(Value::Int(2), Value::Int(2))));
+    }
+
+    #[test]
+    fn test_unhashable_key() {
+        let mut map = Map::new();
+        assert_eq!(map.insert(Value::Float(1.0), Value::Nil), Err(Unhashable));
+        assert_eq!(map.get(&Value::Float(1.0)), Err(Unhashable));
+    }
+}
@@ -0,0 +1,439 @@
+// a value in one 64-bit word, for the stack of the vm with the `nanbox` feature.
+// a float is its own bits, the other values are in the bits of a quiet NaN that no
+// float has since the NaNs are all made the same one:
+//   float    any bits but the ones below
+//   nil      QNAN | NIL
+//   bool     QNAN | BOOL | 0 or 1
+//   int      QNAN | INT | 48 bits, the bigger ints are boxed
+//   boxed    SIGN | QNAN | the kind | the 48 bits of an `Rc`
+// a boxed value keeps the `Rc` of its string or object, a copy is a count of it like
+// for a `Value`. the kind is 5 bits, 2 in the tag and 3 in the low bits of the pointer
+// which are 0 since an `Rc` points after its counts. the ranges and the bigger ints
+// are in an `Rc<Value>` of their own.
+// the feature is for the 64-bit targets, a pointer must be in 48 bits and the vm
+// panics on the first one which isn't.
+
+#[cfg(not(target_pointer_width = "64"))]
+compile_error!("the `nanbox` feature needs a 64-bit target");
+
+use std::{borrow::Cow, cell::RefCell, fmt, marker::PhantomData, mem::ManuallyDrop, ptr, rc::Rc};
+
+use crate::{
+    coroutine::Coroutine,
+    iter::Iter,
+    map::Map,
+    native::NativeFn,
+    object::{EnumObj, Function, StructObj},
+    stack::Repr,
+    string::Str,
+    userdata::UserData,
+    value::Value,
+};
+
+const SIGN: u64 = 0x8000_0000_0000_0000;
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+// the NaN every NaN float is stored as, it isn't a `QNAN`
+const CANONICAL_NAN: u64 = 0x7ff8_0000_0000_0000;
+
+const TAG: u64 = 0x0003_0000_0000_0000;
+const NIL: u64 = 0x0001_0000_0000_0000;
+const BOOL: u64 = 0x0002_0000_0000_0000;
+const INT: u64 = 0x0003_0000_0000_0000;
+const PAYLOAD: u64 = 0x0000_ffff_ffff_ffff;
+const POINTER: u64 = PAYLOAD & !7;
+
+const INT_MIN: i64 = -(1 << 47);
+const INT_MAX: i64 = (1 << 47) - 1;
+
+// the kinds of the boxed values, by the `Rc` they keep
+const VALUE: u64 = 0;
+const STR: u64 = 1;
+const INTERNED: u64 = 2;
+const TUPLE: u64 = 3;
+const LIST: u64 = 4;
+const ENUM: u64 = 5;
+const STRUCT: u64 = 6;
+const MAP: u64 = 7;
+const ITER: u64 = 8;
+const NATIVE: u64 = 9;
+const FUNCTION: u64 = 10;
+const USER_DATA: u64 = 11;
+const COROUTINE: u64 = 12;
+
+type Count = unsafe fn(u64);
+
+// the counts of the `Rc` of each kind
+const INCREMENT: [Count; 13] = [
+    increment::<Value>,
+    increment::<String>,
+    increment::<String>,
+    increment::<Vec<Value>>,
+    increment::<RefCell<Vec<Value>>>,
+    increment::<EnumObj>,
+    increment::<StructObj>,
+    increment::<RefCell<Map>>,
+    increment::<RefCell<Iter>>,
+    increment::<NativeFn>,
+    increment::<Function>,
+    increment::<UserData>,
+    increment::<Coroutine>,
+];
+const DECREMENT: [Count; 13] = [
+    decrement::<Value>,
+    decrement::<String>,
+    decrement::<String>,
+    decrement::<Vec<Value>>,
+    decrement::<RefCell<Vec<Value>>>,
+    decrement::<EnumObj>,
+    decrement::<StructObj>,
+    decrement::<RefCell<Map>>,
+    decrement::<RefCell<Iter>>,
+    decrement::<NativeFn>,
+    decrement::<Function>,
+    decrement::<UserData>,
+    decrement::<Coroutine>,
+];
+
+unsafe fn increment<T>(ptr: u64) {
+    Rc::increment_strong_count(ptr as *const T);
+}
+
+unsafe fn decrement<T>(ptr: u64) {
+    Rc::decrement_strong_count(ptr as *const T);
+}
+
+// the `Rc` of a pointer, it takes over the count the pointer held
+#[inline]
+unsafe fn rc<T>(ptr: u64) -> Rc<T> {
+    Rc::from_raw(ptr as *const T)
+}
+
+// the value of a boxed kind, it takes over the count of the `Rc`
+#[inline]
+unsafe fn boxed_value(kind: u64, ptr: u64) -> Value {
+    match kind {
+        VALUE => Rc::try_unwrap(rc(ptr)).unwrap_or_else(|value: Rc<Value>| (*value).clone()),
+        STR => Value::Str(Str::from_rc(rc(ptr), false)),
+        INTERNED => Value::Str(Str::from_rc(rc(ptr), true)),
+        TUPLE => Value::Tuple(rc(ptr)),
+        LIST => Value::List(rc(ptr)),
+        ENUM => Value::Enum(rc(ptr)),
+        STRUCT => Value::Struct(rc(ptr)),
+        MAP => Value::Map(rc(ptr)),
+        ITER => Value::Iter(rc(ptr)),
+        NATIVE => Value::NativeFn(rc(ptr)),
+        FUNCTION => Value::Function(rc(ptr)),
+        USER_DATA => Value::UserData(rc(ptr)),
+        COROUTINE => Value::Coroutine(rc(ptr)),
+        _ => unreachable!("a boxed value of kind {kind}"),
+    }
+}
+
+pub struct NanBox {
+    bits: u64,
+    // it may own an `Rc`, it stays on its thread
+    marker: PhantomData<Rc<Value>>,
+}
+
+impl NanBox {
+    #[inline]
+    fn from_bits(bits: u64) -> NanBox {
+        NanBox {
+            bits,
+            marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn nil() -> NanBox {
+        NanBox::from_bits(QNAN | NIL)
+    }
+
+    #[inline]
+    pub fn bool(b: bool) -> NanBox {
+        NanBox::from_bits(QNAN | BOOL | b as u64)
+    }
+
+    #[inline]
+    pub fn float(f: f64) -> NanBox {
+        if f.is_nan() {
+            return NanBox::from_bits(CANONICAL_NAN);
+        }
+        NanBox::from_bits(f.to_bits())
+    }
+
+    #[inline]
+    pub fn int(i: i64) -> NanBox {
+        if (INT_MIN..=INT_MAX).contains(&i) {
+            return NanBox::from_bits(QNAN | INT | (i as u64 & PAYLOAD));
+        }
+        NanBox::boxed(VALUE, Rc::into_raw(Rc::new(Value::Int(i))))
+    }
+
+    // the box takes over the count of the `Rc`
+    #[inline]
+    fn boxed<T>(kind: u64, ptr: *const T) -> NanBox {
+        let ptr = ptr as u64;
+        assert_eq!(
+            ptr & !POINTER,
+            0,
+            "a pointer over 48 bits or not aligned on 8"
+        );
+        NanBox::from_bits(SIGN | QNAN | ((kind << 45) & TAG) | (kind & 7) | ptr)
+    }
+
+    // the `Rc` of a value is moved into the box, the value is forgotten
+    #[inline]
+    unsafe fn take<T>(kind: u64, rc: &Rc<T>) -> NanBox {
+        NanBox::boxed(kind, Rc::into_raw(ptr::read(rc)))
+    }
+
+    #[inline]
+    pub fn bits(&self) -> u64 {
+        self.bits
+    }
+
+    #[inline]
+    pub fn is_float(&self) -> bool {
+        self.bits & QNAN != QNAN
+    }
+
+    #[inline]
+    fn is_boxed(&self) -> bool {
+        self.bits & (SIGN | QNAN) == SIGN | QNAN
+    }
+
+    #[inline]
+    fn kind(&self) -> u64 {
+        ((self.bits & TAG) >> 45) | (self.bits & 7)
+    }
+
+    #[inline]
+    fn ptr(&self) -> u64 {
+        self.bits & POINTER
+    }
+
+    #[inline]
+    fn tag(&self) -> Option<u64> {
+        (self.bits & (SIGN | QNAN) == QNAN).then_some(self.bits & TAG)
+    }
+
+    #[inline]
+    pub fn as_float(&self) -> Option<f64> {
+        self.is_float().then(|| f64::from_bits(self.bits))
+    }
+
+    // only the ints in 48 bits, a boxed int is a `Value`
+    #[inline]
+    pub fn as_int(&self) -> Option<i64> {
+        // the sign of the payload is spread over the tag bits
+        (self.tag() == Some(INT)).then_some(((self.bits << 16) as i64) >> 16)
+    }
+
+    #[inline]
+    pub fn as_bool(&self) -> Option<bool> {
+        (self.tag() == Some(BOOL)).then_some(self.bits & 1 == 1)
+    }
+
+    #[inline]
+    pub fn is_nil(&self) -> bool {
+        self.bits == QNAN | NIL
+    }
+
+    #[inline]
+    pub fn to_value(&self) -> Value {
+        if let Some(f) = self.as_float() {
+            return Value::Float(f);
+        }
+        match self.tag() {
+            Some(NIL) => Value::Nil,
+            Some(BOOL) => Value::Bool(self.bits & 1 == 1),
+            Some(INT) => Value::Int(((self.bits << 16) as i64) >> 16),
+            // the value takes a new count
+            _ => unsafe {
+                INCREMENT[self.kind() as usize](self.ptr());
+                boxed_value(self.kind(), self.ptr())
+            },
+        }
+    }
+
+    #[inline]
+    pub fn into_value(self) -> Value {
+        if !self.is_boxed() {
+            return self.to_value();
+        }
+        let this = ManuallyDrop::new(self);
+        unsafe { boxed_value(this.kind(), this.ptr()) }
+    }
+}
+
+impl From<Value> for NanBox {
+    #[inline]
+    fn from(value: Value) -> NanBox {
+        let value = ManuallyDrop::new(value);
+        // the `Rc`s are moved out of the value, it isn't dropped
+        unsafe {
+            match &*value {
+                Value::Nil => NanBox::nil(),
+                Value::Bool(b) => NanBox::bool(*b),
+                Value::Int(i) => NanBox::int(*i),
+                Value::Float(f) => NanBox::float(*f),
+                Value::Str(s) => {
+                    let (text, interned) = ptr::read(s).into_rc();
+                    NanBox::boxed(if interned { INTERNED } else { STR }, Rc::into_raw(text))
+                }
+                Value::Tuple(items) => NanBox::take(TUPLE, items),
+                Value::List(items) => NanBox::take(LIST, items),
+                Value::Enum(e) => NanBox::take(ENUM, e),
+                Value::Struct(s) => NanBox::take(STRUCT, s),
+                Value::Map(map) => NanBox::take(MAP, map),
+                Value::Range(range) => {
+                    let range = Value::Range(ptr::read(range));
+                    NanBox::boxed(VALUE, Rc::into_raw(Rc::new(range)))
+                }
+                Value::Iter(iter) => NanBox::take(ITER, iter),
+                Value::NativeFn(f) => NanBox::take(NATIVE, f),
+                Value::Function(f) => NanBox::take(FUNCTION, f),
+                Value::UserData(data) => NanBox::take(USER_DATA, data),
+                Value::Coroutine(co) => NanBox::take(COROUTINE, co),
+            }
+        }
+    }
+}
+
+impl Clone for NanBox {
+    #[inline]
+    fn clone(&self) -> NanBox {
+        if self.is_boxed() {
+            unsafe { INCREMENT[self.kind() as usize](self.ptr()) };
+        }
+        NanBox::from_bits(self.bits)
+    }
+}
+
+impl Drop for NanBox {
+    #[inline]
+    fn drop(&mut self) {
+        if self.is_boxed() {
+            unsafe { DECREMENT[self.kind() as usize](self.ptr()) };
+        }
+    }
+}
+
+impl fmt::Debug for NanBox {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.to_value(), f)
+    }
+}
+
+impl Repr for NanBox {
+    #[inline]
+    fn from_value(value: Value) -> NanBox {
+        value.into()
+    }
+
+    #[inline]
+    fn into_value(self) -> Value {
+        NanBox::into_value(self)
+    }
+
+    #[inline]
+    fn to_value(&self) -> Value {
+        NanBox::to_value(self)
+    }
+
+    #[inline]
+    fn heap(&self) -> Option<Cow<'_, Value>> {
+        self.is_boxed().then(|| Cow::Owned(self.to_value()))
+    }
+
+    #[inline]
+    fn int(&self) -> Option<i64> {
+        self.as_int()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NanBox, INT_MAX, INT_MIN, POINTER};
+    use crate::{iter::Range, map::Map, string::Str, value::Value};
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn test_encoding() {
+        assert!(NanBox::nil().is_nil());
+        assert_eq!(NanBox::bool(true).as_bool(), Some(true));
+        assert_eq!(NanBox::bool(false).as_int(), None);
+        for i in [0, -1, 7, INT_MIN, INT_MAX] {
+            assert_eq!(NanBox::int(i).as_int(), Some(i));
+        }
+        for f in [
+            0.0,
+            -0.0,
+            1.5,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::MIN_POSITIVE,
+        ] {
+            let bits = NanBox::float(f).as_float().unwrap().to_bits();
+            assert_eq!(bits, f.to_bits());
+        }
+
+        // every NaN is the same one, a NaN with the bits of a tag is still a float
+        let nan = f64::from_bits(0xfffc_0000_0000_0001);
+        assert!(NanBox::float(nan).as_float().unwrap().is_nan());
+        assert_eq!(NanBox::float(nan).bits(), NanBox::float(f64::NAN).bits());
+    }
+
+    #[test]
+    fn test_boxed() {
+        let big = NanBox::int(INT_MAX + 1);
+        assert_eq!(big.as_int(), None);
+        assert_eq!(big.to_value(), Value::Int(INT_MAX + 1));
+
+        // the box keeps the `Rc` of the list itself
+        let list = Rc::new(RefCell::new(vec![Value::Int(1)]));
+        let a = NanBox::from(Value::List(list.clone()));
+        assert_eq!(a.bits() & POINTER, Rc::as_ptr(&list) as u64);
+        let b = a.clone();
+        assert_eq!(Rc::strong_count(&list), 3);
+        assert_eq!(a.into_value(), Value::List(list.clone()));
+        assert_eq!(Rc::strong_count(&list), 2);
+        drop(b);
+        assert_eq!(Rc::strong_count(&list), 1);
+    }
+
+    #[test]
+    fn test_kinds() {
+        let values = [
+            Value::Str("a".into()),
+            Value::Str(Str::intern("b")),
+            Value::Tuple(Rc::new(vec![Value::Int(1), Value::Nil])),
+            Value::Map(Rc::new(RefCell::new(Map::default()))),
+            Value::Range(Range {
+                start: 0,
+                end: 3,
+                step: 1,
+                inclusive: false,
+            }),
+            Value::Int(i64::MIN),
+        ];
+        for value in values {
+            let slot = NanBox::from(value.clone());
+            assert_eq!(slot.to_value(), value);
+            assert_eq!(slot.clone().into_value(), value);
+            assert_eq!(slot.into_value(), value);
+        }
+
+        let text = Str::intern("c");
+        let slot = NanBox::from(Value::Str(text.clone()));
+        match &slot.into_value() {
+            Value::Str(s) => {
+                assert!(s.is_interned());
+                assert_eq!(s.as_str().as_ptr(), text.as_str().as_ptr());
+            }
+            value => panic!("{value:?}"),
+        }
+    }
+}
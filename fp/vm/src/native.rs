@@ -0,0 +1,249 @@
+// functions written in rust and called by the scripts, see `Vm::define_native`
+
+use core::fmt;
+
+mod system;
+
+use std::rc::Rc;
+
+use crate::{
+    coroutine::Coroutine,
+    error::RuntimeError,
+    value::Value,
+    vm::{Outcome, Vm},
+};
+
+// a closure may keep the state of the host, e.g. a counter in a `Rc<Cell<_>>`
+pub type NativeFnBox = Box<dyn Fn(&mut Vm, &[Value]) -> Result<Value, RuntimeError>>;
+
+// how many arguments a function takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Exact(usize),
+    // from `min` to `max`, both included
+    Range(usize, usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, n: usize) -> bool {
+        match *self {
+            Arity::Exact(m) => n == m,
+            Arity::Range(min, max) => (min..=max).contains(&n),
+            Arity::AtLeast(min) => n >= min,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        match *self {
+            Arity::Exact(n) => write!(f, "{} argument{}", n, plural(n)),
+            Arity::Range(min, max) => write!(f, "{} to {} arguments", min, max),
+            Arity::AtLeast(n) => write!(f, "at least {} argument{}", n, plural(n)),
+        }
+    }
+}
+
+pub struct NativeFn {
+    pub name: String,
+    pub arity: Arity,
+    pub fun: NativeFnBox,
+}
+
+impl fmt::Debug for NativeFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+// a function is only equal to itself
+impl PartialEq for NativeFn {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl PartialOrd for NativeFn {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        (self == other).then_some(std::cmp::Ordering::Equal)
+    }
+}
+
+// the functions every vm has
+pub(crate) fn define_builtins(vm: &mut Vm) {
+    vm.define_native("print", Arity::AtLeast(0), print);
+    vm.define_native("println", Arity::AtLeast(0), println);
+    vm.define_native("input", Arity::Range(0, 1), input);
+    vm.define_native("type_of", Arity::Exact(1), type_of);
+    vm.define_native("len", Arity::Exact(1), len);
+    vm.define_native("str", Arity::Exact(1), str);
+    vm.define_native("int", Arity::Exact(1), int);
+    vm.define_native("float", Arity::Exact(1), float);
+    vm.define_native("assert", Arity::Range(1, 2), assert);
+    vm.define_native("panic", Arity::Range(0, 1), panic);
+    vm.define_native("coroutine", Arity::Exact(1), coroutine);
+    vm.define_native("resume", Arity::Range(1, 2), resume);
+    vm.define_native("status", Arity::Exact(1), status);
+    system::define(vm);
+}
+
+fn io_error(e: std::io::Error) -> RuntimeError {
+    RuntimeError::Io(e.to_string())
+}
+
+// the values are separated by spaces
+fn write_values(vm: &mut Vm, args: &[Value], end: &str) -> Result<Value, RuntimeError> {
+    let out = vm.output();
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            write!(out, " ").map_err(io_error)?;
+        }
+        write!(out, "{}", arg).map_err(io_error)?;
+    }
+    write!(out, "{}", end).map_err(io_error)?;
+    out.flush().map_err(io_error)?;
+    Ok(Value::Nil)
+}
+
+fn print(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    write_values(vm, args, "")
+}
+
+fn println(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    write_values(vm, args, "\n")
+}
+
+// a line without the `\n`, nil at the end of the input
+fn input(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    write_values(vm, args, "")?;
+    let mut line = String::new();
+    if vm.read_line(&mut line).map_err(io_error)? == 0 {
+        return Ok(Value::Nil);
+    }
+    let len = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(len);
+    Ok(Value::Str(line.into()))
+}
+
+fn type_of(_: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Str(args[0].type_name().into()))
+}
+
+fn len(_: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let len = match &args[0] {
+        Value::Str(s) => s.chars().count(),
+        Value::Tuple(items) => items.len(),
+        Value::List(items) => items.borrow().len(),
+        Value::Map(map) => map.borrow().len(),
+        value => {
+            return Err(RuntimeError::TypeError(format!(
+                "`{}` has no length",
+                value.type_name()
+            )))
+        }
+    };
+    Ok(Value::Int(len as i64))
+}
+
+fn str(_: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    Ok(Value::Str(args[0].to_string().into()))
+}
+
+fn int(_: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let value = match &args[0] {
+        Value::Int(v) => *v,
+        Value::Float(v) if v.is_finite() => *v as i64,
+        Value::Bool(b) => *b as i64,
+        Value::Str(s) => s
+            .trim()
+            .parse()
+            .map_err(|_| RuntimeError::TypeError(format!("{:?} can't be converted to int", s)))?,
+        value => {
+            return Err(RuntimeError::TypeError(format!(
+                "`{}` can't be converted to int",
+                value
+            )))
+        }
+    };
+    Ok(Value::Int(value))
+}
+
+fn float(_: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let value = match &args[0] {
+        Value::Int(v) => *v as f64,
+        Value::Float(v) => *v,
+        Value::Str(s) => s
+            .trim()
+            .parse()
+            .map_err(|_| RuntimeError::TypeError(format!("{:?} can't be converted to float", s)))?,
+        value => {
+            return Err(RuntimeError::TypeError(format!(
+                "`{}` can't be converted to float",
+                value
+            )))
+        }
+    };
+    Ok(Value::Float(value))
+}
+
+fn assert(_: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    match (&args[0], args.get(1)) {
+        (Value::Bool(true), _) => Ok(Value::Nil),
+        (Value::Bool(false), Some(message)) => Err(RuntimeError::Panic(message.to_string())),
+        (Value::Bool(false), None) => Err(RuntimeError::Panic("assertion failed".to_owned())),
+        (value, _) => Err(RuntimeError::TypeError(format!(
+            "the condition of `assert` must be bool, found `{}`",
+            value
+        ))),
+    }
+}
+
+fn panic(_: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let message = match args.first() {
+        Some(message) => message.to_string(),
+        None => "explicit panic".to_owned(),
+    };
+    Err(RuntimeError::Panic(message))
+}
+
+// a coroutine running the function, it starts at the first `resume`
+fn coroutine(_: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    match &args[0] {
+        Value::Function(_) => Ok(Value::Coroutine(Rc::new(Coroutine::new(
+            args[0].clone(),
+            &[],
+        )))),
+        value => Err(RuntimeError::TypeError(format!(
+            "expected `fn`, found `{}`",
+            value.type_name()
+        ))),
+    }
+}
+
+fn expect_coroutine(value: &Value) -> Result<&Rc<Coroutine>, RuntimeError> {
+    match value {
+        Value::Coroutine(co) => Ok(co),
+        value => Err(RuntimeError::TypeError(format!(
+            "expected `coroutine`, found `{}`",
+            value.type_name()
+        ))),
+    }
+}
+
+// the value of the next `yield`, or the returned value when it ends
+fn resume(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let co = expect_coroutine(&args[0])?.clone();
+    let value = args.get(1).cloned().unwrap_or(Value::Nil);
+    match vm.resume_coroutine(&co, value)? {
+        Outcome::Suspended(value) => Ok(value),
+        Outcome::Done(value) => Ok(value.unwrap_or(Value::Nil)),
+    }
+}
+
+// "suspended", "running", "normal" or "dead"
+fn status(_: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let co = expect_coroutine(&args[0])?;
+    Ok(Value::Str(co.status().name().into()))
+}
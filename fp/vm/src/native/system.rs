@@ -0,0 +1,188 @@
+// the functions touching the world outside the vm, each one checks the capability
+// it needs, see `Capabilities`.
+
+use std::{
+    cell::Cell,
+    env, fs,
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{convert::FromValue, error::RuntimeError, value::Value, vm::Vm};
+
+use super::{io_error, Arity};
+
+pub(super) fn define(vm: &mut Vm) {
+    vm.define_native("read_file", Arity::Exact(1), read_file);
+    vm.define_native("write_file", Arity::Exact(2), write_file);
+    vm.define_native("env", Arity::Exact(1), env_var);
+    vm.define_native("clock", Arity::Exact(0), clock);
+    let state = Cell::new(seed());
+    vm.define_native("random", Arity::Exact(0), move |vm, _| {
+        permit(vm.capabilities().random, || "randomness".to_owned())?;
+        Ok(Value::Float(next_random(&state)))
+    });
+    vm.define_native("exec", Arity::Range(1, 2), exec);
+}
+
+fn permit(allowed: bool, what: impl FnOnce() -> String) -> Result<(), RuntimeError> {
+    if allowed {
+        Ok(())
+    } else {
+        Err(RuntimeError::PermissionDenied(what()))
+    }
+}
+
+fn read_file(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = String::from_value(&args[0])?;
+    permit(vm.capabilities().can_read(Path::new(&path)), || {
+        format!("reading `{}`", path)
+    })?;
+    fs::read_to_string(&path)
+        .map(|s| Value::Str(s.into()))
+        .map_err(io_error)
+}
+
+fn write_file(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let path = String::from_value(&args[0])?;
+    permit(vm.capabilities().can_write(Path::new(&path)), || {
+        format!("writing `{}`", path)
+    })?;
+    let contents = match &args[1] {
+        Value::Str(s) => s.clone(),
+        value => value.to_string().into(),
+    };
+    fs::write(&path, contents.as_bytes()).map_err(io_error)?;
+    Ok(Value::Nil)
+}
+
+// nil when the variable isn't set
+fn env_var(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let name = String::from_value(&args[0])?;
+    permit(vm.capabilities().can_read_env(&name), || {
+        format!("reading the environment variable `{}`", name)
+    })?;
+    Ok(env::var(&name)
+        .map(|s| Value::Str(s.into()))
+        .unwrap_or(Value::Nil))
+}
+
+// the seconds since the unix epoch
+fn clock(vm: &mut Vm, _: &[Value]) -> Result<Value, RuntimeError> {
+    permit(vm.capabilities().clock, || "reading the clock".to_owned())?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(Value::Float(now.as_secs_f64()))
+}
+
+fn seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+    // never zero, xorshift would only give zeros
+    nanos | 1
+}
+
+// xorshift64*, a float from 0 included to 1 excluded
+fn next_random(state: &Cell<u64>) -> f64 {
+    let mut x = state.get();
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    state.set(x);
+    (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// `exec("ls", ["-l"])` is the output of the command, a failed command is an error
+fn exec(vm: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+    let program = String::from_value(&args[0])?;
+    permit(vm.capabilities().subprocess, || {
+        format!("running `{}`", program)
+    })?;
+    let arguments = match args.get(1) {
+        Some(arguments) => Vec::<String>::from_value(arguments)?,
+        None => Vec::new(),
+    };
+    let output = Command::new(&program)
+        .args(&arguments)
+        .output()
+        .map_err(io_error)?;
+    if !output.status.success() {
+        return Err(RuntimeError::Io(format!(
+            "`{}` failed with {}",
+            program, output.status
+        )));
+    }
+    Ok(Value::Str(
+        String::from_utf8_lossy(&output.stdout).into_owned().into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use crate::{
+        capability::{Capabilities, Scope},
+        error::RuntimeError,
+        value::Value,
+        vm::Vm,
+    };
+
+    fn call(vm: &mut Vm, name: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+        let native = vm.native(name).unwrap().clone();
+        vm.call_value(&native, args)
+    }
+
+    fn str(s: &str) -> Value {
+        Value::Str(s.into())
+    }
+
+    #[test]
+    fn test_pure() {
+        let mut vm = Vm::new();
+        let denied = |result: Result<Value, RuntimeError>| {
+            matches!(result, Err(RuntimeError::PermissionDenied(_)))
+        };
+        assert!(denied(call(&mut vm, "read_file", &[str("Cargo.toml")])));
+        assert!(denied(call(&mut vm, "write_file", &[str("x"), str("")])));
+        assert!(denied(call(&mut vm, "env", &[str("PATH")])));
+        assert!(denied(call(&mut vm, "clock", &[])));
+        assert!(denied(call(&mut vm, "random", &[])));
+        assert!(denied(call(&mut vm, "exec", &[str("true")])));
+        assert_eq!(
+            call(&mut vm, "read_file", &[str("Cargo.toml")])
+                .unwrap_err()
+                .to_string(),
+            "permission denied: reading `Cargo.toml`"
+        );
+    }
+
+    #[test]
+    fn test_granted() {
+        let dir = env::temp_dir().join(format!("fpig_system_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a.txt").to_string_lossy().into_owned();
+
+        let mut vm = Vm::with_capabilities(Capabilities {
+            fs_read: Scope::Only(vec![dir.clone()]),
+            fs_write: Scope::Only(vec![dir.clone()]),
+            env: Scope::Only(vec!["FPIG_UNSET".to_owned()]),
+            random: true,
+            ..Capabilities::pure()
+        });
+        call(&mut vm, "write_file", &[str(&file), str("hi")]).unwrap();
+        assert_eq!(call(&mut vm, "read_file", &[str(&file)]), Ok(str("hi")));
+        assert_eq!(call(&mut vm, "env", &[str("FPIG_UNSET")]), Ok(Value::Nil));
+        assert!(call(&mut vm, "env", &[str("PATH")]).is_err());
+        assert!(call(&mut vm, "clock", &[]).is_err());
+        match call(&mut vm, "random", &[]) {
+            Ok(Value::Float(x)) => assert!((0.0..1.0).contains(&x)),
+            other => panic!("unexpected {:?}", other),
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+
+use crate::{chunk::Chunk, register::Code, value::Value};
+
+// user defined types, created by the compiler when it meets `enum` and `struct`.
+// the definitions are shared between the compiler and every instance.
+
+#[derive(Debug, PartialEq)]
+pub struct EnumDef {
+    pub name: String,
+    pub variants: Vec<VariantDef>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct VariantDef {
+    pub name: String,
+    pub arity: u8,
+}
+
+impl EnumDef {
+    pub fn variant(&self, name: &str) -> Option<(u16, &VariantDef)> {
+        self.variants
+            .iter()
+            .enumerate()
+            .find(|(_, v)| v.name == name)
+            .map(|(i, v)| (i as u16, v))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct StructDef {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+impl StructDef {
+    pub fn field(&self, name: &str) -> Option<usize> {
+        self.fields.iter().position(|f| f == name)
+    }
+}
+
+fn same_def<T: PartialEq>(a: &Rc<T>, b: &Rc<T>) -> bool {
+    Rc::ptr_eq(a, b) || a == b
+}
+
+#[derive(Debug)]
+pub struct EnumObj {
+    pub def: Rc<EnumDef>,
+    pub tag: u16,
+    pub fields: Vec<Value>,
+}
+
+impl EnumObj {
+    pub fn same_enum(&self, other: &EnumObj) -> bool {
+        same_def(&self.def, &other.def)
+    }
+
+    pub fn same_variant(&self, other: &EnumObj) -> bool {
+        self.tag == other.tag && self.same_enum(other)
+    }
+
+    pub fn variant(&self) -> &VariantDef {
+        &self.def.variants[self.tag as usize]
+    }
+}
+
+impl PartialEq for EnumObj {
+    fn eq(&self, other: &Self) -> bool {
+        self.same_variant(other) && self.fields == other.fields
+    }
+}
+
+impl PartialOrd for EnumObj {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if !same_def(&self.def, &other.def) {
+            return None;
+        }
+        match self.tag.cmp(&other.tag) {
+            Ordering::Equal => self.fields.partial_cmp(&other.fields),
+            ord => Some(ord),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct StructObj {
+    pub def: Rc<StructDef>,
+    pub fields: RefCell<Vec<Value>>,
+}
+
+impl StructObj {
+    pub fn same_struct(&self, other: &StructObj) -> bool {
+        same_def(&self.def, &other.def)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        let i = self.def.field(name)?;
+        self.fields.borrow().get(i).cloned()
+    }
+}
+
+impl PartialEq for StructObj {
+    fn eq(&self, other: &Self) -> bool {
+        self.same_struct(other) && self.fields == other.fields
+    }
+}
+
+impl PartialOrd for StructObj {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        if !self.same_struct(other) {
+            return None;
+        }
+        self.fields.partial_cmp(&other.fields)
+    }
+}
+
+// a function declared by `fn`, the arguments are the first locals of its chunk
+#[derive(Debug)]
+pub struct Function {
+    pub name: String,
+    pub arity: u8,
+    pub chunk: Rc<Chunk>,
+    // `fn*`, a call makes a coroutine
+    pub generator: bool,
+    // the code of the register backend, it runs instead of the chunk, see `register`
+    pub registers: Option<Rc<Code>>,
+}
+
+// a function is only equal to itself
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl PartialOrd for Function {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (self == other).then_some(Ordering::Equal)
+    }
+}
@@ -1,5 +1,9 @@
+// the operands are big endian, `L` opcodes take a u16 and `W` opcodes take a u32.
+// forward jumps (Jump, JumpIfFalse, ForIter and the table of SwitchTag) always take
+// a u32, their length isn't known when they are emitted.
+// the opcodes from `AddLocalConstant` are superinstructions, only made by `peephole`.
 #[rustfmt::skip]
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpCode {
     Add          = 0x00,
     Sub          = 0x01,
@@ -29,4 +33,99 @@ pub enum OpCode {
     Jump         = 0x19,
     JumpIfFalse  = 0x1A,
     JumpBack     = 0x1B,
+    MakeTuple    = 0x1C,
+    MakeList     = 0x1D,
+    Construct    = 0x1E,
+    GetField     = 0x1F,
+    GetItem      = 0x20,
+    GetItemBack  = 0x21,
+    MatchKind    = 0x22,
+    MatchTuple   = 0x23,
+    MatchList    = 0x24,
+    MatchListMin = 0x25,
+    SwitchTag    = 0x26,
+    NoMatch      = 0x27,
+    MakeRange    = 0x28,
+    MakeMap      = 0x29,
+    GetIndex     = 0x2A,
+    SetIndex     = 0x2B,
+    SetField     = 0x2C,
+    GetIter      = 0x2D,
+    ForIter      = 0x2E,
+    ConstantW    = 0x2F,
+    JumpBackW    = 0x30,
+    BlockEndL    = 0x31,
+    Call         = 0x32,
+    Invoke       = 0x33,
+    Yield        = 0x34,
+    Throw        = 0x35,
+    JumpIfOk     = 0x36,
+    // `GetLocal slot; Constant i; Add`, the slot and the constant take a byte each
+    AddLocalConstant = 0x37,
+    // `Eq; Not`
+    NotEq        = 0x38,
+    // `Lt; JumpIfFalse`
+    LtJumpIfFalse = 0x39,
+}
+
+impl TryFrom<u8> for OpCode {
+    // the byte which isn't an opcode
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<OpCode, u8> {
+        use OpCode::*;
+
+        // the opcodes in the order of their bytes
+        #[rustfmt::skip]
+        const OPCODES: [OpCode; 58] = [
+            Add, Sub, Neg, Mult, Div, True, False, Nil,
+            Not, Eq, Gt, Lt, Return, Constant, ConstantL, Pop,
+            SetGlobal, SetGlobalL, GetGlobal, GetGlobalL, BlockEnd, SetLocal, SetLocalL, GetLocal,
+            GetLocalL, Jump, JumpIfFalse, JumpBack, MakeTuple, MakeList, Construct, GetField,
+            GetItem, GetItemBack, MatchKind, MatchTuple, MatchList, MatchListMin, SwitchTag, NoMatch,
+            MakeRange, MakeMap, GetIndex, SetIndex, SetField, GetIter, ForIter, ConstantW,
+            JumpBackW, BlockEndL, Call, Invoke, Yield, Throw, JumpIfOk, AddLocalConstant,
+            NotEq, LtJumpIfFalse,
+        ];
+        OPCODES.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+impl OpCode {
+    // the bytes of the operands, the table of `SwitchTag` after its count isn't counted
+    pub fn operands(self) -> usize {
+        use OpCode::*;
+
+        match self {
+            Constant | SetGlobal | GetGlobal | BlockEnd | SetLocal | GetLocal | MakeTuple
+            | MakeList | Construct | GetItem | GetItemBack | MatchTuple | MatchList
+            | MatchListMin | SwitchTag | MakeRange | MakeMap | Call | Invoke => 1,
+            ConstantL | SetGlobalL | GetGlobalL | SetLocalL | GetLocalL | JumpBack | BlockEndL
+            | AddLocalConstant => 2,
+            Jump | JumpIfFalse | ForIter | ConstantW | JumpBackW | JumpIfOk | LtJumpIfFalse => 4,
+            _ => 0,
+        }
+    }
+
+    // the next instruction isn't run after it
+    pub fn ends_block(self) -> bool {
+        use OpCode::*;
+
+        matches!(self, Return | Jump | JumpBack | JumpBackW | NoMatch | Throw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::OpCode;
+
+    #[test]
+    fn test_try_from() {
+        for byte in 0..=OpCode::LtJumpIfFalse as u8 {
+            let op = OpCode::try_from(byte).unwrap();
+            assert_eq!(op as u8, byte);
+        }
+        assert_eq!(OpCode::try_from(OpCode::LtJumpIfFalse as u8 + 1), Err(0x3A));
+        assert_eq!(OpCode::try_from(0xFF), Err(0xFF));
+    }
 }
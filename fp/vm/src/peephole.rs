@@ -0,0 +1,343 @@
+// a pass over the bytecode of a compiled chunk, it replaces the common sequences of
+// instructions with one superinstruction and drops the ones doing nothing:
+// GetLocal s; Constant i; Add   -> AddLocalConstant s, i
+// Eq; Not                       -> NotEq
+// Lt; JumpIfFalse               -> LtJumpIfFalse
+// Constant i; Pop               -> nothing, the same for `Nil; Pop`
+// a jump landing on a `Jump` goes where that one goes instead. a sequence is kept when
+// a jump or a handler lands inside it. the jumps, the handlers and the locations are
+// moved with the instructions, the chunks of the functions in the constants aren't
+// changed.
+
+use crate::{
+    chunk::{Chunk, Handler},
+    op::OpCode,
+};
+
+// an instruction read from the chunk
+struct Read {
+    ip: usize,
+    op: OpCode,
+    // the operands which aren't jumps, the count of entries for `SwitchTag`
+    operands: Vec<u8>,
+    // where the jumps go, the entries of `SwitchTag` in order
+    targets: Vec<usize>,
+}
+
+// an instruction of the new chunk
+struct Write {
+    // the first instruction it replaces
+    ip: usize,
+    op: OpCode,
+    operands: Vec<u8>,
+    targets: Vec<usize>,
+}
+
+impl Write {
+    fn len(&self) -> usize {
+        match self.op {
+            OpCode::SwitchTag => 2 + self.targets.len() * 4,
+            OpCode::JumpBack => 3,
+            op if jumps_forward(op) || op == OpCode::JumpBackW => 5,
+            _ => 1 + self.operands.len(),
+        }
+    }
+}
+
+fn jumps_forward(op: OpCode) -> bool {
+    use OpCode::*;
+
+    matches!(op, Jump | JumpIfFalse | ForIter | JumpIfOk | LtJumpIfFalse)
+}
+
+// the chunk is given back as it is when it doesn't pass `Chunk::verify`
+pub fn optimize(chunk: Chunk) -> Chunk {
+    match rewrite(&chunk) {
+        Some(optimized) => optimized,
+        None => chunk,
+    }
+}
+
+fn rewrite(chunk: &Chunk) -> Option<Chunk> {
+    chunk.verify().ok()?;
+    let code = chunk.code();
+    let mut reads = read(code);
+    let mut index = vec![None; code.len()];
+    for (i, read) in reads.iter().enumerate() {
+        index[read.ip] = Some(i);
+    }
+
+    // jumps to jumps, the forward ones only go further so it ends
+    let thread = |mut target: usize| {
+        while let Some(Read {
+            op: OpCode::Jump,
+            targets,
+            ..
+        }) = index.get(target).copied().flatten().map(|i| &reads[i])
+        {
+            target = targets[0];
+        }
+        target
+    };
+    let threaded: Vec<Vec<usize>> = reads
+        .iter()
+        .map(|read| match read.op {
+            OpCode::JumpBack | OpCode::JumpBackW => read.targets.clone(),
+            _ => read.targets.iter().map(|t| thread(*t)).collect(),
+        })
+        .collect();
+    for (read, targets) in reads.iter_mut().zip(threaded) {
+        read.targets = targets;
+    }
+
+    let mut labels = vec![false; code.len() + 1];
+    for target in reads.iter().flat_map(|read| &read.targets) {
+        labels[*target] = true;
+    }
+    for h in chunk.handlers() {
+        for at in [h.start, h.end, h.target] {
+            *labels.get_mut(at)? = true;
+        }
+    }
+
+    // where each instruction starts in the new code, a dropped one is where the next is
+    let mut moved = vec![None; code.len() + 1];
+    let mut writes = Vec::new();
+    let mut len = 0;
+    let mut i = 0;
+    while i < reads.len() {
+        // the instructions after the first aren't jumped to
+        let fused = |n: usize| {
+            i + n <= reads.len() && reads[i + 1..i + n].iter().all(|read| !labels[read.ip])
+        };
+        let op = |n: usize| reads.get(i + n).map(|read| read.op);
+        let read = &reads[i];
+        let (n, write) = match (read.op, op(1), op(2)) {
+            (OpCode::GetLocal, Some(OpCode::Constant), Some(OpCode::Add)) if fused(3) => {
+                let operands = vec![read.operands[0], reads[i + 1].operands[0]];
+                (3, Some((OpCode::AddLocalConstant, operands, Vec::new())))
+            }
+            (OpCode::Eq, Some(OpCode::Not), _) if fused(2) => {
+                (2, Some((OpCode::NotEq, Vec::new(), Vec::new())))
+            }
+            (OpCode::Lt, Some(OpCode::JumpIfFalse), _) if fused(2) => {
+                let targets = reads[i + 1].targets.clone();
+                (2, Some((OpCode::LtJumpIfFalse, Vec::new(), targets)))
+            }
+            (
+                OpCode::Constant | OpCode::ConstantL | OpCode::ConstantW | OpCode::Nil,
+                Some(OpCode::Pop),
+                _,
+            ) if fused(2) => (2, None),
+            _ => {
+                let write = (read.op, read.operands.clone(), read.targets.clone());
+                (1, Some(write))
+            }
+        };
+        for read in &reads[i..i + n] {
+            moved[read.ip] = Some(len);
+        }
+        if let Some((op, operands, targets)) = write {
+            let write = Write {
+                ip: read.ip,
+                op,
+                operands,
+                targets,
+            };
+            len += write.len();
+            writes.push(write);
+        }
+        i += n;
+    }
+    moved[code.len()] = Some(len);
+    let moved = |at: usize| moved.get(at).copied().flatten();
+
+    let mut optimized = Chunk::new();
+    for value in (0..).map_while(|i| chunk.get_constant(i)) {
+        optimized.write_constant(value.clone());
+    }
+    for write in &writes {
+        let ip = optimized.get_code_len();
+        let mut bytes = vec![write.op as u8];
+        match write.op {
+            OpCode::SwitchTag => {
+                bytes.extend(&write.operands);
+                let end = ip + write.len();
+                for target in &write.targets {
+                    bytes.extend(((moved(*target)? - end) as u32).to_be_bytes());
+                }
+            }
+            OpCode::JumpBack => {
+                let offset = ip + 1 - moved(write.targets[0])?;
+                bytes.extend((offset as u16).to_be_bytes());
+            }
+            OpCode::JumpBackW => {
+                let offset = ip + 1 - moved(write.targets[0])?;
+                bytes.extend((offset as u32).to_be_bytes());
+            }
+            op if jumps_forward(op) => {
+                let offset = moved(write.targets[0])? - (ip + 5);
+                bytes.extend((offset as u32).to_be_bytes());
+            }
+            _ => bytes.extend(&write.operands),
+        }
+        for byte in bytes {
+            optimized.write_code(byte);
+            if let Some(location) = chunk.get_location(write.ip) {
+                optimized.write_location(*location);
+            }
+        }
+    }
+    for h in chunk.handlers() {
+        optimized.add_handler(Handler {
+            start: moved(h.start)?,
+            end: moved(h.end)?,
+            target: moved(h.target)?,
+            height: h.height,
+        });
+    }
+    Some(optimized)
+}
+
+// the instructions of verified code, the offsets of the jumps are made targets
+fn read(code: &[u8]) -> Vec<Read> {
+    let long = |at: usize| usize::from(u16::from_be_bytes([code[at], code[at + 1]]));
+    let wide = |at: usize| u32::from_be_bytes(code[at..at + 4].try_into().unwrap()) as usize;
+    let mut reads = Vec::new();
+    let mut ip = 0;
+    while ip < code.len() {
+        let op = OpCode::try_from(code[ip]).unwrap();
+        let at = ip + 1;
+        let mut next = at + op.operands();
+        let (operands, targets) = match op {
+            OpCode::SwitchTag => {
+                let n = usize::from(code[at]);
+                next = at + 1 + (n + 1) * 4;
+                let targets = (0..=n).map(|entry| next + wide(at + 1 + entry * 4));
+                (vec![code[at]], targets.collect())
+            }
+            OpCode::JumpBack => (Vec::new(), vec![at - long(at)]),
+            OpCode::JumpBackW => (Vec::new(), vec![at - wide(at)]),
+            op if jumps_forward(op) => (Vec::new(), vec![next + wide(at)]),
+            _ => (code[at..next].to_vec(), Vec::new()),
+        };
+        reads.push(Read {
+            ip,
+            op,
+            operands,
+            targets,
+        });
+        ip = next;
+    }
+    reads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::optimize;
+    use crate::{
+        chunk::{Chunk, Handler},
+        debug::disassemble,
+        location::Location,
+        op::OpCode,
+        value::Value,
+    };
+
+    fn chunk(codes: &[u8]) -> Chunk {
+        let mut chunk = Chunk::new();
+        chunk.write_constant(Value::Int(1));
+        for (i, code) in codes.iter().enumerate() {
+            chunk.write_code(*code);
+            chunk.write_location(Location::new(i + 1, 1));
+        }
+        chunk
+    }
+
+    #[test]
+    fn test_fuse() {
+        use OpCode::*;
+
+        #[rustfmt::skip]
+        let chunk = optimize(chunk(&[
+            GetLocal as u8, 0, Constant as u8, 0, Add as u8,
+            GetLocal as u8, 0, Eq as u8, Not as u8,
+            Constant as u8, 0, Pop as u8, Nil as u8, Pop as u8,
+            GetLocal as u8, 0, Constant as u8, 0, Lt as u8,
+            JumpIfFalse as u8, 0, 0, 0, 0,
+            Return as u8,
+        ]));
+        let expect = "\
+0000 AddLocalConstant 0 0 (1)
+0003 GetLocal        0
+0005 NotEq
+0006 GetLocal        0
+0008 Constant        0 (1)
+0010 LtJumpIfFalse   0 (-> 0015)
+0015 Return
+";
+        assert_eq!(disassemble(&chunk), expect);
+        // the location of an instruction is the one of the first it replaces
+        assert_eq!(chunk.get_location(5), Some(&Location::new(8, 1)));
+        assert_eq!(chunk.get_location(10), Some(&Location::new(19, 1)));
+        assert_eq!(chunk.verify(), Ok(()));
+    }
+
+    #[test]
+    fn test_labels() {
+        use OpCode::*;
+
+        // the jump lands on the `Not` and the `try` covers the `Pop`, they are kept
+        #[rustfmt::skip]
+        let mut before = chunk(&[
+            True as u8, JumpIfFalse as u8, 0, 0, 0, 1,
+            Eq as u8, Not as u8,
+            Constant as u8, 0, Pop as u8,
+            Return as u8,
+        ]);
+        before.add_handler(Handler {
+            start: 10,
+            end: 11,
+            target: 11,
+            height: 0,
+        });
+        let chunk = optimize(before);
+        let expect = "\
+0000 True
+0001 JumpIfFalse     1 (-> 0007)
+0006 Eq
+0007 Not
+0008 Constant        0 (1)
+0010 Pop
+0011 Return
+try 0010..0011 -> 0011, height 0
+";
+        assert_eq!(disassemble(&chunk), expect);
+    }
+
+    #[test]
+    fn test_thread_jumps() {
+        use OpCode::*;
+
+        #[rustfmt::skip]
+        let chunk = optimize(chunk(&[
+            True as u8, JumpIfFalse as u8, 0, 0, 0, 2,
+            Nil as u8, Pop as u8,
+            Jump as u8, 0, 0, 0, 0,
+            Jump as u8, 0, 0, 0, 1,
+            Nil as u8,
+            Return as u8,
+            JumpBack as u8, 0, 21,
+        ]));
+        // the `Nil; Pop` is dropped, the jumps are shorter
+        let expect = "\
+0000 True
+0001 JumpIfFalse     11 (-> 0017)
+0006 Jump            6 (-> 0017)
+0011 Jump            1 (-> 0017)
+0016 Nil
+0017 Return
+0018 JumpBack        19 (-> 0000)
+";
+        assert_eq!(disassemble(&chunk), expect);
+    }
+}
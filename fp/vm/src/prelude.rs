@@ -0,0 +1,143 @@
+// the enums every program has, `Option { Some(v), None }` and `Result { Ok(v), Err(e) }`.
+// the compiler declares them, the vm knows them by their definitions for `?` and
+// for their methods, e.g. `x.map(f).unwrap_or(0)`.
+
+use std::rc::Rc;
+
+use crate::{
+    error::RuntimeError,
+    native::Arity,
+    object::{EnumDef, EnumObj, VariantDef},
+    value::Value,
+    vm::Vm,
+};
+
+pub fn option_def() -> EnumDef {
+    enum_def("Option", [("Some", 1), ("None", 0)])
+}
+
+pub fn result_def() -> EnumDef {
+    enum_def("Result", [("Ok", 1), ("Err", 1)])
+}
+
+fn enum_def(name: &str, variants: [(&str, u8); 2]) -> EnumDef {
+    EnumDef {
+        name: name.to_owned(),
+        variants: variants
+            .map(|(name, arity)| VariantDef {
+                name: name.to_owned(),
+                arity,
+            })
+            .into(),
+    }
+}
+
+// the variant names are enough, a program declaring its own `Option` with the same
+// variants makes the same values.
+pub fn is_prelude(def: &EnumDef) -> bool {
+    let names = def.variants.iter().map(|v| v.name.as_str());
+    match def.name.as_str() {
+        "Option" => names.eq(["Some", "None"]),
+        "Result" => names.eq(["Ok", "Err"]),
+        _ => false,
+    }
+}
+
+// the values made by the host, e.g. by the conversion of a rust `Option` or `Result`
+pub fn some(value: Value) -> Value {
+    variant(option_def(), 0, vec![value])
+}
+
+pub fn none() -> Value {
+    variant(option_def(), 1, Vec::new())
+}
+
+pub fn ok(value: Value) -> Value {
+    variant(result_def(), 0, vec![value])
+}
+
+pub fn err(value: Value) -> Value {
+    variant(result_def(), 1, vec![value])
+}
+
+fn variant(def: EnumDef, tag: u16, fields: Vec<Value>) -> Value {
+    Value::Enum(Rc::new(EnumObj {
+        def: Rc::new(def),
+        tag,
+        fields,
+    }))
+}
+
+// the tag and the fields of a value of the prelude enum `name`, `Option` or `Result`
+pub(crate) fn variant_of<'a>(value: &'a Value, name: &str) -> Option<(u16, &'a [Value])> {
+    match value {
+        Value::Enum(e) if is_prelude(&e.def) && e.def.name == name => Some((e.tag, &e.fields)),
+        _ => None,
+    }
+}
+
+// the value in `Some(v)` or `Ok(v)`, `None` for `None` and `Err(e)`
+pub(crate) fn success(value: &Value) -> Result<Option<Value>, RuntimeError> {
+    match value {
+        Value::Enum(e) if is_prelude(&e.def) => Ok(match e.tag {
+            0 => Some(e.fields[0].clone()),
+            _ => None,
+        }),
+        value => Err(RuntimeError::TypeError(format!(
+            "`?` needs an `Option` or a `Result`, found `{}`",
+            value.type_name()
+        ))),
+    }
+}
+
+// the value of `next` for `for x in s`, the items are in `Some(v)` until `None`
+pub(crate) fn next_item(value: &Value) -> Result<Option<Value>, RuntimeError> {
+    match variant_of(value, "Option") {
+        Some((0, fields)) => Ok(Some(fields[0].clone())),
+        Some(_) => Ok(None),
+        None => Err(RuntimeError::TypeError(format!(
+            "`next` must return an `Option`, found `{}`",
+            value.type_name()
+        ))),
+    }
+}
+
+pub(crate) fn call_method(
+    vm: &mut Vm,
+    e: &Rc<EnumObj>,
+    name: &str,
+    args: &[Value],
+) -> Result<Value, RuntimeError> {
+    if !matches!(name, "map" | "and_then" | "unwrap_or") {
+        return Err(RuntimeError::TypeError(format!(
+            "`{}` has no method `{}`",
+            e.def.name, name
+        )));
+    }
+    if args.len() != 1 {
+        return Err(RuntimeError::WrongArity {
+            name: format!("{}.{}", e.def.name, name),
+            arity: Arity::Exact(1),
+            found: args.len(),
+        });
+    }
+    let value = match e.tag {
+        0 => &e.fields[0],
+        // `None` and `Err(e)` go through `map` and `and_then` as they are
+        _ if name == "unwrap_or" => return Ok(args[0].clone()),
+        _ => return Ok(Value::Enum(e.clone())),
+    };
+    // the function runs nested in this run, see `Vm::call_value`
+    match name {
+        "map" => {
+            let value = vm.call_value(&args[0], std::slice::from_ref(value))?;
+            Ok(Value::Enum(Rc::new(EnumObj {
+                def: e.def.clone(),
+                tag: 0,
+                fields: vec![value],
+            })))
+        }
+        "and_then" => vm.call_value(&args[0], std::slice::from_ref(value)),
+        _ => Ok(value.clone()),
+    }
+}
@@ -0,0 +1,403 @@
+// the instructions of the register backend, an alternative to the bytecode of `Chunk`.
+// a function has a window of registers in the stack of the vm, its arguments are the
+// first ones, and an instruction names the registers it reads and writes, e.g.
+// `Add r2, r0, r1` instead of `GetLocal 0; GetLocal 1; Add; SetLocal 2`.
+// the jumps go to the index of an instruction.
+
+use std::fmt;
+
+use crate::{error::RuntimeError, value::Value};
+
+// a register of the window of the running function
+pub type Reg = u16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instr {
+    Nil {
+        dst: Reg,
+    },
+    Bool {
+        dst: Reg,
+        value: bool,
+    },
+    Constant {
+        dst: Reg,
+        i: u32,
+    },
+    Move {
+        dst: Reg,
+        src: Reg,
+    },
+    GetGlobal {
+        dst: Reg,
+        i: u16,
+    },
+    SetGlobal {
+        i: u16,
+        src: Reg,
+    },
+    Add {
+        dst: Reg,
+        a: Reg,
+        b: Reg,
+    },
+    Sub {
+        dst: Reg,
+        a: Reg,
+        b: Reg,
+    },
+    Mult {
+        dst: Reg,
+        a: Reg,
+        b: Reg,
+    },
+    Div {
+        dst: Reg,
+        a: Reg,
+        b: Reg,
+    },
+    Eq {
+        dst: Reg,
+        a: Reg,
+        b: Reg,
+    },
+    Gt {
+        dst: Reg,
+        a: Reg,
+        b: Reg,
+    },
+    Lt {
+        dst: Reg,
+        a: Reg,
+        b: Reg,
+    },
+    Neg {
+        dst: Reg,
+        src: Reg,
+    },
+    Not {
+        dst: Reg,
+        src: Reg,
+    },
+    Jump {
+        to: u32,
+    },
+    // only `false` jumps, like `JumpIfFalse` of the bytecode
+    JumpIfFalse {
+        test: Reg,
+        to: u32,
+    },
+    // the value in `Some(v)` or `Ok(v)` replaces `src` and it jumps
+    JumpIfOk {
+        src: Reg,
+        to: u32,
+    },
+    // the arguments are the `n` registers after the callee
+    Call {
+        dst: Reg,
+        callee: Reg,
+        n: u8,
+    },
+    // `object.name(args)`, the arguments are the `n` registers after the object
+    Invoke {
+        dst: Reg,
+        object: Reg,
+        name: u32,
+        n: u8,
+    },
+    Return {
+        src: Reg,
+    },
+    // the items are the `n` registers from `start`, a map has `n` keys and values
+    MakeTuple {
+        dst: Reg,
+        start: Reg,
+        n: u8,
+    },
+    MakeList {
+        dst: Reg,
+        start: Reg,
+        n: u8,
+    },
+    MakeMap {
+        dst: Reg,
+        start: Reg,
+        n: u8,
+    },
+    // the start, the end and the step when the flags have it, see `MakeRange`
+    MakeRange {
+        dst: Reg,
+        start: Reg,
+        flags: u8,
+    },
+    // the enum or struct `template` with the fields in the `n` registers from `start`
+    Construct {
+        dst: Reg,
+        template: u32,
+        start: Reg,
+        n: u8,
+    },
+    GetField {
+        dst: Reg,
+        object: Reg,
+        name: u32,
+    },
+    SetField {
+        object: Reg,
+        name: u32,
+        src: Reg,
+    },
+    GetIndex {
+        dst: Reg,
+        object: Reg,
+        index: Reg,
+    },
+    SetIndex {
+        object: Reg,
+        index: Reg,
+        src: Reg,
+    },
+    GetItem {
+        dst: Reg,
+        src: Reg,
+        i: u8,
+    },
+    GetIter {
+        dst: Reg,
+        src: Reg,
+    },
+    // the next item of the iterator, or jump to `exit`
+    ForIter {
+        dst: Reg,
+        iter: Reg,
+        exit: u32,
+    },
+    Throw {
+        src: Reg,
+    },
+}
+
+impl Instr {
+    // the next instruction isn't run after it
+    pub fn ends_block(self) -> bool {
+        matches!(
+            self,
+            Instr::Jump { .. } | Instr::Return { .. } | Instr::Throw { .. }
+        )
+    }
+
+    // the registers it touches, from the first to the one after the last
+    fn registers(self) -> Vec<(Reg, usize)> {
+        use Instr::*;
+
+        let one = |r: Reg| (r, 1);
+        match self {
+            Nil { dst } | Bool { dst, .. } | Constant { dst, .. } | GetGlobal { dst, .. } => {
+                vec![one(dst)]
+            }
+            SetGlobal { src, .. } | Return { src } | Throw { src } | JumpIfOk { src, .. } => {
+                vec![one(src)]
+            }
+            Move { dst, src }
+            | Neg { dst, src }
+            | Not { dst, src }
+            | GetItem { dst, src, .. }
+            | GetIter { dst, src } => vec![one(dst), one(src)],
+            Add { dst, a, b }
+            | Sub { dst, a, b }
+            | Mult { dst, a, b }
+            | Div { dst, a, b }
+            | Eq { dst, a, b }
+            | Gt { dst, a, b }
+            | Lt { dst, a, b } => vec![one(dst), one(a), one(b)],
+            Jump { .. } => vec![],
+            JumpIfFalse { test, .. } => vec![one(test)],
+            Call { dst, callee, n } => vec![one(dst), (callee, n as usize + 1)],
+            Invoke { dst, object, n, .. } => vec![one(dst), (object, n as usize + 1)],
+            MakeTuple { dst, start, n } | MakeList { dst, start, n } => {
+                vec![one(dst), (start, n as usize)]
+            }
+            MakeMap { dst, start, n } => vec![one(dst), (start, n as usize * 2)],
+            MakeRange { dst, start, flags } => vec![one(dst), (start, 2 + (flags >> 1) as usize)],
+            Construct { dst, start, n, .. } => vec![one(dst), (start, n as usize)],
+            GetField { dst, object, .. } => vec![one(dst), one(object)],
+            SetField { object, src, .. } => vec![one(object), one(src)],
+            GetIndex { dst, object, index } => vec![one(dst), one(object), one(index)],
+            SetIndex { object, index, src } => vec![one(object), one(index), one(src)],
+            ForIter { dst, iter, .. } => vec![one(dst), one(iter)],
+        }
+    }
+
+    fn target(self) -> Option<u32> {
+        match self {
+            Instr::Jump { to } | Instr::JumpIfFalse { to, .. } | Instr::JumpIfOk { to, .. } => {
+                Some(to)
+            }
+            Instr::ForIter { exit, .. } => Some(exit),
+            _ => None,
+        }
+    }
+
+    fn constant(self) -> Option<u32> {
+        match self {
+            Instr::Constant { i, .. } => Some(i),
+            Instr::Invoke { name: i, .. }
+            | Instr::Construct { template: i, .. }
+            | Instr::GetField { name: i, .. }
+            | Instr::SetField { name: i, .. } => Some(i),
+            _ => None,
+        }
+    }
+}
+
+// e.g. `Add r2, r0, r1`, `JumpIfFalse r0 -> 12`
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instr::*;
+
+        match *self {
+            Nil { dst } => write!(f, "Nil r{}", dst),
+            Bool { dst, value } => write!(f, "Bool r{}, {}", dst, value),
+            Constant { dst, i } => write!(f, "Constant r{}, #{}", dst, i),
+            Move { dst, src } => write!(f, "Move r{}, r{}", dst, src),
+            GetGlobal { dst, i } => write!(f, "GetGlobal r{}, g{}", dst, i),
+            SetGlobal { i, src } => write!(f, "SetGlobal g{}, r{}", i, src),
+            Add { dst, a, b } => write!(f, "Add r{}, r{}, r{}", dst, a, b),
+            Sub { dst, a, b } => write!(f, "Sub r{}, r{}, r{}", dst, a, b),
+            Mult { dst, a, b } => write!(f, "Mult r{}, r{}, r{}", dst, a, b),
+            Div { dst, a, b } => write!(f, "Div r{}, r{}, r{}", dst, a, b),
+            Eq { dst, a, b } => write!(f, "Eq r{}, r{}, r{}", dst, a, b),
+            Gt { dst, a, b } => write!(f, "Gt r{}, r{}, r{}", dst, a, b),
+            Lt { dst, a, b } => write!(f, "Lt r{}, r{}, r{}", dst, a, b),
+            Neg { dst, src } => write!(f, "Neg r{}, r{}", dst, src),
+            Not { dst, src } => write!(f, "Not r{}, r{}", dst, src),
+            Jump { to } => write!(f, "Jump -> {:04}", to),
+            JumpIfFalse { test, to } => write!(f, "JumpIfFalse r{} -> {:04}", test, to),
+            JumpIfOk { src, to } => write!(f, "JumpIfOk r{} -> {:04}", src, to),
+            Call { dst, callee, n } => write!(f, "Call r{}, r{}, {}", dst, callee, n),
+            Invoke {
+                dst,
+                object,
+                name,
+                n,
+            } => write!(f, "Invoke r{}, r{}, #{}, {}", dst, object, name, n),
+            Return { src } => write!(f, "Return r{}", src),
+            MakeTuple { dst, start, n } => write!(f, "MakeTuple r{}, r{}, {}", dst, start, n),
+            MakeList { dst, start, n } => write!(f, "MakeList r{}, r{}, {}", dst, start, n),
+            MakeMap { dst, start, n } => write!(f, "MakeMap r{}, r{}, {}", dst, start, n),
+            MakeRange { dst, start, flags } => {
+                write!(f, "MakeRange r{}, r{}, {:#04b}", dst, start, flags)
+            }
+            Construct {
+                dst,
+                template,
+                start,
+                n,
+            } => write!(f, "Construct r{}, #{}, r{}, {}", dst, template, start, n),
+            GetField { dst, object, name } => {
+                write!(f, "GetField r{}, r{}, #{}", dst, object, name)
+            }
+            SetField { object, name, src } => {
+                write!(f, "SetField r{}, #{}, r{}", object, name, src)
+            }
+            GetIndex { dst, object, index } => {
+                write!(f, "GetIndex r{}, r{}, r{}", dst, object, index)
+            }
+            SetIndex { object, index, src } => {
+                write!(f, "SetIndex r{}, r{}, r{}", object, index, src)
+            }
+            GetItem { dst, src, i } => write!(f, "GetItem r{}, r{}, {}", dst, src, i),
+            GetIter { dst, src } => write!(f, "GetIter r{}, r{}", dst, src),
+            ForIter { dst, iter, exit } => {
+                write!(f, "ForIter r{}, r{} -> {:04}", dst, iter, exit)
+            }
+            Throw { src } => write!(f, "Throw r{}", src),
+        }
+    }
+}
+
+// the code of a function of the register backend. it is checked when it is made, the
+// vm runs it without checks: the jumps and the constants are there, the last
+// instruction doesn't go on past the end and the window has every register used.
+#[derive(Debug)]
+pub struct Code {
+    instrs: Vec<Instr>,
+    constants: Vec<Value>,
+    // the size of the window, the arguments are the first registers
+    registers: usize,
+}
+
+impl Code {
+    pub fn new(instrs: Vec<Instr>, constants: Vec<Value>, arity: u8) -> Result<Code, RuntimeError> {
+        if !instrs.last().is_some_and(|instr| instr.ends_block()) {
+            return Err(RuntimeError::UnexpectedEnd);
+        }
+        let mut registers = arity as usize;
+        for (at, instr) in instrs.iter().enumerate() {
+            if instr.target().is_some_and(|to| to as usize >= instrs.len()) {
+                return Err(RuntimeError::InvalidJump(at));
+            }
+            if let Some(i) = instr.constant().filter(|i| *i as usize >= constants.len()) {
+                return Err(RuntimeError::InvalidConstant(i as usize));
+            }
+            for (start, n) in instr.registers() {
+                registers = registers.max(start as usize + n);
+            }
+        }
+        Ok(Code {
+            instrs,
+            constants,
+            registers,
+        })
+    }
+
+    pub fn instrs(&self) -> &[Instr] {
+        &self.instrs
+    }
+
+    pub fn constants(&self) -> &[Value] {
+        &self.constants
+    }
+
+    pub fn registers(&self) -> usize {
+        self.registers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Code, Instr};
+    use crate::{error::RuntimeError, value::Value};
+
+    #[test]
+    fn test_new() {
+        let instrs = vec![
+            Instr::Constant { dst: 1, i: 0 },
+            Instr::Call {
+                dst: 0,
+                callee: 1,
+                n: 2,
+            },
+            Instr::Return { src: 0 },
+        ];
+        let code = Code::new(instrs, vec![Value::Int(1)], 1).unwrap();
+        // the arguments of the call are the registers 2 and 3
+        assert_eq!(code.registers(), 4);
+        assert_eq!(code.instrs()[1].to_string(), "Call r0, r1, 2");
+
+        let cases = [
+            (vec![Instr::Nil { dst: 0 }], RuntimeError::UnexpectedEnd),
+            (
+                vec![Instr::Constant { dst: 0, i: 1 }, Instr::Return { src: 0 }],
+                RuntimeError::InvalidConstant(1),
+            ),
+            (
+                vec![Instr::Return { src: 0 }, Instr::Jump { to: 2 }],
+                RuntimeError::InvalidJump(1),
+            ),
+        ];
+        for (instrs, error) in cases {
+            assert_eq!(Code::new(instrs, vec![Value::Nil], 0).unwrap_err(), error);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+// the stack of the vm and of the parked coroutines. a slot is a `Value`, or one word
+// with the `nanbox` feature, see `nanbox`. the vm pushes and pops values either way.
+
+use std::{borrow::Cow, fmt, mem};
+
+use crate::value::Value;
+
+#[cfg(feature = "nanbox")]
+type Slot = crate::nanbox::NanBox;
+#[cfg(not(feature = "nanbox"))]
+type Slot = Value;
+
+// how a value is kept in a slot
+pub(crate) trait Repr: Clone {
+    fn from_value(value: Value) -> Self;
+    fn into_value(self) -> Value;
+    fn to_value(&self) -> Value;
+    // the value behind a pointer, the others aren't on the heap. it is a copy when the
+    // slot isn't a `Value`
+    fn heap(&self) -> Option<Cow<'_, Value>>;
+    fn int(&self) -> Option<i64>;
+}
+
+impl Repr for Value {
+    #[inline]
+    fn from_value(value: Value) -> Value {
+        value
+    }
+
+    #[inline]
+    fn into_value(self) -> Value {
+        self
+    }
+
+    #[inline]
+    fn to_value(&self) -> Value {
+        self.clone()
+    }
+
+    #[inline]
+    fn heap(&self) -> Option<Cow<'_, Value>> {
+        Some(Cow::Borrowed(self))
+    }
+
+    #[inline]
+    fn int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Stack {
+    slots: Vec<Slot>,
+}
+
+impl Stack {
+    #[inline]
+    pub(crate) fn with_capacity(n: usize) -> Stack {
+        Stack {
+            slots: Vec::with_capacity(n),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    // the bytes of the slots, the values they point to aren't counted
+    #[inline]
+    pub(crate) fn bytes(&self) -> usize {
+        self.slots.capacity() * mem::size_of::<Slot>()
+    }
+
+    #[inline]
+    pub(crate) fn push(&mut self, value: Value) {
+        self.slots.push(Slot::from_value(value));
+    }
+
+    #[inline]
+    pub(crate) fn pop(&mut self) -> Option<Value> {
+        self.slots.pop().map(Repr::into_value)
+    }
+
+    #[inline]
+    pub(crate) fn last(&self) -> Option<Value> {
+        self.slots.last().map(Repr::to_value)
+    }
+
+    #[inline]
+    pub(crate) fn get(&self, i: usize) -> Option<Value> {
+        self.slots.get(i).map(Repr::to_value)
+    }
+
+    // the value that was at `i`, `None` when there is no slot
+    #[inline]
+    pub(crate) fn replace(&mut self, i: usize, value: Value) -> Option<Value> {
+        let slot = self.slots.get_mut(i)?;
+        Some(mem::replace(slot, Slot::from_value(value)).into_value())
+    }
+
+    #[inline]
+    pub(crate) fn remove(&mut self, i: usize) -> Value {
+        self.slots.remove(i).into_value()
+    }
+
+    #[inline]
+    pub(crate) fn clear(&mut self) {
+        self.slots.clear();
+    }
+
+    #[inline]
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.slots.truncate(len);
+    }
+
+    #[inline]
+    pub(crate) fn split_off(&mut self, start: usize) -> Vec<Value> {
+        self.slots.drain(start..).map(Repr::into_value).collect()
+    }
+
+    #[inline]
+    pub(crate) fn extend(&mut self, values: impl IntoIterator<Item = Value>) {
+        self.slots.extend(values.into_iter().map(Slot::from_value));
+    }
+
+    // copy the slot at `i` on top, e.g. a local
+    #[inline]
+    pub(crate) fn push_copy(&mut self, i: usize) -> Option<()> {
+        let slot = self.slots.get(i)?.clone();
+        self.slots.push(slot);
+        Some(())
+    }
+
+    // move the top into the slot at `i`, e.g. a local
+    #[inline]
+    pub(crate) fn pop_into(&mut self, i: usize) -> Option<()> {
+        let slot = self.slots.pop()?;
+        *self.slots.get_mut(i)? = slot;
+        Some(())
+    }
+
+    // drop `n` values under the top
+    #[inline]
+    pub(crate) fn drop_under(&mut self, n: usize) -> Option<()> {
+        let top = self.slots.pop()?;
+        let len = self.slots.len().checked_sub(n)?;
+        self.slots.truncate(len);
+        self.slots.push(top);
+        Some(())
+    }
+
+    // the values that may hold objects, for the collector and the heap limit
+    #[inline]
+    pub(crate) fn heap_values(&self) -> impl Iterator<Item = Cow<'_, Value>> {
+        self.slots.iter().filter_map(Repr::heap)
+    }
+
+    // the two ints on top, for the fast paths of the arithmetic
+    #[inline]
+    pub(crate) fn top_ints(&self) -> Option<(i64, i64)> {
+        match self.slots.as_slice() {
+            [.., a, b] => Some((a.int()?, b.int()?)),
+            _ => None,
+        }
+    }
+
+    // the ints at `a` and `b`, for the fast paths of the register backend
+    #[inline]
+    pub(crate) fn ints(&self, a: usize, b: usize) -> Option<(i64, i64)> {
+        Some((self.slots[a].int()?, self.slots[b].int()?))
+    }
+
+    // a register of the register backend, its window is always in the stack
+    #[inline]
+    pub(crate) fn at(&self, i: usize) -> Value {
+        self.slots[i].to_value()
+    }
+
+    #[inline]
+    pub(crate) fn set(&mut self, i: usize, value: Value) {
+        self.slots[i] = Slot::from_value(value);
+    }
+
+    #[inline]
+    pub(crate) fn copy(&mut self, src: usize, dst: usize) {
+        self.slots[dst] = self.slots[src].clone();
+    }
+
+    // cut the stack or fill it with nils up to `len`
+    #[inline]
+    pub(crate) fn resize(&mut self, len: usize) {
+        self.slots.resize_with(len, || Slot::from_value(Value::Nil));
+    }
+
+    // replace the two values on top by the result of their operation
+    #[inline]
+    pub(crate) fn replace_top2(&mut self, value: Value) {
+        let len = self.slots.len();
+        self.slots.truncate(len - 1);
+        self.slots[len - 2] = Slot::from_value(value);
+    }
+}
+
+impl From<Vec<Value>> for Stack {
+    #[inline]
+    fn from(values: Vec<Value>) -> Stack {
+        Stack {
+            slots: values.into_iter().map(Slot::from_value).collect(),
+        }
+    }
+}
+
+impl fmt::Debug for Stack {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(&self.slots).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stack;
+    use crate::value::Value;
+
+    #[test]
+    fn test_values() {
+        let values = vec![
+            Value::Nil,
+            Value::Bool(true),
+            Value::Int(-3),
+            Value::Int(i64::MAX),
+            Value::Float(-0.5),
+            Value::Str("a".into()),
+        ];
+        let mut stack = Stack::from(values);
+        assert_eq!(stack.get(3), Some(Value::Int(i64::MAX)));
+        assert!(stack.heap_values().count() >= 1);
+
+        stack.push_copy(2);
+        stack.push(Value::Int(5));
+        assert_eq!(stack.top_ints(), Some((-3, 5)));
+        stack.replace_top2(Value::Int(2));
+        assert_eq!(stack.pop(), Some(Value::Int(2)));
+
+        assert_eq!(stack.replace(0, Value::Int(1)), Some(Value::Nil));
+        stack.pop_into(1);
+        stack.drop_under(2);
+        assert_eq!(stack.len(), 3);
+        assert_eq!(stack.last(), Some(Value::Float(-0.5)));
+        assert_eq!(
+            stack.split_off(0)[..2],
+            [Value::Int(1), Value::Str("a".into())]
+        );
+        assert_eq!(stack.len(), 0);
+    }
+}
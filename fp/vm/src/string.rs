@@ -0,0 +1,227 @@
+// the strings of the values, they can't be changed so a copy is a clone of an `Rc`.
+// the strings of the code, e.g. the names and the literals, are interned by the
+// compiler: there is one `Rc` for each text, two of them are equal when they are the
+// same `Rc`. the strings made by a run, e.g. by `+`, aren't, most of them are short lived.
+// a text is forgotten once the interner holds its only `Rc`, a later `intern` makes
+// a new one: no other `Str` can see the difference.
+// the text is an `Rc<String>`, a thin pointer a slot of the `nanbox` stack can hold.
+
+use std::{
+    borrow::Borrow,
+    cell::RefCell,
+    cmp::Ordering,
+    collections::HashSet,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+    rc::Rc,
+};
+
+thread_local! {
+    // the compiler and the vm of a thread share it like they share the values
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::default());
+}
+
+// the texts are pruned when their number doubles since the last pruning
+const MIN_PRUNE: usize = 1024;
+
+#[derive(Default)]
+struct Interner {
+    texts: HashSet<Text>,
+    prune_at: usize,
+}
+
+// an interned text, looked up by its `str`
+#[derive(PartialEq, Eq, Hash)]
+struct Text(Rc<String>);
+
+impl Borrow<str> for Text {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Interner {
+    fn intern(&mut self, text: &str) -> Rc<String> {
+        if let Some(text) = self.texts.get(text) {
+            return text.0.clone();
+        }
+        if self.texts.len() >= self.prune_at {
+            self.texts.retain(|text| Rc::strong_count(&text.0) > 1);
+            self.prune_at = MIN_PRUNE.max(self.texts.len() * 2);
+        }
+        let text = Rc::new(text.to_owned());
+        self.texts.insert(Text(text.clone()));
+        text
+    }
+}
+
+#[derive(Clone)]
+pub struct Str {
+    text: Rc<String>,
+    interned: bool,
+}
+
+impl Str {
+    pub fn new(text: &str) -> Str {
+        Str {
+            text: Rc::new(text.to_owned()),
+            interned: false,
+        }
+    }
+
+    // the `Rc` of the text, for the `nanbox` stack
+    #[cfg(feature = "nanbox")]
+    pub(crate) fn into_rc(self) -> (Rc<String>, bool) {
+        (self.text, self.interned)
+    }
+
+    #[cfg(feature = "nanbox")]
+    pub(crate) fn from_rc(text: Rc<String>, interned: bool) -> Str {
+        Str { text, interned }
+    }
+
+    // the same `Rc` for the same text while a `Str` of it is alive
+    pub fn intern(text: &str) -> Str {
+        let text = INTERNER.with(|interner| interner.borrow_mut().intern(text));
+        Str {
+            text,
+            interned: true,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn is_interned(&self) -> bool {
+        self.interned
+    }
+}
+
+impl Deref for Str {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl Borrow<str> for Str {
+    fn borrow(&self) -> &str {
+        &self.text
+    }
+}
+
+impl AsRef<str> for Str {
+    fn as_ref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl From<&str> for Str {
+    fn from(text: &str) -> Str {
+        Str::new(text)
+    }
+}
+
+impl From<String> for Str {
+    fn from(text: String) -> Str {
+        Str {
+            text: Rc::new(text),
+            interned: false,
+        }
+    }
+}
+
+impl From<Str> for String {
+    fn from(s: Str) -> String {
+        Rc::try_unwrap(s.text).unwrap_or_else(|text| (*text).clone())
+    }
+}
+
+impl PartialEq for Str {
+    fn eq(&self, other: &Self) -> bool {
+        if Rc::ptr_eq(&self.text, &other.text) {
+            return true;
+        }
+        // two interned strings are only equal to themselves
+        !(self.interned && other.interned) && self.text == other.text
+    }
+}
+
+impl Eq for Str {}
+
+impl PartialEq<str> for Str {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Str {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialOrd for Str {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Str {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.text.cmp(&other.text)
+    }
+}
+
+// like a `str`, for the lookups by `&str`
+impl Hash for Str {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.text.hash(state)
+    }
+}
+
+impl fmt::Display for Str {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Debug for Str {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Str, INTERNER, MIN_PRUNE};
+
+    #[test]
+    fn test_intern() {
+        let a = Str::intern("name");
+        let b = Str::intern(&String::from("name"));
+        assert!(std::ptr::eq(a.as_str(), b.as_str()));
+        assert_eq!(a, b);
+        assert_ne!(a, Str::intern("other"));
+
+        let c = Str::new("name");
+        assert!(!c.is_interned());
+        assert_eq!(a, c);
+        assert_eq!(c, "name");
+        assert!(Str::new("a") < Str::intern("b"));
+    }
+
+    #[test]
+    fn test_prune() {
+        let kept = Str::intern("kept");
+        for i in 0..MIN_PRUNE * 10 {
+            Str::intern(&format!("text {}", i));
+        }
+        let len = INTERNER.with(|interner| interner.borrow().texts.len());
+        assert!(len <= MIN_PRUNE * 2, "{} texts", len);
+        assert!(std::ptr::eq(kept.as_str(), Str::intern("kept").as_str()));
+    }
+}
@@ -0,0 +1,272 @@
+// rust values given to the scripts, e.g. a request of the host with `request.header("x")`.
+// the type is registered once with its methods and properties, see `Vm::register_type`.
+
+use std::{
+    any::{self, Any, TypeId},
+    cell::{Ref, RefCell, RefMut},
+    collections::HashMap,
+    fmt,
+    marker::PhantomData,
+    rc::Rc,
+};
+
+use crate::{error::RuntimeError, native::Arity, value::Value, vm::Vm};
+
+type MethodBox = Box<dyn Fn(&mut Vm, &UserData, &[Value]) -> Result<Value, RuntimeError>>;
+type GetterBox = Box<dyn Fn(&UserData) -> Result<Value, RuntimeError>>;
+type SetterBox = Box<dyn Fn(&UserData, Value) -> Result<(), RuntimeError>>;
+type FmtBox = Box<dyn Fn(&dyn Any, &mut fmt::Formatter<'_>) -> fmt::Result>;
+type EqBox = Box<dyn Fn(&dyn Any, &dyn Any) -> bool>;
+
+pub struct UserType {
+    pub name: String,
+    type_id: TypeId,
+    methods: HashMap<String, (Arity, MethodBox)>,
+    getters: HashMap<String, GetterBox>,
+    setters: HashMap<String, SetterBox>,
+    // `<Name>` and identity when they aren't given
+    fmt: Option<FmtBox>,
+    eq: Option<EqBox>,
+}
+
+impl UserType {
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+}
+
+// the methods and properties of the type `T`
+pub struct TypeBuilder<T> {
+    ty: UserType,
+    marker: PhantomData<T>,
+}
+
+impl<T: 'static> TypeBuilder<T> {
+    // `name` is the type seen by the scripts, e.g. by `type_of`
+    pub fn new(name: &str) -> Self {
+        let ty = UserType {
+            name: name.to_owned(),
+            type_id: TypeId::of::<T>(),
+            methods: HashMap::new(),
+            getters: HashMap::new(),
+            setters: HashMap::new(),
+            fmt: None,
+            eq: None,
+        };
+        TypeBuilder {
+            ty,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn method(
+        mut self,
+        name: &str,
+        arity: Arity,
+        fun: impl Fn(&T, &mut Vm, &[Value]) -> Result<Value, RuntimeError> + 'static,
+    ) -> Self {
+        let method = move |vm: &mut Vm, data: &UserData, args: &[Value]| {
+            let this = data.borrow::<T>()?;
+            fun(&this, vm, args)
+        };
+        self.ty
+            .methods
+            .insert(name.to_owned(), (arity, Box::new(method)));
+        self
+    }
+
+    // the value can't be used by the script while the method runs
+    pub fn method_mut(
+        mut self,
+        name: &str,
+        arity: Arity,
+        fun: impl Fn(&mut T, &mut Vm, &[Value]) -> Result<Value, RuntimeError> + 'static,
+    ) -> Self {
+        let method = move |vm: &mut Vm, data: &UserData, args: &[Value]| {
+            let mut this = data.borrow_mut::<T>()?;
+            fun(&mut this, vm, args)
+        };
+        self.ty
+            .methods
+            .insert(name.to_owned(), (arity, Box::new(method)));
+        self
+    }
+
+    // `value.name`
+    pub fn getter(mut self, name: &str, fun: impl Fn(&T) -> Value + 'static) -> Self {
+        let getter = move |data: &UserData| Ok(fun(&*data.borrow::<T>()?));
+        self.ty.getters.insert(name.to_owned(), Box::new(getter));
+        self
+    }
+
+    // `value.name = x`
+    pub fn setter(
+        mut self,
+        name: &str,
+        fun: impl Fn(&mut T, Value) -> Result<(), RuntimeError> + 'static,
+    ) -> Self {
+        let setter = move |data: &UserData, value| fun(&mut *data.borrow_mut::<T>()?, value);
+        self.ty.setters.insert(name.to_owned(), Box::new(setter));
+        self
+    }
+
+    // print the values with the `Display` of `T`
+    pub fn display(mut self) -> Self
+    where
+        T: fmt::Display,
+    {
+        let fmt = |value: &dyn Any, f: &mut fmt::Formatter<'_>| match value.downcast_ref::<T>() {
+            Some(value) => value.fmt(f),
+            None => Ok(()),
+        };
+        self.ty.fmt = Some(Box::new(fmt));
+        self
+    }
+
+    // compare the values with the `PartialEq` of `T`
+    pub fn eq(mut self) -> Self
+    where
+        T: PartialEq,
+    {
+        let eq = |a: &dyn Any, b: &dyn Any| match (a.downcast_ref::<T>(), b.downcast_ref::<T>()) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        };
+        self.ty.eq = Some(Box::new(eq));
+        self
+    }
+
+    pub fn build(self) -> UserType {
+        self.ty
+    }
+}
+
+pub struct UserData {
+    pub ty: Rc<UserType>,
+    value: RefCell<Box<dyn Any>>,
+}
+
+impl UserData {
+    pub(crate) fn new(ty: Rc<UserType>, value: Box<dyn Any>) -> UserData {
+        UserData {
+            ty,
+            value: RefCell::new(value),
+        }
+    }
+
+    pub fn is<T: 'static>(&self) -> bool {
+        self.ty.type_id == TypeId::of::<T>()
+    }
+
+    pub fn borrow<T: 'static>(&self) -> Result<Ref<'_, T>, RuntimeError> {
+        self.check::<T>()?;
+        let value = self.value.try_borrow().map_err(|_| self.borrowed())?;
+        Ok(Ref::map(value, |v| v.downcast_ref::<T>().unwrap()))
+    }
+
+    pub fn borrow_mut<T: 'static>(&self) -> Result<RefMut<'_, T>, RuntimeError> {
+        self.check::<T>()?;
+        let value = self.value.try_borrow_mut().map_err(|_| self.borrowed())?;
+        Ok(RefMut::map(value, |v| v.downcast_mut::<T>().unwrap()))
+    }
+
+    fn check<T: 'static>(&self) -> Result<(), RuntimeError> {
+        if !self.is::<T>() {
+            return Err(RuntimeError::TypeError(format!(
+                "expected `{}`, found `{}`",
+                short_type_name::<T>(),
+                self.ty.name
+            )));
+        }
+        Ok(())
+    }
+
+    fn borrowed(&self) -> RuntimeError {
+        RuntimeError::TypeError(format!("the `{}` is in use", self.ty.name))
+    }
+
+    pub(crate) fn call_method(
+        &self,
+        vm: &mut Vm,
+        name: &str,
+        args: &[Value],
+    ) -> Result<Value, RuntimeError> {
+        let (arity, method) = self.ty.methods.get(name).ok_or_else(|| {
+            RuntimeError::TypeError(format!("`{}` has no method `{}`", self.ty.name, name))
+        })?;
+        if !arity.accepts(args.len()) {
+            return Err(RuntimeError::WrongArity {
+                name: format!("{}.{}", self.ty.name, name),
+                arity: *arity,
+                found: args.len(),
+            });
+        }
+        method(vm, self, args)
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Result<Value, RuntimeError> {
+        match self.ty.getters.get(name) {
+            Some(getter) => getter(self),
+            None => Err(self.no_property(name)),
+        }
+    }
+
+    pub(crate) fn set(&self, name: &str, value: Value) -> Result<(), RuntimeError> {
+        match self.ty.setters.get(name) {
+            Some(setter) => setter(self, value),
+            None => Err(self.no_property(name)),
+        }
+    }
+
+    fn no_property(&self, name: &str) -> RuntimeError {
+        RuntimeError::TypeError(format!("`{}` has no property `{}`", self.ty.name, name))
+    }
+}
+
+// `Request` instead of `my_crate::http::Request`
+fn short_type_name<T>() -> &'static str {
+    let name = any::type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+impl fmt::Debug for UserData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<{}>", self.ty.name)
+    }
+}
+
+impl fmt::Display for UserData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.ty.fmt, self.value.try_borrow()) {
+            (Some(fmt), Ok(value)) => fmt(&**value, f),
+            _ => write!(f, "<{}>", self.ty.name),
+        }
+    }
+}
+
+// a value is always equal to itself, the values of a type without `eq` are only
+// equal to themselves.
+impl PartialEq for UserData {
+    fn eq(&self, other: &Self) -> bool {
+        if std::ptr::eq(self, other) {
+            return true;
+        }
+        if self.ty.type_id != other.ty.type_id {
+            return false;
+        }
+        match (
+            &self.ty.eq,
+            self.value.try_borrow(),
+            other.value.try_borrow(),
+        ) {
+            (Some(eq), Ok(a), Ok(b)) => eq(&**a, &**b),
+            _ => false,
+        }
+    }
+}
+
+impl PartialOrd for UserData {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        (self == other).then_some(std::cmp::Ordering::Equal)
+    }
+}
@@ -1,12 +1,255 @@
-use std::ops;
+use std::{cell::RefCell, cmp::Ordering, fmt, ops, rc::Rc};
 
-#[derive(Debug, Clone, PartialEq, PartialOrd)]
+use crate::{
+    coroutine::Coroutine,
+    iter::{Iter, Range},
+    map::Map,
+    native::NativeFn,
+    object::{EnumObj, Function, StructObj},
+    prelude,
+    string::Str,
+    userdata::UserData,
+};
+
+mod compare;
+
+// how deep the values are written, see `Value::fmt_nested`
+const MAX_FMT_DEPTH: usize = 100;
+
+#[derive(Debug, Clone)]
 pub enum Value {
     Nil,
     Int(i64),
     Float(f64),
-    Str(String),
+    Str(Str),
     Bool(bool),
+    Tuple(Rc<Vec<Value>>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Enum(Rc<EnumObj>),
+    Struct(Rc<StructObj>),
+    Map(Rc<RefCell<Map>>),
+    Range(Range),
+    Iter(Rc<RefCell<Iter>>),
+    NativeFn(Rc<NativeFn>),
+    Function(Rc<Function>),
+    UserData(Rc<UserData>),
+    Coroutine(Rc<Coroutine>),
+}
+
+impl Value {
+    // the length of the string made by `self + other` or `self * other`, None when it
+    // doesn't make a string
+    pub(crate) fn str_len(&self, op: &str, other: &Value) -> Option<usize> {
+        let len = |value: &Value| match value {
+            Value::Int(v) => Some(v.to_string().len()),
+            Value::Float(v) => Some(v.to_string().len()),
+            Value::Str(s) => Some(s.len()),
+            _ => None,
+        };
+        match (op, self, other) {
+            ("+", Value::Str(_), _) | ("+", _, Value::Str(_)) => {
+                Some(len(self)?.saturating_add(len(other)?))
+            }
+            ("*", Value::Str(s), Value::Int(n)) if *n >= 0 => {
+                Some(s.len().saturating_mul(*n as usize))
+            }
+            _ => None,
+        }
+    }
+
+    // the name of the enum or struct for their values
+    pub fn type_name(&self) -> &str {
+        match self {
+            Value::Nil => "nil",
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Str(_) => "str",
+            Value::Bool(_) => "bool",
+            Value::Tuple(_) => "tuple",
+            Value::List(_) => "list",
+            Value::Enum(e) => &e.def.name,
+            Value::Struct(s) => &s.def.name,
+            Value::Map(_) => "map",
+            Value::Range(_) => "range",
+            Value::Iter(_) => "iterator",
+            Value::NativeFn(_) | Value::Function(_) => "fn",
+            Value::UserData(data) => &data.ty.name,
+            Value::Coroutine(_) => "coroutine",
+        }
+    }
+
+    // strings in a container are quoted. `open` has the lists, structs and maps being
+    // written, one holding itself is written `[...]`, and the values nested deeper than
+    // `MAX_FMT_DEPTH` are written `...`.
+    fn fmt_nested(&self, f: &mut fmt::Formatter<'_>, open: &mut Vec<*const ()>) -> fmt::Result {
+        let nested = !open.is_empty();
+        let list = |f: &mut fmt::Formatter<'_>, open: &mut Vec<*const ()>, items: &[Value]| {
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                item.fmt_nested(f, open)?;
+            }
+            Ok(())
+        };
+        let ptr = match self {
+            Value::Tuple(items) => Some(Rc::as_ptr(items) as *const ()),
+            Value::List(items) => Some(Rc::as_ptr(items) as *const ()),
+            Value::Enum(e) => Some(Rc::as_ptr(e) as *const ()),
+            Value::Struct(s) => Some(Rc::as_ptr(s) as *const ()),
+            Value::Map(map) => Some(Rc::as_ptr(map) as *const ()),
+            _ => None,
+        };
+        if let Some(ptr) = ptr {
+            if open.contains(&ptr) {
+                return match self {
+                    Value::List(_) => write!(f, "[...]"),
+                    Value::Map(_) => write!(f, "#{{...}}"),
+                    Value::Struct(s) => write!(f, "{} {{ ... }}", s.def.name),
+                    _ => write!(f, "..."),
+                };
+            }
+            if open.len() >= MAX_FMT_DEPTH {
+                return write!(f, "...");
+            }
+            open.push(ptr);
+        }
+        let result = match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Int(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{:?}", v),
+            Value::Str(s) if nested => write!(f, "{:?}", s),
+            Value::Str(s) => write!(f, "{}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Tuple(items) => {
+                write!(f, "(")?;
+                list(f, open, items)?;
+                if items.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+            Value::List(items) => {
+                write!(f, "[")?;
+                list(f, open, &items.borrow())?;
+                write!(f, "]")
+            }
+            Value::Enum(e) => {
+                // `Some(1)` rather than `Option::Some(1)`
+                if !prelude::is_prelude(&e.def) {
+                    write!(f, "{}::", e.def.name)?;
+                }
+                write!(f, "{}", e.variant().name)?;
+                if !e.fields.is_empty() {
+                    write!(f, "(")?;
+                    list(f, open, &e.fields)?;
+                    write!(f, ")")?;
+                }
+                Ok(())
+            }
+            Value::Struct(s) => {
+                write!(f, "{} {{ ", s.def.name)?;
+                for (i, (name, value)) in s
+                    .def
+                    .fields
+                    .iter()
+                    .zip(s.fields.borrow().iter())
+                    .enumerate()
+                {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", name)?;
+                    value.fmt_nested(f, open)?;
+                }
+                write!(f, " }}")
+            }
+            Value::Map(map) => {
+                write!(f, "#{{")?;
+                for (i, (k, v)) in map.borrow().entries().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    k.fmt_nested(f, open)?;
+                    write!(f, ": ")?;
+                    v.fmt_nested(f, open)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Range(r) => {
+                let op = if r.inclusive { "..=" } else { ".." };
+                write!(f, "{}{}{}", r.start, op, r.end)?;
+                if r.step != 1 {
+                    write!(f, " step {}", r.step)?;
+                }
+                Ok(())
+            }
+            Value::Iter(_) => write!(f, "<iterator>"),
+            Value::NativeFn(fun) => write!(f, "<fn {}>", fun.name),
+            Value::Function(fun) => write!(f, "<fn {}>", fun.name),
+            Value::UserData(data) => write!(f, "{}", data),
+            Value::Coroutine(_) => write!(f, "<coroutine>"),
+        };
+        if ptr.is_some() {
+            open.pop();
+        }
+        result
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_nested(f, &mut Vec::new())
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        compare::eq(self, other)
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        compare::partial_cmp(self, other)
+    }
+}
+
+// a list nested deeper than the rust stack, e.g. made by `l = [l]` in a loop, is freed
+// one level at a time: the items of a container losing its last reference are taken
+// out of it, so it is freed without dropping them, then they are dropped by the loop.
+impl Drop for Value {
+    fn drop(&mut self) {
+        let mut items = Vec::new();
+        take_items(self, &mut items);
+        while let Some(mut item) = items.pop() {
+            take_items(&mut item, &mut items);
+        }
+    }
+}
+
+// the items of the value when it is the last reference to them, the immutable ones are
+// cloned and the value is replaced by nil before they are released
+fn take_items(value: &mut Value, items: &mut Vec<Value>) {
+    match value {
+        Value::List(list) if Rc::strong_count(list) == 1 => items.extend(list.take()),
+        Value::Struct(s) if Rc::strong_count(s) == 1 => items.extend(s.fields.take()),
+        Value::Map(map) if Rc::strong_count(map) == 1 => {
+            let entries = map.take().into_entries();
+            items.extend(entries.flat_map(|(k, v)| [k, v]));
+        }
+        Value::Tuple(tuple) if Rc::strong_count(tuple) == 1 => {
+            let tuple = tuple.clone();
+            *value = Value::Nil;
+            items.extend(tuple.iter().cloned());
+        }
+        Value::Enum(e) if Rc::strong_count(e) == 1 => {
+            let e = e.clone();
+            *value = Value::Nil;
+            items.extend(e.fields.iter().cloned());
+        }
+        _ => {}
+    }
 }
 
 type OpResult = Result<Value, ()>;
@@ -18,8 +261,9 @@ impl ops::Add for Value {
             Self::Nil => binary_ops::op_with_nil(),
             Self::Int(v) => binary_ops::add_int(v, rhs),
             Self::Float(v) => binary_ops::add_float(v, rhs),
-            Self::Str(s) => binary_ops::add_str(s, rhs),
+            Self::Str(ref s) => binary_ops::add_str(s.clone(), rhs),
             Self::Bool(_) => binary_ops::op_with_bool(),
+            _ => binary_ops::op_with_object(),
         }
     }
 }
@@ -31,8 +275,9 @@ impl ops::Sub for Value {
             Self::Nil => binary_ops::op_with_nil(),
             Self::Int(v) => binary_ops::sub_int(v, rhs),
             Self::Float(v) => binary_ops::sub_float(v, rhs),
-            Self::Str(s) => binary_ops::sub_str(s, rhs),
+            Self::Str(ref s) => binary_ops::sub_str(s.clone(), rhs),
             Self::Bool(_) => binary_ops::op_with_bool(),
+            _ => binary_ops::op_with_object(),
         }
     }
 }
@@ -44,8 +289,9 @@ impl ops::Mul for Value {
             Self::Nil => binary_ops::op_with_nil(),
             Self::Int(v) => binary_ops::mul_int(v, rhs),
             Self::Float(v) => binary_ops::mul_float(v, rhs),
-            Self::Str(s) => binary_ops::mul_str(s, rhs),
+            Self::Str(ref s) => binary_ops::mul_str(s.clone(), rhs),
             Self::Bool(_) => binary_ops::op_with_bool(),
+            _ => binary_ops::op_with_object(),
         }
     }
 }
@@ -57,16 +303,40 @@ impl ops::Div for Value {
             Self::Nil => binary_ops::op_with_nil(),
             Self::Int(v) => binary_ops::div_int(v, rhs),
             Self::Float(v) => binary_ops::div_float(v, rhs),
-            Self::Str(s) => binary_ops::div_str(s, rhs),
+            Self::Str(ref s) => binary_ops::div_str(s.clone(), rhs),
             Self::Bool(_) => binary_ops::op_with_bool(),
+            _ => binary_ops::op_with_object(),
+        }
+    }
+}
+
+impl ops::Neg for Value {
+    type Output = OpResult;
+    fn neg(self) -> Self::Output {
+        match self {
+            Self::Int(v) => v.checked_neg().map(Self::Int).ok_or(()),
+            Self::Float(v) => Ok(Self::Float(-v)),
+            _ => Err(()),
         }
     }
 }
 
+// the casts are kept as they were written, they are no-ops for `f64`
+#[allow(clippy::unnecessary_cast)]
 mod binary_ops {
-    use super::{OpResult, Value};
+    use super::{OpResult, Str, Value};
     // TODO: use custom #[derive] macros to impl add, sub...
 
+    // an allocation failing is an error, not an abort
+    fn concat(a: &str, b: &str) -> OpResult {
+        let mut s = String::new();
+        s.try_reserve_exact(a.len().checked_add(b.len()).ok_or(())?)
+            .map_err(|_| ())?;
+        s.push_str(a);
+        s.push_str(b);
+        Ok(Value::Str(s.into()))
+    }
+
     pub(super) fn op_with_nil() -> OpResult {
         Err(())
     }
@@ -75,17 +345,19 @@ mod binary_ops {
         Err(())
     }
 
+    pub(super) fn op_with_object() -> OpResult {
+        Err(())
+    }
+
     // === add ===
     pub(super) fn add_int(lhs: i64, rhs: Value) -> OpResult {
         match rhs {
             Value::Nil => Err(()),
-            Value::Int(v) => Ok(Value::Int(lhs + v)),
+            Value::Int(v) => lhs.checked_add(v).map(Value::Int).ok_or(()),
             Value::Float(v) => Ok(Value::Float(lhs as f64 + v)),
-            Value::Str(s) => {
-                let lhs = lhs.to_string();
-                Ok(Value::Str(lhs + &s))
-            }
+            Value::Str(ref s) => concat(&lhs.to_string(), s),
             Value::Bool(_) => Err(()),
+            _ => Err(()),
         }
     }
 
@@ -94,27 +366,20 @@ mod binary_ops {
             Value::Nil => Err(()),
             Value::Int(v) => Ok(Value::Float(lhs + v as f64)),
             Value::Float(v) => Ok(Value::Float(lhs as f64 + v)),
-            Value::Str(s) => {
-                let lhs = lhs.to_string();
-                Ok(Value::Str(lhs + &s))
-            }
+            Value::Str(ref s) => concat(&lhs.to_string(), s),
             Value::Bool(_) => Err(()),
+            _ => Err(()),
         }
     }
 
-    pub(super) fn add_str(lhs: String, rhs: Value) -> OpResult {
+    pub(super) fn add_str(lhs: Str, rhs: Value) -> OpResult {
         match rhs {
             Value::Nil => Err(()),
-            Value::Int(v) => {
-                let v = v.to_string();
-                Ok(Value::Str(lhs + &v))
-            }
-            Value::Float(v) => {
-                let v = v.to_string();
-                Ok(Value::Str(lhs + &v))
-            }
-            Value::Str(s) => Ok(Value::Str(lhs + &s)),
+            Value::Int(v) => concat(&lhs, &v.to_string()),
+            Value::Float(v) => concat(&lhs, &v.to_string()),
+            Value::Str(ref s) => concat(&lhs, s),
             Value::Bool(_) => Err(()),
+            _ => Err(()),
         }
     }
     // === end ===
@@ -123,10 +388,11 @@ mod binary_ops {
     pub(super) fn sub_int(lhs: i64, rhs: Value) -> OpResult {
         match rhs {
             Value::Nil => Err(()),
-            Value::Int(v) => Ok(Value::Int(lhs - v)),
+            Value::Int(v) => lhs.checked_sub(v).map(Value::Int).ok_or(()),
             Value::Float(v) => Ok(Value::Float(lhs as f64 - v)),
             Value::Str(_) => Err(()),
             Value::Bool(_) => Err(()),
+            _ => Err(()),
         }
     }
 
@@ -137,16 +403,18 @@ mod binary_ops {
             Value::Float(v) => Ok(Value::Float(lhs as f64 - v)),
             Value::Str(_) => Err(()),
             Value::Bool(_) => Err(()),
+            _ => Err(()),
         }
     }
 
-    pub(super) fn sub_str(_lhs: String, rhs: Value) -> OpResult {
+    pub(super) fn sub_str(_lhs: Str, rhs: Value) -> OpResult {
         match rhs {
             Value::Nil => Err(()),
             Value::Int(_) => Err(()),
             Value::Float(_) => Err(()),
             Value::Str(_) => Err(()),
             Value::Bool(_) => Err(()),
+            _ => Err(()),
         }
     }
     // === end ===
@@ -155,10 +423,11 @@ mod binary_ops {
     pub(super) fn mul_int(lhs: i64, rhs: Value) -> OpResult {
         match rhs {
             Value::Nil => Err(()),
-            Value::Int(v) => Ok(Value::Int(lhs * v)),
+            Value::Int(v) => lhs.checked_mul(v).map(Value::Int).ok_or(()),
             Value::Float(v) => Ok(Value::Float(lhs as f64 * v)),
             Value::Str(_) => Err(()),
             Value::Bool(_) => Err(()),
+            _ => Err(()),
         }
     }
 
@@ -169,22 +438,34 @@ mod binary_ops {
             Value::Float(v) => Ok(Value::Float(lhs as f64 * v)),
             Value::Str(_) => Err(()),
             Value::Bool(_) => Err(()),
+            _ => Err(()),
         }
     }
 
-    pub(super) fn mul_str(lhs: String, rhs: Value) -> OpResult {
+    pub(super) fn mul_str(lhs: Str, rhs: Value) -> OpResult {
         match rhs {
             Value::Nil => Err(()),
             Value::Int(v) => {
                 if v < 0 {
                     return Err(());
                 }
-                let result = lhs.repeat(v as usize);
-                Ok(Value::Str(result))
+                let len = lhs.len().checked_mul(v as usize).ok_or(())?;
+                let mut bytes = Vec::new();
+                bytes.try_reserve_exact(len).map_err(|_| ())?;
+                if len > 0 {
+                    // doubled until it is long enough, like `str::repeat`
+                    bytes.extend_from_slice(lhs.as_bytes());
+                    while bytes.len() < len {
+                        bytes.extend_from_within(..bytes.len().min(len - bytes.len()));
+                    }
+                }
+                let result = String::from_utf8(bytes).map_err(|_| ())?;
+                Ok(Value::Str(result.into()))
             }
             Value::Float(_) => Err(()),
             Value::Str(_) => Err(()),
             Value::Bool(_) => Err(()),
+            _ => Err(()),
         }
     }
     // === end ===
@@ -197,6 +478,7 @@ mod binary_ops {
             Value::Float(v) => Ok(Value::Float(lhs as f64 / v)),
             Value::Str(_) => Err(()),
             Value::Bool(_) => Err(()),
+            _ => Err(()),
         }
     }
 
@@ -207,22 +489,25 @@ mod binary_ops {
             Value::Float(v) => Ok(Value::Float(lhs as f64 / v)),
             Value::Str(_) => Err(()),
             Value::Bool(_) => Err(()),
+            _ => Err(()),
         }
     }
 
-    pub(super) fn div_str(_lhs: String, rhs: Value) -> OpResult {
+    pub(super) fn div_str(_lhs: Str, rhs: Value) -> OpResult {
         match rhs {
             Value::Nil => Err(()),
             Value::Int(_) => Err(()),
             Value::Float(_) => Err(()),
             Value::Str(_) => Err(()),
             Value::Bool(_) => Err(()),
+            _ => Err(()),
         }
     }
     // === end ===
 }
 
 #[cfg(test)]
+#[allow(clippy::unnecessary_cast)]
 mod test {
     use super::{OpResult, Value};
 
@@ -246,7 +531,7 @@ mod test {
                 (Value::Nil, $r_nil),
                 (Value::Int(42), $r_int),
                 (Value::Float(42.1), $r_float),
-                (Value::Str("test".to_owned()), $r_str),
+                (Value::Str("test".into()), $r_str),
                 (Value::Bool(true), $r_bool),
             ]
         };
@@ -290,7 +575,7 @@ mod test {
             Err(()),
             Ok(Value::Int(54)),
             Ok(Value::Float(54.1)),
-            Ok(Value::Str("12test".to_owned())),
+            Ok(Value::Str("12test".into())),
             Err(())
         );
         value_op_any(a, b_and_result, Box::new(|a, b| a + b));
@@ -344,7 +629,7 @@ mod test {
             Err(()),
             Ok(Value::Float(54.1)),
             Ok(Value::Float(54.2)),
-            Ok(Value::Str("12.1test".to_owned())),
+            Ok(Value::Str("12.1test".into())),
             Err(())
         );
         value_op_any(a, b_and_result, Box::new(|a, b| a + b));
@@ -393,12 +678,12 @@ mod test {
     // === str ===
     #[test]
     fn str_add_any() {
-        let a = Value::Str("a test str".to_owned());
+        let a = Value::Str("a test str".into());
         let b_and_result = make_a_and_result!(
             Err(()),
-            Ok(Value::Str("a test str42".to_owned())),
-            Ok(Value::Str("a test str42.1".to_owned())),
-            Ok(Value::Str("a test strtest".to_owned())),
+            Ok(Value::Str("a test str42".into())),
+            Ok(Value::Str("a test str42.1".into())),
+            Ok(Value::Str("a test strtest".into())),
             Err(())
         );
         value_op_any(a, b_and_result, Box::new(|a, b| a + b));
@@ -406,17 +691,17 @@ mod test {
 
     #[test]
     fn str_sub_any() {
-        let a = Value::Str("a test str".to_owned());
+        let a = Value::Str("a test str".into());
         let b_and_result = make_a_and_result!(Err(()), Err(()), Err(()), Err(()), Err(()));
         value_op_any(a, b_and_result, Box::new(|a, b| a - b));
     }
 
     #[test]
     fn str_mul_any() {
-        let a = Value::Str("a test str".to_owned());
+        let a = Value::Str("a test str".into());
         let b_and_result = make_a_and_result!(
             Err(()),
-            Ok(Value::Str("a test str".to_owned().repeat(42))),
+            Ok(Value::Str("a test str".repeat(42).into())),
             Err(()),
             Err(()),
             Err(())
@@ -426,7 +711,7 @@ mod test {
 
     #[test]
     fn str_div_any() {
-        let a = Value::Str("a test str".to_owned());
+        let a = Value::Str("a test str".into());
         let b_and_result = make_a_and_result!(Err(()), Err(()), Err(()), Err(()), Err(()));
         value_op_any(a, b_and_result, Box::new(|a, b| a / b));
     }
@@ -459,4 +744,103 @@ mod test {
         value_op_any(a, b_and_result, Box::new(|a, b| a / b));
     }
     // === end ===
+
+    // === neg ===
+    #[test]
+    fn neg_any() {
+        assert_eq!(-Value::Nil, Err(()));
+        assert_eq!(-Value::Int(42), Ok(Value::Int(-42)));
+        assert_eq!(-Value::Float(42.1), Ok(Value::Float(-42.1)));
+        assert_eq!(-Value::Str("test".into()), Err(()));
+        assert_eq!(-Value::Bool(true), Err(()));
+    }
+    // === end ===
+
+    // === overflow ===
+    #[test]
+    fn int_overflow() {
+        assert_eq!(Value::Int(i64::MAX) + Value::Int(1), Err(()));
+        assert_eq!(Value::Int(i64::MIN) - Value::Int(1), Err(()));
+        assert_eq!(Value::Int(i64::MAX) * Value::Int(2), Err(()));
+        assert_eq!(-Value::Int(i64::MIN), Err(()));
+        assert_eq!(
+            Value::Int(i64::MAX) + Value::Float(1.0),
+            Ok(Value::Float(i64::MAX as f64 + 1.0))
+        );
+    }
+    // === end ===
+
+    // === display ===
+    #[test]
+    fn display() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let list = Value::List(Rc::new(RefCell::new(vec![
+            Value::Int(1),
+            Value::Str("a".into()),
+            Value::Tuple(Rc::new(vec![Value::Float(1.0)])),
+        ])));
+        assert_eq!(list.to_string(), "[1, \"a\", (1.0,)]");
+        assert_eq!(Value::Str("a".into()).to_string(), "a");
+        assert_eq!(Value::Nil.to_string(), "nil");
+    }
+    // === end ===
+
+    // === cycles ===
+    #[test]
+    fn cycles() {
+        use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+
+        use crate::object::{StructDef, StructObj};
+
+        // `let a = [1]  a[0] = a`, twice
+        let cyclic = || {
+            let items = Rc::new(RefCell::new(vec![Value::Int(1), Value::Nil]));
+            items.borrow_mut()[1] = Value::List(items.clone());
+            Value::List(items)
+        };
+        let (a, b) = (cyclic(), cyclic());
+        assert_eq!(a.to_string(), "[1, [...]]");
+        assert_eq!(a, b);
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Equal));
+        let Value::List(items) = &b else {
+            unreachable!()
+        };
+        items.borrow_mut()[0] = Value::Int(2);
+        assert_ne!(a, b);
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Less));
+
+        let def = Rc::new(StructDef {
+            name: "Node".to_string(),
+            fields: vec!["next".to_string()],
+        });
+        let node = Rc::new(StructObj {
+            def,
+            fields: RefCell::new(vec![Value::Nil]),
+        });
+        node.fields.borrow_mut()[0] = Value::Struct(node.clone());
+        let value = Value::Struct(node.clone());
+        assert_eq!(value.to_string(), "Node { next: Node { ... } }");
+        assert_eq!(value, value.clone());
+        // the cycles are broken to be dropped
+        node.fields.borrow_mut()[0] = Value::Nil;
+        for value in [a, b] {
+            let Value::List(items) = &value else {
+                unreachable!()
+            };
+            items.borrow_mut().clear();
+        }
+
+        // nested deeper than the rust stack would take
+        let nested = || {
+            (0..5000).fold(Value::Int(0), |value, _| {
+                Value::List(Rc::new(RefCell::new(vec![value])))
+            })
+        };
+        let (a, b) = (nested(), nested());
+        assert_eq!(a, b);
+        let written = "[".repeat(100) + "..." + &"]".repeat(100);
+        assert_eq!(a.to_string(), written);
+    }
+    // === end ===
 }
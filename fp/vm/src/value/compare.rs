@@ -0,0 +1,214 @@
+// `==` and `<` on values, without recursion: a list may hold itself, e.g. after
+// `a[0] = a`, or be nested deeper than the rust stack. the pairs of lists, structs and
+// maps being compared are kept, a pair met again is taken as equal, so two lists
+// holding themselves are equal. otherwise it's the same as a derived `PartialEq` and
+// `PartialOrd`: the values of different kinds are ordered by their kind, the items are
+// compared in order, then the lengths.
+
+use std::{cmp::Ordering, collections::HashSet, rc::Rc};
+
+use super::Value;
+
+// what is left to compare, the last is the next
+enum Task {
+    Values(Value, Value),
+    Lens(usize, usize),
+}
+
+// the order of the kinds, the one of the variants of `Value`
+fn kind(value: &Value) -> u8 {
+    match value {
+        Value::Nil => 0,
+        Value::Int(_) => 1,
+        Value::Float(_) => 2,
+        Value::Str(_) => 3,
+        Value::Bool(_) => 4,
+        Value::Tuple(_) => 5,
+        Value::List(_) => 6,
+        Value::Enum(_) => 7,
+        Value::Struct(_) => 8,
+        Value::Map(_) => 9,
+        Value::Range(_) => 10,
+        Value::Iter(_) => 11,
+        Value::NativeFn(_) => 12,
+        Value::Function(_) => 13,
+        Value::UserData(_) => 14,
+        Value::Coroutine(_) => 15,
+    }
+}
+
+// the pairs of mutable values met, only they can make a cycle
+#[derive(Default)]
+struct Seen(HashSet<(usize, usize)>);
+
+impl Seen {
+    // false when the pair was met before
+    fn first<T: ?Sized>(&mut self, a: &Rc<T>, b: &Rc<T>) -> bool {
+        let pair = (
+            Rc::as_ptr(a) as *const () as usize,
+            Rc::as_ptr(b) as *const () as usize,
+        );
+        self.0.insert(pair)
+    }
+}
+
+// the items compared one by one, then the lengths
+fn push_items(tasks: &mut Vec<Task>, a: &[Value], b: &[Value]) {
+    tasks.push(Task::Lens(a.len(), b.len()));
+    let pairs = a.iter().zip(b).rev();
+    tasks.extend(pairs.map(|(a, b)| Task::Values(a.clone(), b.clone())));
+}
+
+// the values which hold other values, the others are compared without the stack
+fn holds_values(value: &Value) -> bool {
+    matches!(
+        value,
+        Value::Tuple(_) | Value::List(_) | Value::Enum(_) | Value::Struct(_) | Value::Map(_)
+    )
+}
+
+pub(super) fn eq(a: &Value, b: &Value) -> bool {
+    if !holds_values(a) {
+        return shallow_eq(a, b);
+    }
+    let mut seen = Seen::default();
+    let mut tasks = vec![Task::Values(a.clone(), b.clone())];
+    while let Some(task) = tasks.pop() {
+        let (a, b) = match task {
+            Task::Values(a, b) => (a, b),
+            Task::Lens(a, b) if a == b => continue,
+            Task::Lens(..) => return false,
+        };
+        let equal = match (&a, &b) {
+            (Value::Tuple(a), Value::Tuple(b)) => {
+                push_items(&mut tasks, a, b);
+                true
+            }
+            (Value::List(x), Value::List(y)) => {
+                if seen.first(x, y) {
+                    push_items(&mut tasks, &x.borrow(), &y.borrow());
+                }
+                true
+            }
+            (Value::Enum(x), Value::Enum(y)) => {
+                push_items(&mut tasks, &x.fields, &y.fields);
+                x.same_variant(y)
+            }
+            (Value::Struct(x), Value::Struct(y)) => {
+                if seen.first(x, y) {
+                    push_items(&mut tasks, &x.fields.borrow(), &y.fields.borrow());
+                }
+                x.same_struct(y)
+            }
+            (Value::Map(x), Value::Map(y)) => {
+                if seen.first(x, y) {
+                    let (x, y) = (x.borrow(), y.borrow());
+                    if x.len() != y.len() {
+                        return false;
+                    }
+                    for (key, value) in x.entries() {
+                        match y.get(key) {
+                            Ok(Some(other)) => {
+                                tasks.push(Task::Values(value.clone(), other.clone()))
+                            }
+                            _ => return false,
+                        }
+                    }
+                }
+                true
+            }
+            (a, b) => shallow_eq(a, b),
+        };
+        if !equal {
+            return false;
+        }
+    }
+    true
+}
+
+// the values which don't hold other values
+fn shallow_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Nil, Value::Nil) => true,
+        (Value::Int(a), Value::Int(b)) => a == b,
+        (Value::Float(a), Value::Float(b)) => a == b,
+        (Value::Str(a), Value::Str(b)) => a == b,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Range(a), Value::Range(b)) => a == b,
+        (Value::Iter(a), Value::Iter(b)) => a == b,
+        (Value::NativeFn(a), Value::NativeFn(b)) => a == b,
+        (Value::Function(a), Value::Function(b)) => a == b,
+        (Value::UserData(a), Value::UserData(b)) => a == b,
+        (Value::Coroutine(a), Value::Coroutine(b)) => a == b,
+        _ => false,
+    }
+}
+
+pub(super) fn partial_cmp(a: &Value, b: &Value) -> Option<Ordering> {
+    if !holds_values(a) && !holds_values(b) {
+        return shallow_cmp(a, b);
+    }
+    let mut seen = Seen::default();
+    let mut tasks = vec![Task::Values(a.clone(), b.clone())];
+    while let Some(task) = tasks.pop() {
+        let (a, b) = match task {
+            Task::Values(a, b) => (a, b),
+            Task::Lens(a, b) => match a.cmp(&b) {
+                Ordering::Equal => continue,
+                ord => return Some(ord),
+            },
+        };
+        let ord = match (&a, &b) {
+            (a, b) if kind(a) != kind(b) => Some(kind(a).cmp(&kind(b))),
+            (Value::Tuple(a), Value::Tuple(b)) => {
+                push_items(&mut tasks, a, b);
+                Some(Ordering::Equal)
+            }
+            (Value::List(x), Value::List(y)) => {
+                if seen.first(x, y) {
+                    push_items(&mut tasks, &x.borrow(), &y.borrow());
+                }
+                Some(Ordering::Equal)
+            }
+            (Value::Enum(x), Value::Enum(y)) => match x.tag.cmp(&y.tag) {
+                _ if !x.same_enum(y) => None,
+                Ordering::Equal => {
+                    push_items(&mut tasks, &x.fields, &y.fields);
+                    Some(Ordering::Equal)
+                }
+                ord => Some(ord),
+            },
+            (Value::Struct(x), Value::Struct(y)) if x.same_struct(y) => {
+                if seen.first(x, y) {
+                    push_items(&mut tasks, &x.fields.borrow(), &y.fields.borrow());
+                }
+                Some(Ordering::Equal)
+            }
+            (Value::Struct(_), Value::Struct(_)) => None,
+            // maps are not ordered, only equal maps can be compared
+            (Value::Map(_), Value::Map(_)) => eq(&a, &b).then_some(Ordering::Equal),
+            (a, b) => shallow_cmp(a, b),
+        };
+        if ord != Some(Ordering::Equal) {
+            return ord;
+        }
+    }
+    Some(Ordering::Equal)
+}
+
+fn shallow_cmp(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Nil, Value::Nil) => Some(Ordering::Equal),
+        (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        (Value::Str(a), Value::Str(b)) => a.partial_cmp(b),
+        (Value::Bool(a), Value::Bool(b)) => a.partial_cmp(b),
+        (Value::Range(a), Value::Range(b)) => a.partial_cmp(b),
+        (Value::Iter(a), Value::Iter(b)) => a.partial_cmp(b),
+        (Value::NativeFn(a), Value::NativeFn(b)) => a.partial_cmp(b),
+        (Value::Function(a), Value::Function(b)) => a.partial_cmp(b),
+        (Value::UserData(a), Value::UserData(b)) => a.partial_cmp(b),
+        (Value::Coroutine(a), Value::Coroutine(b)) => a.partial_cmp(b),
+        (a, b) => Some(kind(a).cmp(&kind(b))),
+    }
+}
@@ -1,102 +1,787 @@
-use std::collections::HashMap;
+use std::{
+    any::{self, Any, TypeId},
+    borrow::Cow,
+    cell::RefCell,
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    rc::Rc,
+    time::Instant,
+};
 
-use crate::{chunk::Chunk, value::Value};
+use crate::{
+    capability::Capabilities,
+    chunk::Chunk,
+    convert::IntoValue,
+    coroutine::{Coroutine, Status},
+    error::RuntimeError,
+    gc::{GcConfig, GcStats, Heap},
+    iter::{Iter, Range},
+    limits::{self, InterruptHandle, Limits},
+    map::Map,
+    native::{self, Arity, NativeFn},
+    object::{EnumObj, Function, StructDef, StructObj},
+    op::OpCode,
+    prelude,
+    stack::Stack,
+    userdata::{UserData, UserType},
+    value::Value,
+};
 
-type IntResult = Result<(), ()>;
+mod registers;
+
+type IntResult = Result<(), RuntimeError>;
+
+// how many instructions between the checks of the deadline and the interrupt
+const CHECK_INTERVAL: u64 = 1024;
+
+// the runs nested in another one, e.g. by `call_value` or by a generator resumed by
+// `for`, each takes some of the rust stack, so they are capped even without limits.
+// a debug build takes much more of it for each, the cap fits the 2 MiB of a thread.
+const MAX_NESTED_RUNS: usize = if cfg!(debug_assertions) { 40 } else { 200 };
 
 pub struct Vm {
-    chunk: Chunk,
+    chunk: Rc<Chunk>,
     ip: usize,
-    stack: Vec<Value>,
+    // where the locals of the running function start in the stack
+    base: usize,
+    // the callers of the running function
+    frames: Vec<Frame>,
+    // the register functions running, they have no frame, see `registers`
+    register_calls: Vec<RegisterCall>,
+    // the trace of an error which left the functions it was raised in, see `keep_trace`
+    error_trace: Option<Vec<String>>,
+    // the runs going on in the rust stack, see `nested`
+    nested_runs: usize,
+    stack: Stack,
     global: HashMap<u16, Value>,
+    // instructions run since the chunk is set
+    instructions: u64,
+    // the native functions by name, the compiler declares them as globals
+    natives: HashMap<String, Value>,
+    // the output of `print` and the input of `input`, the input is stdin when it is `None`
+    output: Box<dyn Write>,
+    input: Option<Box<dyn BufRead>>,
+    // the rust types the scripts can use, see `userdata`
+    types: HashMap<TypeId, Rc<UserType>>,
+    limits: Limits,
+    interrupt: InterruptHandle,
+    // what the native functions may touch, nothing by default
+    capabilities: Capabilities,
+    // if a run is going, a call from a native function is a part of it
+    running: bool,
+    deadline: Option<Instant>,
+    // bytes allocated since the heap is measured
+    allocated: usize,
+    // bytes of the heap when it was measured
+    heap_used: usize,
+    // the run stopped at a `yield` and waits for `resume`
+    suspended: bool,
+    // the value given by `suspend`, the run stops when the native function returns
+    suspending: Option<Value>,
+    // the running coroutines with the state of their resumer, the innermost last
+    resumers: Vec<(Rc<Coroutine>, Context)>,
+    // the struct of the errors caught by `catch`, see `error_value`
+    error_def: Rc<StructDef>,
+    // the values made by the scripts, for the cycles, see `gc`
+    heap: Heap,
+}
+
+// how a run stopped
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    // the value of the chunk, see `interpret`
+    Done(Option<Value>),
+    // the value of the `yield`, the run goes on with `resume`
+    Suspended(Value),
+}
+
+// the state of a caller, it is restored when the function returns
+struct Frame {
+    chunk: Rc<Chunk>,
+    ip: usize,
+    base: usize,
+    // the function called by the frame, for the traces
+    callee: Rc<Function>,
+}
+
+// a running register function, it runs above the first `frames` frames of the
+// coroutine at `level` in the resumers, 0 for the main run, see `trace`
+struct RegisterCall {
+    level: usize,
+    frames: usize,
+    callee: Rc<Function>,
+}
+
+// the running state of the main run or of a coroutine, it is swapped when a
+// coroutine is resumed and when it stops.
+pub(crate) struct Context {
+    chunk: Rc<Chunk>,
+    ip: usize,
+    base: usize,
+    frames: Vec<Frame>,
+    pub(crate) stack: Stack,
+}
+
+// the functions called by the frames, the innermost first
+fn frame_names(frames: &[Frame]) -> impl Iterator<Item = String> + '_ {
+    frames.iter().rev().map(|frame| frame.callee.name.clone())
+}
+
+impl Context {
+    pub(crate) fn new(stack: Stack) -> Context {
+        Context {
+            chunk: Rc::new(Chunk::new()),
+            ip: 0,
+            base: 0,
+            frames: Vec::new(),
+            stack,
+        }
+    }
 }
 
 impl Vm {
+    // a pure vm, see `with_capabilities`
     pub fn new() -> Self {
-        Vm {
-            chunk: Chunk::new(),
+        Self::with_capabilities(Capabilities::pure())
+    }
+
+    pub fn with_capabilities(capabilities: Capabilities) -> Self {
+        let mut vm = Vm {
+            chunk: Rc::new(Chunk::new()),
             ip: 0,
-            stack: Vec::with_capacity(8),
+            base: 0,
+            frames: Vec::new(),
+            register_calls: Vec::new(),
+            error_trace: None,
+            nested_runs: 0,
+            stack: Stack::with_capacity(8),
             global: HashMap::new(),
-        }
+            instructions: 0,
+            natives: HashMap::new(),
+            output: Box::new(io::stdout()),
+            input: None,
+            types: HashMap::new(),
+            limits: Limits::default(),
+            interrupt: InterruptHandle::default(),
+            capabilities,
+            running: false,
+            deadline: None,
+            allocated: 0,
+            heap_used: 0,
+            suspended: false,
+            suspending: None,
+            resumers: Vec::new(),
+            error_def: Rc::new(StructDef {
+                name: "Error".to_owned(),
+                fields: ["kind", "message", "trace"].map(str::to_owned).to_vec(),
+            }),
+            heap: Heap::new(GcConfig::default()),
+        };
+        native::define_builtins(&mut vm);
+        vm
     }
 
-    pub fn set_chunk(&mut self, chunk: Chunk) {
+    // the values left by the last chunk (e.g. it failed) are dropped. it fails while a
+    // chunk is running, a native function calls `call_value` to run code instead.
+    pub fn set_chunk(&mut self, chunk: Chunk) -> Result<(), RuntimeError> {
+        if self.running {
+            return Err(RuntimeError::AlreadyRunning);
+        }
         self.ip = 0;
-        self.chunk = chunk;
+        self.chunk = Rc::new(chunk);
+        self.base = 0;
+        self.frames.clear();
+        self.stack.clear();
+        self.instructions = 0;
+        self.suspended = false;
+        self.suspending = None;
+        self.resumers.clear();
+        Ok(())
     }
 
-    pub fn interpret(&mut self, chunk: Chunk) -> IntResult {
-        self.set_chunk(chunk);
+    // the value is returned when the chunk is compiled to keep it, see `Compiler::compile_value`.
+    // the chunk can't be suspended, see `start` for that.
+    pub fn interpret(&mut self, chunk: Chunk) -> Result<Option<Value>, RuntimeError> {
+        match self.start(chunk)? {
+            Outcome::Done(value) => Ok(value),
+            Outcome::Suspended(_) => {
+                self.suspended = false;
+                Err(RuntimeError::CantSuspend)
+            }
+        }
+    }
+
+    // run the chunk until it returns or yields, a suspended run keeps its frames and
+    // its stack until `resume`.
+    pub fn start(&mut self, chunk: Chunk) -> Result<Outcome, RuntimeError> {
+        self.set_chunk(chunk)?;
         self.run()
     }
 
-    #[allow(unused)]
-    fn run(&mut self) -> IntResult {
-        loop {
-            let byte = self.read_byte().ok_or(())?;
+    // go on with the suspended run, the `yield` gives `value` back.
+    // the limits are counted again from zero.
+    pub fn resume(&mut self, value: Value) -> Result<Outcome, RuntimeError> {
+        if !self.suspended {
+            return Err(RuntimeError::NotSuspended);
+        }
+        self.suspended = false;
+        self.stack.push(value);
+        self.run()
+    }
 
-            match byte {
-                0x00 => {
-                    // Add
-                    let b = self.get_val()?;
-                    let a = self.get_val()?;
-                    let result = (a + b)?;
-                    self.stack.push(result);
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    // called by a native function to suspend the run when it returns, e.g. `wait(1.5)`
+    // in a game. the value given to `resume` replaces the result of the function.
+    // in a coroutine, the coroutine is suspended like by a `yield`.
+    pub fn suspend(&mut self, value: Value) {
+        self.suspending = Some(value);
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    pub fn set_gc_config(&mut self, config: GcConfig) {
+        self.heap.set_config(config);
+    }
+
+    pub fn gc_config(&self) -> &GcConfig {
+        self.heap.config()
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    // free the cycles of values the scripts can't reach anymore in the whole heap,
+    // it is done when the heap grows, see `GcConfig`.
+    pub fn collect_garbage(&mut self) {
+        self.collect(true);
+    }
+
+    fn collect(&mut self, all: bool) {
+        // the locals of the frames are in the stack
+        let parked = self
+            .resumers
+            .iter()
+            .flat_map(|(_, c)| c.stack.heap_values());
+        let roots = self
+            .stack
+            .heap_values()
+            .chain(self.global.values().map(Cow::Borrowed))
+            .chain(self.natives.values().map(Cow::Borrowed))
+            .chain(parked)
+            .chain(self.suspending.iter().map(Cow::Borrowed));
+        if all {
+            self.heap.collect_all(roots);
+        } else {
+            self.heap.collect(roots);
+        }
+    }
+
+    fn begin_run(&mut self) {
+        self.running = true;
+        self.error_trace = None;
+        self.instructions = 0;
+        self.allocated = 0;
+        self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    pub fn get_global(&self, i: u16) -> Option<&Value> {
+        self.global.get(&i)
+    }
+
+    // the function replaces the one with the same name, e.g. a builtin
+    pub fn define_native(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        fun: impl Fn(&mut Vm, &[Value]) -> Result<Value, RuntimeError> + 'static,
+    ) {
+        let native = NativeFn {
+            name: name.to_owned(),
+            arity,
+            fun: Box::new(fun),
+        };
+        let value = Value::NativeFn(Rc::new(native));
+        self.natives.insert(name.to_owned(), value);
+    }
+
+    pub fn native(&self, name: &str) -> Option<&Value> {
+        self.natives.get(name)
+    }
+
+    pub fn natives(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.natives
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    // set a global variable without running code, e.g. a native function
+    pub fn define_global(&mut self, i: u16, value: Value) {
+        self.global.insert(i, value);
+    }
+
+    // the type replaces the one registered for the same rust type
+    pub fn register_type(&mut self, ty: UserType) {
+        self.types.insert(ty.type_id(), Rc::new(ty));
+    }
+
+    // give a rust value to the scripts, its type must be registered
+    pub fn user_data<T: Any>(&self, value: T) -> Result<Value, RuntimeError> {
+        let ty = self.types.get(&TypeId::of::<T>()).ok_or_else(|| {
+            RuntimeError::TypeError(format!(
+                "the type `{}` isn't registered",
+                any::type_name::<T>()
+            ))
+        })?;
+        let data = UserData::new(ty.clone(), Box::new(value));
+        Ok(Value::UserData(Rc::new(data)))
+    }
+
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output = Box::new(output);
+    }
+
+    pub fn output(&mut self) -> &mut dyn Write {
+        &mut self.output
+    }
+
+    pub fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.input = Some(Box::new(input));
+    }
+
+    // read a line of the input with the `\n`, see `BufRead::read_line`
+    pub fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+        match &mut self.input {
+            Some(input) => input.read_line(buf),
+            None => io::stdin().read_line(buf),
+        }
+    }
+
+    // the number of instructions run by the last chunk
+    pub fn instruction_count(&self) -> u64 {
+        self.instructions
+    }
+
+    // call a script or native function from rust, e.g. a native function calling back.
+    // the running chunk is resumed after the call.
+    pub fn call_value(&mut self, callee: &Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        if self.running {
+            return self.call_nested(callee, args);
+        }
+        self.begin_run();
+        let result = self.call_nested(callee, args);
+        self.running = false;
+        result
+    }
+
+    // `for x in s` over a struct calls `s.next(s)`, it returns `Some(x)` until `None`
+    pub(crate) fn next_item(&mut self, s: &Rc<StructObj>) -> Result<Option<Value>, RuntimeError> {
+        let next = get_field(&Value::Struct(s.clone()), &Value::Str("next".into()))?;
+        let value = self.call_value(&next, &[Value::Struct(s.clone())])?;
+        prelude::next_item(&value)
+    }
+
+    fn call_nested(&mut self, callee: &Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        self.nested(|vm| vm.call_run(callee, args))
+    }
+
+    fn call_run(&mut self, callee: &Value, args: &[Value]) -> Result<Value, RuntimeError> {
+        let start = self.stack.len();
+        self.stack.push(callee.clone());
+        self.stack.extend(args.iter().cloned());
+        let depth = self.frames.len() + 1;
+        if let Err(e) = self.call(args.len()) {
+            self.stack.truncate(start);
+            return Err(e);
+        }
+        // a native function, the result is already pushed
+        if self.frames.len() < depth {
+            let value = self.get_val()?;
+            if self.suspending.take().is_some() {
+                return Err(RuntimeError::CantSuspend);
+            }
+            return Ok(value);
+        }
+        match self.execute(depth) {
+            Ok(Outcome::Done(value)) => Ok(value.unwrap_or(Value::Nil)),
+            Ok(Outcome::Suspended(_)) => unreachable!("a nested run is never suspended"),
+            Err(e) => {
+                self.keep_trace(&e);
+                // go back to the state before the call
+                self.frames.truncate(depth);
+                self.return_frame();
+                self.stack.truncate(start);
+                Err(e)
+            }
+        }
+    }
+
+    // run the coroutine until it yields or returns, `value` is the value of the `yield`
+    // it stopped at. it is a part of the running run like `call_value`.
+    pub fn resume_coroutine(
+        &mut self,
+        co: &Rc<Coroutine>,
+        value: Value,
+    ) -> Result<Outcome, RuntimeError> {
+        if self.running {
+            return self.resume_nested(co, value);
+        }
+        self.begin_run();
+        let result = self.resume_nested(co, value);
+        self.running = false;
+        result
+    }
+
+    fn resume_nested(&mut self, co: &Rc<Coroutine>, value: Value) -> Result<Outcome, RuntimeError> {
+        self.nested(|vm| vm.resume_run(co, value))
+    }
+
+    fn resume_run(&mut self, co: &Rc<Coroutine>, value: Value) -> Result<Outcome, RuntimeError> {
+        match co.status() {
+            Status::Suspended => {}
+            Status::Dead => {
+                return Err(RuntimeError::TypeError(
+                    "can't resume a dead coroutine".to_owned(),
+                ))
+            }
+            Status::Running | Status::Normal => {
+                return Err(RuntimeError::TypeError(
+                    "can't resume a running coroutine".to_owned(),
+                ))
+            }
+        }
+        let mut context = co
+            .context
+            .borrow_mut()
+            .take()
+            .expect("a suspended coroutine");
+        self.swap_context(&mut context);
+        if let Some((resumer, _)) = self.resumers.last() {
+            resumer.status.set(Status::Normal);
+        }
+        co.status.set(Status::Running);
+        self.resumers.push((co.clone(), context));
+
+        let result = if co.started.replace(true) {
+            self.stack.push(value);
+            self.execute(1)
+        } else {
+            // the function and its arguments are the whole stack, a generator runs its
+            // body instead of making another coroutine.
+            match &self.stack.get(0).unwrap_or(Value::Nil) {
+                // it can't yield, it runs to the end
+                Value::Function(fun) if fun.registers.is_some() => {
+                    let n = self.stack.len() - 1;
+                    let result = self.call_registers(fun.clone(), 0, n);
+                    result.map(|_| Outcome::Done(self.stack.pop()))
                 }
-                0x01 => {
-                    // Sub
-                    let b = self.get_val()?;
-                    let a = self.get_val()?;
-                    let result = (a - b)?;
-                    self.stack.push(result);
+                Value::Function(fun) => {
+                    let n = self.stack.len() - 1;
+                    self.enter(fun.clone(), 0, n).and_then(|_| self.execute(1))
+                }
+                value => Err(RuntimeError::NotCallable(value.type_name().to_owned())),
+            }
+        };
+
+        let (co, mut context) = self.resumers.pop().expect("the running coroutine");
+        self.swap_context(&mut context);
+        if let Some((resumer, _)) = self.resumers.last() {
+            resumer.status.set(Status::Running);
+        }
+        match result {
+            Ok(Outcome::Suspended(value)) => {
+                co.status.set(Status::Suspended);
+                *co.context.borrow_mut() = Some(context);
+                Ok(Outcome::Suspended(value))
+            }
+            Ok(done) => {
+                co.status.set(Status::Dead);
+                Ok(done)
+            }
+            Err(e) => {
+                co.status.set(Status::Dead);
+                Err(self.coroutine_error(e, &context))
+            }
+        }
+    }
+
+    // the error with the functions of the coroutine and of its resumers, the innermost
+    // first, a coroutine boundary is `resume`.
+    fn coroutine_error(&mut self, error: RuntimeError, context: &Context) -> RuntimeError {
+        // it already has the whole trace, from a nested coroutine
+        if let RuntimeError::Coroutine { .. } = error {
+            return error;
+        }
+        let trace = self.error_trace.take().unwrap_or_else(|| {
+            let mut trace: Vec<String> = frame_names(&context.frames).collect();
+            trace.push("resume".to_owned());
+            trace.extend(self.trace());
+            trace
+        });
+        RuntimeError::Coroutine {
+            error: Box::new(error),
+            trace,
+        }
+    }
+
+    // the functions running now, the innermost first. the register functions are
+    // between the frames they were called above.
+    fn trace(&self) -> Vec<String> {
+        let mut trace = Vec::new();
+        let mut calls = self.register_calls.iter().rev().peekable();
+        let resumers = self
+            .resumers
+            .iter()
+            .rev()
+            .map(|(_, context)| &context.frames);
+        let contexts = std::iter::once(&self.frames).chain(resumers);
+        for (level, frames) in (0..=self.resumers.len()).rev().zip(contexts) {
+            if level < self.resumers.len() {
+                trace.push("resume".to_owned());
+            }
+            for depth in (0..=frames.len()).rev() {
+                while let Some(call) =
+                    calls.next_if(|call| call.level == level && call.frames >= depth)
+                {
+                    // the script of the register backend is a function too
+                    if call.callee.name != "<script>" {
+                        trace.push(call.callee.name.clone());
+                    }
+                }
+                if depth > 0 {
+                    trace.push(frames[depth - 1].callee.name.clone());
                 }
-                0x02 => {
-                    // Neg
+            }
+        }
+        trace.push("<script>".to_owned());
+        trace
+    }
+
+    // the error leaves the functions it was raised in before it is caught, e.g. a
+    // register function or a nested run, the trace is taken while they are running.
+    // the innermost one keeps it, a coroutine error has its own.
+    fn keep_trace(&mut self, error: &RuntimeError) {
+        if self.error_trace.is_none() && !matches!(error, RuntimeError::Coroutine { .. }) {
+            self.error_trace = Some(self.trace());
+        }
+    }
+
+    fn push_register_call(&mut self, callee: Rc<Function>) {
+        self.register_calls.push(RegisterCall {
+            level: self.resumers.len(),
+            frames: self.frames.len(),
+            callee,
+        });
+    }
+
+    fn swap_context(&mut self, context: &mut Context) {
+        std::mem::swap(&mut self.chunk, &mut context.chunk);
+        std::mem::swap(&mut self.ip, &mut context.ip);
+        std::mem::swap(&mut self.base, &mut context.base);
+        std::mem::swap(&mut self.frames, &mut context.frames);
+        std::mem::swap(&mut self.stack, &mut context.stack);
+    }
+
+    // a run in the running one, it fails when there are too many of them
+    fn nested<T>(
+        &mut self,
+        run: impl FnOnce(&mut Vm) -> Result<T, RuntimeError>,
+    ) -> Result<T, RuntimeError> {
+        if self.nested_runs >= MAX_NESTED_RUNS {
+            return Err(RuntimeError::CallDepthExceeded);
+        }
+        self.nested_runs += 1;
+        let result = run(self);
+        self.nested_runs -= 1;
+        result
+    }
+
+    fn run(&mut self) -> Result<Outcome, RuntimeError> {
+        self.begin_run();
+        let result = self.execute(0);
+        self.running = false;
+        if result.is_err() {
+            self.suspending = None;
+        }
+        result
+    }
+
+    // run until the chunk returns, or until the function called by `call_value`
+    // returns when `depth` is the number of frames after the call.
+    // an error goes on from its `catch` or `finally` when there is one.
+    fn execute(&mut self, depth: usize) -> Result<Outcome, RuntimeError> {
+        loop {
+            match self.dispatch(depth) {
+                Err(e) if self.catch(&e, depth) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    // jump to the handler of the error in the frames above `depth`, the frames under
+    // it are unwound. false when there is none, the frames are kept for the caller.
+    fn catch(&mut self, error: &RuntimeError, depth: usize) -> bool {
+        if !error.is_catchable() {
+            return false;
+        }
+        let mut found = self.chunk.handler(self.ip).map(|h| (self.frames.len(), *h));
+        // the caller of a frame is running its call
+        for level in (depth..self.frames.len()).rev() {
+            if found.is_some() {
+                break;
+            }
+            let frame = &self.frames[level];
+            found = frame.chunk.handler(frame.ip).map(|h| (level, *h));
+        }
+        let (level, handler) = match found {
+            Some(found) => found,
+            None => return false,
+        };
+        let value = self.error_value(error);
+        while self.frames.len() > level {
+            self.return_frame();
+        }
+        self.stack.truncate(self.base + handler.height as usize);
+        self.stack.push(value);
+        self.ip = handler.target;
+        true
+    }
+
+    // the thrown value, or an `Error { kind, message, trace }` for the other errors
+    fn error_value(&mut self, error: &RuntimeError) -> Value {
+        let (error, trace) = match error {
+            RuntimeError::Coroutine { error, trace } => (&**error, trace.clone()),
+            error => {
+                let trace = self.error_trace.take();
+                (error, trace.unwrap_or_else(|| self.trace()))
+            }
+        };
+        if let RuntimeError::Thrown(value) = error {
+            return value.clone();
+        }
+        let fields = vec![
+            Value::Str(error.kind().into()),
+            Value::Str(error.message().into()),
+            trace
+                .into_iter()
+                .map(|name| Value::Str(name.into()))
+                .collect::<Vec<_>>()
+                .into_value(),
+        ];
+        Value::Struct(Rc::new(StructObj {
+            def: self.error_def.clone(),
+            fields: RefCell::new(fields),
+        }))
+    }
+
+    // the running chunk and the ip are kept in locals, `self.ip` is set once an
+    // instruction is read for the calls, the handlers and the suspended runs. they are
+    // loaded again when a call or a return changes the running chunk.
+    fn dispatch(&mut self, depth: usize) -> Result<Outcome, RuntimeError> {
+        let mut chunk = self.chunk.clone();
+        chunk.verify()?;
+        let mut ip = self.ip;
+        loop {
+            let code = chunk.code();
+            let op = OpCode::try_from(byte(code, ip)).map_err(RuntimeError::InvalidOpCode)?;
+            // the operands are from `at` to `ip`
+            let at = ip + 1;
+            ip = at + op.operands();
+            self.ip = ip;
+            self.instructions += 1;
+            if self
+                .limits
+                .fuel
+                .is_some_and(|fuel| self.instructions > fuel)
+            {
+                return Err(RuntimeError::OutOfFuel);
+            }
+            if self.instructions.is_multiple_of(CHECK_INTERVAL) {
+                self.check_limits()?;
+            }
+
+            match op {
+                OpCode::Add => match self.stack.top_ints() {
+                    Some((a, b)) => self.stack.replace_top2(int(a.checked_add(b))?),
+                    None => self.binary_op("+", |a, b| a + b)?,
+                },
+                OpCode::Sub => match self.stack.top_ints() {
+                    Some((a, b)) => self.stack.replace_top2(int(a.checked_sub(b))?),
+                    None => self.binary_op("-", |a, b| a - b)?,
+                },
+                OpCode::Neg => {
                     let value = self.get_val()?;
-                    // self.stack.push(-value);
+                    self.stack.push(neg(value)?);
                 }
-                0x03 => {
-                    // Mult
-                    let b = self.get_val()?;
-                    let a = self.get_val()?;
-                    let result = (a * b)?;
-                    self.stack.push(result);
+                OpCode::Mult => match self.stack.top_ints() {
+                    Some((a, b)) => self.stack.replace_top2(int(a.checked_mul(b))?),
+                    None => self.binary_op("*", |a, b| a * b)?,
+                },
+                OpCode::Div => {
+                    if let Some((_, 0)) = self.stack.top_ints() {
+                        return Err(RuntimeError::DivisionByZero);
+                    }
+                    self.binary_op("/", |a, b| a / b)?
                 }
-                0x04 => {
-                    // Div
-                    let b = self.get_val()?;
-                    let a = self.get_val()?;
-                    let result = (a / b)?;
-                    self.stack.push(result);
-                }
-                0x05 => self.stack.push(Value::Bool(true)), // True
-                0x06 => self.stack.push(Value::Bool(false)), // False
-                0x07 => self.stack.push(Value::Nil),        // Nil
-                0x08 => todo!(),                            // Not
-                0x09 => {
-                    // Eq
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::Not => match self.get_val()? {
+                    Value::Bool(b) => self.stack.push(Value::Bool(!b)),
+                    _ => {
+                        return Err(RuntimeError::TypeError(
+                            "unsupported operand type for `!`".to_owned(),
+                        ))
+                    }
+                },
+                OpCode::Eq => {
+                    if let Some((a, b)) = self.stack.top_ints() {
+                        self.stack.replace_top2(Value::Bool(a == b));
+                        continue;
+                    }
                     let b = self.get_val()?;
                     let a = self.get_val()?;
                     let result = a == b;
                     self.stack.push(Value::Bool(result));
                 }
-                0x0A => {
-                    // Gt
+                OpCode::Gt => {
+                    if let Some((a, b)) = self.stack.top_ints() {
+                        self.stack.replace_top2(Value::Bool(a > b));
+                        continue;
+                    }
                     let b = self.get_val()?;
                     let a = self.get_val()?;
                     let result = a > b;
                     self.stack.push(Value::Bool(result));
                 }
-                0x0B => {
-                    // Lt
+                OpCode::Lt => {
+                    if let Some((a, b)) = self.stack.top_ints() {
+                        self.stack.replace_top2(Value::Bool(a < b));
+                        continue;
+                    }
                     let b = self.get_val()?;
                     let a = self.get_val()?;
                     let result = a < b;
                     self.stack.push(Value::Bool(result));
                 }
-                0x0C => {
-                    // Return
+                OpCode::Return => {
                     #[cfg(feature = "vm_dev")]
                     {
                         println!("Returned");
@@ -105,106 +790,809 @@ impl Vm {
                         println!();
                     }
 
-                    return Ok(());
+                    let value = self.stack.pop();
+                    // the chunk run by `interpret`
+                    if self.frames.is_empty() {
+                        return Ok(Outcome::Done(value));
+                    }
+                    self.return_frame();
+                    if self.frames.len() < depth {
+                        return Ok(Outcome::Done(value));
+                    }
+                    self.stack.push(value.unwrap_or(Value::Nil));
+                    chunk = self.chunk.clone();
+                    ip = self.ip;
                 }
-                0x0D => {
-                    // Constant
-                    let constant = self.read_byte().ok_or(())?;
-                    let value = self.chunk.get_constant(constant as usize).ok_or(())?;
-                    self.stack.push(value.clone());
+                OpCode::Constant => {
+                    let constant = byte(code, at);
+                    let value = self.get_constant(constant as usize)?;
+                    self.stack.push(value);
                 }
-                0x0E => {
-                    // ConstantLong
-                    let constant = self.read_long_byte().ok_or(())?;
-                    let value = self.chunk.get_constant(constant as usize).ok_or(())?;
-                    self.stack.push(value.clone())
+                OpCode::ConstantL => {
+                    let constant = long(code, at);
+                    let value = self.get_constant(constant as usize)?;
+                    self.stack.push(value)
                 }
-                0x0F => {
-                    // Pop
-                    let a = self.stack.pop().unwrap_or(Value::Nil);
+                OpCode::Pop => {
+                    let value = self.stack.pop();
 
                     #[cfg(feature = "vm_dev")]
-                    println!("Pop value: {:?}\n", a);
+                    println!("Pop value: {:?}\n", value);
+                    drop(value);
                 }
-                0x10 => {
-                    // DefineGlobal
-                    let i = self.read_byte().ok_or(())? as u16;
-                    let value = self.stack.pop().ok_or(())?;
-                    self.global.insert(i, value);
-
-                    #[cfg(feature = "vm_dev")]
-                    println!("Define Global: {:?}\n", self.global)
-                }
-                0x11 => {
-                    // DefineGlobalLong
-                    todo!()
-                }
-                0x12 => {
-                    // GetGlobal
-                    let i = self.read_byte().ok_or(())?;
-                    let value = self.global.get(&(i as u16)).ok_or(())?;
-                    self.stack.push(value.clone());
-                }
-                0x13 => todo!(), // GetGlobalLong
-                0x14 => {
-                    // BlockEnd
-                    let n = self.read_byte().ok_or(())?;
-                    let value = self.stack.pop().unwrap();
-                    let final_n = self.stack.len() - n as usize;
-                    self.stack.truncate(final_n);
-                    self.stack.push(value);
+                OpCode::SetGlobal => {
+                    let i = byte(code, at) as u16;
+                    self.set_global(i)?;
+                }
+                OpCode::SetGlobalL => {
+                    let i = long(code, at);
+                    self.set_global(i)?;
+                }
+                OpCode::GetGlobal => {
+                    let i = byte(code, at) as u16;
+                    self.push_global(i)?;
+                }
+                OpCode::GetGlobalL => {
+                    let i = long(code, at);
+                    self.push_global(i)?;
+                }
+                OpCode::BlockEnd => {
+                    let n = byte(code, at) as usize;
+                    self.block_end(n)?;
+                }
+                OpCode::SetLocal => {
+                    let i = byte(code, at) as usize;
+                    self.set_local(i)?;
+                }
+                OpCode::SetLocalL => {
+                    let i = long(code, at) as usize;
+                    self.set_local(i)?;
+                }
+                OpCode::GetLocal => {
+                    let i = byte(code, at) as usize;
+                    self.push_local(i)?;
                 }
-                0x15 => todo!(), // SetLocal
-                0x16 => todo!(), // SetLocalLong
-                0x17 => {
-                    // GetLocal
-                    let i = self.read_byte().ok_or(())?;
-                    self.stack.push(self.stack[i as usize].clone());
-                }
-                0x18 => todo!(), // GetLocalL
-                0x19 => {
-                    // Jump
-                    let offset = self.read_long_byte().ok_or(())? as usize;
-                    self.ip += offset;
-                }
-                0x1A => {
-                    // JumpIfFalse
-                    let offset = self.read_long_byte().ok_or(())? as usize;
-                    let test = self.stack.pop().unwrap();
+                OpCode::GetLocalL => {
+                    let i = long(code, at) as usize;
+                    self.push_local(i)?;
+                }
+                OpCode::Jump => {
+                    let offset = wide(code, at) as usize;
+                    ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = wide(code, at) as usize;
+                    let test = self.get_val()?;
                     if let Value::Bool(b) = test {
                         if b {
                             continue;
                         }
 
-                        self.ip += offset;
+                        ip += offset;
                         continue;
                     }
                 }
-                0x1B => {
-                    // JumpBack
-                    let offset = self.read_long_byte().ok_or(())? as usize;
-                    self.ip -= 2;
-                    self.ip -= offset;
+                OpCode::JumpBack => {
+                    ip = at - long(code, at) as usize;
+                }
+                OpCode::MakeTuple => {
+                    let n = byte(code, at);
+                    let items = self.get_vals(n as usize)?;
+                    self.push_new(Value::Tuple(items.into()))?;
+                }
+                OpCode::MakeList => {
+                    let n = byte(code, at);
+                    let items = self.get_vals(n as usize)?;
+                    self.push_new(Value::List(Rc::new(RefCell::new(items))))?;
+                }
+                OpCode::Construct => {
+                    let n = byte(code, at);
+                    let fields = self.get_vals(n as usize)?;
+                    let value = construct(&self.get_val()?, fields)?;
+                    self.push_new(value)?;
+                }
+                OpCode::GetField => {
+                    let name = self.get_val()?;
+                    let object = self.get_val()?;
+                    let value = get_field(&object, &name)?;
+                    self.stack.push(value);
+                }
+                OpCode::GetItem => {
+                    let i = byte(code, at) as usize;
+                    let container = self.get_val()?;
+                    let value = item(&container, |_| i)?;
+                    self.stack.push(value);
+                }
+                OpCode::GetItemBack => {
+                    let i = byte(code, at) as usize;
+                    let container = self.get_val()?;
+                    let value = item(&container, |len| len.wrapping_sub(i + 1))?;
+                    self.stack.push(value);
+                }
+                OpCode::MatchKind => {
+                    let template = self.get_val()?;
+                    let value = self.get_val()?;
+                    let result = match (&value, &template) {
+                        (Value::Enum(a), Value::Enum(b)) => a.same_variant(b),
+                        (Value::Struct(a), Value::Struct(b)) => a.same_struct(b),
+                        _ => false,
+                    };
+                    self.stack.push(Value::Bool(result));
+                }
+                OpCode::MatchTuple => {
+                    let n = byte(code, at) as usize;
+                    let value = self.get_val()?;
+                    let result = matches!(&value, Value::Tuple(items) if items.len() == n);
+                    self.stack.push(Value::Bool(result));
+                }
+                OpCode::MatchList => {
+                    let n = byte(code, at) as usize;
+                    let value = self.get_val()?;
+                    let result = matches!(&value, Value::List(items) if items.borrow().len() == n);
+                    self.stack.push(Value::Bool(result));
+                }
+                OpCode::MatchListMin => {
+                    let n = byte(code, at) as usize;
+                    let value = self.get_val()?;
+                    let result = matches!(&value, Value::List(items) if items.borrow().len() >= n);
+                    self.stack.push(Value::Bool(result));
+                }
+                OpCode::SwitchTag => {
+                    let n = byte(code, at) as usize;
+                    let table = ip;
+                    ip += (n + 1) * 4;
+                    let template = self.get_val()?;
+                    let value = self.get_val()?;
+                    let entry = match (&value, &template) {
+                        (Value::Enum(a), Value::Enum(b))
+                            if Rc::ptr_eq(&a.def, &b.def) || a.def == b.def =>
+                        {
+                            a.tag as usize
+                        }
+                        _ => n,
+                    };
+                    ip += wide(code, table + entry * 4) as usize;
+                }
+                OpCode::NoMatch => return Err(RuntimeError::NoMatchArm),
+                OpCode::MakeRange => {
+                    let flags = byte(code, at);
+                    let inclusive = flags & 0b01 != 0;
+                    let step = if flags & 0b10 != 0 {
+                        self.get_val()?
+                    } else {
+                        Value::Int(1)
+                    };
+                    let end = self.get_val()?;
+                    let start = self.get_val()?;
+                    self.stack.push(make_range(start, end, step, inclusive)?);
+                }
+                OpCode::MakeMap => {
+                    let n = byte(code, at) as usize;
+                    let items = self.get_vals(n * 2)?;
+                    let mut map = Map::new();
+                    let mut items = items.into_iter();
+                    while let (Some(k), Some(v)) = (items.next(), items.next()) {
+                        map.insert(k, v).map_err(|_| unhashable())?;
+                    }
+                    self.push_new(Value::Map(Rc::new(RefCell::new(map))))?;
+                }
+                OpCode::GetIndex => {
+                    let index = self.get_val()?;
+                    let container = self.get_val()?;
+                    let value = get_index(&container, &index)?;
+                    self.stack.push(value);
+                }
+                OpCode::SetIndex => {
+                    let value = self.get_val()?;
+                    let index = self.get_val()?;
+                    let container = self.get_val()?;
+                    set_index(&container, index, value)?;
+                }
+                OpCode::SetField => {
+                    let value = self.get_val()?;
+                    let name = self.get_val()?;
+                    let object = self.get_val()?;
+                    set_field(&object, &name, value)?;
+                }
+                OpCode::GetIter => {
+                    let value = self.get_val()?;
+                    let iter = match &value {
+                        Value::Iter(iter) => iter.clone(),
+                        // it is resumed by `ForIter`, the `next` of a struct is called by it
+                        Value::Coroutine(_) => {
+                            self.stack.push(value);
+                            continue;
+                        }
+                        Value::Struct(s) if s.def.field("next").is_some() => {
+                            self.stack.push(value);
+                            continue;
+                        }
+                        value => {
+                            let iter = Iter::new(value).ok_or_else(|| {
+                                RuntimeError::TypeError(format!("`{}` is not iterable", value))
+                            })?;
+                            Rc::new(RefCell::new(iter))
+                        }
+                    };
+                    self.push_new(Value::Iter(iter))?;
+                }
+                OpCode::ForIter => {
+                    let offset = wide(code, at) as usize;
+                    let iter = match &self.stack.last() {
+                        Some(Value::Iter(iter)) => iter.clone(),
+                        // the yielded values of a generator, until it returns
+                        Some(Value::Coroutine(co)) => {
+                            match self.resume_nested(co, Value::Nil)? {
+                                Outcome::Suspended(value) => self.stack.push(value),
+                                Outcome::Done(_) => ip += offset,
+                            }
+                            continue;
+                        }
+                        Some(Value::Struct(s)) => {
+                            match self.next_item(s)? {
+                                Some(value) => self.stack.push(value),
+                                None => ip += offset,
+                            }
+                            continue;
+                        }
+                        _ => return Err(RuntimeError::TypeError("not an iterator".to_owned())),
+                    };
+                    let next = iter.borrow_mut().next();
+                    match next {
+                        Some(value) => self.stack.push(value),
+                        None => ip += offset,
+                    }
+                }
+                OpCode::ConstantW => {
+                    let constant = wide(code, at);
+                    let value = self.get_constant(constant as usize)?;
+                    self.stack.push(value)
+                }
+                OpCode::JumpBackW => {
+                    ip = at - wide(code, at) as usize;
+                }
+                OpCode::BlockEndL => {
+                    let n = long(code, at) as usize;
+                    self.block_end(n)?;
+                }
+                OpCode::Call => {
+                    let n = byte(code, at) as usize;
+                    self.call(n)?;
+                    if let Some(value) = self.suspending.take() {
+                        self.stack.pop();
+                        return self.suspend_run(depth, value);
+                    }
+                    chunk = self.chunk.clone();
+                    chunk.verify()?;
+                    ip = self.ip;
+                }
+                OpCode::Invoke => {
+                    let n = byte(code, at) as usize;
+                    self.invoke(n)?;
+                    if let Some(value) = self.suspending.take() {
+                        self.stack.pop();
+                        return self.suspend_run(depth, value);
+                    }
+                    chunk = self.chunk.clone();
+                    chunk.verify()?;
+                    ip = self.ip;
+                }
+                OpCode::Yield => {
+                    let value = self.get_val()?;
+                    return self.suspend_run(depth, value);
+                }
+                OpCode::Throw => {
+                    let value = self.get_val()?;
+                    return Err(RuntimeError::Thrown(value));
+                }
+                OpCode::JumpIfOk => {
+                    let offset = wide(code, at) as usize;
+                    let value = self.stack.last().ok_or(RuntimeError::StackUnderflow)?;
+                    // the value in `Some(v)` or `Ok(v)` replaces it
+                    if let Some(value) = prelude::success(&value)? {
+                        self.stack.replace(self.stack.len() - 1, value);
+                        ip += offset;
+                    }
+                }
+                // the superinstructions run like the instructions they replace
+                OpCode::AddLocalConstant => {
+                    self.push_local(byte(code, at) as usize)?;
+                    let value = self.get_constant(byte(code, at + 1) as usize)?;
+                    self.stack.push(value);
+                    match self.stack.top_ints() {
+                        Some((a, b)) => self.stack.replace_top2(int(a.checked_add(b))?),
+                        None => self.binary_op("+", |a, b| a + b)?,
+                    }
+                }
+                OpCode::NotEq => {
+                    if let Some((a, b)) = self.stack.top_ints() {
+                        self.stack.replace_top2(Value::Bool(a != b));
+                        continue;
+                    }
+                    let b = self.get_val()?;
+                    let a = self.get_val()?;
+                    let result = a != b;
+                    self.stack.push(Value::Bool(result));
+                }
+                OpCode::LtJumpIfFalse => {
+                    let offset = wide(code, at) as usize;
+                    let less = match self.stack.top_ints() {
+                        Some((a, b)) => {
+                            self.stack.truncate(self.stack.len() - 2);
+                            a < b
+                        }
+                        None => {
+                            let b = self.get_val()?;
+                            let a = self.get_val()?;
+                            a < b
+                        }
+                    };
+                    if !less {
+                        ip += offset;
+                    }
+                }
+            }
+        }
+    }
+
+    // the running coroutine, or the run when there is none, is suspended.
+    // the state is kept as it is, the value given back is pushed by `resume`.
+    fn suspend_run(&mut self, depth: usize, value: Value) -> Result<Outcome, RuntimeError> {
+        // the rust code between the frames can't be suspended, a coroutine runs from 1
+        let base = match self.resumers.is_empty() {
+            true => 0,
+            false => 1,
+        };
+        if depth != base {
+            return Err(RuntimeError::CantSuspend);
+        }
+        if base == 0 {
+            self.suspended = true;
+        }
+        Ok(Outcome::Suspended(value))
+    }
+
+    fn set_global(&mut self, i: u16) -> IntResult {
+        let value = self.get_val()?;
+        self.global.insert(i, value);
+
+        #[cfg(feature = "vm_dev")]
+        println!("Define Global: {:?}\n", self.global);
+        Ok(())
+    }
+
+    fn push_global(&mut self, i: u16) -> IntResult {
+        let value = self
+            .global
+            .get(&i)
+            .ok_or(RuntimeError::UndefinedGlobal(i))?;
+        self.stack.push(value.clone());
+        Ok(())
+    }
+
+    fn set_local(&mut self, i: usize) -> IntResult {
+        self.stack
+            .pop_into(self.base + i)
+            .ok_or(RuntimeError::StackUnderflow)
+    }
+
+    fn push_local(&mut self, i: usize) -> IntResult {
+        self.stack
+            .push_copy(self.base + i)
+            .ok_or(RuntimeError::StackUnderflow)
+    }
+
+    // the function is under the `n` arguments. a native function replaces them by
+    // the result, a script function keeps them as its first locals and runs.
+    fn call(&mut self, n: usize) -> IntResult {
+        let i = self
+            .stack
+            .len()
+            .checked_sub(n + 1)
+            .ok_or(RuntimeError::StackUnderflow)?;
+        match &self.stack.get(i).unwrap_or(Value::Nil) {
+            Value::NativeFn(native) => {
+                check_arity(&native.name, native.arity, n)?;
+                let args = self.get_vals(n)?;
+                self.stack.pop();
+                let result = (native.fun)(self, &args)?;
+                self.push_new(result)?;
+            }
+            Value::Function(fun) if fun.generator => {
+                check_arity(&fun.name, Arity::Exact(fun.arity as usize), n)?;
+                let args = self.get_vals(n)?;
+                self.stack.pop();
+                let co = Coroutine::new(Value::Function(fun.clone()), &args);
+                self.push_new(Value::Coroutine(Rc::new(co)))?;
+            }
+            Value::Function(fun) if fun.registers.is_some() => {
+                self.call_registers(fun.clone(), i, n)?
+            }
+            Value::Function(fun) => self.enter(fun.clone(), i, n)?,
+            value => return Err(RuntimeError::NotCallable(value.type_name().to_owned())),
+        }
+        Ok(())
+    }
+
+    // push the frame of the function at `i` in the stack, the `n` arguments are above it
+    fn enter(&mut self, fun: Rc<Function>, i: usize, n: usize) -> IntResult {
+        check_arity(&fun.name, Arity::Exact(fun.arity as usize), n)?;
+        self.check_call()?;
+        self.frames.push(Frame {
+            chunk: std::mem::replace(&mut self.chunk, fun.chunk.clone()),
+            ip: std::mem::replace(&mut self.ip, 0),
+            base: std::mem::replace(&mut self.base, i + 1),
+            callee: fun,
+        });
+        Ok(())
+    }
+
+    // the calls going on are the frames, the register functions and the coroutines
+    // resumed with the calls of their resumers. the values of the resumers are in
+    // their contexts.
+    fn check_call(&self) -> IntResult {
+        let resumers = || {
+            self.resumers
+                .iter()
+                .map(|(_, c)| (c.frames.len() + 1, c.stack.len()))
+        };
+        let depth = || {
+            let parked: usize = resumers().map(|(frames, _)| frames).sum();
+            self.frames.len() + self.register_calls.len() + parked
+        };
+        if self.limits.call_depth.is_some_and(|max| depth() >= max) {
+            return Err(RuntimeError::CallDepthExceeded);
+        }
+        let size = || self.stack.len() + resumers().map(|(_, size)| size).sum::<usize>();
+        if self.limits.stack_size.is_some_and(|max| size() > max) {
+            return Err(RuntimeError::StackOverflow);
+        }
+        Ok(())
+    }
+
+    // `object.name(args)`, the object and the name are under the `n` arguments.
+    // it calls the method of a userdata or of an `Option` or a `Result`, or the function
+    // in the field of a struct.
+    fn invoke(&mut self, n: usize) -> IntResult {
+        let i = self
+            .stack
+            .len()
+            .checked_sub(n + 2)
+            .ok_or(RuntimeError::StackUnderflow)?;
+        let name = self.stack.remove(i + 1);
+        let object = self.stack.get(i).unwrap_or(Value::Nil);
+        match (&object, &name) {
+            (Value::UserData(data), Value::Str(name)) => {
+                let args = self.get_vals(n)?;
+                self.stack.pop();
+                let result = data.call_method(self, name, &args)?;
+                self.push_new(result)?;
+                Ok(())
+            }
+            (Value::Enum(e), Value::Str(name)) if prelude::is_prelude(&e.def) => {
+                let args = self.get_vals(n)?;
+                self.stack.pop();
+                let result = prelude::call_method(self, e, name, &args)?;
+                self.push_new(result)?;
+                Ok(())
+            }
+            _ => {
+                self.stack.replace(i, get_field(&object, &name)?);
+                self.call(n)
+            }
+        }
+    }
+
+    // drop the locals of the function and the function itself, and go back to the caller
+    fn return_frame(&mut self) {
+        self.stack.truncate(self.base.saturating_sub(1));
+        if let Some(frame) = self.frames.pop() {
+            self.chunk = frame.chunk;
+            self.ip = frame.ip;
+            self.base = frame.base;
+        }
+    }
+
+    // drop `n` values under the top of stack
+    fn block_end(&mut self, n: usize) -> IntResult {
+        self.stack.drop_under(n).ok_or(RuntimeError::StackUnderflow)
+    }
+
+    fn get_constant(&self, i: usize) -> Result<Value, RuntimeError> {
+        self.chunk
+            .get_constant(i)
+            .cloned()
+            .ok_or(RuntimeError::InvalidConstant(i))
+    }
+
+    fn get_val(&mut self) -> Result<Value, RuntimeError> {
+        self.stack.pop().ok_or(RuntimeError::StackUnderflow)
+    }
+
+    // pop n values, keep them in the order they were pushed
+    fn get_vals(&mut self, n: usize) -> Result<Vec<Value>, RuntimeError> {
+        let start = self
+            .stack
+            .len()
+            .checked_sub(n)
+            .ok_or(RuntimeError::StackUnderflow)?;
+        Ok(self.stack.split_off(start))
+    }
+
+    fn binary_op(&mut self, op: &str, f: fn(Value, Value) -> Result<Value, ()>) -> IntResult {
+        let b = self.get_val()?;
+        let a = self.get_val()?;
+        let result = self.binary(a, b, op, f)?;
+        self.push_new(result)
+    }
+
+    // the string made by `+` or `*` is measured before it is made, it fails when it
+    // can't be allocated
+    fn binary(
+        &mut self,
+        a: Value,
+        b: Value,
+        op: &str,
+        f: fn(Value, Value) -> Result<Value, ()>,
+    ) -> Result<Value, RuntimeError> {
+        let len = a.str_len(op, &b);
+        if let Some(len) = len {
+            self.reserve(len)?;
+        }
+        let ints = matches!((&a, &b), (Value::Int(_), Value::Int(_)));
+        f(a, b).map_err(|_| match len {
+            Some(_) => RuntimeError::OutOfMemory,
+            None if ints => RuntimeError::Overflow,
+            None => RuntimeError::TypeError(format!("unsupported operand types for `{}`", op)),
+        })
+    }
+
+    // `len` bytes are about to be allocated, not more than what is left under the cap
+    fn reserve(&self, len: usize) -> IntResult {
+        let left = match self.limits.heap_bytes {
+            Some(max) => max.saturating_sub(self.heap_used + self.allocated),
+            None => isize::MAX as usize,
+        };
+        if len > left {
+            return Err(RuntimeError::OutOfMemory);
+        }
+        Ok(())
+    }
+
+    // push a value which may be just allocated, e.g. a list, for the heap and its cap
+    fn push_new(&mut self, value: Value) -> IntResult {
+        if self.heap.track(&value) {
+            // the value isn't in the stack yet, it is held here
+            self.collect(false);
+        }
+        if let Some(max) = self.limits.heap_bytes {
+            self.allocated += limits::shallow_size(&value);
+            // measure the heap when it may be over a fraction of the cap
+            if self.allocated > max / 8 {
+                self.allocated = 0;
+                let parked = self
+                    .resumers
+                    .iter()
+                    .flat_map(|(_, c)| c.stack.heap_values());
+                let roots = self
+                    .stack
+                    .heap_values()
+                    .chain(self.global.values().map(Cow::Borrowed))
+                    .chain(parked);
+                self.heap_used = limits::heap_size(roots.chain([Cow::Borrowed(&value)]));
+                if self.heap_used > max {
+                    return Err(RuntimeError::OutOfMemory);
                 }
-                _ => return Err(()),
             }
         }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    // the limits not checked for every instruction
+    fn check_limits(&mut self) -> IntResult {
+        if self.interrupt.take() {
+            return Err(RuntimeError::Interrupted);
+        }
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return Err(RuntimeError::Timeout);
+        }
+        if self
+            .limits
+            .stack_size
+            .is_some_and(|max| self.stack.len() > max)
+        {
+            return Err(RuntimeError::StackOverflow);
+        }
+        Ok(())
+    }
+}
+
+// the code of a verified chunk, see `Chunk::verify`: the vm only reads the opcodes
+// and the operands of its instructions, they are all in the code.
+#[inline]
+fn byte(code: &[u8], i: usize) -> u8 {
+    debug_assert!(i < code.len());
+    // SAFETY: `i` is in an instruction of the verified code
+    unsafe { *code.get_unchecked(i) }
+}
+
+#[inline]
+fn long(code: &[u8], i: usize) -> u16 {
+    u16::from_be_bytes([byte(code, i), byte(code, i + 1)])
+}
+
+#[inline]
+fn wide(code: &[u8], i: usize) -> u32 {
+    u32::from_be_bytes([
+        byte(code, i),
+        byte(code, i + 1),
+        byte(code, i + 2),
+        byte(code, i + 3),
+    ])
+}
+
+// the int made by the fast paths of the arithmetic, None when it overflowed
+#[inline]
+fn int(result: Option<i64>) -> Result<Value, RuntimeError> {
+    result.map(Value::Int).ok_or(RuntimeError::Overflow)
+}
+
+// `-` fails on an int only when it overflows
+fn neg(value: Value) -> Result<Value, RuntimeError> {
+    let int = matches!(value, Value::Int(_));
+    (-value).map_err(|_| {
+        if int {
+            RuntimeError::Overflow
+        } else {
+            RuntimeError::TypeError("unsupported operand type for `-`".to_owned())
+        }
+    })
+}
+
+// get an item from a tuple, list, enum or struct by position,
+// `index` maps the length of the container to the wanted position.
+fn item(container: &Value, index: impl FnOnce(usize) -> usize) -> Result<Value, RuntimeError> {
+    let get = |items: &[Value]| {
+        let i = index(items.len());
+        items
+            .get(i)
+            .cloned()
+            .ok_or(RuntimeError::IndexOutOfRange(i))
+    };
+
+    match container {
+        Value::Tuple(items) => get(items),
+        Value::List(items) => get(&items.borrow()),
+        Value::Enum(e) => get(&e.fields),
+        Value::Struct(s) => get(&s.fields.borrow()),
+        _ => Err(RuntimeError::TypeError(
+            "only tuples, lists, enums and structs have items".to_owned(),
+        )),
+    }
+}
+
+// `object.name`
+fn get_field(object: &Value, name: &Value) -> Result<Value, RuntimeError> {
+    match (object, name) {
+        (Value::Struct(s), Value::Str(name)) => s.get(name).ok_or_else(|| {
+            RuntimeError::TypeError(format!("struct `{}` has no field `{}`", s.def.name, name))
+        }),
+        (Value::UserData(data), Value::Str(name)) => data.get(name),
+        _ => Err(RuntimeError::TypeError(
+            "only structs have fields".to_owned(),
+        )),
+    }
+}
+
+// `object.name = value`
+fn set_field(object: &Value, name: &Value, value: Value) -> IntResult {
+    match (object, name) {
+        (Value::Struct(s), Value::Str(name)) => {
+            let i = s.def.field(name).ok_or_else(|| {
+                RuntimeError::TypeError(format!("struct `{}` has no field `{}`", s.def.name, name))
+            })?;
+            s.fields.borrow_mut()[i] = value;
+            Ok(())
+        }
+        (Value::UserData(data), Value::Str(name)) => data.set(name, value),
+        _ => Err(RuntimeError::TypeError(
+            "only structs have fields".to_owned(),
+        )),
+    }
+}
+
+// an enum or a struct like the template with the fields
+fn construct(template: &Value, fields: Vec<Value>) -> Result<Value, RuntimeError> {
+    match template {
+        Value::Enum(template) => Ok(Value::Enum(Rc::new(EnumObj {
+            def: template.def.clone(),
+            tag: template.tag,
+            fields,
+        }))),
+        Value::Struct(template) => Ok(Value::Struct(Rc::new(StructObj {
+            def: template.def.clone(),
+            fields: RefCell::new(fields),
+        }))),
+        _ => Err(RuntimeError::TypeError("not a constructor".to_owned())),
+    }
+}
+
+fn make_range(
+    start: Value,
+    end: Value,
+    step: Value,
+    inclusive: bool,
+) -> Result<Value, RuntimeError> {
+    match (start, end, step) {
+        (Value::Int(start), Value::Int(end), Value::Int(step)) => {
+            let range = Range::new(start, end, step, inclusive).ok_or_else(|| {
+                RuntimeError::TypeError("the step of range can't be 0".to_owned())
+            })?;
+            Ok(Value::Range(range))
+        }
+        _ => Err(RuntimeError::TypeError(
+            "the bounds and step of range must be int".to_owned(),
+        )),
     }
+}
 
-    fn read_byte(&mut self) -> Option<u8> {
-        let byte = self.chunk.get_byte(self.ip);
-        self.ip += 1;
-        byte
+fn check_arity(name: &str, arity: Arity, n: usize) -> IntResult {
+    if !arity.accepts(n) {
+        return Err(RuntimeError::WrongArity {
+            name: name.to_owned(),
+            arity,
+            found: n,
+        });
     }
+    Ok(())
+}
 
-    fn read_long_byte(&mut self) -> Option<u16> {
-        let long_byte = self.chunk.get_long_bytes(self.ip);
-        self.ip += 2;
-        long_byte
+fn unhashable() -> RuntimeError {
+    RuntimeError::TypeError("only nil, bool, int, str and tuples of them can be keys".to_owned())
+}
+
+// `container[index]`
+fn get_index(container: &Value, index: &Value) -> Result<Value, RuntimeError> {
+    match (container, index) {
+        (Value::Map(map), key) => match map.borrow().get(key).map_err(|_| unhashable())? {
+            Some(value) => Ok(value.clone()),
+            None => Err(RuntimeError::KeyNotFound(key.to_string())),
+        },
+        (Value::List(_) | Value::Tuple(_), Value::Int(i)) => {
+            let i = usize::try_from(*i).map_err(|_| RuntimeError::IndexOutOfRange(0))?;
+            item(container, |_| i)
+        }
+        _ => Err(RuntimeError::TypeError(format!(
+            "`{}` can't be indexed by `{}`",
+            container, index
+        ))),
     }
+}
 
-    fn get_val(&mut self) -> Result<Value, ()> {
-        self.stack.pop().ok_or(())
+// `container[index] = value`
+fn set_index(container: &Value, index: Value, value: Value) -> Result<(), RuntimeError> {
+    match (container, index) {
+        (Value::Map(map), key) => map
+            .borrow_mut()
+            .insert(key, value)
+            .map_err(|_| unhashable()),
+        (Value::List(list), Value::Int(i)) => {
+            let mut list = list.borrow_mut();
+            let slot = usize::try_from(i)
+                .ok()
+                .and_then(|i| list.get_mut(i))
+                .ok_or(RuntimeError::IndexOutOfRange(i.max(0) as usize))?;
+            *slot = value;
+            Ok(())
+        }
+        (container, index) => Err(RuntimeError::TypeError(format!(
+            "`{}` can't be indexed by `{}`",
+            container, index
+        ))),
     }
 }
 
@@ -218,9 +1606,9 @@ impl Default for Vm {
 mod tests {
     use crate::op::OpCode;
 
-    use crate::{chunk::Chunk, value::Value};
+    use crate::{chunk::Chunk, error::RuntimeError, native::Arity, value::Value};
 
-    use super::Vm;
+    use super::{Outcome, Vm};
 
     fn vm_with_chunk(codes: &[u8], constants: Vec<Value>) -> Vm {
         let mut chunk = Chunk::new();
@@ -229,10 +1617,10 @@ mod tests {
         }
 
         for code in codes {
-            chunk.write_code(*code as u8);
+            chunk.write_code(*code);
         }
         let mut vm = Vm::new();
-        vm.set_chunk(chunk);
+        vm.set_chunk(chunk).unwrap();
         vm
     }
 
@@ -241,6 +1629,142 @@ mod tests {
         let constants = vec![];
         let codes = vec![OpCode::Return as u8];
         let mut vm = vm_with_chunk(&codes, constants);
-        assert_eq!(vm.run(), Ok(()))
+        assert_eq!(vm.run(), Ok(Outcome::Done(None)))
+    }
+
+    #[test]
+    fn test_return_value() {
+        let constants = vec![Value::Int(1)];
+        let codes = vec![OpCode::Constant as u8, 0, OpCode::Return as u8];
+        let mut vm = vm_with_chunk(&codes, constants);
+        assert_eq!(vm.run(), Ok(Outcome::Done(Some(Value::Int(1)))))
+    }
+
+    #[test]
+    fn test_resume() {
+        let constants = vec![Value::Int(1)];
+        let codes = vec![
+            OpCode::Constant as u8,
+            0,
+            OpCode::Yield as u8,
+            OpCode::Return as u8,
+        ];
+        let mut vm = vm_with_chunk(&codes, constants);
+        assert_eq!(vm.run(), Ok(Outcome::Suspended(Value::Int(1))));
+        assert!(vm.is_suspended());
+        assert_eq!(
+            vm.resume(Value::Int(2)),
+            Ok(Outcome::Done(Some(Value::Int(2))))
+        );
+        assert_eq!(vm.resume(Value::Nil), Err(RuntimeError::NotSuspended));
+    }
+
+    #[test]
+    fn test_call_native() {
+        fn add(_: &mut Vm, args: &[Value]) -> Result<Value, RuntimeError> {
+            let sum = args[0].clone() + args[1].clone();
+            sum.map_err(|_| RuntimeError::TypeError("can't add".to_owned()))
+        }
+
+        let mut vm = Vm::new();
+        vm.define_native("add", Arity::Exact(2), add);
+        let add = vm
+            .natives()
+            .find(|(name, _)| *name == "add")
+            .unwrap()
+            .1
+            .clone();
+        let constants = vec![add, Value::Int(1), Value::Int(2)];
+        let codes = [
+            OpCode::Constant as u8,
+            0,
+            OpCode::Constant as u8,
+            1,
+            OpCode::Constant as u8,
+            2,
+            OpCode::Call as u8,
+            2,
+            OpCode::Return as u8,
+        ];
+        let mut chunk = Chunk::new();
+        for v in constants {
+            chunk.write_constant(v);
+        }
+        for code in codes {
+            chunk.write_code(code);
+        }
+        assert_eq!(vm.interpret(chunk), Ok(Some(Value::Int(3))));
+    }
+
+    #[test]
+    fn test_interpret_while_running() {
+        // the run in progress isn't reset by a native function starting a chunk
+        fn reenter(vm: &mut Vm, _: &[Value]) -> Result<Value, RuntimeError> {
+            match vm.interpret(Chunk::new()) {
+                Err(e) => Ok(Value::Str(e.kind().into())),
+                Ok(_) => Ok(Value::Nil),
+            }
+        }
+
+        let mut vm = Vm::new();
+        vm.define_native("reenter", Arity::Exact(0), reenter);
+        let reenter = vm.native("reenter").unwrap().clone();
+        let codes = [
+            OpCode::Constant as u8,
+            0,
+            OpCode::Call as u8,
+            0,
+            OpCode::Return as u8,
+        ];
+        let mut chunk = Chunk::new();
+        chunk.write_constant(reenter);
+        for code in codes {
+            chunk.write_code(code);
+        }
+        let kind = Value::Str("AlreadyRunning".into());
+        assert_eq!(vm.interpret(chunk), Ok(Some(kind)));
+        assert_eq!(vm.set_chunk(Chunk::new()), Ok(()));
+    }
+
+    #[test]
+    fn test_call_errors() {
+        let mut vm = Vm::new();
+        let len = vm
+            .natives()
+            .find(|(name, _)| *name == "len")
+            .unwrap()
+            .1
+            .clone();
+        let codes = [
+            OpCode::Constant as u8,
+            0,
+            OpCode::Call as u8,
+            0,
+            OpCode::Return as u8,
+        ];
+        let mut chunk = Chunk::new();
+        chunk.write_constant(len);
+        for code in codes {
+            chunk.write_code(code);
+        }
+        let error = RuntimeError::WrongArity {
+            name: "len".to_owned(),
+            arity: Arity::Exact(1),
+            found: 0,
+        };
+        assert_eq!(vm.interpret(chunk), Err(error));
+
+        let codes = [
+            OpCode::Nil as u8,
+            OpCode::Call as u8,
+            0,
+            OpCode::Return as u8,
+        ];
+        let mut chunk = Chunk::new();
+        for code in codes {
+            chunk.write_code(code);
+        }
+        let error = RuntimeError::NotCallable("nil".to_owned());
+        assert_eq!(vm.interpret(chunk), Err(error));
     }
 }
@@ -0,0 +1,358 @@
+// the loop of the register backend, see `register`. a call of a register function from
+// the bytecode or from rust runs the loop until the function returns, the calls between
+// register functions stay in the loop with their own callers. the windows are in the
+// stack, the window of a callee starts after the callee in the window of its caller.
+
+use std::{cell::RefCell, mem, rc::Rc};
+
+use super::{
+    check_arity, construct, get_field, get_index, int, item, make_range, neg, set_field, set_index,
+    unhashable, IntResult, Outcome, Vm, CHECK_INTERVAL,
+};
+use crate::{
+    error::RuntimeError,
+    iter::Iter,
+    map::Map,
+    native::Arity,
+    object::Function,
+    prelude,
+    register::{Code, Instr, Reg},
+    value::Value,
+};
+
+// the running register function
+struct Window {
+    code: Rc<Code>,
+    pc: usize,
+    // where its registers start in the stack
+    base: usize,
+}
+
+// a register function waiting for its callee, the result goes to `dst` in the stack
+struct Caller {
+    window: Window,
+    dst: usize,
+}
+
+impl Vm {
+    // run the register function at `i` in the stack, the `n` arguments are above it.
+    // they are replaced by the result like for a native function.
+    pub(super) fn call_registers(&mut self, fun: Rc<Function>, i: usize, n: usize) -> IntResult {
+        check_arity(&fun.name, Arity::Exact(fun.arity as usize), n)?;
+        let code = fun.registers.clone().expect("a register function");
+        let calls = self.register_calls.len();
+        let result = self.check_call().and_then(|_| {
+            self.push_register_call(fun.clone());
+            self.stack.resize(i + 1 + code.registers());
+            self.nested(|vm| {
+                vm.run_registers(Window {
+                    code,
+                    pc: 0,
+                    base: i + 1,
+                })
+            })
+        });
+        if let Err(e) = &result {
+            self.keep_trace(e);
+        }
+        self.register_calls.truncate(calls);
+        self.stack.truncate(i);
+        self.stack.push(result?);
+        Ok(())
+    }
+
+    fn run_registers(&mut self, mut window: Window) -> Result<Value, RuntimeError> {
+        let mut callers: Vec<Caller> = Vec::new();
+        loop {
+            let instr = window.code.instrs()[window.pc];
+            window.pc += 1;
+            self.instructions += 1;
+            if self
+                .limits
+                .fuel
+                .is_some_and(|fuel| self.instructions > fuel)
+            {
+                return Err(RuntimeError::OutOfFuel);
+            }
+            if self.instructions.is_multiple_of(CHECK_INTERVAL) {
+                self.check_limits()?;
+            }
+
+            let base = window.base;
+            let r = |reg: Reg| base + reg as usize;
+            match instr {
+                Instr::Nil { dst } => self.stack.set(r(dst), Value::Nil),
+                Instr::Bool { dst, value } => self.stack.set(r(dst), Value::Bool(value)),
+                Instr::Constant { dst, i } => {
+                    let value = window.code.constants()[i as usize].clone();
+                    self.stack.set(r(dst), value);
+                }
+                Instr::Move { dst, src } => self.stack.copy(r(src), r(dst)),
+                Instr::GetGlobal { dst, i } => {
+                    let value = self
+                        .global
+                        .get(&i)
+                        .ok_or(RuntimeError::UndefinedGlobal(i))?;
+                    self.stack.set(r(dst), value.clone());
+                }
+                Instr::SetGlobal { i, src } => {
+                    self.global.insert(i, self.stack.at(r(src)));
+                }
+                Instr::Add { dst, a, b } => match self.stack.ints(r(a), r(b)) {
+                    Some((a, b)) => self.stack.set(r(dst), int(a.checked_add(b))?),
+                    None => self.binary_registers(r(dst), r(a), r(b), "+", |a, b| a + b)?,
+                },
+                Instr::Sub { dst, a, b } => match self.stack.ints(r(a), r(b)) {
+                    Some((a, b)) => self.stack.set(r(dst), int(a.checked_sub(b))?),
+                    None => self.binary_registers(r(dst), r(a), r(b), "-", |a, b| a - b)?,
+                },
+                Instr::Mult { dst, a, b } => match self.stack.ints(r(a), r(b)) {
+                    Some((a, b)) => self.stack.set(r(dst), int(a.checked_mul(b))?),
+                    None => self.binary_registers(r(dst), r(a), r(b), "*", |a, b| a * b)?,
+                },
+                Instr::Div { dst, a, b } => {
+                    if let Some((_, 0)) = self.stack.ints(r(a), r(b)) {
+                        return Err(RuntimeError::DivisionByZero);
+                    }
+                    self.binary_registers(r(dst), r(a), r(b), "/", |a, b| a / b)?
+                }
+                Instr::Eq { dst, a, b } => {
+                    let result = match self.stack.ints(r(a), r(b)) {
+                        Some((a, b)) => a == b,
+                        None => self.stack.at(r(a)) == self.stack.at(r(b)),
+                    };
+                    self.stack.set(r(dst), Value::Bool(result));
+                }
+                Instr::Gt { dst, a, b } => {
+                    let result = match self.stack.ints(r(a), r(b)) {
+                        Some((a, b)) => a > b,
+                        None => self.stack.at(r(a)) > self.stack.at(r(b)),
+                    };
+                    self.stack.set(r(dst), Value::Bool(result));
+                }
+                Instr::Lt { dst, a, b } => {
+                    let result = match self.stack.ints(r(a), r(b)) {
+                        Some((a, b)) => a < b,
+                        None => self.stack.at(r(a)) < self.stack.at(r(b)),
+                    };
+                    self.stack.set(r(dst), Value::Bool(result));
+                }
+                Instr::Neg { dst, src } => {
+                    let result = neg(self.stack.at(r(src)))?;
+                    self.stack.set(r(dst), result);
+                }
+                Instr::Not { dst, src } => match self.stack.at(r(src)) {
+                    Value::Bool(b) => self.stack.set(r(dst), Value::Bool(!b)),
+                    _ => {
+                        return Err(RuntimeError::TypeError(
+                            "unsupported operand type for `!`".to_owned(),
+                        ))
+                    }
+                },
+                Instr::Jump { to } => window.pc = to as usize,
+                Instr::JumpIfFalse { test, to } => {
+                    if let Value::Bool(false) = self.stack.at(r(test)) {
+                        window.pc = to as usize;
+                    }
+                }
+                Instr::JumpIfOk { src, to } => {
+                    if let Some(value) = prelude::success(&self.stack.at(r(src)))? {
+                        self.stack.set(r(src), value);
+                        window.pc = to as usize;
+                    }
+                }
+                Instr::Call { dst, callee, n } => {
+                    self.call_register(&mut window, &mut callers, dst, callee, n)?
+                }
+                Instr::Invoke {
+                    dst,
+                    object,
+                    name,
+                    n,
+                } => {
+                    let name = window.code.constants()[name as usize].clone();
+                    let object_value = self.stack.at(r(object));
+                    let result = match (&object_value, &name) {
+                        (Value::UserData(data), Value::Str(name)) => {
+                            let args = self.registers(r(object) + 1, n as usize);
+                            data.call_method(self, name, &args)?
+                        }
+                        (Value::Enum(e), Value::Str(name)) if prelude::is_prelude(&e.def) => {
+                            let args = self.registers(r(object) + 1, n as usize);
+                            prelude::call_method(self, e, name, &args)?
+                        }
+                        // the function in the field is called like by `Call`
+                        _ => {
+                            let callee = get_field(&object_value, &name)?;
+                            self.stack.set(r(object), callee);
+                            self.call_register(&mut window, &mut callers, dst, object, n)?;
+                            continue;
+                        }
+                    };
+                    if self.suspending.take().is_some() {
+                        return Err(RuntimeError::CantSuspend);
+                    }
+                    self.set_new(r(dst), result)?;
+                }
+                Instr::Return { src } => {
+                    let value = self.stack.at(r(src));
+                    let caller = match callers.pop() {
+                        Some(caller) => caller,
+                        None => return Ok(value),
+                    };
+                    self.register_calls.pop();
+                    window = caller.window;
+                    self.stack.truncate(base);
+                    self.stack.resize(window.base + window.code.registers());
+                    self.stack.set(caller.dst, value);
+                }
+                Instr::MakeTuple { dst, start, n } => {
+                    let items = self.registers(r(start), n as usize);
+                    self.set_new(r(dst), Value::Tuple(items.into()))?;
+                }
+                Instr::MakeList { dst, start, n } => {
+                    let items = self.registers(r(start), n as usize);
+                    self.set_new(r(dst), Value::List(Rc::new(RefCell::new(items))))?;
+                }
+                Instr::MakeMap { dst, start, n } => {
+                    let mut map = Map::new();
+                    for k in 0..n as usize {
+                        let key = self.stack.at(r(start) + k * 2);
+                        let value = self.stack.at(r(start) + k * 2 + 1);
+                        map.insert(key, value).map_err(|_| unhashable())?;
+                    }
+                    self.set_new(r(dst), Value::Map(Rc::new(RefCell::new(map))))?;
+                }
+                Instr::MakeRange { dst, start, flags } => {
+                    let step = match flags & 0b10 {
+                        0 => Value::Int(1),
+                        _ => self.stack.at(r(start) + 2),
+                    };
+                    let (from, to) = (self.stack.at(r(start)), self.stack.at(r(start) + 1));
+                    let range = make_range(from, to, step, flags & 0b01 != 0)?;
+                    self.stack.set(r(dst), range);
+                }
+                Instr::Construct {
+                    dst,
+                    template,
+                    start,
+                    n,
+                } => {
+                    let fields = self.registers(r(start), n as usize);
+                    let value = construct(&window.code.constants()[template as usize], fields)?;
+                    self.set_new(r(dst), value)?;
+                }
+                Instr::GetField { dst, object, name } => {
+                    let name = &window.code.constants()[name as usize];
+                    let value = get_field(&self.stack.at(r(object)), name)?;
+                    self.stack.set(r(dst), value);
+                }
+                Instr::SetField { object, name, src } => {
+                    let name = &window.code.constants()[name as usize];
+                    set_field(&self.stack.at(r(object)), name, self.stack.at(r(src)))?;
+                }
+                Instr::GetIndex { dst, object, index } => {
+                    let value = get_index(&self.stack.at(r(object)), &self.stack.at(r(index)))?;
+                    self.stack.set(r(dst), value);
+                }
+                Instr::SetIndex { object, index, src } => {
+                    let (index, value) = (self.stack.at(r(index)), self.stack.at(r(src)));
+                    set_index(&self.stack.at(r(object)), index, value)?;
+                }
+                Instr::GetItem { dst, src, i } => {
+                    let value = item(&self.stack.at(r(src)), |_| i as usize)?;
+                    self.stack.set(r(dst), value);
+                }
+                Instr::GetIter { dst, src } => match self.stack.at(r(src)) {
+                    // a coroutine is resumed by `ForIter`, the `next` of a struct is called
+                    // by it
+                    value @ (Value::Iter(_) | Value::Coroutine(_)) => self.stack.set(r(dst), value),
+                    Value::Struct(ref s) if s.def.field("next").is_some() => {
+                        self.stack.set(r(dst), self.stack.at(r(src)))
+                    }
+                    value => {
+                        let iter = Iter::new(&value).ok_or_else(|| {
+                            RuntimeError::TypeError(format!("`{}` is not iterable", value))
+                        })?;
+                        self.set_new(r(dst), Value::Iter(Rc::new(RefCell::new(iter))))?;
+                    }
+                },
+                Instr::ForIter { dst, iter, exit } => {
+                    let next = match &self.stack.at(r(iter)) {
+                        Value::Iter(iter) => iter.borrow_mut().next(),
+                        // the yielded values of a generator, until it returns
+                        Value::Coroutine(co) => match self.resume_nested(co, Value::Nil)? {
+                            Outcome::Suspended(value) => Some(value),
+                            Outcome::Done(_) => None,
+                        },
+                        Value::Struct(s) => self.next_item(s)?,
+                        _ => return Err(RuntimeError::TypeError("not an iterator".to_owned())),
+                    };
+                    match next {
+                        Some(value) => self.stack.set(r(dst), value),
+                        None => window.pc = exit as usize,
+                    }
+                }
+                Instr::Throw { src } => return Err(RuntimeError::Thrown(self.stack.at(r(src)))),
+            }
+        }
+    }
+
+    // a register function gets a window above the arguments and runs in the loop,
+    // the other values are called like by `call_value`.
+    fn call_register(
+        &mut self,
+        window: &mut Window,
+        callers: &mut Vec<Caller>,
+        dst: Reg,
+        callee: Reg,
+        n: u8,
+    ) -> IntResult {
+        let (at, n) = (window.base + callee as usize, n as usize);
+        let dst = window.base + dst as usize;
+        let callee = self.stack.at(at);
+        if let Value::Function(fun) = &callee {
+            if let Some(code) = &fun.registers {
+                check_arity(&fun.name, Arity::Exact(fun.arity as usize), n)?;
+                self.check_call()?;
+                self.push_register_call(fun.clone());
+                self.stack.truncate(at + 1 + n);
+                self.stack.resize(at + 1 + code.registers());
+                let callee = Window {
+                    code: code.clone(),
+                    pc: 0,
+                    base: at + 1,
+                };
+                let window = mem::replace(window, callee);
+                callers.push(Caller { window, dst });
+                return Ok(());
+            }
+        }
+        let args = self.registers(at + 1, n);
+        let value = self.call_value(&callee, &args)?;
+        self.stack.set(dst, value);
+        Ok(())
+    }
+
+    fn registers(&self, start: usize, n: usize) -> Vec<Value> {
+        (start..start + n).map(|i| self.stack.at(i)).collect()
+    }
+
+    fn binary_registers(
+        &mut self,
+        dst: usize,
+        a: usize,
+        b: usize,
+        op: &str,
+        f: fn(Value, Value) -> Result<Value, ()>,
+    ) -> IntResult {
+        let result = self.binary(self.stack.at(a), self.stack.at(b), op, f)?;
+        self.set_new(dst, result)
+    }
+
+    // set a register to a value which may be just allocated, see `push_new`
+    fn set_new(&mut self, i: usize, value: Value) -> IntResult {
+        self.push_new(value)?;
+        self.stack.pop_into(i).ok_or(RuntimeError::StackUnderflow)
+    }
+}
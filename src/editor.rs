@@ -0,0 +1,483 @@
+// a line editor for the REPL: editing with arrow keys, history in `~/.fpig_history`,
+// reverse search (Ctrl-R), highlighting, bracket matching and tab completion.
+// when the input isn't a terminal the lines are read as they are.
+
+mod history;
+mod key;
+mod term;
+
+use std::{
+    env,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+};
+
+use compiler::Style;
+
+use history::History;
+use key::Key;
+
+const HISTORY_FILE: &str = ".fpig_history";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReadLine {
+    Line(String),
+    // Ctrl-C, the input should be dropped
+    Interrupted,
+    // Ctrl-D or the end of the input
+    Eof,
+}
+
+// the words for tab completion
+pub trait Complete {
+    // `field` is true after a `.`, e.g. `point.x`
+    fn words(&self, field: bool) -> Vec<String>;
+}
+
+pub struct Editor {
+    history: History,
+}
+
+// how the reverse search ends
+enum SearchEnd {
+    // edit the line found
+    Accept(String),
+    // run the line found
+    Submit(String),
+    Cancel,
+}
+
+impl Editor {
+    // the history is only kept for a terminal
+    pub fn new() -> Editor {
+        let history = match env::var_os("HOME") {
+            Some(home) if term::is_tty() => History::load(PathBuf::from(home).join(HISTORY_FILE)),
+            _ => History::default(),
+        };
+        Editor { history }
+    }
+
+    pub fn read_line(&mut self, prompt: &str, complete: &impl Complete) -> io::Result<ReadLine> {
+        // the raw mode is kept until the line is read
+        let raw = term::RawMode::enable()?;
+        match raw {
+            Some(_raw) => self.edit(prompt, complete),
+            None => read_plain(prompt),
+        }
+    }
+
+    pub fn save_history(&self) -> io::Result<()> {
+        self.history.save()
+    }
+
+    fn edit(&mut self, prompt: &str, complete: &impl Complete) -> io::Result<ReadLine> {
+        let mut out = io::stdout();
+        let mut line = Line::default();
+        // the history entry shown, `history.len()` is the new line
+        let mut shown = self.history.len();
+        // the new line when a history entry is shown
+        let mut draft = String::new();
+
+        loop {
+            write!(out, "{}", render(prompt, &line, true))?;
+            out.flush()?;
+            let key = match key::read_key(term::read_byte)? {
+                Some(key) => key,
+                None => return Ok(ReadLine::Eof),
+            };
+
+            match key {
+                Key::Enter => {
+                    write!(out, "{}\r\n", render(prompt, &line, false))?;
+                    let text = line.text();
+                    self.history.add(&text);
+                    return Ok(ReadLine::Line(text));
+                }
+                Key::Ctrl('c') => {
+                    write!(out, "^C\r\n")?;
+                    return Ok(ReadLine::Interrupted);
+                }
+                Key::Ctrl('d') if line.chars.is_empty() => return Ok(ReadLine::Eof),
+                Key::Char(c) => line.insert(c),
+                Key::Backspace => line.backspace(),
+                Key::Delete | Key::Ctrl('d') => line.delete(),
+                Key::Left | Key::Ctrl('b') => line.pos = line.pos.saturating_sub(1),
+                Key::Right | Key::Ctrl('f') => line.pos = (line.pos + 1).min(line.chars.len()),
+                Key::Home | Key::Ctrl('a') => line.pos = 0,
+                Key::End | Key::Ctrl('e') => line.pos = line.chars.len(),
+                Key::Ctrl('k') => line.chars.truncate(line.pos),
+                Key::Ctrl('u') => {
+                    line.chars.drain(..line.pos);
+                    line.pos = 0;
+                }
+                Key::Ctrl('w') => line.delete_word(),
+                Key::Ctrl('l') => write!(out, "\x1b[H\x1b[2J")?,
+                Key::Up | Key::Ctrl('p') if shown > 0 => {
+                    if shown == self.history.len() {
+                        draft = line.text();
+                    }
+                    shown -= 1;
+                    line.set(self.history.get(shown).unwrap_or_default());
+                }
+                Key::Down | Key::Ctrl('n') if shown < self.history.len() => {
+                    shown += 1;
+                    match self.history.get(shown) {
+                        Some(entry) => line.set(entry),
+                        None => line.set(&draft),
+                    }
+                }
+                Key::Tab => {
+                    let words = line.complete(complete);
+                    if !words.is_empty() {
+                        write!(out, "\r\n{}\r\n", words.join("  "))?;
+                    }
+                }
+                Key::Ctrl('r') => match self.search(&mut out, &line.text())? {
+                    SearchEnd::Accept(text) => line.set(&text),
+                    SearchEnd::Submit(text) => {
+                        line.set(&text);
+                        write!(out, "{}\r\n", render(prompt, &line, false))?;
+                        self.history.add(&text);
+                        return Ok(ReadLine::Line(text));
+                    }
+                    SearchEnd::Cancel => {}
+                },
+                _ => {}
+            }
+        }
+    }
+
+    // search the history backward for the lines containing the query
+    fn search(&self, out: &mut impl Write, text: &str) -> io::Result<SearchEnd> {
+        let mut query = String::new();
+        let mut found: Option<usize> = None;
+        loop {
+            let entry = found.and_then(|i| self.history.get(i)).unwrap_or(text);
+            let failed = if found.is_none() && !query.is_empty() {
+                "failed "
+            } else {
+                ""
+            };
+            write!(
+                out,
+                "\r({}reverse-i-search)`{}': {}\x1b[K",
+                failed, query, entry
+            )?;
+            out.flush()?;
+
+            let key = match key::read_key(term::read_byte)? {
+                Some(key) => key,
+                None => return Ok(SearchEnd::Cancel),
+            };
+            match key {
+                Key::Char(c) => {
+                    query.push(c);
+                    // the entry shown may still match
+                    let before = found.map_or(self.history.len(), |i| i + 1);
+                    found = self.history.search(&query, before);
+                }
+                Key::Backspace => {
+                    query.pop();
+                    found = self.history.search(&query, self.history.len());
+                }
+                // an older one
+                Key::Ctrl('r') if !query.is_empty() => {
+                    let before = found.unwrap_or(self.history.len());
+                    if let Some(i) = self.history.search(&query, before) {
+                        found = Some(i);
+                    }
+                }
+                Key::Enter => return Ok(SearchEnd::Submit(entry.to_owned())),
+                Key::Ctrl('c') | Key::Ctrl('g') | Key::Esc => return Ok(SearchEnd::Cancel),
+                _ => return Ok(SearchEnd::Accept(entry.to_owned())),
+            }
+        }
+    }
+}
+
+impl Default for Editor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the line without the `\n`
+fn read_plain(prompt: &str) -> io::Result<ReadLine> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().lock().read_line(&mut line)? == 0 {
+        return Ok(ReadLine::Eof);
+    }
+    let len = line.trim_end_matches(['\n', '\r']).len();
+    line.truncate(len);
+    Ok(ReadLine::Line(line))
+}
+
+// the line being edited, `pos` is the index of the char after the cursor
+#[derive(Default)]
+struct Line {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Line {
+    fn text(&self) -> String {
+        self.chars.iter().collect()
+    }
+
+    // the cursor is moved to the end
+    fn set(&mut self, text: &str) {
+        self.chars = text.chars().collect();
+        self.pos = self.chars.len();
+    }
+
+    fn insert(&mut self, c: char) {
+        self.chars.insert(self.pos, c);
+        self.pos += 1;
+    }
+
+    fn insert_str(&mut self, s: &str) {
+        for c in s.chars() {
+            self.insert(c);
+        }
+    }
+
+    fn backspace(&mut self) {
+        if self.pos > 0 {
+            self.pos -= 1;
+            self.chars.remove(self.pos);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.pos < self.chars.len() {
+            self.chars.remove(self.pos);
+        }
+    }
+
+    // the spaces and the word before the cursor
+    fn delete_word(&mut self) {
+        let mut start = self.pos;
+        while start > 0 && self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        while start > 0 && !self.chars[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        self.chars.drain(start..self.pos);
+        self.pos = start;
+    }
+
+    // complete the word before the cursor as much as possible, the words matching it
+    // are returned when it can't be completed more
+    fn complete(&mut self, complete: &impl Complete) -> Vec<String> {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let mut start = self.pos;
+        while start > 0 && is_word(self.chars[start - 1]) {
+            start -= 1;
+        }
+        let prefix: String = self.chars[start..self.pos].iter().collect();
+        // `a.x` but not `0..x`
+        let field = start > 0
+            && self.chars[start - 1] == '.'
+            && (start < 2 || self.chars[start - 2] != '.');
+        if prefix.is_empty() && !field {
+            return Vec::new();
+        }
+
+        let mut words: Vec<String> = complete
+            .words(field)
+            .into_iter()
+            .filter(|word| word.starts_with(&prefix))
+            .collect();
+        words.sort();
+        words.dedup();
+        let common = match words.split_first() {
+            Some((first, rest)) => rest.iter().fold(first.as_str(), |common, word| {
+                let len = common
+                    .char_indices()
+                    .zip(word.chars())
+                    .take_while(|((_, a), b)| a == b)
+                    .last()
+                    .map_or(0, |((i, a), _)| i + a.len_utf8());
+                &common[..len]
+            }),
+            None => return Vec::new(),
+        };
+        if common.len() > prefix.len() {
+            let rest = common[prefix.len()..].to_owned();
+            self.insert_str(&rest);
+            return Vec::new();
+        }
+        if words.len() == 1 {
+            return Vec::new();
+        }
+        words
+    }
+}
+
+// the escape codes drawing the prompt and the line, and moving the cursor.
+// the bracket matching the one at the cursor is marked when `brackets` is true.
+fn render(prompt: &str, line: &Line, brackets: bool) -> String {
+    let text = line.text();
+    let marked = match brackets {
+        true => matching_brackets(&text, line.pos),
+        false => None,
+    };
+    let mut out = format!("\r{}{}\x1b[K\r", prompt, highlight(&text, marked));
+    let col = prompt.chars().count() + line.pos;
+    if col > 0 {
+        out += &format!("\x1b[{}C", col);
+    }
+    out
+}
+
+fn color(style: Style) -> &'static str {
+    match style {
+        Style::Keyword => "\x1b[35m",
+        Style::Literal | Style::Number => "\x1b[33m",
+        Style::Str => "\x1b[32m",
+        Style::Label => "\x1b[36m",
+        Style::Error => "\x1b[31m",
+        Style::Ident | Style::Punct => "",
+    }
+}
+
+// the text with colors, the bytes at `marked` are bold and underlined
+fn highlight(text: &str, marked: Option<(usize, usize)>) -> String {
+    let spans = compiler::spans(text);
+    let mut spans = spans.iter().peekable();
+    let mut out = String::with_capacity(text.len() * 2);
+    let mut last = ("", false);
+    for (i, c) in text.char_indices() {
+        while spans.next_if(|span| span.range.end <= i).is_some() {}
+        let color = match spans.peek() {
+            Some(span) if span.range.contains(&i) => color(span.style),
+            _ => "",
+        };
+        let style = (color, marked.is_some_and(|(a, b)| i == a || i == b));
+        if style != last {
+            out += "\x1b[0m";
+            out += style.0;
+            if style.1 {
+                out += "\x1b[1;4m";
+            }
+            last = style;
+        }
+        out.push(c);
+    }
+    if last != ("", false) {
+        out += "\x1b[0m";
+    }
+    out
+}
+
+// the byte offsets of the bracket at or before the char `pos` and the one matching it
+fn matching_brackets(text: &str, pos: usize) -> Option<(usize, usize)> {
+    let offset = |pos: usize| text.char_indices().nth(pos).map(|(i, _)| i);
+    let at = [offset(pos), pos.checked_sub(1).and_then(offset)];
+
+    let mut stack = Vec::new();
+    for span in compiler::spans(text) {
+        if span.style != Style::Punct {
+            continue;
+        }
+        let start = span.range.start;
+        match &text[span.range] {
+            "(" => stack.push((start, ")")),
+            "[" => stack.push((start, "]")),
+            "{" => stack.push((start, "}")),
+            close @ (")" | "]" | "}") => {
+                let (open, expected) = stack.pop()?;
+                if close != expected {
+                    return None;
+                }
+                if at.contains(&Some(open)) || at.contains(&Some(start)) {
+                    return Some((open, start));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{highlight, matching_brackets, Complete, Line};
+
+    struct Words;
+
+    impl Complete for Words {
+        fn words(&self, field: bool) -> Vec<String> {
+            let words: &[&str] = match field {
+                true => &["x", "y"],
+                false => &["let", "loop", "local", "while"],
+            };
+            words.iter().map(|w| w.to_string()).collect()
+        }
+    }
+
+    fn complete(text: &str) -> (String, Vec<String>) {
+        let mut line = Line::default();
+        line.set(text);
+        let words = line.complete(&Words);
+        (line.text(), words)
+    }
+
+    #[test]
+    fn test_complete() {
+        assert_eq!(complete("wh"), ("while".to_owned(), vec![]));
+        assert_eq!(
+            complete("lo"),
+            ("lo".to_owned(), vec!["local".to_owned(), "loop".to_owned()])
+        );
+        assert_eq!(complete("a = loc"), ("a = local".to_owned(), vec![]));
+        assert_eq!(
+            complete("p."),
+            ("p.".to_owned(), vec!["x".to_owned(), "y".to_owned()])
+        );
+        assert_eq!(
+            complete("0..l"),
+            (
+                "0..l".to_owned(),
+                vec!["let".to_owned(), "local".to_owned(), "loop".to_owned()]
+            )
+        );
+        assert_eq!(complete("a "), ("a ".to_owned(), vec![]));
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut line = Line::default();
+        line.set("let ab = 1");
+        line.pos = 6;
+        line.backspace();
+        line.insert('c');
+        assert_eq!(line.text(), "let ac = 1");
+        line.delete_word();
+        assert_eq!(line.text(), "let  = 1");
+        line.delete();
+        assert_eq!((line.text().as_str(), line.pos), ("let = 1", 4));
+    }
+
+    #[test]
+    fn test_matching_brackets() {
+        let text = "f([1, 2], \"(\")";
+        // at `[`, after `]` and after the last `)`
+        assert_eq!(matching_brackets(text, 2), Some((2, 7)));
+        assert_eq!(matching_brackets(text, 8), Some((2, 7)));
+        assert_eq!(matching_brackets(text, 14), Some((1, 13)));
+        assert_eq!(matching_brackets(text, 4), None);
+        assert_eq!(matching_brackets("(]", 0), None);
+    }
+
+    #[test]
+    fn test_highlight() {
+        let expect = "\x1b[0m\x1b[35mlet\x1b[0m a = \x1b[0m\x1b[33m1\x1b[0m";
+        assert_eq!(highlight("let a = 1", None), expect);
+        let expect = "\x1b[0m\x1b[1;4m()\x1b[0m";
+        assert_eq!(highlight("()", Some((0, 1))), expect);
+    }
+}
@@ -0,0 +1,97 @@
+use std::{fs, io, path::PathBuf};
+
+// the lines kept at most, the oldest ones are dropped
+const MAX_LEN: usize = 1000;
+
+// the lines entered, one line in the file for each
+#[derive(Default)]
+pub(crate) struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+}
+
+impl History {
+    // nothing is loaded when the file doesn't exist, it is created by `save`
+    pub(crate) fn load(path: PathBuf) -> History {
+        let entries = match fs::read_to_string(&path) {
+            Ok(text) => text.lines().map(|line| line.to_owned()).collect(),
+            Err(_) => Vec::new(),
+        };
+        let mut history = History {
+            entries,
+            path: Some(path),
+        };
+        history.truncate();
+        history
+    }
+
+    pub(crate) fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let text: String = self.entries.iter().map(|e| format!("{}\n", e)).collect();
+        fs::write(path, text)
+    }
+
+    // empty lines and a repeat of the last line aren't kept
+    pub(crate) fn add(&mut self, line: &str) {
+        if line.trim().is_empty() || self.entries.last().map(|e| e.as_str()) == Some(line) {
+            return;
+        }
+        self.entries.push(line.to_owned());
+        self.truncate();
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn get(&self, i: usize) -> Option<&str> {
+        self.entries.get(i).map(|e| e.as_str())
+    }
+
+    // the newest entry before `before` containing `query`
+    pub(crate) fn search(&self, query: &str, before: usize) -> Option<usize> {
+        let before = before.min(self.entries.len());
+        self.entries[..before]
+            .iter()
+            .rposition(|entry| entry.contains(query))
+    }
+
+    fn truncate(&mut self) {
+        if self.entries.len() > MAX_LEN {
+            self.entries.drain(..self.entries.len() - MAX_LEN);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+
+    #[test]
+    fn test_add_search() {
+        let mut history = History::default();
+        for line in ["let a = 1", "", "a + 1", "a + 1", "let b = a"] {
+            history.add(line);
+        }
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.search("let", 3), Some(2));
+        assert_eq!(history.search("let", 2), Some(0));
+        assert_eq!(history.search("c", 3), None);
+    }
+
+    #[test]
+    fn test_save_load() {
+        let name = format!("fpig_history_{}", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        let mut history = History::load(path.clone());
+        history.add("let a = 1");
+        history.add("a");
+        history.save().unwrap();
+        let history = History::load(path.clone());
+        assert_eq!(history.get(1), Some("a"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use std::io;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Key {
+    Char(char),
+    // Ctrl and a letter, e.g. `Ctrl('a')`
+    Ctrl(char),
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Esc,
+    // an escape sequence not supported
+    Unknown,
+}
+
+// read a key from the bytes of the terminal, `None` at the end of the input
+pub(crate) fn read_key(
+    mut next: impl FnMut() -> io::Result<Option<u8>>,
+) -> io::Result<Option<Key>> {
+    let byte = match next()? {
+        Some(byte) => byte,
+        None => return Ok(None),
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        b'\t' => Key::Tab,
+        0x7F | 0x08 => Key::Backspace,
+        0x1B => escape(&mut next)?,
+        0x01..=0x1A => Key::Ctrl((b'a' + byte - 1) as char),
+        0x00..=0x1F => Key::Unknown,
+        _ => match utf8(byte, &mut next)? {
+            Some(c) => Key::Char(c),
+            None => Key::Unknown,
+        },
+    };
+    Ok(Some(key))
+}
+
+// `ESC [ A` is up, `ESC [ 3 ~` is delete...
+fn escape(next: &mut impl FnMut() -> io::Result<Option<u8>>) -> io::Result<Key> {
+    let kind = match next()? {
+        Some(kind @ (b'[' | b'O')) => kind,
+        _ => return Ok(Key::Esc),
+    };
+    let key = match next()? {
+        Some(b'A') => Key::Up,
+        Some(b'B') => Key::Down,
+        Some(b'C') => Key::Right,
+        Some(b'D') => Key::Left,
+        Some(b'H') => Key::Home,
+        Some(b'F') => Key::End,
+        Some(digit @ b'0'..=b'9') if kind == b'[' => {
+            // read to the end of the sequence, e.g. `ESC [ 1 ; 5 C`
+            let mut params = vec![digit];
+            let last = loop {
+                match next()? {
+                    Some(b @ (b'0'..=b'9' | b';')) => params.push(b),
+                    Some(b) => break b,
+                    None => return Ok(Key::Unknown),
+                }
+            };
+            match (params.as_slice(), last) {
+                (b"1" | b"7", b'~') => Key::Home,
+                (b"3", b'~') => Key::Delete,
+                (b"4" | b"8", b'~') => Key::End,
+                _ => Key::Unknown,
+            }
+        }
+        _ => Key::Unknown,
+    };
+    Ok(key)
+}
+
+// the char starting with `first`
+fn utf8(first: u8, next: &mut impl FnMut() -> io::Result<Option<u8>>) -> io::Result<Option<char>> {
+    let len = match first {
+        0x00..=0x7F => 1,
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => return Ok(None),
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match next()? {
+            Some(b) => bytes.push(b),
+            None => return Ok(None),
+        }
+    }
+    let c = std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|s| s.chars().next());
+    Ok(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_key, Key};
+
+    fn keys(bytes: &[u8]) -> Vec<Key> {
+        let mut bytes = bytes.iter().copied();
+        let mut keys = Vec::new();
+        while let Some(key) = read_key(|| Ok(bytes.next())).unwrap() {
+            keys.push(key);
+        }
+        keys
+    }
+
+    #[test]
+    fn test_read_key() {
+        let bytes = "a\u{e9}\r\t\x7f\x01\x12\x1b[A\x1b[D\x1bOH\x1b[3~\x1b[1;5C\x1bx".as_bytes();
+        let expect = [
+            Key::Char('a'),
+            Key::Char('\u{e9}'),
+            Key::Enter,
+            Key::Tab,
+            Key::Backspace,
+            Key::Ctrl('a'),
+            Key::Ctrl('r'),
+            Key::Up,
+            Key::Left,
+            Key::Home,
+            Key::Delete,
+            Key::Unknown,
+            Key::Esc,
+        ];
+        assert_eq!(keys(bytes), expect);
+    }
+}
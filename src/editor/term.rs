@@ -0,0 +1,102 @@
+// the terminal in raw mode, the keys are read one by one without echo.
+// only unix terminals are supported, the editor reads whole lines elsewhere.
+
+use std::io;
+
+#[cfg(unix)]
+pub(crate) struct RawMode {
+    original: libc::termios,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    // `None` when the input isn't a terminal, e.g. a pipe
+    pub(crate) fn enable() -> io::Result<Option<RawMode>> {
+        if !is_tty() {
+            return Ok(None);
+        }
+        // SAFETY: the termios is filled by `tcgetattr` before it is read
+        let original = unsafe {
+            let mut termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            termios
+        };
+
+        let mut raw = original;
+        // no echo, no line buffering, Ctrl-C and Ctrl-V are read as keys
+        raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::IEXTEN | libc::ISIG);
+        // Ctrl-S and Ctrl-Q are read as keys, the enter is read as `\r`
+        raw.c_iflag &= !(libc::IXON | libc::ICRNL | libc::BRKINT | libc::INPCK | libc::ISTRIP);
+        raw.c_cflag |= libc::CS8;
+        // a read returns after one byte
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        set_termios(&raw)?;
+        Ok(Some(RawMode { original }))
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = set_termios(&self.original);
+    }
+}
+
+#[cfg(unix)]
+fn set_termios(termios: &libc::termios) -> io::Result<()> {
+    // SAFETY: the termios is a valid one from `tcgetattr`
+    if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSADRAIN, termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn is_tty() -> bool {
+    // SAFETY: it only checks the file descriptors
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 && libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
+
+// a byte of the input, `None` at the end of the input.
+// the stdin of std is buffered and can't be used with the raw mode.
+#[cfg(unix)]
+pub(crate) fn read_byte() -> io::Result<Option<u8>> {
+    let mut byte = 0u8;
+    loop {
+        // SAFETY: it writes at most one byte to `byte`
+        let n = unsafe { libc::read(libc::STDIN_FILENO, (&mut byte as *mut u8).cast(), 1) };
+        match n {
+            1 => return Ok(Some(byte)),
+            0 => return Ok(None),
+            _ => {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) struct RawMode;
+
+#[cfg(not(unix))]
+impl RawMode {
+    pub(crate) fn enable() -> io::Result<Option<RawMode>> {
+        Ok(None)
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_tty() -> bool {
+    false
+}
+
+#[cfg(not(unix))]
+pub(crate) fn read_byte() -> io::Result<Option<u8>> {
+    unreachable!("the raw mode is only enabled on unix")
+}
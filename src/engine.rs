@@ -0,0 +1,637 @@
+use core::fmt;
+
+use compiler::{Backend, Compiler};
+use std::any::Any;
+
+use vm::{
+    capability::Capabilities,
+    chunk::Chunk,
+    convert::IntoValue,
+    error::RuntimeError,
+    limits::{InterruptHandle, Limits},
+    native::Arity,
+    userdata::UserType,
+    value::Value,
+    vm::{Outcome, Vm},
+};
+
+// the language embedded in a rust program, the globals are kept between the calls
+pub struct Engine {
+    pub(crate) compiler: Compiler,
+    pub(crate) vm: Vm,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Compile(Vec<compiler::Error>),
+    Runtime(RuntimeError),
+    // a global variable never declared, e.g. by `call`
+    UndefinedGlobal(String),
+    // `set_global` can't declare one more
+    TooManyGlobals,
+}
+
+impl Engine {
+    // a pure engine, the scripts can't touch the files, the environment...
+    pub fn new() -> Engine {
+        Engine::with_capabilities(Capabilities::pure())
+    }
+
+    pub fn with_capabilities(capabilities: Capabilities) -> Engine {
+        Engine {
+            compiler: Compiler::new(),
+            vm: Vm::with_capabilities(capabilities),
+        }
+    }
+
+    // run the code on top of everything run before, it returns the value of the code,
+    // nil when the last statement isn't an expression.
+    pub fn eval(&mut self, code: &str) -> Result<Value, Error> {
+        Ok(self.run(code)?.unwrap_or(Value::Nil))
+    }
+
+    // the same as `eval`, but `None` when the last statement isn't an expression.
+    // a failed compile changes nothing, a failed run keeps the globals already set.
+    // a suspended run is abandoned, see `start`.
+    pub fn run(&mut self, code: &str) -> Result<Option<Value>, Error> {
+        self.abandon();
+        self.compiler.link_natives(&mut self.vm);
+        let chunk = self.compiler.compile_value(code).map_err(Error::Compile)?;
+        let result = self.vm.interpret(chunk);
+        self.finish(result)
+    }
+
+    // the same as `run`, but the code may `yield` a value to the host, or a native
+    // function may suspend it, see `resume`. the code run while it is suspended
+    // abandons it, the globals it hasn't set yet are undeclared like after a failure.
+    pub fn start(&mut self, code: &str) -> Result<Outcome, Error> {
+        self.abandon();
+        self.compiler.link_natives(&mut self.vm);
+        let chunk = self.compiler.compile_value(code).map_err(Error::Compile)?;
+        let result = self.vm.start(chunk);
+        self.finish(result)
+    }
+
+    // go on with the suspended code, `value` is the value of the `yield`
+    pub fn resume(&mut self, value: impl IntoValue) -> Result<Outcome, Error> {
+        let result = self.vm.resume(value.into_value());
+        self.finish(result)
+    }
+
+    fn finish<T>(&mut self, result: Result<T, RuntimeError>) -> Result<T, Error> {
+        result.map_err(|e| {
+            // the globals declared after the failure are never set
+            self.retain_set_globals();
+            Error::Runtime(e)
+        })
+    }
+
+    fn abandon(&mut self) {
+        if self.vm.is_suspended() {
+            // the vm isn't running, the engine is called by the host
+            let _ = self.vm.set_chunk(Chunk::new());
+            self.retain_set_globals();
+        }
+    }
+
+    // the compiler forgets the globals the vm has no value for
+    fn retain_set_globals(&mut self) {
+        let vm = &self.vm;
+        self.compiler.retain_globals(|i| vm.get_global(i).is_some());
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<&Value> {
+        let i = self.compiler.global(name)?;
+        self.vm.get_global(i)
+    }
+
+    // declare the global variable when it isn't, the code run later can use it
+    pub fn set_global(&mut self, name: &str, value: impl IntoValue) -> Result<(), Error> {
+        let i = self
+            .compiler
+            .declare_global(name)
+            .ok_or(Error::TooManyGlobals)?;
+        self.vm.define_global(i, value.into_value());
+        Ok(())
+    }
+
+    // call the function in the global variable `name`, e.g. a `fn` of the code run before
+    pub fn call(&mut self, name: &str, args: &[Value]) -> Result<Value, Error> {
+        self.compiler.link_natives(&mut self.vm);
+        let callee = self
+            .get_global(name)
+            .cloned()
+            .ok_or_else(|| Error::UndefinedGlobal(name.to_owned()))?;
+        self.vm.call_value(&callee, args).map_err(Error::Runtime)
+    }
+
+    // a rust function the code can call by `name`, it replaces the global with the same
+    // name. the closure may keep the state of the host, e.g. in a `Rc<RefCell<_>>`.
+    pub fn register_fn(
+        &mut self,
+        name: &str,
+        arity: Arity,
+        fun: impl Fn(&mut Vm, &[Value]) -> Result<Value, RuntimeError> + 'static,
+    ) -> Result<(), Error> {
+        self.vm.define_native(name, arity, fun);
+        let native = self.vm.native(name).cloned().unwrap_or(Value::Nil);
+        self.set_global(name, native)
+    }
+
+    // the methods and properties of a rust type, see `TypeBuilder`
+    pub fn register_type(&mut self, ty: UserType) {
+        self.vm.register_type(ty);
+    }
+
+    // a rust value of a registered type, e.g. for `set_global` or an argument of `call`
+    pub fn user_data<T: Any>(&self, value: T) -> Result<Value, Error> {
+        self.vm.user_data(value).map_err(Error::Runtime)
+    }
+
+    // the limits of every `eval` and `call`, e.g. for untrusted scripts
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.vm.set_limits(limits);
+    }
+
+    // abort the running `eval` or `call` from another thread
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.vm.interrupt_handle()
+    }
+
+    // the code run from now on is compiled for the backend, see `Backend`
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.compiler.set_backend(backend);
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.compiler.backend()
+    }
+
+    // the peephole pass over the bytecode, see `Compiler::set_optimize`
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.compiler.set_optimize(optimize);
+    }
+
+    pub fn optimize(&self) -> bool {
+        self.compiler.optimize()
+    }
+
+    pub fn vm(&mut self) -> &mut Vm {
+        &mut self.vm
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Compile(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", errors.join("\n"))
+            }
+            Error::Runtime(e) => write!(f, "runtime error: {}", e),
+            Error::UndefinedGlobal(name) => write!(f, "undefined global variable `{}`", name),
+            Error::TooManyGlobals => write!(f, "too many global variables"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::HashMap, rc::Rc, thread, time::Duration};
+
+    use vm::{
+        capability::Capabilities,
+        convert::{FromValue, IntoValue},
+        error::RuntimeError,
+        limits::Limits,
+        native::Arity,
+        userdata::TypeBuilder,
+        value::Value,
+        vm::Outcome,
+    };
+
+    use super::{Engine, Error};
+
+    #[test]
+    fn test_eval() {
+        let mut engine = Engine::new();
+        assert_eq!(engine.eval("let a = 1").unwrap(), Value::Nil);
+        assert_eq!(engine.eval("a + 1").unwrap(), Value::Int(2));
+        assert!(matches!(engine.eval("a +"), Err(Error::Compile(_))));
+        assert!(matches!(engine.eval("a + nil"), Err(Error::Runtime(_))));
+    }
+
+    #[test]
+    fn test_globals() {
+        let mut engine = Engine::new();
+        engine.set_global("limit", 10).unwrap();
+        engine.set_global("names", vec!["a", "b"]).unwrap();
+        engine.eval("let total = limit * len(names)").unwrap();
+        assert_eq!(engine.get_global("total"), Some(&Value::Int(20)));
+        engine.set_global("total", 1).unwrap();
+        assert_eq!(engine.eval("total").unwrap(), Value::Int(1));
+        assert_eq!(engine.get_global("missing"), None);
+
+        let mut engine = Engine::new();
+        let declared = (0..=1 << 16).map(|i| engine.set_global(&format!("g{}", i), i));
+        assert!(matches!(declared.last(), Some(Err(Error::TooManyGlobals))));
+        // the globals already declared can still be set
+        assert!(engine.set_global("g1", 0).is_ok());
+    }
+
+    #[test]
+    fn test_call() {
+        let mut engine = Engine::new();
+        engine
+            .eval("fn add(a, b) { a + b }\nfn fail() { assert(false) }")
+            .unwrap();
+        let sum = engine.call("add", &[1.into_value(), 2.into_value()]);
+        assert_eq!(sum.unwrap(), Value::Int(3));
+        let scores = HashMap::from([("x".to_owned(), 1)]);
+        let result = engine.call("len", &[scores.into_value()]).unwrap();
+        assert_eq!(usize::from_value(&result), Ok(1));
+
+        assert!(matches!(
+            engine.call("add", &[1.into_value()]),
+            Err(Error::Runtime(RuntimeError::WrongArity { .. }))
+        ));
+        assert!(matches!(engine.call("fail", &[]), Err(Error::Runtime(_))));
+        assert!(matches!(
+            engine.call("sub", &[]),
+            Err(Error::UndefinedGlobal(_))
+        ));
+        // the engine still works after the failures
+        assert_eq!(engine.eval("add(2, 3)").unwrap(), Value::Int(5));
+    }
+
+    #[test]
+    fn test_register_fn() {
+        let mut engine = Engine::new();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let host = log.clone();
+        engine
+            .register_fn("log", Arity::Exact(1), move |_, args| {
+                host.borrow_mut().push(String::from_value(&args[0])?);
+                Ok(Value::Nil)
+            })
+            .unwrap();
+        // a native function calling back into the code
+        engine
+            .register_fn("twice", Arity::Exact(2), |vm, args| {
+                let once = vm.call_value(&args[0], &args[1..])?;
+                vm.call_value(&args[0], &[once])
+            })
+            .unwrap();
+        let code = "
+            fn inc(x) { log(str(x)) x + 1 }
+            twice(inc, 1)
+        ";
+        assert_eq!(engine.eval(code).unwrap(), Value::Int(3));
+        assert_eq!(*log.borrow(), ["1", "2"]);
+        assert!(matches!(
+            engine.eval("log(1)"),
+            Err(Error::Runtime(RuntimeError::TypeError(_)))
+        ));
+    }
+
+    #[derive(PartialEq)]
+    struct Request {
+        path: String,
+        headers: HashMap<String, String>,
+    }
+
+    impl std::fmt::Display for Request {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Request({})", self.path)
+        }
+    }
+
+    fn request(path: &str) -> Request {
+        Request {
+            path: path.to_owned(),
+            headers: HashMap::from([("x".to_owned(), "1".to_owned())]),
+        }
+    }
+
+    #[test]
+    fn test_user_data() {
+        let mut engine = Engine::new();
+        let ty = TypeBuilder::<Request>::new("Request")
+            .method("header", Arity::Exact(1), |req, _, args| {
+                let name = String::from_value(&args[0])?;
+                Ok(req.headers.get(&name).cloned().into_value())
+            })
+            .method_mut("set_header", Arity::Exact(2), |req, _, args| {
+                let name = String::from_value(&args[0])?;
+                req.headers.insert(name, String::from_value(&args[1])?);
+                Ok(Value::Nil)
+            })
+            .getter("path", |req| req.path.clone().into_value())
+            .setter("path", |req, value| {
+                req.path = String::from_value(&value)?;
+                Ok(())
+            })
+            .display()
+            .eq()
+            .build();
+        engine.register_type(ty);
+        let req = engine.user_data(request("/a")).unwrap();
+        engine.set_global("request", req).unwrap();
+        let other = engine.user_data(request("/a")).unwrap();
+        engine.set_global("other", other).unwrap();
+
+        let code = r#"
+            request.set_header("y", "2")
+            request.path = request.path + "/b";
+            (request.header("x"), request.header("y"), request.header("z"), request.path)
+        "#;
+        let result = engine.eval(code).unwrap();
+        assert_eq!(
+            result.to_string(),
+            r#"(Some("1"), Some("2"), None, "/a/b")"#
+        );
+        let code = r#"(request.header("z").unwrap_or("0"), request.header("x") == Some("1"))"#;
+        assert_eq!(engine.eval(code).unwrap().to_string(), r#"("0", true)"#);
+        assert_eq!(
+            engine.eval("str(request)").unwrap().to_string(),
+            "Request(/a/b)"
+        );
+        assert_eq!(
+            engine.eval("type_of(request)").unwrap().to_string(),
+            "Request"
+        );
+        assert_eq!(engine.eval("request == other").unwrap(), Value::Bool(false));
+        engine
+            .eval("other.path = \"/a/b\"; other.set_header(\"y\", \"2\")")
+            .unwrap();
+        assert_eq!(engine.eval("request == other").unwrap(), Value::Bool(true));
+
+        let error = |engine: &mut Engine, code| match engine.eval(code) {
+            Err(Error::Runtime(e)) => e.to_string(),
+            result => panic!("unexpected {:?}", result),
+        };
+        assert_eq!(
+            error(&mut engine, "request.body()"),
+            "type error: `Request` has no method `body`"
+        );
+        assert_eq!(
+            error(&mut engine, "request.header()"),
+            "`Request.header` takes 1 argument, found 0"
+        );
+        assert_eq!(
+            error(&mut engine, "request.method"),
+            "type error: `Request` has no property `method`"
+        );
+        assert_eq!(
+            error(&mut engine, "request.header(1)"),
+            "type error: expected `str`, found `int`"
+        );
+        assert!(engine.user_data(1).is_err());
+    }
+
+    #[test]
+    fn test_struct_method() {
+        let mut engine = Engine::new();
+        let code = "
+            struct Counter { step, next }
+            fn next(n) { n + 1 }
+            let c = Counter { step: 2, next: next }
+            c.next(c.step)
+        ";
+        assert_eq!(engine.eval(code).unwrap(), Value::Int(3));
+    }
+
+    #[test]
+    fn test_cyclic() {
+        let mut engine = Engine::new();
+        let code = "
+            let a = [1]
+            a[0] = a
+            let b = [1]
+            b[0] = b
+            let m = #{}
+            m[\"self\"] = m
+            let t = (str(a), a == b, a < b, str(m), m == m)
+            t
+        ";
+        let result = engine.eval(code).unwrap();
+        assert_eq!(
+            result.to_string(),
+            r##"("[[...]]", true, false, "#{\"self\": #{...}}", true)"##
+        );
+        // what the repl prints
+        assert_eq!(engine.eval("a").unwrap().to_string(), "[[...]]");
+    }
+
+    fn limited(limits: Limits, code: &str) -> RuntimeError {
+        let mut engine = Engine::new();
+        engine.set_limits(limits);
+        match engine.eval(code) {
+            Err(Error::Runtime(e)) => {
+                // the next run has its own budget
+                assert_eq!(engine.eval("1 + 1").unwrap(), Value::Int(2));
+                e
+            }
+            result => panic!("unexpected {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let fuel = Limits {
+            fuel: Some(1000),
+            ..Limits::default()
+        };
+        assert_eq!(limited(fuel, "while true {}"), RuntimeError::OutOfFuel);
+        let depth = Limits {
+            call_depth: Some(100),
+            ..Limits::default()
+        };
+        let code = "fn f(n) { f(n + 1) }\nf(0)";
+        assert_eq!(limited(depth, code), RuntimeError::CallDepthExceeded);
+        let stack = Limits {
+            stack_size: Some(1000),
+            ..Limits::default()
+        };
+        let code = "fn f(n) { 1 + f(n) }\nf(0)";
+        assert_eq!(limited(stack, code), RuntimeError::StackOverflow);
+        let heap = Limits {
+            heap_bytes: Some(1 << 20),
+            ..Limits::default()
+        };
+        let code = "let s = \"x\"\nloop { s = s + s }";
+        assert_eq!(limited(heap.clone(), code), RuntimeError::OutOfMemory);
+        // the strings too long for the cap are never made
+        for code in ["\"ab\" * 1000000000", "\"ab\" * (100000 * 100000 * 100)"] {
+            assert_eq!(limited(heap.clone(), code), RuntimeError::OutOfMemory);
+        }
+        let code = "\"ab\" * (1000000000 * 1000000000 * 9)";
+        assert_eq!(limited(Limits::default(), code), RuntimeError::OutOfMemory);
+        let timeout = Limits {
+            timeout: Some(Duration::from_millis(50)),
+            ..Limits::default()
+        };
+        assert_eq!(limited(timeout, "while true {}"), RuntimeError::Timeout);
+    }
+
+    #[test]
+    fn test_deep_values() {
+        // they are dropped without recursing
+        let mut engine = Engine::new();
+        let code = "
+            let l = []
+            let t = ()
+            for i in 0..100000 { l = [l]
+            t = (t,) }
+        ";
+        engine.eval(code).unwrap();
+        assert_eq!(engine.eval("l == l").unwrap(), Value::Bool(true));
+        engine.eval("l = nil\nt = nil").unwrap();
+    }
+
+    #[test]
+    fn test_nested_runs() {
+        // every generator resumes the next one in the rust stack
+        let code = "fn* g() { for x in g() { yield x }\nyield 1 }\nfor x in g() {}";
+        let depth = Limits {
+            call_depth: Some(200),
+            ..Limits::default()
+        };
+        for limits in [Limits::default(), depth] {
+            match limited(limits, code) {
+                RuntimeError::Coroutine { error, .. } => {
+                    assert_eq!(*error, RuntimeError::CallDepthExceeded)
+                }
+                e => panic!("unexpected {:?}", e),
+            }
+        }
+        // the calls of the resumers count
+        let depth = |max| Limits {
+            call_depth: Some(max),
+            ..Limits::default()
+        };
+        let code = "
+            fn* g(n) { if n > 0 { for x in g(n - 1) { yield x } } else { yield 1 } }
+            let s = 0
+            for x in g(10) { s = s + x }
+            s
+        ";
+        let mut engine = Engine::new();
+        engine.set_limits(depth(100));
+        assert_eq!(engine.eval(code).unwrap(), Value::Int(1));
+        assert!(matches!(
+            limited(depth(10), code),
+            RuntimeError::Coroutine { error, .. } if *error == RuntimeError::CallDepthExceeded
+        ));
+    }
+
+    #[test]
+    fn test_heap_not_leaked() {
+        // the values dropped don't count
+        let heap = Limits {
+            heap_bytes: Some(1 << 20),
+            ..Limits::default()
+        };
+        let mut engine = Engine::new();
+        engine.set_limits(heap);
+        let code = "for i in 0..20000 { let s = str(i) + \"0123456789\" * 10; [s, s] }";
+        assert_eq!(engine.eval(code).unwrap(), Value::Nil);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut engine = Engine::new();
+        let handle = engine.interrupt_handle();
+        let interrupter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            handle.interrupt();
+        });
+        let result = engine.eval("while true {}");
+        interrupter.join().unwrap();
+        assert!(matches!(
+            result,
+            Err(Error::Runtime(RuntimeError::Interrupted))
+        ));
+        assert_eq!(engine.eval("1").unwrap(), Value::Int(1));
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let mut engine = Engine::new();
+        let result = engine.eval("env(\"HOME\")");
+        assert!(matches!(
+            result,
+            Err(Error::Runtime(RuntimeError::PermissionDenied(_)))
+        ));
+
+        let mut engine = Engine::with_capabilities(Capabilities {
+            clock: true,
+            ..Capabilities::pure()
+        });
+        assert_eq!(
+            engine.eval("type_of(clock())").unwrap(),
+            "float".into_value()
+        );
+        assert!(engine.eval("random()").is_err());
+    }
+
+    #[test]
+    fn test_resume() {
+        let mut engine = Engine::new();
+        engine
+            .register_fn("wait", Arity::Exact(1), |vm, args| {
+                vm.suspend(args[0].clone());
+                Ok(Value::Nil)
+            })
+            .unwrap();
+        let code = "
+            fn step(n) { yield n * 10 }
+            let got = step(1) + wait(2);
+            got
+        ";
+        assert_eq!(
+            engine.start(code).unwrap(),
+            Outcome::Suspended(Value::Int(10))
+        );
+        assert_eq!(engine.resume(1).unwrap(), Outcome::Suspended(Value::Int(2)));
+        assert_eq!(
+            engine.resume(5).unwrap(),
+            Outcome::Done(Some(Value::Int(6)))
+        );
+        assert!(matches!(
+            engine.eval("yield 1"),
+            Err(Error::Runtime(RuntimeError::CantSuspend))
+        ));
+        assert!(matches!(
+            engine.resume(()),
+            Err(Error::Runtime(RuntimeError::NotSuspended))
+        ));
+
+        // the code run while suspended abandons the suspended run
+        let mut engine = Engine::new();
+        assert_eq!(
+            engine.start("let q = yield 5\nq").unwrap(),
+            Outcome::Suspended(Value::Int(5))
+        );
+        assert_eq!(engine.eval("40 + 2").unwrap(), Value::Int(42));
+        assert!(matches!(engine.eval("q"), Err(Error::Compile(_))));
+        assert!(matches!(
+            engine.resume(1),
+            Err(Error::Runtime(RuntimeError::NotSuspended))
+        ));
+        engine.start("let p = yield 1").unwrap();
+        assert_eq!(
+            engine.start("let q = 7\nq").unwrap(),
+            Outcome::Done(Some(Value::Int(7)))
+        );
+        assert_eq!(engine.get_global("p"), None);
+    }
+}
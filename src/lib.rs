@@ -0,0 +1,9 @@
+mod editor;
+mod engine;
+mod repl;
+mod session;
+
+pub use editor::{Complete, Editor, ReadLine};
+pub use engine::{Engine, Error};
+pub use repl::{Flow, Repl};
+pub use session::Session;
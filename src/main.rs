@@ -1,17 +1,78 @@
 use std::io::{self, Write};
 
-use compiler::Compiler;
-use vm::vm::Vm;
+use compiler::{Backend, Input};
+use fpig::{Editor, Flow, ReadLine, Repl};
+
+const PROMPT: &str = "fpig> ";
+// the prompt of the lines after the first one when the input is incomplete
+const CONTINUE_PROMPT: &str = "....> ";
 
 fn main() {
-    let mut compiler = Compiler::new();
-    let mut vm = Vm::new();
+    let mut repl = Repl::new();
+    // `--backend register` runs the code on the register backend, see `:backend`
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let [flag, name] = args.as_slice() {
+        match Backend::from_name(name) {
+            Some(backend) if flag == "--backend" => repl.set_backend(backend),
+            _ => {
+                eprintln!("usage: fpig [--backend stack|register]");
+                std::process::exit(2);
+            }
+        }
+    }
+    let mut editor = Editor::new();
+    let result = run(&mut repl, &mut editor);
+    if let Err(e) = editor.save_history() {
+        eprintln!("error: fail to save the history: {}", e);
+    }
+    match result {
+        // the reader of the output is gone, e.g. `fpig | head`
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+        Ok(()) => {}
+    }
+}
+
+fn run(repl: &mut Repl, editor: &mut Editor) -> io::Result<()> {
+    // Ctrl-D ends the input
+    while let Some(input) = read_input(editor, repl)? {
+        if repl.input(&input, &mut io::stdout())? == Flow::Quit {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// read lines until the input is complete, an empty line submits the incomplete input.
+// a `:` command is always one line. it returns `None` when there is no more input.
+fn read_input(editor: &mut Editor, repl: &Repl) -> io::Result<Option<String>> {
+    let mut input = String::new();
+    let mut prompt = PROMPT;
     loop {
-        print!("fpig> ");
-        io::stdout().flush().unwrap();
-        let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
-        let chunk = compiler.compile(&input);
-        vm.interpret(chunk).expect("fail to run");
+        let line = match editor.read_line(prompt, repl)? {
+            ReadLine::Line(line) => line,
+            // drop the lines read
+            ReadLine::Interrupted => {
+                input.clear();
+                prompt = PROMPT;
+                continue;
+            }
+            // run what is read
+            ReadLine::Eof => {
+                writeln!(io::stdout())?;
+                return Ok((!input.is_empty()).then_some(input));
+            }
+        };
+        let empty = line.trim().is_empty();
+        let command = input.is_empty() && line.trim_start().starts_with(':');
+        input += &line;
+        input.push('\n');
+        if empty || command || compiler::check(&input) != Input::Incomplete {
+            return Ok(Some(input));
+        }
+        prompt = CONTINUE_PROMPT;
     }
 }
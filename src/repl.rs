@@ -0,0 +1,297 @@
+use std::{
+    fs,
+    io::{self, Write},
+    time::Instant,
+};
+
+use compiler::Backend;
+use vm::value::Value;
+
+use crate::{editor::Complete, session::Session};
+
+const HELP: &str = "\
+:help           show this message
+:quit           exit the REPL, the same as Ctrl-D
+:load <file>    run the code in the file
+:reset          forget everything run before
+:globals        show the global variables
+:type <expr>    run the expression and show the type of its value, not saved
+:ast <code>     show the syntax tree of the code
+:bytecode <code>
+                show the bytecode of the code, it isn't run
+:time <code>    run the code and show the time and the number of instructions
+:save <file>    write the code run without errors to the file
+:backend [name] show or set the backend of the code run next, `stack` or `register`
+:optimize [on|off]
+                show or set the peephole pass over the bytecode of the code run next";
+
+// what the REPL does after an input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    Quit,
+}
+
+// the code and the `:` commands of the REPL, the output is written to `out`
+pub struct Repl {
+    session: Session,
+    // the code run without errors, see `:save`
+    history: Vec<String>,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl {
+            session: Session::new(),
+            history: Vec::new(),
+        }
+    }
+
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.session.set_backend(backend);
+    }
+
+    pub fn input(&mut self, input: &str, out: &mut impl Write) -> io::Result<Flow> {
+        let input = input.trim_end();
+        if input.trim().is_empty() {
+            return Ok(Flow::Continue);
+        }
+        match input.trim_start().strip_prefix(':') {
+            Some(command) => self.command(command, out),
+            None => {
+                self.run(input, out)?;
+                Ok(Flow::Continue)
+            }
+        }
+    }
+
+    fn command(&mut self, command: &str, out: &mut impl Write) -> io::Result<Flow> {
+        let (name, arg) = match command.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (command, ""),
+        };
+        let needs_arg = matches!(name, "load" | "type" | "ast" | "bytecode" | "time" | "save");
+        if needs_arg && arg.is_empty() {
+            writeln!(out, "error: `:{}` needs an argument, see `:help`", name)?;
+            return Ok(Flow::Continue);
+        }
+
+        match name {
+            "help" => writeln!(out, "{}", HELP)?,
+            "quit" => return Ok(Flow::Quit),
+            "load" => match fs::read_to_string(arg) {
+                Ok(code) => self.run(&code, out)?,
+                Err(e) => writeln!(out, "error: fail to read `{}`: {}", arg, e)?,
+            },
+            "reset" => {
+                // the backend is a setting, not a part of the code run
+                let (backend, optimize) = (self.session.backend(), self.session.optimize());
+                *self = Repl::new();
+                self.session.set_backend(backend);
+                self.session.set_optimize(optimize);
+            }
+            "globals" => {
+                for (name, value) in self.session.globals() {
+                    writeln!(out, "{} = {}", name, value)?;
+                }
+            }
+            // the expression is run, there is no type without a value
+            "type" => {
+                if let Some(value) = self.eval(arg, out)? {
+                    writeln!(out, "{}", value.type_name())?;
+                }
+            }
+            "ast" => match compiler::dump_ast(arg) {
+                Ok(ast) => writeln!(out, "{}", ast)?,
+                Err(e) => writeln!(out, "{}", e)?,
+            },
+            "bytecode" => match self.session.bytecode(arg) {
+                Ok(bytecode) => write!(out, "{}", bytecode)?,
+                Err(e) => writeln!(out, "{}", e)?,
+            },
+            "time" => {
+                let start = Instant::now();
+                self.run(arg, out)?;
+                let elapsed = start.elapsed();
+                let count = self.session.instruction_count();
+                writeln!(out, "time: {:?}, instructions: {}", elapsed, count)?;
+            }
+            "save" => {
+                let code: String = self.history.iter().map(|c| format!("{}\n", c)).collect();
+                if let Err(e) = fs::write(arg, code) {
+                    writeln!(out, "error: fail to write `{}`: {}", arg, e)?;
+                }
+            }
+            "backend" if arg.is_empty() => writeln!(out, "{}", self.session.backend().name())?,
+            "backend" => match Backend::from_name(arg) {
+                Some(backend) => self.session.set_backend(backend),
+                None => writeln!(out, "error: unknown backend `{}`", arg)?,
+            },
+            "optimize" => match arg {
+                "" => writeln!(
+                    out,
+                    "{}",
+                    if self.session.optimize() { "on" } else { "off" }
+                )?,
+                "on" | "off" => self.session.set_optimize(arg == "on"),
+                _ => writeln!(out, "error: expected `on` or `off`, found `{}`", arg)?,
+            },
+            _ => writeln!(out, "error: unknown command `:{}`, see `:help`", name)?,
+        }
+        Ok(Flow::Continue)
+    }
+
+    // run the code and show its value, it is kept in the history when it doesn't fail
+    fn run(&mut self, code: &str, out: &mut impl Write) -> io::Result<()> {
+        let value = self.eval(code, out)?;
+        if value.is_some() {
+            self.history.push(code.trim_end().to_owned());
+        }
+        match value {
+            Some(Value::Nil) | None => Ok(()),
+            Some(value) => writeln!(out, "{}", value),
+        }
+    }
+
+    // it returns the value of the code, or nil when the code has no value.
+    // nothing is returned when it fails.
+    fn eval(&mut self, code: &str, out: &mut impl Write) -> io::Result<Option<Value>> {
+        let result = self.session.eval(code);
+        for warning in self.session.take_warnings() {
+            writeln!(out, "{}", warning)?;
+        }
+        match result {
+            Ok(value) => Ok(Some(value.unwrap_or(Value::Nil))),
+            Err(e) => {
+                writeln!(out, "{}", e)?;
+                Ok(None)
+            }
+        }
+    }
+}
+
+// keywords and global variables, or the fields of structs after a `.`
+impl Complete for Repl {
+    fn words(&self, field: bool) -> Vec<String> {
+        if field {
+            let fields = self.session.struct_fields().into_iter();
+            return fields.map(|field| field.to_owned()).collect();
+        }
+        let globals = self.session.globals().into_iter().map(|(name, _)| name);
+        let keywords = compiler::keywords().map(|keyword| keyword.to_owned());
+        keywords
+            .chain(globals.map(|name| name.to_owned()))
+            .collect()
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::Complete;
+
+    use super::{Flow, Repl};
+
+    // run the inputs and get the output of the last one
+    fn run(inputs: &[&str]) -> String {
+        let mut repl = Repl::new();
+        let mut out = Vec::new();
+        for input in inputs {
+            out.clear();
+            repl.input(input, &mut out).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_code() {
+        assert_eq!(run(&["let a = 1", "a + 1"]), "2\n");
+        assert_eq!(run(&["let a = 1"]), "");
+        assert_eq!(run(&["b"]), "error: undefined variable `b`\n");
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut repl = Repl::new();
+        let mut out = Vec::new();
+        assert_eq!(repl.input(":quit", &mut out).unwrap(), Flow::Quit);
+        assert_eq!(
+            run(&["let b = 2", "let a = 1", ":globals"]),
+            "b = 2\na = 1\n"
+        );
+        assert_eq!(run(&["let a = 1", ":reset", ":globals"]), "");
+        assert_eq!(run(&[":type (1, \"a\")"]), "tuple\n");
+        assert_eq!(
+            run(&[":type"]),
+            "error: `:type` needs an argument, see `:help`\n"
+        );
+        assert_eq!(
+            run(&[":foo"]),
+            "error: unknown command `:foo`, see `:help`\n"
+        );
+        assert!(run(&[":time 1 + 2"]).starts_with("3\ntime: "));
+        assert!(run(&[":bytecode 1 + 2"]).contains("Add"));
+        assert!(run(&[":ast 1 + 2"]).contains("Binary"));
+        assert_eq!(run(&[":backend"]), "stack\n");
+        assert_eq!(
+            run(&[":backend register", ":reset", ":backend"]),
+            "register\n"
+        );
+        assert!(run(&[":backend register", ":bytecode 1 + 2"]).contains("Add r"));
+        assert_eq!(run(&[":backend foo"]), "error: unknown backend `foo`\n");
+        assert_eq!(run(&[":optimize"]), "on\n");
+        assert_eq!(run(&[":optimize off", ":reset", ":optimize"]), "off\n");
+        let code = ":bytecode let a = 1\na != 2";
+        assert!(run(&[code]).contains("NotEq"));
+        assert!(!run(&[":optimize off", code]).contains("NotEq"));
+        assert_eq!(
+            run(&[":optimize yes"]),
+            "error: expected `on` or `off`, found `yes`\n"
+        );
+    }
+
+    #[test]
+    fn test_type() {
+        // the expression is run but it isn't kept in the history
+        let mut repl = Repl::new();
+        let mut out = Vec::new();
+        for input in ["let n = 1", ":type { n = n + 1\nn }", ":type 1 + nil"] {
+            repl.input(input, &mut out).unwrap();
+        }
+        let output = String::from_utf8(out).unwrap();
+        assert!(output.starts_with("int\n"), "{}", output);
+        assert_eq!(repl.history, ["let n = 1"]);
+        let mut out = Vec::new();
+        repl.input("n", &mut out).unwrap();
+        assert_eq!(out, b"2\n");
+    }
+
+    #[test]
+    fn test_words() {
+        let mut repl = Repl::new();
+        let mut out = Vec::new();
+        repl.input("struct Point { x, y }\nlet origin = 1", &mut out)
+            .unwrap();
+        assert_eq!(repl.words(true), ["x", "y"]);
+        let words = repl.words(false);
+        assert!(words.iter().any(|w| w == "while"));
+        assert!(words.iter().any(|w| w == "origin"));
+    }
+
+    #[test]
+    fn test_save_load() {
+        let path = std::env::temp_dir().join(format!("fpig_repl_{}.fp", std::process::id()));
+        let path = path.to_str().unwrap();
+        let save = format!(":save {}", path);
+        let load = format!(":load {}", path);
+        // the failed code isn't saved
+        run(&["let a = 1", "let b = c", "let b = a + 1", &save]);
+        assert_eq!(run(&[&load, "b"]), "2\n");
+        std::fs::remove_file(path).unwrap();
+    }
+}
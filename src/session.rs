@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+
+use compiler::{Backend, Warning};
+use vm::{capability::Capabilities, debug, value::Value, vm::Vm};
+
+use crate::engine::{Engine, Error};
+
+// the state kept between the inputs of the REPL
+pub struct Session {
+    engine: Engine,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            // the user runs their own code
+            engine: Engine::with_capabilities(Capabilities::all()),
+        }
+    }
+
+    // see `Engine::run`
+    pub fn eval(&mut self, code: &str) -> Result<Option<Value>, Error> {
+        self.engine.run(code)
+    }
+
+    pub fn set_backend(&mut self, backend: Backend) {
+        self.engine.set_backend(backend);
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.engine.backend()
+    }
+
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.engine.set_optimize(optimize);
+    }
+
+    pub fn optimize(&self) -> bool {
+        self.engine.optimize()
+    }
+
+    pub fn vm(&mut self) -> &mut Vm {
+        self.engine.vm()
+    }
+
+    // the value of a global variable
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.engine.get_global(name)
+    }
+
+    // the global variables set, in the order they are declared.
+    // the native functions are skipped unless they are replaced.
+    pub fn globals(&self) -> Vec<(&str, &Value)> {
+        let Engine { compiler, vm } = &self.engine;
+        let natives: HashMap<_, _> = vm.natives().collect();
+        let mut globals: Vec<_> = compiler
+            .globals()
+            .filter_map(|(name, i)| Some((i, name, vm.get_global(i)?)))
+            .filter(|(_, name, value)| natives.get(name) != Some(value))
+            .collect();
+        globals.sort_by_key(|(i, _, _)| *i);
+        globals
+            .into_iter()
+            .map(|(_, name, value)| (name, value))
+            .collect()
+    }
+
+    // the fields of every struct declared
+    pub fn struct_fields(&self) -> Vec<&str> {
+        self.engine.compiler.struct_fields().collect()
+    }
+
+    // the bytecode of the code, it isn't run
+    pub fn bytecode(&mut self, code: &str) -> Result<String, Error> {
+        let Engine { compiler, vm } = &mut self.engine;
+        compiler.link_natives(vm);
+        let chunk = compiler.compile_dry(code).map_err(Error::Compile)?;
+        Ok(debug::disassemble(&chunk))
+    }
+
+    // the number of instructions run by the last `eval`
+    pub fn instruction_count(&self) -> u64 {
+        self.engine.vm.instruction_count()
+    }
+
+    // warnings of the code evaluated since the last call
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        self.engine.compiler.take_warnings()
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value() {
+        let mut session = Session::new();
+        assert_eq!(session.eval("let a = 1").unwrap(), None);
+        assert_eq!(session.eval("a + 1").unwrap(), Some(Value::Int(2)));
+        assert_eq!(session.eval("a = a * 5; a").unwrap(), Some(Value::Int(5)));
+        assert_eq!(session.global("a"), Some(&Value::Int(5)));
+    }
+
+    #[test]
+    fn test_compile_error() {
+        let mut session = Session::new();
+        session.eval("let a = 1").unwrap();
+        // nothing is declared by the failed code
+        let result = session.eval("let b = 2\nlet c = d");
+        assert!(matches!(result, Err(Error::Compile(_))));
+        assert!(matches!(session.eval("b"), Err(Error::Compile(_))));
+        let result = session.eval("let e = (");
+        assert!(matches!(result, Err(Error::Compile(_))));
+        assert_eq!(session.eval("a").unwrap(), Some(Value::Int(1)));
+    }
+
+    #[test]
+    fn test_runtime_error() {
+        let mut session = Session::new();
+        let result = session.eval("let a = 1\nlet b = a + true\nlet c = 3");
+        assert!(matches!(result, Err(Error::Runtime(_))));
+        // `a` is set before the failure, `b` and `c` never are
+        assert_eq!(session.eval("a").unwrap(), Some(Value::Int(1)));
+        assert!(matches!(session.eval("b"), Err(Error::Compile(_))));
+        assert!(matches!(session.eval("c"), Err(Error::Compile(_))));
+        // declared again, they get new indexes
+        session.eval("let b = 2\nlet c = a + b").unwrap();
+        assert_eq!(session.global("c"), Some(&Value::Int(3)));
+    }
+
+    #[test]
+    fn test_globals() {
+        let mut session = Session::new();
+        session.eval("let b = 1\nlet a = b + 1").unwrap();
+        let _ = session.eval("let c = 3\nlet d = c + nil");
+        let expect = [
+            ("b", &Value::Int(1)),
+            ("a", &Value::Int(2)),
+            ("c", &Value::Int(3)),
+        ];
+        assert_eq!(session.globals(), expect);
+    }
+
+    #[test]
+    fn test_bytecode() {
+        let mut session = Session::new();
+        let bytecode = session.bytecode("let a = 1").unwrap();
+        assert!(bytecode.contains("SetGlobal"));
+        // nothing is declared
+        assert!(matches!(session.eval("a"), Err(Error::Compile(_))));
+    }
+}